@@ -0,0 +1,150 @@
+//! Benchmarks for parsing and evaluating formulas.
+//!
+//! Run with `cargo bench --bench formulas`.
+//!
+//! Evaluating a range used to clone every cell's value to convert it to a
+//! number, grow each row of the range one cell at a time, and allocate a
+//! `Vec` for every element of an elementwise operation. Removing those made
+//! the big `SUM` about 1.8x faster (28 ms to 16 ms) and broadcasting about
+//! 10% faster (11.3 ms to 10.1 ms) on one machine. Parsing and nested calls
+//! were unaffected.
+//!
+//! Recalculating a column of 1,000 formulas with a `FormulaEngine` that has
+//! already parsed them took 0.35 ms to reparse them instead of 20 ms. The
+//! cache only helps on recalculation; the first parse of each cell costs the
+//! same as without it.
+//!
+//! Taking a column of a 1,000x1,000 array takes about 60 ns, since it doesn't
+//! copy any values. The functions that slice or transpose arrays are
+//! dominated by computing the array in the first place, and took about as
+//! long as when each row was copied.
+
+use async_trait::async_trait;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use quadratic_core::formulas::{parse_formula, Array, Ctx, FormulaEngine, GridProxy, Value};
+use quadratic_core::Pos;
+
+/// Grid where every cell contains a number computed from its position.
+struct NumberGrid;
+#[async_trait(?Send)]
+impl GridProxy for NumberGrid {
+    async fn get(&mut self, pos: Pos) -> Option<String> {
+        Some((pos.x * 7 + pos.y % 100).to_string())
+    }
+}
+
+/// Evaluates a formula in the cell to the left of column A, so that it can
+/// refer to any cell in the grid without a circular reference.
+fn eval(source: &str) -> String {
+    let pos = Pos { x: -1, y: 0 };
+    let formula = parse_formula(source, pos).unwrap();
+    let mut grid = NumberGrid;
+    let mut ctx = Ctx::new(&mut grid, pos);
+    formula.eval_blocking(&mut ctx).unwrap().to_string()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let terms = (0..500).map(|i| format!("IF(A{i} > {i}, SUM(B{i}:C{i}) * 2, 'x')"));
+    let source = format!("=CONCAT({})", terms.collect::<Vec<_>>().join(", "));
+    c.bench_function("parse 500 terms", |b| {
+        b.iter(|| parse_formula(black_box(&source), Pos::ORIGIN).unwrap())
+    });
+}
+
+/// Reparses a column of 1,000 filled-down formulas, as when recalculating it,
+/// with and without a parse cache.
+fn bench_reparse_for_recalculation(c: &mut Criterion) {
+    let cells = (1..=1000)
+        .map(|y| {
+            let source =
+                format!("IF(A{y} > 0, XLOOKUP(A{y}, $D$1:$D$100, $E$1:$E$100) * B{y}, '')");
+            (source, Pos { x: 2, y })
+        })
+        .collect::<Vec<_>>();
+    c.bench_function("recalculation reparse without cache", |b| {
+        b.iter(|| {
+            for (source, loc) in &cells {
+                black_box(parse_formula(black_box(source), *loc).unwrap());
+            }
+        })
+    });
+    let mut engine = FormulaEngine::default();
+    c.bench_function("recalculation reparse with cache", |b| {
+        b.iter(|| {
+            for (source, loc) in &cells {
+                black_box(engine.parse(black_box(source), *loc).unwrap());
+            }
+        })
+    });
+}
+
+fn bench_big_sum(c: &mut Criterion) {
+    c.bench_function("sum of 100,000 cells", |b| {
+        b.iter(|| eval(black_box("SUM(A1:J10000)")))
+    });
+}
+
+fn bench_nested_calls(c: &mut Criterion) {
+    let mut source = "1".to_string();
+    for i in 0..200 {
+        source = format!("IF(ROUND({source}, 2) > {i}, SUM({source}, 1) / 2, {i})");
+        if source.len() > 4000 {
+            source = format!("ROUND({i}, 1)");
+        }
+    }
+    c.bench_function("nested function calls", |b| {
+        b.iter(|| eval(black_box(&source)))
+    });
+}
+
+fn bench_array_broadcasting(c: &mut Criterion) {
+    c.bench_function("broadcast over 10,000 cells", |b| {
+        b.iter(|| eval(black_box("SUM((A1:J1000 + 1) * 2 - B1:K1000 / 3)")))
+    });
+}
+
+/// Takes rows and columns of a large array, and the functions that do the
+/// same to arrays computed by formulas.
+fn bench_array_slicing(c: &mut Criterion) {
+    let array = Array::from_fn(1000, 1000, |row, col| {
+        Value::from((row * 1000 + col) as f64)
+    });
+    c.bench_function("column of 1,000x1,000 array", |b| {
+        b.iter(|| black_box(&array).column(black_box(500)).first().cloned())
+    });
+    c.bench_function("transposed row of 1,000x1,000 array", |b| {
+        b.iter(|| {
+            black_box(&array)
+                .transpose()
+                .row(black_box(500))
+                .iter()
+                .count()
+        })
+    });
+
+    c.bench_function("TOCOL by column of 500x500 array", |b| {
+        b.iter(|| eval(black_box("SUM(TOCOL(SEQUENCE(500, 500), 0, TRUE()))")))
+    });
+    c.bench_function("SORT by column of 500x500 array", |b| {
+        b.iter(|| eval(black_box("SUM(SORT(SEQUENCE(500, 500), 1, -1, TRUE()))")))
+    });
+    c.bench_function("XLOOKUP column of 500x500 array", |b| {
+        b.iter(|| {
+            eval(black_box(
+                "SUM(XLOOKUP(250, SEQUENCE(1, 500), SEQUENCE(500, 500)))",
+            ))
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_reparse_for_recalculation,
+    bench_big_sum,
+    bench_nested_calls,
+    bench_array_broadcasting,
+    bench_array_slicing,
+);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Compares incremental reparsing against full reparsing for a small edit in
+//! a large formula.
+//!
+//! Run with `cargo bench --bench reparse`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use quadratic_core::formulas::{parse_formula, reparse_formula, TextEdit};
+use quadratic_core::Pos;
+
+const TERMS: usize = 2000;
+const ITERATIONS: u32 = 200;
+
+fn main() {
+    let terms = (0..TERMS).map(|i| format!("({i} + A{i} * 2)"));
+    let old_source = format!("=SUM({})", terms.collect::<Vec<_>>().join(", "));
+    let old = parse_formula(&old_source, Pos::ORIGIN).unwrap();
+
+    // Change `* 2` to `* 20` in the middle term.
+    let term = format!("({} + A{} * 2", TERMS / 2, TERMS / 2);
+    let start = old_source.find(&term).unwrap() + term.len();
+    let new_source = format!("{}0{}", &old_source[..start], &old_source[start..]);
+    let edit = TextEdit {
+        start,
+        old_end: start,
+        new_end: start + 1,
+    };
+    assert_eq!(
+        parse_formula(&new_source, Pos::ORIGIN).unwrap(),
+        reparse_formula(&old, edit, &new_source, Pos::ORIGIN).unwrap(),
+    );
+
+    println!("formula length: {} bytes", new_source.len());
+    let full = time(|| parse_formula(black_box(&new_source), Pos::ORIGIN));
+    println!("full reparse:        {full:?} per iteration");
+    let incremental = time(|| reparse_formula(&old, edit, black_box(&new_source), Pos::ORIGIN));
+    println!("incremental reparse: {incremental:?} per iteration");
+}
+
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS
+}
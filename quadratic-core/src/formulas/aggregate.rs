@@ -0,0 +1,185 @@
+//! Aggregation functions selected by number, as in `AGGREGATE`.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use super::*;
+
+/// Function selected by the `function_num` argument of `AGGREGATE`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aggregation {
+    Average,
+    Count,
+    CountA,
+    Max,
+    Min,
+    Product,
+    StdevS,
+    StdevP,
+    Sum,
+    VarS,
+    VarP,
+    Median,
+    Mode,
+    Large,
+    Small,
+    PercentileInc,
+    QuartileInc,
+    PercentileExc,
+    QuartileExc,
+}
+impl Aggregation {
+    /// Returns the function with the given number, using the same numbering
+    /// as Excel.
+    pub fn from_number(n: i64) -> Option<Self> {
+        use Aggregation::*;
+        Some(match n {
+            1 => Average,
+            2 => Count,
+            3 => CountA,
+            4 => Max,
+            5 => Min,
+            6 => Product,
+            7 => StdevS,
+            8 => StdevP,
+            9 => Sum,
+            10 => VarS,
+            11 => VarP,
+            12 => Median,
+            13 => Mode,
+            14 => Large,
+            15 => Small,
+            16 => PercentileInc,
+            17 => QuartileInc,
+            18 => PercentileExc,
+            19 => QuartileExc,
+            _ => return None,
+        })
+    }
+
+    /// Returns whether the function takes a single array and a parameter `k`,
+    /// rather than any number of arrays.
+    pub fn takes_k(self) -> bool {
+        use Aggregation::*;
+        matches!(
+            self,
+            Large | Small | PercentileInc | QuartileInc | PercentileExc | QuartileExc,
+        )
+    }
+
+    /// Applies the function to a list of values. Non-numeric values are
+    /// ignored, except by `CountA`, and error values are returned as errors.
+    pub fn apply(self, values: &[&Value], k: Option<f64>) -> FormulaResult<f64> {
+        if self == Aggregation::CountA {
+            return Ok(values.iter().map(|v| v.count()).sum::<usize>() as f64);
+        }
+        if let Some(Value::Error(e)) = values.iter().find(|v| matches!(v, Value::Error(_))) {
+            return Err(e.clone().into());
+        }
+        let mut numbers = values.iter().filter_map(|v| v.as_number()).collect_vec();
+        let n = numbers.len() as f64;
+        let k = || k.ok_or_else(|| internal_error_value!("missing k"));
+
+        match self {
+            Aggregation::Average => Ok(numbers.iter().sum::<f64>() / nonzero(n)?),
+            Aggregation::Count => Ok(n),
+            Aggregation::CountA => unreachable!(),
+            Aggregation::Max => Ok(numbers.into_iter().reduce(f64::max).unwrap_or(0.0)),
+            Aggregation::Min => Ok(numbers.into_iter().reduce(f64::min).unwrap_or(0.0)),
+            Aggregation::Product => Ok(numbers.into_iter().fold(1.0, arithmetic::mul)),
+            Aggregation::Sum => Ok(numbers.into_iter().fold(0.0, arithmetic::add)),
+            Aggregation::StdevS => Ok((sum_of_squares(&numbers) / nonzero(n - 1.0)?).sqrt()),
+            Aggregation::StdevP => Ok((sum_of_squares(&numbers) / nonzero(n)?).sqrt()),
+            Aggregation::VarS => Ok(sum_of_squares(&numbers) / nonzero(n - 1.0)?),
+            Aggregation::VarP => Ok(sum_of_squares(&numbers) / nonzero(n)?),
+            Aggregation::Median => percentile_inc(&mut numbers, 0.5),
+            Aggregation::Mode => mode(&numbers),
+            Aggregation::Large | Aggregation::Small => {
+                let k = k()?.trunc();
+                if k < 1.0 || k > n {
+                    return Err(FormulaErrorMsg::BadNumber.into());
+                }
+                numbers.sort_by(f64::total_cmp);
+                if self == Aggregation::Large {
+                    numbers.reverse();
+                }
+                Ok(numbers[k as usize - 1])
+            }
+            Aggregation::PercentileInc => percentile_inc(&mut numbers, k()?),
+            Aggregation::QuartileInc => match k()?.trunc() {
+                q @ 0.0..=4.0 => percentile_inc(&mut numbers, q / 4.0),
+                _ => Err(FormulaErrorMsg::BadNumber.into()),
+            },
+            Aggregation::PercentileExc => percentile_exc(&mut numbers, k()?),
+            Aggregation::QuartileExc => match k()?.trunc() {
+                q @ 1.0..=3.0 => percentile_exc(&mut numbers, q / 4.0),
+                _ => Err(FormulaErrorMsg::BadNumber.into()),
+            },
+        }
+    }
+}
+
+/// Returns `n`, or an error if it is not positive.
+fn nonzero(n: f64) -> FormulaResult<f64> {
+    if n > 0.0 {
+        Ok(n)
+    } else {
+        Err(FormulaErrorMsg::DivideByZero.into())
+    }
+}
+
+/// Returns the sum of squared deviations from the mean.
+fn sum_of_squares(numbers: &[f64]) -> f64 {
+    let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+    numbers.iter().map(|x| (x - mean).powi(2)).sum()
+}
+
+/// Returns the most common number, preferring whichever appears first in
+/// case of a tie. Returns an error if no number appears more than once.
+fn mode(numbers: &[f64]) -> FormulaResult<f64> {
+    let mut counts = HashMap::<u64, usize>::new();
+    for x in numbers {
+        *counts.entry(x.to_bits()).or_default() += 1;
+    }
+    let mut best = None;
+    for &x in numbers {
+        let count = counts[&x.to_bits()];
+        if count > 1 && best.is_none_or(|(_, best_count)| count > best_count) {
+            best = Some((x, count));
+        }
+    }
+    best.map(|(x, _)| x)
+        .ok_or_else(|| FormulaErrorMsg::NotAvailable.into())
+}
+
+/// Returns the `k`th percentile, interpolating between values, where `k` is
+/// between 0 and 1 inclusive.
+fn percentile_inc(numbers: &mut [f64], k: f64) -> FormulaResult<f64> {
+    if numbers.is_empty() || !(0.0..=1.0).contains(&k) {
+        return Err(FormulaErrorMsg::BadNumber.into());
+    }
+    Ok(interpolate(numbers, k * (numbers.len() - 1) as f64))
+}
+
+/// Returns the `k`th percentile, interpolating between values, where `k` is
+/// between 0 and 1 exclusive and not too close to either for the number of
+/// values.
+fn percentile_exc(numbers: &mut [f64], k: f64) -> FormulaResult<f64> {
+    let rank = k * (numbers.len() + 1) as f64;
+    if rank < 1.0 || rank > numbers.len() as f64 {
+        return Err(FormulaErrorMsg::BadNumber.into());
+    }
+    Ok(interpolate(numbers, rank - 1.0))
+}
+
+/// Sorts the numbers and returns the value at a fractional zero-based index.
+fn interpolate(numbers: &mut [f64], index: f64) -> f64 {
+    numbers.sort_by(f64::total_cmp);
+    let i = index.floor() as usize;
+    let fract = index - i as f64;
+    match numbers.get(i + 1) {
+        Some(next) if fract > 0.0 => numbers[i] + fract * (next - numbers[i]),
+        _ => numbers[i],
+    }
+}
@@ -0,0 +1,145 @@
+//! Arithmetic on numeric values.
+//!
+//! By default, numbers are `f64` and arithmetic follows IEEE 754. If the
+//! `decimal` feature is enabled, each operation instead converts its operands
+//! to decimals via their shortest round-trip representation, computes the
+//! result exactly (or to 28 significant digits), and converts back to `f64`.
+//! This makes results like `0.1 + 0.2` come out exactly as written as long as
+//! the values involved have no more than about 15 significant digits.
+//!
+//! Values are still stored as `f64`, so decimals only cover part of their
+//! range. An operation falls back to `f64` arithmetic, giving the same result
+//! as without the feature, if an operand has a magnitude above about 7.9e28
+//! or can't be written exactly in 28 decimal places, if the result doesn't
+//! fit in a decimal, or if a product or quotient is smaller than `1e-13` and
+//! so may have lost precision to rounding. In particular, dividing by zero
+//! gives an infinite or NaN result in both modes.
+//!
+//! These functions never fail. Values that don't fit in a decimal are still
+//! valid `f64`s, so the feature only changes how each result is rounded.
+
+pub use imp::*;
+
+/// Arithmetic on `f64`, which is also the fallback for decimal arithmetic.
+mod float {
+    /// Returns `a + b`.
+    pub fn add(a: f64, b: f64) -> f64 {
+        a + b
+    }
+    /// Returns `a - b`.
+    pub fn sub(a: f64, b: f64) -> f64 {
+        a - b
+    }
+    /// Returns `a * b`.
+    pub fn mul(a: f64, b: f64) -> f64 {
+        a * b
+    }
+    /// Returns `a / b`.
+    pub fn div(a: f64, b: f64) -> f64 {
+        a / b
+    }
+    /// Rounds `x` to `digits` digits after the decimal point, with halves
+    /// rounded away from zero. If `digits` is negative, rounds to the left of
+    /// the decimal point.
+    pub fn round(x: f64, digits: i64) -> f64 {
+        let scale = 10.0_f64.powi(digits.clamp(-308, 308) as i32);
+        let ret = (x * scale).round() / scale;
+        if ret.is_finite() {
+            ret
+        } else {
+            x
+        }
+    }
+}
+
+#[cfg(not(feature = "decimal"))]
+mod imp {
+    pub use super::float::*;
+}
+
+#[cfg(feature = "decimal")]
+mod imp {
+    use rust_decimal::prelude::*;
+
+    use super::float;
+
+    /// Smallest magnitude of a nonzero product or quotient that is computed
+    /// with decimals. Decimals have 28 digits after the decimal point, so
+    /// smaller results would keep fewer than 15 significant digits.
+    pub const MIN_DECIMAL_PRODUCT: f64 = 1e-13;
+
+    /// Returns `a + b`.
+    pub fn add(a: f64, b: f64) -> f64 {
+        decimal_op(a, b, Decimal::checked_add).unwrap_or_else(|| float::add(a, b))
+    }
+    /// Returns `a - b`.
+    pub fn sub(a: f64, b: f64) -> f64 {
+        decimal_op(a, b, Decimal::checked_sub).unwrap_or_else(|| float::sub(a, b))
+    }
+    /// Returns `a * b`.
+    pub fn mul(a: f64, b: f64) -> f64 {
+        let product =
+            decimal_op(a, b, Decimal::checked_mul).filter(|&n| is_precise_product(n, a, b));
+        product.unwrap_or_else(|| float::mul(a, b))
+    }
+    /// Returns `a / b`.
+    pub fn div(a: f64, b: f64) -> f64 {
+        let quotient =
+            decimal_op(a, b, Decimal::checked_div).filter(|&n| is_precise_product(n, a, b));
+        quotient.unwrap_or_else(|| float::div(a, b))
+    }
+    /// Rounds `x` to `digits` digits after the decimal point, with halves
+    /// rounded away from zero. If `digits` is negative, rounds to the left of
+    /// the decimal point.
+    pub fn round(x: f64, digits: i64) -> f64 {
+        let Some(d) = to_decimal(x) else {
+            return float::round(x, digits);
+        };
+        let strategy = RoundingStrategy::MidpointAwayFromZero;
+        if digits >= 0 {
+            let digits = digits.min(Decimal::MAX_SCALE as i64) as u32;
+            let rounded = from_decimal(d.round_dp_with_strategy(digits, strategy));
+            return rounded.unwrap_or_else(|| float::round(x, digits as i64));
+        }
+        if -digits > Decimal::MAX_SCALE as i64 {
+            // Every decimal is smaller than half of `10^29`.
+            return 0.0;
+        }
+        let scale = Decimal::from_i128_with_scale(10_i128.pow(-digits as u32), 0);
+        let rounded = (d / scale).round_dp_with_strategy(0, strategy);
+        let rounded = rounded.checked_mul(scale).and_then(from_decimal);
+        rounded.unwrap_or_else(|| float::round(x, digits))
+    }
+
+    /// Applies `op` to `a` and `b` as decimals. Returns `None` if either
+    /// operand or the result can't be represented as a decimal.
+    fn decimal_op(a: f64, b: f64, op: fn(Decimal, Decimal) -> Option<Decimal>) -> Option<f64> {
+        op(to_decimal(a)?, to_decimal(b)?).and_then(from_decimal)
+    }
+    /// Returns whether a product or quotient `n` of `a` and `b` computed with
+    /// decimals kept enough significant digits.
+    fn is_precise_product(n: f64, a: f64, b: f64) -> bool {
+        if n == 0.0 {
+            a == 0.0 || b == 0.0
+        } else {
+            n.abs() >= MIN_DECIMAL_PRODUCT
+        }
+    }
+
+    /// Converts a number to the decimal with the same shortest round-trip
+    /// representation, so `0.1` becomes exactly `0.1` rather than its binary
+    /// approximation. Returns `None` if there is no such decimal.
+    fn to_decimal(n: f64) -> Option<Decimal> {
+        if !n.is_finite() {
+            return None;
+        }
+        let d = Decimal::from_str(&n.to_string())
+            .or_else(|_| Decimal::from_scientific(&format!("{n:e}")))
+            .ok()?;
+        // Digits past the 28th decimal place are rounded off.
+        (from_decimal(d) == Some(n)).then_some(d)
+    }
+    fn from_decimal(d: Decimal) -> Option<f64> {
+        d.to_string().parse().ok()
+    }
+}
@@ -0,0 +1,447 @@
+//! Two-dimensional arrays of values, such as the value of a range.
+//!
+//! An array is a view of values that may be shared with other arrays. The
+//! values are stored row by row, and the view is described by where its
+//! top-left value is and whether it is transposed, so taking a row, a column,
+//! or any rectangle of an array, or transposing it, makes a new view of the
+//! same values instead of copying them. Values are only copied when they are modified or
+//! taken out of an array whose values are shared.
+//!
+//! A view keeps all of the values it was taken from alive, even the ones
+//! outside it.
+
+use itertools::{Either, Itertools};
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::{FormulaError, FormulaErrorMsg, Value};
+
+/// Rectangular array of values, which may have no rows or no columns.
+///
+/// This is small enough that [`Value`] is no larger than it would be without
+/// arrays.
+#[derive(Default, Clone)]
+pub struct Array {
+    storage: Arc<Storage>,
+    rows: usize,
+    cols: usize,
+    /// Index in the storage of the top-left value.
+    offset: usize,
+    /// Whether the rows of the array are columns of the storage.
+    transposed: bool,
+}
+/// Values shared by arrays, stored row by row.
+#[derive(Debug, Default, Clone)]
+struct Storage {
+    values: Vec<Value>,
+    /// Number of values in each row.
+    row_len: usize,
+}
+impl fmt::Debug for Array {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.rows().map(|row| row.iter().cloned().collect_vec()))
+            .finish()
+    }
+}
+impl PartialEq for Array {
+    fn eq(&self, other: &Self) -> bool {
+        self.size() == other.size() && self.iter().eq(other.iter())
+    }
+}
+
+impl Array {
+    /// Constructs an array from its values in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values is not `rows * cols`.
+    pub fn from_row_major(rows: usize, cols: usize, values: Vec<Value>) -> Self {
+        assert_eq!(rows * cols, values.len(), "wrong number of array values");
+        Self {
+            storage: Arc::new(Storage {
+                values,
+                row_len: cols,
+            }),
+            rows,
+            cols,
+            offset: 0,
+            transposed: false,
+        }
+    }
+    /// Constructs an array from a list of rows, which must all have the same
+    /// length.
+    ///
+    /// ```
+    /// # use quadratic_core::formulas::{Array, FormulaErrorMsg, Value};
+    /// let array = Array::from_rows(vec![
+    ///     vec![1.0.into(), "a".into()],
+    ///     vec![true.into(), 2_i64.into()],
+    /// ])
+    /// .unwrap();
+    /// assert_eq!((2, 2), array.size());
+    /// assert_eq!(Some(&Value::from(true)), array.get(1, 0));
+    ///
+    /// let err = Array::from_rows(vec![vec![1.0.into(), 2.0.into()], vec![3.0.into()]]);
+    /// assert_eq!(FormulaErrorMsg::NonRectangularArray, err.unwrap_err().msg);
+    /// ```
+    pub fn from_rows(rows: Vec<Vec<Value>>) -> Result<Self, FormulaError> {
+        if !rows.iter().map(|row| row.len()).all_equal() {
+            return Err(FormulaErrorMsg::NonRectangularArray.into());
+        }
+        let (row_count, col_count) = (rows.len(), rows.first().map_or(0, Vec::len));
+        let mut values = Vec::with_capacity(row_count * col_count);
+        rows.into_iter().for_each(|row| values.extend(row));
+        Ok(Self::from_row_major(row_count, col_count, values))
+    }
+    /// Constructs an array with one column.
+    pub fn from_column(values: Vec<Value>) -> Self {
+        Self::from_row_major(values.len(), 1, values)
+    }
+    /// Constructs an array with one row.
+    pub fn from_row(values: Vec<Value>) -> Self {
+        Self::from_row_major(1, values.len(), values)
+    }
+    /// Constructs an array by calling `f` with the row and column of each
+    /// value, in row-major order.
+    pub fn from_fn(rows: usize, cols: usize, mut f: impl FnMut(usize, usize) -> Value) -> Self {
+        let mut values = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                values.push(f(row, col));
+            }
+        }
+        Self::from_row_major(rows, cols, values)
+    }
+    /// Constructs an array by calling `f` with the row and column of each
+    /// value, in row-major order, stopping at the first error.
+    pub fn try_from_fn<E>(
+        rows: usize,
+        cols: usize,
+        mut f: impl FnMut(usize, usize) -> Result<Value, E>,
+    ) -> Result<Self, E> {
+        let mut values = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                values.push(f(row, col)?);
+            }
+        }
+        Ok(Self::from_row_major(rows, cols, values))
+    }
+
+    /// Returns the size `(rows, columns)` of the array.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+    /// Returns the number of values in the array.
+    pub fn len(&self) -> usize {
+        self.rows * self.cols
+    }
+    /// Returns whether the array has no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value at a row and column, starting from zero, or `None`
+    /// if it is out of bounds.
+    pub fn get(&self, row: usize, col: usize) -> Option<&Value> {
+        (row < self.rows && col < self.cols).then(|| &self.storage.values[self.index(row, col)])
+    }
+    /// Returns the top-left value, or `None` if the array is empty.
+    pub fn first(&self) -> Option<&Value> {
+        self.get(0, 0)
+    }
+    /// Iterates over the values in row-major order.
+    pub fn iter(&self) -> impl '_ + ExactSizeIterator<Item = &Value> {
+        match self.as_slice() {
+            Some(slice) => Either::Left(slice.iter()),
+            None => Either::Right(StridedIter {
+                array: self,
+                row: 0,
+                col: 0,
+            }),
+        }
+    }
+    /// Iterates over the rows, each of which is an array with one row.
+    pub fn rows(&self) -> impl '_ + Iterator<Item = Array> {
+        (0..self.rows).map(|row| self.row(row))
+    }
+
+    /// Returns a row, without copying its values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the row is out of bounds.
+    pub fn row(&self, row: usize) -> Array {
+        self.slice(row..row + 1, 0..self.cols)
+    }
+    /// Returns a column, without copying its values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the column is out of bounds.
+    pub fn column(&self, col: usize) -> Array {
+        self.slice(0..self.rows, col..col + 1)
+    }
+    /// Returns a rectangle of the array, without copying its values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either range is decreasing or out of bounds.
+    pub fn slice(&self, rows: Range<usize>, cols: Range<usize>) -> Array {
+        assert!(
+            rows.start <= rows.end && rows.end <= self.rows,
+            "array rows out of bounds",
+        );
+        assert!(
+            cols.start <= cols.end && cols.end <= self.cols,
+            "array columns out of bounds",
+        );
+        let (row_stride, col_stride) = self.strides();
+        Array {
+            storage: Arc::clone(&self.storage),
+            rows: rows.len(),
+            cols: cols.len(),
+            offset: self.offset + rows.start * row_stride + cols.start * col_stride,
+            transposed: self.transposed,
+        }
+    }
+    /// Returns the array with its rows and columns swapped, without copying
+    /// its values.
+    pub fn transpose(&self) -> Array {
+        Array {
+            storage: Arc::clone(&self.storage),
+            rows: self.cols,
+            cols: self.rows,
+            offset: self.offset,
+            transposed: !self.transposed,
+        }
+    }
+
+    /// Returns an array of the same size with `f` applied to each value.
+    pub fn map(&self, f: impl FnMut(&Value) -> Value) -> Array {
+        Self::from_row_major(self.rows, self.cols, self.iter().map(f).collect())
+    }
+    /// Returns the values in row-major order, which are only copied if they
+    /// are shared with another array or the array is a view of only some of
+    /// them.
+    pub fn into_values(self) -> Vec<Value> {
+        if self.is_whole() {
+            match Arc::try_unwrap(self.storage) {
+                Ok(storage) => storage.values,
+                Err(storage) => storage.values.clone(),
+            }
+        } else {
+            self.iter().cloned().collect()
+        }
+    }
+    /// Returns the rows of the array.
+    pub fn into_rows(self) -> Vec<Vec<Value>> {
+        let (rows, cols) = self.size();
+        let mut values = self.into_values().into_iter();
+        (0..rows)
+            .map(|_| values.by_ref().take(cols).collect())
+            .collect()
+    }
+    /// Returns the values in row-major order for modifying them, first
+    /// copying them if they are shared with another array or the array is a
+    /// view of only some of them.
+    pub fn values_mut(&mut self) -> &mut [Value] {
+        if !self.is_whole() {
+            *self = Self::from_row_major(self.rows, self.cols, self.iter().cloned().collect());
+        }
+        &mut Arc::make_mut(&mut self.storage).values
+    }
+
+    /// Returns the distances in the storage between vertically adjacent
+    /// values and between horizontally adjacent values.
+    fn strides(&self) -> (usize, usize) {
+        match self.transposed {
+            false => (self.storage.row_len, 1),
+            true => (1, self.storage.row_len),
+        }
+    }
+    /// Returns the index in the storage of a value in the array.
+    fn index(&self, row: usize, col: usize) -> usize {
+        let (row_stride, col_stride) = self.strides();
+        self.offset + row * row_stride + col * col_stride
+    }
+    /// Returns the values in row-major order if they are stored that way.
+    fn as_slice(&self) -> Option<&[Value]> {
+        let (row_stride, col_stride) = self.strides();
+        if self.is_empty() {
+            Some(&[])
+        } else if (self.cols == 1 || col_stride == 1) && (self.rows == 1 || row_stride == self.cols)
+        {
+            Some(&self.storage.values[self.offset..self.offset + self.len()])
+        } else {
+            None
+        }
+    }
+    /// Returns whether the array is a view of all of its values, in the order
+    /// they are stored.
+    fn is_whole(&self) -> bool {
+        self.len() == self.storage.values.len() && self.as_slice().is_some()
+    }
+}
+
+/// Iterator over the values of an array that aren't stored in row-major
+/// order.
+struct StridedIter<'a> {
+    array: &'a Array,
+    row: usize,
+    col: usize,
+}
+impl<'a> Iterator for StridedIter<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.row >= self.array.rows || self.array.cols == 0 {
+            return None;
+        }
+        let value = &self.array.storage.values[self.array.index(self.row, self.col)];
+        self.col += 1;
+        if self.col == self.array.cols {
+            self.col = 0;
+            self.row += 1;
+        }
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let done = self.row * self.array.cols + self.col;
+        let remaining = self.array.len().saturating_sub(done);
+        (remaining, Some(remaining))
+    }
+}
+impl ExactSizeIterator for StridedIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Array of distinct numbers, along with its rows copied into nested
+    /// vectors.
+    fn numbered(rows: usize, cols: usize) -> (Array, Vec<Vec<Value>>) {
+        let naive = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| Value::from((row * cols + col) as f64))
+                    .collect()
+            })
+            .collect_vec();
+        (Array::from_row_major(rows, cols, naive.concat()), naive)
+    }
+
+    fn naive_transpose(rows: &[Vec<Value>], cols: usize) -> Vec<Vec<Value>> {
+        (0..cols)
+            .map(|col| rows.iter().map(|row| row[col].clone()).collect())
+            .collect()
+    }
+
+    fn naive_slice(rows: &[Vec<Value>], r: Range<usize>, c: Range<usize>) -> Vec<Vec<Value>> {
+        rows[r].iter().map(|row| row[c.clone()].to_vec()).collect()
+    }
+
+    /// Checks every way of reading an array against its expected rows.
+    fn assert_matches(array: &Array, expected: &[Vec<Value>]) {
+        let cols = expected.first().map_or(array.size().1, Vec::len);
+        assert_eq!((expected.len(), cols), array.size());
+        assert_eq!(expected.concat(), array.iter().cloned().collect_vec());
+        for (row, expected_row) in array.rows().zip(expected) {
+            assert_eq!(*expected_row, row.into_values());
+        }
+        for (r, expected_row) in expected.iter().enumerate() {
+            for (c, expected_value) in expected_row.iter().enumerate() {
+                assert_eq!(Some(expected_value), array.get(r, c));
+            }
+            assert_eq!(None, array.get(r, cols));
+        }
+        assert_eq!(None, array.get(expected.len(), 0));
+        assert_eq!(expected, array.clone().into_rows());
+    }
+
+    #[test]
+    fn test_value_size() {
+        // Arrays fit alongside the other kinds of values.
+        assert_eq!(
+            std::mem::size_of::<FormulaErrorMsg>(),
+            std::mem::size_of::<Value>(),
+        );
+    }
+
+    #[test]
+    fn test_empty_arrays() {
+        for (rows, cols) in [(0, 0), (0, 3), (3, 0)] {
+            let (array, naive) = numbered(rows, cols);
+            assert_eq!((rows, cols), array.size());
+            assert!(array.is_empty());
+            assert_eq!(None, array.first());
+            assert_eq!((cols, rows), array.transpose().size());
+            assert_eq!(naive, array.into_rows());
+        }
+        // Arrays of different sizes are different, even with no values.
+        assert_ne!(numbered(0, 3).0, numbered(3, 0).0);
+    }
+
+    #[test]
+    fn test_values_mut_copies_shared_values() {
+        let (array, naive) = numbered(3, 4);
+        let mut column = array.column(2);
+        column.values_mut()[1] = Value::Blank;
+        assert_eq!(naive, array.clone().into_rows());
+        assert_eq!(Some(&Value::Blank), column.get(1, 0));
+
+        let mut copy = array.clone();
+        copy.values_mut()[0] = Value::Blank;
+        assert_eq!(naive, array.into_rows());
+        assert_eq!(Some(&Value::Blank), copy.first());
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_views_match_copies(
+            rows in 0..8_usize,
+            cols in 0..8_usize,
+            bounds in prop::array::uniform4(0..=8_usize),
+            transpose_first: bool,
+        ) {
+            let (mut array, mut naive) = numbered(rows, cols);
+            let (mut rows, mut cols) = (rows, cols);
+            if transpose_first {
+                array = array.transpose();
+                naive = naive_transpose(&naive, cols);
+                (rows, cols) = (cols, rows);
+            }
+            assert_matches(&array, &naive);
+
+            let [r0, r1, c0, c1] = bounds;
+            let r = r0.min(r1).min(rows)..r0.max(r1).min(rows);
+            let c = c0.min(c1).min(cols)..c0.max(c1).min(cols);
+            let slice = array.slice(r.clone(), c.clone());
+            let naive_slice = naive_slice(&naive, r.clone(), c.clone());
+            assert_matches(&slice, &naive_slice);
+            assert_matches(&slice.transpose(), &naive_transpose(&naive_slice, c.len()));
+            assert_matches(&slice.transpose().transpose(), &naive_slice);
+            let copy = Array::from_row_major(r.len(), c.len(), naive_slice.concat());
+            prop_assert_eq!(&copy, &slice);
+
+            for i in 0..r.len() {
+                assert_matches(&slice.row(i), &naive_slice[i..=i]);
+            }
+            for j in 0..c.len() {
+                let naive_column = naive_slice.iter().map(|row| vec![row[j].clone()]).collect_vec();
+                assert_matches(&slice.column(j), &naive_column);
+            }
+
+            let mut modified = slice.clone();
+            modified.values_mut().iter_mut().for_each(|v| *v = Value::from(v.to_string() + "!"));
+            let expected = slice.map(|v| Value::from(v.to_string() + "!"));
+            prop_assert_eq!(expected, modified);
+            assert_matches(&array, &naive);
+        }
+    }
+}
@@ -1,13 +1,17 @@
 use futures::future::{FutureExt, LocalBoxFuture};
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use serde::{Deserialize, Serialize};
-use smallvec::smallvec;
 use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use super::aggregate::Aggregation;
+use super::lambda::{Binding, Lambda};
 
 use super::*;
 
 /// Abstract syntax tree of a formula expression.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Formula {
     pub ast: AstNode,
 }
@@ -19,7 +23,7 @@ impl fmt::Display for Formula {
 
 pub type AstNode = Spanned<AstNodeContents>;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum AstNodeContents {
     FunctionCall {
         func: Spanned<String>,
@@ -28,8 +32,16 @@ pub enum AstNodeContents {
     Paren(Box<AstNode>),
     Array(Vec<Vec<AstNode>>),
     CellRef(CellRef),
+    /// Range of whole columns, as in `A:A`. Its rows are bounded by the
+    /// grid's used region.
+    ColumnRange(CellRefCoord, CellRefCoord),
+    /// Name bound by `LET` or `LAMBDA`.
+    Name(String),
     String(String),
     Number(f64),
+    Error(#[serde(with = "error_code")] FormulaErrorMsg),
+    /// Argument left empty in a function call, as in `IF(A1,,2)`.
+    Omitted,
 }
 impl fmt::Display for AstNodeContents {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -52,8 +64,12 @@ impl fmt::Display for AstNodeContents {
                 a.iter().map(|row| row.iter().join(", ")).join("; "),
             ),
             AstNodeContents::CellRef(cellref) => write!(f, "{cellref}"),
+            AstNodeContents::ColumnRange(start, end) => write!(f, "C{start}:C{end}"),
+            AstNodeContents::Name(name) => write!(f, "{name}"),
             AstNodeContents::String(s) => write!(f, "{s:?}"),
             AstNodeContents::Number(n) => write!(f, "{n:?}"),
+            AstNodeContents::Error(e) => write!(f, "{}", e.excel_code().unwrap_or("#ERROR!")),
+            AstNodeContents::Omitted => Ok(()),
         }
     }
 }
@@ -68,12 +84,54 @@ impl AstNodeContents {
             AstNodeContents::Paren(contents) => contents.inner.type_string(),
             AstNodeContents::Array(_) => "array literal",
             AstNodeContents::CellRef(_) => "cell reference",
+            AstNodeContents::ColumnRange(..) => "column range",
+            AstNodeContents::Name(_) => "name",
             AstNodeContents::String(_) => "string literal",
             AstNodeContents::Number(_) => "numeric literal",
+            AstNodeContents::Error(_) => "error literal",
+            AstNodeContents::Omitted => "omitted argument",
         }
     }
 }
 impl Spanned<AstNodeContents> {
+    /// Returns whether this expression calls a volatile function, such as
+    /// `RANDARRAY` or `CELL`, anywhere inside it, including in the body of a
+    /// `LAMBDA`. See [`Formula::is_volatile()`].
+    pub fn has_volatile(&self) -> bool {
+        self.calls_any(functions::is_volatile)
+    }
+
+    /// Returns whether this calls any function whose name matches `pred`.
+    fn calls_any(&self, pred: fn(&str) -> bool) -> bool {
+        match &self.inner {
+            AstNodeContents::FunctionCall { func, args } => {
+                pred(&func.inner) || args.iter().any(|arg| arg.calls_any(pred))
+            }
+            AstNodeContents::Paren(contents) => contents.calls_any(pred),
+            AstNodeContents::Array(a) => a.iter().flatten().any(|elem| elem.calls_any(pred)),
+            AstNodeContents::CellRef(_)
+            | AstNodeContents::ColumnRange(..)
+            | AstNodeContents::Name(_)
+            | AstNodeContents::String(_)
+            | AstNodeContents::Number(_)
+            | AstNodeContents::Error(_)
+            | AstNodeContents::Omitted => false,
+        }
+    }
+
+    /// Returns the name if this is a name, for binding it in `LET` or
+    /// `LAMBDA`.
+    fn to_name(&self) -> FormulaResult<&str> {
+        match &self.inner {
+            AstNodeContents::Name(name) => Ok(name),
+            _ => Err(FormulaErrorMsg::Expected {
+                expected: "name".into(),
+                got: Some(self.inner.type_string().into()),
+            }
+            .with_span(self.span)),
+        }
+    }
+
     pub fn to_cell_ref(&self) -> FormulaResult<CellRef> {
         match &self.inner {
             AstNodeContents::CellRef(cellref) => Ok(*cellref),
@@ -87,132 +145,1415 @@ impl Spanned<AstNodeContents> {
     }
 }
 
+/// Returns whether two expressions have the same meaning, ignoring spans,
+/// redundant parentheses, and the case of function names and names bound by
+/// `LET` or `LAMBDA`. Everything else must be the same, including the order of
+/// arguments, whether each cell reference is relative or absolute, and how
+/// operators are grouped: `(a+b)+c` is equivalent to `a+b+c` but not to
+/// `a+(b+c)`.
+pub fn asts_equivalent(a: &AstNode, b: &AstNode) -> bool {
+    use AstNodeContents as A;
+
+    match (&a.inner, &b.inner) {
+        (A::Paren(a), _) => asts_equivalent(a, b),
+        (_, A::Paren(b)) => asts_equivalent(a, b),
+        (
+            A::FunctionCall {
+                func: func_a,
+                args: args_a,
+            },
+            A::FunctionCall {
+                func: func_b,
+                args: args_b,
+            },
+        ) => {
+            func_a.inner.eq_ignore_ascii_case(&func_b.inner)
+                && args_a.len() == args_b.len()
+                && std::iter::zip(args_a, args_b).all(|(a, b)| asts_equivalent(a, b))
+        }
+        (A::Array(rows_a), A::Array(rows_b)) => {
+            rows_a.len() == rows_b.len()
+                && std::iter::zip(rows_a, rows_b).all(|(row_a, row_b)| {
+                    row_a.len() == row_b.len()
+                        && std::iter::zip(row_a, row_b).all(|(a, b)| asts_equivalent(a, b))
+                })
+        }
+        (A::Name(a), A::Name(b)) => a.eq_ignore_ascii_case(b),
+        (a, b) => a == b,
+    }
+}
+
 impl Formula {
     /// Evaluates a formula, blocking on async calls.
     ///
     /// Use this when the grid proxy isn't actually doing anything async.
-    pub fn eval_blocking(&self, grid: &mut impl GridProxy, pos: Pos) -> FormulaResult {
-        pollster::block_on(self.eval(grid, pos))
+    pub fn eval_blocking(&self, ctx: &mut Ctx<'_>) -> FormulaResult {
+        pollster::block_on(self.eval(ctx))
     }
 
     /// Evaluates a formula.
-    pub async fn eval(&self, grid: &mut impl GridProxy, pos: Pos) -> FormulaResult {
-        self.ast.eval(grid, pos).await
+    ///
+    /// Cells that the grid reports as pending are read as blank. Use
+    /// [`Formula::eval_outcome()`] to find out whether the result depends on
+    /// any of them.
+    pub async fn eval(&self, ctx: &mut Ctx<'_>) -> FormulaResult {
+        self.ast.eval(ctx).await
+    }
+
+    /// Evaluates a formula, blocking on async calls, and returns a pending
+    /// outcome if it read any pending cells.
+    pub fn eval_blocking_outcome(&self, ctx: &mut Ctx<'_>) -> EvalOutcome {
+        pollster::block_on(self.eval_outcome(ctx))
+    }
+
+    /// Evaluates a formula, and returns a pending outcome if it read any
+    /// cells that the grid reports as pending. An array is returned along
+    /// with the rectangle that it spills into, starting at the formula's
+    /// cell.
+    ///
+    /// Pending propagates through everything: if any pending cell is read
+    /// while evaluating the formula, the outcome is pending, even if the
+    /// formula would otherwise have returned an error or ignored the cell's
+    /// value, as in `ISBLANK` or `IFERROR`.
+    pub async fn eval_outcome(&self, ctx: &mut Ctx<'_>) -> EvalOutcome {
+        let result = self.eval(ctx).await;
+        if !ctx.pending_cells().is_empty() {
+            return EvalOutcome::Pending(ctx.pending_cells().to_vec());
+        }
+        match result {
+            Ok(Spanned {
+                inner: Value::Array(values),
+                ..
+            }) => EvalOutcome::Array(ArrayResult::new(ctx.pos, values)),
+            Ok(value) => EvalOutcome::Value(value.inner),
+            Err(e) => EvalOutcome::Error(e),
+        }
+    }
+
+    /// Evaluates a formula, also returning warnings about anything that
+    /// didn't stop evaluation but may be a mistake, blocking on async calls.
+    pub fn eval_blocking_with_warnings(&self, ctx: &mut Ctx<'_>) -> FormulaResult<EvalResult> {
+        pollster::block_on(self.eval_with_warnings(ctx))
+    }
+
+    /// Evaluates a formula, also returning warnings about anything that
+    /// didn't stop evaluation but may be a mistake.
+    pub async fn eval_with_warnings(&self, ctx: &mut Ctx<'_>) -> FormulaResult<EvalResult> {
+        ctx.start_warnings();
+        let result = self.eval(ctx).await;
+        let warnings = ctx.take_warnings();
+        Ok(EvalResult {
+            value: result?.inner,
+            warnings,
+        })
+    }
+
+    /// Returns whether the formula calls a function, such as `RANDARRAY`,
+    /// whose result may change each time it is evaluated, so the formula
+    /// should be recomputed even when the cells it reads don't change.
+    pub fn is_volatile(&self) -> bool {
+        self.ast.has_volatile()
+    }
+
+    /// Returns whether the formula reads cells whose locations are only known
+    /// once it is evaluated, such as with `CELL`. The cell references in such
+    /// a formula don't list every cell it depends on.
+    pub fn has_dynamic_references(&self) -> bool {
+        self.ast.calls_any(functions::has_dynamic_references)
     }
 }
 
+/// Value of a formula along with any warnings from evaluating it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalResult {
+    /// Result of the formula.
+    pub value: Value,
+    /// Non-fatal problems found while evaluating the formula, in the order
+    /// they were found.
+    pub warnings: Vec<FormulaWarning>,
+}
+
+/// Outcome of evaluating a formula that may read cells whose contents aren't
+/// known yet.
+#[derive(Debug, Clone)]
+pub enum EvalOutcome {
+    /// Result of the formula, if it is not an array.
+    Value(Value),
+    /// Array that the formula produced, which spills into the grid.
+    Array(ArrayResult),
+    /// Error that stopped evaluation.
+    Error(FormulaError),
+    /// The formula read cells that are still being computed, listed in the
+    /// order they were first read. The host should evaluate the formula
+    /// again once they are.
+    Pending(Vec<Pos>),
+}
+
+/// Array produced by a formula, which spills into a rectangle of cells whose
+/// top-left corner is the formula's own cell.
+///
+/// The host should show the array only if none of the other cells in the
+/// rectangle have contents, and otherwise show a spill error in the anchor.
+/// Once it is shown, [`GridProxy::get_spill_extent()`] for the anchor should
+/// return [`ArrayResult::extent()`], so that `A1#` refers to the same
+/// rectangle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayResult {
+    /// Cell that the array spills from, which is where the formula is.
+    pub anchor: CellRef,
+    /// Number of rows that the array covers, which is at least 1.
+    pub rows: usize,
+    /// Number of columns that the array covers, which is at least 1.
+    pub cols: usize,
+    /// Values of the array.
+    pub values: Array,
+}
+impl ArrayResult {
+    /// Constructs the result for an array produced by the formula at
+    /// `anchor`.
+    fn new(anchor: Pos, values: Array) -> Self {
+        let (rows, cols) = values.size();
+        Self {
+            anchor: CellRef::absolute(anchor),
+            rows,
+            cols,
+            values,
+        }
+    }
+
+    /// Returns the position of the anchor.
+    pub fn anchor_pos(&self) -> Pos {
+        self.anchor.resolve_from(Pos::ORIGIN)
+    }
+    /// Returns the bottom-right cell of the rectangle that the array spills
+    /// into.
+    pub fn extent(&self) -> Pos {
+        let anchor = self.anchor_pos();
+        Pos {
+            x: anchor.x + self.cols as i64 - 1,
+            y: anchor.y + self.rows as i64 - 1,
+        }
+    }
+    /// Returns whether the array spills into a cell, including the anchor.
+    pub fn contains(&self, pos: Pos) -> bool {
+        let (min, max) = (self.anchor_pos(), self.extent());
+        (min.x..=max.x).contains(&pos.x) && (min.y..=max.y).contains(&pos.y)
+    }
+}
+
+/// Parses and evaluates a formula, blocking on async calls, and also returns
+/// the value of every sub-expression alongside its span, for explaining how
+/// a result was computed.
+///
+/// Values are listed in the order that evaluation finished, so each
+/// sub-expression comes before the expressions that contain it. If
+/// evaluation fails, the last value is the error at the span where
+/// evaluation stopped. If parsing fails, no values are returned.
+pub fn evaluate_with_trace(
+    src: &str,
+    ctx: &mut Ctx<'_>,
+) -> (FormulaResult<Value>, Vec<(Span, Value)>) {
+    let formula = match parse_formula(src, ctx.pos) {
+        Ok(formula) => formula,
+        Err(e) => return (Err(e), vec![]),
+    };
+    ctx.start_trace();
+    let result = formula.eval_blocking(ctx).map(|value| value.inner);
+    (result, ctx.take_trace())
+}
+
+/// Calls a function created by `LAMBDA`. Arguments that are `None` are
+/// omitted, as are any missing at the end.
+pub(crate) async fn call_lambda(
+    ctx: &mut Ctx<'_>,
+    lambda: &Lambda,
+    args: Vec<Option<Value>>,
+    span: Span,
+) -> FormulaResult<Value> {
+    let bindings = lambda.bindings_for_call(args, span)?;
+    ctx.enter_call().map_err(|e| e.with_span(span))?;
+    let outer = std::mem::replace(&mut ctx.bindings, bindings);
+    let result = lambda.body.eval(ctx).await;
+    ctx.bindings = outer;
+    ctx.exit_call();
+    Ok(result?.inner)
+}
+
+/// Binds a name in `LET` to the result of evaluating its value.
+fn bind_let_name(
+    ctx: &mut Ctx<'_>,
+    name: &str,
+    value: FormulaResult<Spanned<Value>>,
+) -> FormulaResult<()> {
+    ctx.bindings.push(Binding {
+        name: name.to_string(),
+        value: value?.inner,
+        omitted: None,
+    });
+    Ok(())
+}
+
 impl AstNode {
-    fn eval<'a>(
+    fn eval<'ctx: 'a, 'a>(
         &'a self,
-        grid: &'a mut impl GridProxy,
-        pos: Pos,
+        ctx: &'a mut Ctx<'ctx>,
     ) -> LocalBoxFuture<'a, FormulaResult<Spanned<Value>>> {
         // See this link for why we need to box here:
         // https://rust-lang.github.io/async-book/07_workarounds/04_recursion.html
-        async move { self.eval_inner(grid, pos).await }.boxed_local()
+        async move {
+            let result = match self.start_eval(ctx) {
+                Ok(()) => {
+                    let result = self.eval_inner(ctx).await;
+                    ctx.exit_nested();
+                    result
+                }
+                Err(e) => Err(e),
+            };
+            self.finish_eval(ctx, result)
+        }
+        .boxed_local()
+    }
+
+    /// Takes an evaluation step and enters the expression, returning an
+    /// error if either limit is exceeded.
+    fn start_eval(&self, ctx: &mut Ctx<'_>) -> FormulaResult<()> {
+        ctx.take_steps(1)
+            .and_then(|()| ctx.enter_nested())
+            .map_err(|e| e.with_span(self.span))
     }
 
-    async fn eval_inner(
+    /// Records the result of evaluating the expression.
+    ///
+    /// This and `start_eval()` are separate from `eval()` so that its stack
+    /// frame, which stays on the stack while subexpressions are evaluated,
+    /// is small.
+    fn finish_eval(
         &self,
-        grid: &mut impl GridProxy,
-        pos: Pos,
+        ctx: &mut Ctx<'_>,
+        result: FormulaResult<Value>,
     ) -> FormulaResult<Spanned<Value>> {
-        let value = match &self.inner {
-            // Cell range
-            AstNodeContents::FunctionCall { func, args } if func.inner == ":" => {
-                if args.len() != 2 {
-                    internal_error!("invalid arguments to cell range operator");
-                }
-                let corner1 = args[0].to_cell_ref()?.resolve_from(pos);
-                let corner2 = args[1].to_cell_ref()?.resolve_from(pos);
+        let mut result = result.map(|inner| Spanned {
+            span: self.span,
+            inner,
+        });
+        if ctx.settings.trace_function_calls {
+            if let Err(e) = &mut result {
+                self.add_to_call_trace(e);
+            }
+        }
+        ctx.trace_result(self.span, &result);
+        result
+    }
+
+    /// Records that an error passed through this expression, if it is a call
+    /// to a function. Operators are not recorded.
+    fn add_to_call_trace(&self, e: &mut FormulaError) {
+        if let AstNodeContents::FunctionCall { func, .. } = &self.inner {
+            if func.inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                e.call_trace.push(func.inner.to_ascii_uppercase());
+            }
+        }
+    }
 
-                let x1 = std::cmp::min(corner1.x, corner2.x);
-                let y1 = std::cmp::min(corner1.y, corner2.y);
+    /// Returns the evaluation of the expression, without recording it.
+    ///
+    /// Most functions have all their arguments evaluated before they are
+    /// called, but these functions are lazy and skip arguments that can't
+    /// affect the result, so errors in those arguments are not returned:
+    /// - `IF` only evaluates the chosen value, unless the condition is an
+    ///   array
+    /// - `AND` stops at the first argument containing FALSE, and `OR` stops
+    ///   at the first argument containing TRUE
+    /// - `LAMBDA` only evaluates its body when it is called
+    ///
+    /// This only chooses how to evaluate the expression, and each way is a
+    /// separate boxed future, so that deeply nested expressions use only a
+    /// little bit of stack space per level even in unoptimized builds.
+    fn eval_inner<'ctx: 'a, 'a>(
+        &'a self,
+        ctx: &'a mut Ctx<'ctx>,
+    ) -> LocalBoxFuture<'a, FormulaResult<Value>> {
+        let ready = |result| futures::future::ready(result).boxed_local();
 
-                let x2 = std::cmp::max(corner1.x, corner2.x);
-                let y2 = std::cmp::max(corner1.y, corner2.y);
+        match &self.inner {
+            // Cell range or spill range
+            AstNodeContents::FunctionCall { func, .. }
+                if func.inner == ":" || func.inner == "#" =>
+            {
+                self.eval_cell_range(ctx).boxed_local()
+            }
 
-                let mut array = vec![];
-                for y in y1..=y2 {
-                    let mut row = smallvec![];
-                    for x in x1..=x2 {
-                        let cell_ref = CellRef::absolute(Pos { x, y });
-                        row.push(self.get_cell(grid, pos, cell_ref).await?);
+            // Implicit intersection
+            AstNodeContents::FunctionCall { func, args } if func.inner == "@" => {
+                let [arg] = args.as_slice() else {
+                    return ready(Err(internal_error_value!(
+                        "invalid arguments to implicit intersection operator"
+                    )));
+                };
+                self.implicit_intersection(ctx, arg).boxed_local()
+            }
+
+            // A name bound to a `LAMBDA` can be called like a function, and
+            // takes precedence over any function with the same name.
+            AstNodeContents::FunctionCall { func, args } if ctx.is_lambda_name(&func.inner) => {
+                self.call_name(ctx, func, args).boxed_local()
+            }
+
+            // `LET` and `LAMBDA` bind names, so their arguments can't be
+            // evaluated up front.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("let") =>
+            {
+                self.eval_let(ctx, args).boxed_local()
+            }
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("lambda") =>
+            {
+                ready(self.eval_lambda(ctx, args))
+            }
+
+            // `MAP`, `REDUCE`, and `SCAN` call a `LAMBDA`, whose body may
+            // depend on cells, so they can't be ordinary functions.
+            AstNodeContents::FunctionCall { func, args }
+                if ["map", "reduce", "scan"]
+                    .iter()
+                    .any(|name| func.inner.eq_ignore_ascii_case(name)) =>
+            {
+                self.eval_array_lambda(ctx, func, args).boxed_local()
+            }
+
+            // `INDEX` returns a reference if its first argument is one.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("index") =>
+            {
+                async move {
+                    match self.eval_reference(ctx).await? {
+                        Some((pos, _)) => self.get_cell(ctx, CellRef::absolute(pos)).await,
+                        None => self.index_array(ctx, args).await,
                     }
-                    array.push(row);
                 }
+                .boxed_local()
+            }
+
+            // `SUBTOTAL` and `AGGREGATE` need to know which cells their
+            // ranges refer to.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("subtotal") =>
+            {
+                self.subtotal(ctx, args).boxed_local()
+            }
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("aggregate") =>
+            {
+                self.aggregate(ctx, args).boxed_local()
+            }
+
+            // `IF` only evaluates the value that it returns.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("if") =>
+            {
+                self.eval_if(ctx, func, args).boxed_local()
+            }
+
+            // `AND` and `OR` stop at the first argument that decides the
+            // result.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("and")
+                    || func.inner.eq_ignore_ascii_case("or") =>
+            {
+                self.eval_and_or(ctx, func, args).boxed_local()
+            }
+
+            // `ISERROR` needs to catch errors from its argument.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("iserror") =>
+            {
+                self.eval_iserror(ctx, args).boxed_local()
+            }
+
+            // `TYPE` needs to catch errors from its argument, and an array is
+            // a single type rather than one type per value.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("type") =>
+            {
+                self.eval_type(ctx, args).boxed_local()
+            }
+
+            // `ISFORMULA` and `FORMULATEXT` look at the formula in a cell
+            // rather than its value.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("isformula")
+                    || func.inner.eq_ignore_ascii_case("formulatext") =>
+            {
+                self.eval_formula_info(ctx, func, args).boxed_local()
+            }
+
+            // `ISOMITTED` looks at how a parameter of `LAMBDA` was bound.
+            AstNodeContents::FunctionCall { func, args }
+                if func.inner.eq_ignore_ascii_case("isomitted") =>
+            {
+                ready(self.eval_isomitted(ctx, args))
+            }
 
-                Value::Array(array)
+            // A 3-D reference, as in `Sheet1:Sheet3!A1`, is only accepted as an
+            // argument to a function that aggregates it, such as `SUM`.
+            AstNodeContents::FunctionCall { func, .. } if func.inner == "!" => {
+                ready(Err(FormulaErrorMsg::Unimplemented(
+                    "3-D references outside of aggregate functions".into(),
+                )
+                .with_span(self.span)))
             }
 
             // Other operator/function
             AstNodeContents::FunctionCall { func, args } => {
-                let mut arg_values = vec![];
-                for arg in args {
-                    arg_values.push(arg.eval(grid, pos).await?);
+                self.call_function(ctx, func, args).boxed_local()
+            }
+
+            AstNodeContents::Paren(expr) => expr
+                .eval(ctx)
+                .map(|result| result.map(|value| value.inner))
+                .boxed_local(),
+
+            AstNodeContents::Array(a) => self.eval_array_literal(ctx, a).boxed_local(),
+
+            AstNodeContents::CellRef(cell_ref) => self.get_cell(ctx, *cell_ref).boxed_local(),
+
+            AstNodeContents::ColumnRange(..) => self.eval_cell_range(ctx).boxed_local(),
+
+            AstNodeContents::Name(name) => ready(self.eval_name(ctx, name)),
+
+            AstNodeContents::String(s) => ready(Ok(Value::String(s.clone()))),
+
+            AstNodeContents::Number(n) => ready(Ok(Value::Number(*n))),
+
+            AstNodeContents::Error(e) => ready(Ok(Value::Error(e.clone()))),
+
+            // An omitted argument is the same as a blank cell.
+            AstNodeContents::Omitted => ready(Ok(Value::Blank)),
+        }
+    }
+
+    /// Evaluates `ISERROR(value)`, which catches errors from its argument.
+    async fn eval_iserror(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [arg] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        match arg.eval(ctx).await {
+            Ok(value) => Ok(match value.inner {
+                Value::Array(a) => Value::Array(a.map(|v| v.is_error().into())),
+                other => other.is_error().into(),
+            }),
+            // Only errors that a cell could display are caught.
+            Err(e) if e.msg.excel_code().is_some() => Ok(Value::Bool(true)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Calls an operator, a built-in function, or a custom function, after
+    /// evaluating all of its arguments.
+    async fn call_function(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        let ignores_blanks = functions::ignores_blanks(&func.inner);
+        let mut arg_values = vec![];
+        for arg in args {
+            arg_values.push(match ignores_blanks {
+                true => arg.eval_ignoring_blanks(ctx).await?,
+                false => arg.eval(ctx).await?,
+            });
+        }
+        self.apply_function(ctx, func, args, arg_values)
+    }
+
+    /// Calls an operator, a built-in function, or a custom function with the
+    /// values of its arguments.
+    ///
+    /// This is separate from `call_function()` so that the stack frame of the
+    /// future that evaluates the arguments is small.
+    fn apply_function(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+        arg_values: Vec<Spanned<Value>>,
+    ) -> FormulaResult<Value> {
+        self.warn_implicit_intersection(ctx, func, args, &arg_values);
+        let spanned_arg_values = Spanned {
+            span: self.span,
+            inner: arg_values,
+        };
+
+        let arg_spans = spanned_arg_values
+            .inner
+            .iter()
+            .map(|arg| arg.span)
+            .collect_vec();
+
+        let result = match func.inner.to_ascii_lowercase().as_str() {
+            "cell" | "c" => self.array_mapped_get_cell(ctx, spanned_arg_values),
+            _ => match functions::pure_function_from_name(&func.inner) {
+                Some(f) => f(ctx, spanned_arg_values),
+                None => match ctx
+                    .custom_functions
+                    .and_then(|custom| custom.call(&func.inner, &spanned_arg_values))
+                {
+                    Some(result) => result,
+                    None => {
+                        let custom_names = ctx.custom_functions.into_iter().flat_map(|c| c.names());
+                        let mut e = FormulaErrorMsg::BadFunctionName.with_span(func.span);
+                        e.suggestion = similar_function_name(&func.inner, custom_names);
+                        return Err(e);
+                    }
+                },
+            },
+        };
+        result.map_err(|e| with_argument_type_info(e, &func.inner, &arg_spans))
+    }
+
+    /// Evaluates an array literal, as in `{1, 2; 3, 4}`.
+    async fn eval_array_literal(
+        &self,
+        ctx: &mut Ctx<'_>,
+        a: &[Vec<AstNode>],
+    ) -> FormulaResult<Value> {
+        self.check_array_literal_size(ctx, a)?;
+        let mut array_of_values = vec![];
+        for row in a {
+            let mut row_of_values = vec![];
+            for elem_expr in row {
+                row_of_values.push(elem_expr.eval(ctx).await?.inner);
+            }
+            array_of_values.push(row_of_values);
+        }
+        Value::array(array_of_values).map_err(|e| e.with_span(self.span))
+    }
+
+    /// Evaluates a reference to a rectangle of cells, fetching every cell in
+    /// it.
+    async fn eval_cell_range(&self, ctx: &mut Ctx<'_>) -> FormulaResult<Value> {
+        let Some((min, max)) = self.eval_reference(ctx).await? else {
+            internal_error!("cell range is not a reference");
+        };
+        let (xs, ys) = self.cell_range_bounds(ctx, min, max)?;
+
+        let (rows, cols) = (ys.clone().count(), xs.clone().count());
+        let mut values = Vec::with_capacity(rows * cols);
+        for y in ys {
+            for x in xs.clone() {
+                let cell_ref = CellRef::absolute(Pos { x, y });
+                values.push(self.get_cell(ctx, cell_ref).await?);
+            }
+        }
+        Ok(Value::Array(Array::from_row_major(rows, cols, values)))
+    }
+
+    /// Evaluates an argument to a function that ignores blank cells. A column
+    /// range only fetches its nonblank cells, and evaluates to a single
+    /// column of their values, or a single blank if there are none.
+    ///
+    /// Cells that look like numbers are read as numbers, so that these
+    /// functions can tell them apart from text produced by formulas; see
+    /// [`EvalSettings::coerce_numeric_text`].
+    fn eval_ignoring_blanks<'ctx: 'a, 'a>(
+        &'a self,
+        ctx: &'a mut Ctx<'ctx>,
+    ) -> LocalBoxFuture<'a, FormulaResult<Spanned<Value>>> {
+        let sheet_range = match &self.inner {
+            AstNodeContents::FunctionCall { func, args } if func.inner == "!" => Some(args),
+            _ => None,
+        };
+        // Evaluate anything else as usual, without adding a stack frame to
+        // each level of nested function calls.
+        if !self.is_cell_reference() && sheet_range.is_none() {
+            return self.eval(ctx);
+        }
+        async move {
+            let mut result = if matches!(self.inner, AstNodeContents::ColumnRange(..)) {
+                let result = self.nonblank_cells(ctx).await.map(|inner| Spanned {
+                    span: self.span,
+                    inner,
+                });
+                ctx.trace_result(self.span, &result);
+                result?
+            } else if let Some(args) = sheet_range {
+                let result = self
+                    .sheet_range_cells(ctx, args)
+                    .await
+                    .map(|inner| Spanned {
+                        span: self.span,
+                        inner,
+                    });
+                ctx.trace_result(self.span, &result);
+                result?
+            } else {
+                self.eval(ctx).await?
+            };
+            read_numbers_in_cells(&mut result.inner);
+            Ok(result)
+        }
+        .boxed_local()
+    }
+    /// Returns whether this is a reference to cells, such as `A1`, `A1:B3`,
+    /// `A:A`, or `A1#`, rather than an expression that computes a value. These
+    /// are the expressions that `eval_reference()` accepts.
+    fn is_cell_reference(&self) -> bool {
+        match &self.inner {
+            AstNodeContents::CellRef(_) | AstNodeContents::ColumnRange(..) => true,
+            AstNodeContents::Paren(contents) => contents.is_cell_reference(),
+            AstNodeContents::FunctionCall { func, args } => match func.inner.as_str() {
+                ":" | "#" => true,
+                f if f.eq_ignore_ascii_case("index") => {
+                    args.first().is_some_and(|array| array.is_cell_reference())
                 }
-                let spanned_arg_values = Spanned {
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    async fn nonblank_cells(&self, ctx: &mut Ctx<'_>) -> FormulaResult<Value> {
+        ctx.take_steps(1).map_err(|e| e.with_span(self.span))?;
+        let Some((min, max)) = self.eval_reference(ctx).await? else {
+            internal_error!("column range is not a reference");
+        };
+        let contains_formula = (min.x..=max.x).contains(&ctx.pos.x);
+        if contains_formula && (min.y..=max.y).contains(&ctx.pos.y) {
+            return Err(FormulaErrorMsg::CircularReference.with_span(self.span));
+        }
+
+        let cells = ctx.grid.iter_range(min, max).await.collect_vec();
+        ctx.take_steps(cells.len() as u64)
+            .and_then(|()| ctx.check_array_size(cells.len() as u64, 1))
+            .map_err(|e| e.with_span(self.span))?;
+        let mut column = Vec::with_capacity(cells.len());
+        for (pos, contents) in cells {
+            column.push(cell_value(ctx, pos, Some(contents)).await);
+        }
+        if column.is_empty() {
+            column.push(Value::Blank);
+        }
+        Ok(Value::Array(Array::from_column(column)))
+    }
+
+    /// Evaluates a 3-D reference, as in `Sheet1:Sheet3!A1:B2`, to the cells of
+    /// the range on each sheet from the first to the last, stacked vertically.
+    async fn sheet_range_cells(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [first, last, range] = args else {
+            internal_error!("3-D reference has {} arguments", args.len());
+        };
+        let (AstNodeContents::String(first_name), AstNodeContents::String(last_name)) =
+            (&first.inner, &last.inner)
+        else {
+            internal_error!("sheet name is not a string");
+        };
+        let sheets = ctx.grid.sheet_names().await;
+        let sheet_index = |name: &str, span: Span| {
+            sheets
+                .iter()
+                .position(|s| s.eq_ignore_ascii_case(name))
+                .ok_or_else(|| FormulaErrorMsg::BadCellReference.with_span(span))
+        };
+        let first_index = sheet_index(first_name, first.span)?;
+        let last_index = sheet_index(last_name, last.span)?;
+        if first_index > last_index {
+            return Err(
+                FormulaErrorMsg::BadCellReference.with_span(Span::merge(first.span, last.span))
+            );
+        }
+        let sheets = &sheets[first_index..=last_index];
+
+        let Some((min, max)) = range.eval_reference(ctx).await? else {
+            return Err(FormulaErrorMsg::BadCellReference.with_span(range.span));
+        };
+        let (xs, ys) = range.cell_range_bounds(ctx, min, max)?;
+        let (width, height) = (xs.clone().count(), ys.clone().count());
+        let rows = height * sheets.len();
+        ctx.take_steps((width * (rows - height)) as u64)
+            .and_then(|()| ctx.check_array_size(rows as u64, width as u64))
+            .map_err(|e| e.with_span(self.span))?;
+
+        let mut values = Vec::with_capacity(width * rows);
+        for sheet in sheets {
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let contents = ctx.grid.get_on_sheet(sheet, Pos { x, y }).await;
+                    values.push(contents_value(ctx, contents));
+                }
+            }
+        }
+        Ok(Value::Array(Array::from_row_major(rows, width, values)))
+    }
+
+    /// Returns the value bound to a name.
+    fn eval_name(&self, ctx: &Ctx<'_>, name: &str) -> FormulaResult<Value> {
+        match ctx.lookup_name(name) {
+            Some(binding) => Ok(binding.value.clone()),
+            None => Err(FormulaErrorMsg::BadName.with_span(self.span)),
+        }
+    }
+
+    /// Evaluates `LET(name1, value1, ..., calculation)`, which evaluates each
+    /// value once and binds it to a name that the later values and the
+    /// calculation can use. Each name can only be bound once.
+    async fn eval_let(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let Some((calculation, pairs)) = args.split_last() else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        }
+        let mut names: Vec<&str> = vec![];
+        for pair in pairs.chunks_exact(2) {
+            let name = pair[0].to_name()?;
+            if names.iter().any(|other| other.eq_ignore_ascii_case(name)) {
+                return Err(FormulaErrorMsg::InvalidArgument.with_span(pair[0].span));
+            }
+            names.push(name);
+        }
+        let outer_len = ctx.bindings.len();
+        let mut result = Ok(());
+        for (name, pair) in names.into_iter().zip(pairs.chunks_exact(2)) {
+            let value = pair[1].eval(ctx).await;
+            result = bind_let_name(ctx, name, value);
+            if result.is_err() {
+                break;
+            }
+        }
+        let result = match result {
+            Ok(()) => calculation.eval(ctx).await,
+            Err(e) => Err(e),
+        };
+        ctx.bindings.truncate(outer_len);
+        Ok(result?.inner)
+    }
+
+    /// Evaluates `LAMBDA(param1, ..., body)`, which returns a function without
+    /// evaluating its body. The function keeps the names bound where it was
+    /// created.
+    fn eval_lambda(&self, ctx: &Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let Some((body, params)) = args.split_last() else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let mut names: Vec<String> = vec![];
+        for param in params {
+            let name = param.to_name()?;
+            if names.iter().any(|other| other.eq_ignore_ascii_case(name)) {
+                return Err(FormulaErrorMsg::InvalidArgument.with_span(param.span));
+            }
+            names.push(name.to_string());
+        }
+        Ok(Value::Lambda(Arc::new(Lambda {
+            params: names,
+            body: body.clone(),
+            captured: ctx.bindings.clone(),
+        })))
+    }
+
+    /// Calls the `LAMBDA` bound to the name of a function call. The arguments
+    /// are evaluated where it is called.
+    async fn call_name(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        let Some(Value::Lambda(lambda)) = ctx.lookup_name(&func.inner).map(|b| &b.value) else {
+            return Err(FormulaErrorMsg::BadFunctionName.with_span(func.span));
+        };
+        let lambda = Arc::clone(lambda);
+        let mut arg_values = vec![];
+        for arg in args {
+            arg_values.push(match arg.inner {
+                AstNodeContents::Omitted => None,
+                _ => Some(arg.eval(ctx).await?.inner),
+            });
+        }
+        call_lambda(ctx, &lambda, arg_values, self.span).await
+    }
+
+    /// Evaluates `MAP(array, lambda)`, `REDUCE(initial_value, array, lambda)`,
+    /// or `SCAN(initial_value, array, lambda)`, which call the lambda for each
+    /// value in the array, in row-major order. `REDUCE` and `SCAN` also pass
+    /// the previous result, starting with the initial value.
+    ///
+    /// An error from the lambda becomes the result for that value instead of
+    /// failing the whole call.
+    async fn eval_array_lambda(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        let is_map = func.inner.eq_ignore_ascii_case("map");
+        let is_reduce = func.inner.eq_ignore_ascii_case("reduce");
+        let mut values = vec![];
+        for arg in args {
+            values.push(arg.eval(ctx).await?);
+        }
+        let (mut acc, array, lambda_arg) = match values.as_slice() {
+            [array, lambda] if is_map => (None, array, lambda),
+            [init, array, lambda] if !is_map => (Some(init.inner.clone()), array, lambda),
+            _ => return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span)),
+        };
+        let lambda = match &lambda_arg.inner {
+            Value::Lambda(lambda) => Arc::clone(lambda),
+            other => {
+                return Err(FormulaErrorMsg::Expected {
+                    expected: "lambda".into(),
+                    got: Some(other.type_name().into()),
+                }
+                .with_span(lambda_arg.span))
+            }
+        };
+        if lambda.params.len() != if is_map { 1 } else { 2 } {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(lambda_arg.span));
+        }
+
+        let values = match &array.inner {
+            Value::Array(a) => a.clone(),
+            other => Array::from_row(vec![other.clone()]),
+        };
+        let mut output = Vec::with_capacity(values.len());
+        for value in values.iter() {
+            let call_args = match &acc {
+                Some(acc) => vec![Some(acc.clone()), Some(value.clone())],
+                None => vec![Some(value.clone())],
+            };
+            let result = call_lambda(ctx, &lambda, call_args, self.span).await;
+            // Only `REDUCE` can return an array from the lambda.
+            let result = match is_reduce {
+                true => result,
+                false => result.and_then(|value| {
+                    Spanned {
+                        span: self.span,
+                        inner: value,
+                    }
+                    .to_scalar()
+                    .map(|value| value.inner)
+                }),
+            };
+            let result = result.unwrap_or_else(|e| Value::Error(e.msg));
+            if acc.is_some() {
+                acc = Some(result.clone());
+            }
+            output.push(result);
+        }
+
+        Ok(match acc {
+            Some(acc) if is_reduce => acc,
+            _ if !matches!(array.inner, Value::Array(_)) => output.swap_remove(0),
+            _ => {
+                let (rows, cols) = values.size();
+                Value::Array(Array::from_row_major(rows, cols, output))
+            }
+        })
+    }
+
+    /// Evaluates `ISOMITTED`, which returns whether the argument for a
+    /// parameter of `LAMBDA` was omitted.
+    fn eval_isomitted(&self, ctx: &Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [arg] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        if !ctx.bindings.iter().any(|binding| binding.omitted.is_some()) {
+            return Err(FormulaErrorMsg::NotInLambda.with_span(self.span));
+        }
+        let omitted = match &arg.inner {
+            AstNodeContents::Name(name) => ctx.lookup_name(name).and_then(|b| b.omitted),
+            _ => None,
+        };
+        match omitted {
+            Some(omitted) => Ok(Value::Bool(omitted)),
+            None => Err(FormulaErrorMsg::Expected {
+                expected: "LAMBDA parameter".into(),
+                got: Some(arg.inner.type_string().into()),
+            }
+            .with_span(arg.span)),
+        }
+    }
+
+    /// Evaluates `IF`, which only evaluates the value that it returns unless
+    /// its condition is an array.
+    async fn eval_if(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        // Nested `IF`s are common, so the chosen value is evaluated here,
+        // where the stack frame is small.
+        match self.choose_if_value(ctx, func, args).boxed_local().await? {
+            Either::Left(value) => Ok(value),
+            Either::Right(chosen) => Ok(chosen.eval(ctx).await?.inner),
+        }
+    }
+
+    /// Evaluates the condition of `IF`, returning the expression that it
+    /// chooses, or the result if the condition is an array.
+    async fn choose_if_value<'a>(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &'a [AstNode],
+    ) -> FormulaResult<Either<Value, &'a AstNode>> {
+        let [cond, if_true, if_false] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let cond_value = cond.eval(ctx).await?;
+        if let Value::Array(_) = cond_value.inner {
+            // Each value of the condition chooses separately, so both values
+            // are needed.
+            let Some(f) = functions::pure_function_from_name(&func.inner) else {
+                internal_error!("IF is missing from the function registry");
+            };
+            let arg_values = vec![
+                cond_value,
+                if_true.eval(ctx).await?,
+                if_false.eval(ctx).await?,
+            ];
+            let arg_spans = args.iter().map(|arg| arg.span).collect_vec();
+            f(
+                ctx,
+                Spanned {
                     span: self.span,
                     inner: arg_values,
-                };
+                },
+            )
+            .map(Either::Left)
+            .map_err(|e| with_argument_type_info(e, &func.inner, &arg_spans))
+        } else {
+            let arg_spans = [cond.span];
+            match cond_value.to_bool() {
+                Ok(true) => Ok(Either::Right(if_true)),
+                Ok(false) => Ok(Either::Right(if_false)),
+                Err(e) => Err(with_argument_type_info(e, &func.inner, &arg_spans)),
+            }
+        }
+    }
 
-                match func.inner.to_ascii_lowercase().as_str() {
-                    "cell" | "c" => self.array_mapped_get_cell(grid, pos, spanned_arg_values)?,
-                    _ => match functions::pure_function_from_name(&func.inner) {
-                        Some(f) => f(spanned_arg_values)?,
-                        None => return Err(FormulaErrorMsg::BadFunctionName.with_span(func.span)),
-                    },
-                }
+    /// Evaluates `AND` or `OR`, stopping at the first argument that decides
+    /// the result.
+    async fn eval_and_or(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        // The result of `AND` is FALSE if any value is FALSE, and the result
+        // of `OR` is TRUE if any value is TRUE.
+        let deciding_value = !func.inner.eq_ignore_ascii_case("and");
+        let arg_spans = args.iter().map(|arg| arg.span).collect_vec();
+        for arg in args {
+            let bools = arg
+                .eval(ctx)
+                .await?
+                .to_bools()
+                .map_err(|e| with_argument_type_info(e, &func.inner, &arg_spans))?;
+            if bools.contains(&deciding_value) {
+                return Ok(Value::Bool(deciding_value));
             }
+        }
+        Ok(Value::Bool(!deciding_value))
+    }
 
-            AstNodeContents::Paren(expr) => expr.eval(grid, pos).await?.inner,
+    /// Evaluates `TYPE`, which catches errors from its argument and returns
+    /// a single type for an array rather than one type per value.
+    async fn eval_type(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [arg] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let type_code = match arg.eval(ctx).await {
+            Ok(value) => match value.inner {
+                // Blank is a number, like in Excel.
+                Value::Number(_) | Value::Blank => 1,
+                #[cfg(feature = "datetime")]
+                Value::DateTime(_) => 1,
+                Value::String(_) => 2,
+                Value::Bool(_) => 4,
+                Value::Error(_) => 16,
+                Value::Array(_) => 64,
+                Value::Lambda(_) => 128,
+            },
+            // Only errors that a cell could display are caught.
+            Err(e) if e.msg.excel_code().is_some() => 16,
+            Err(e) => return Err(e),
+        };
+        Ok(Value::Number(type_code as f64))
+    }
 
-            AstNodeContents::Array(a) => {
-                let mut array_of_values = vec![];
-                for row in a {
-                    let mut row_of_values = smallvec![];
-                    for elem_expr in row {
-                        row_of_values.push(elem_expr.eval(grid, pos).await?.inner);
+    /// Evaluates `ISFORMULA` or `FORMULATEXT`, which look at the formula in
+    /// the top-left cell of a reference.
+    async fn eval_formula_info(
+        &self,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+    ) -> FormulaResult<Value> {
+        let [arg] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let Some((top_left, _)) = arg.eval_reference(ctx).await? else {
+            return Err(FormulaErrorMsg::Expected {
+                expected: "cell reference".into(),
+                got: Some(arg.inner.type_string().into()),
+            }
+            .with_span(arg.span));
+        };
+        let formula = ctx.grid.get_formula(top_left).await;
+        if func.inner.eq_ignore_ascii_case("isformula") {
+            Ok(Value::Bool(formula.is_some()))
+        } else {
+            formula
+                .map(Value::String)
+                .ok_or_else(|| FormulaErrorMsg::NotAvailable.with_span(self.span))
+        }
+    }
+
+    /// Evaluates an expression that refers to a rectangle of cells, returning
+    /// its top-left and bottom-right corners, or returns `None` if the
+    /// expression is not a reference.
+    ///
+    /// References are cell references, cell ranges, spill ranges such as
+    /// `A1#`, and calls to `INDEX` on a reference. Either side of a cell range
+    /// may be any reference, and the result is the smallest range that
+    /// contains both.
+    fn eval_reference<'ctx: 'a, 'a>(
+        &'a self,
+        ctx: &'a mut Ctx<'ctx>,
+    ) -> LocalBoxFuture<'a, FormulaResult<Option<(Pos, Pos)>>> {
+        async move {
+            match &self.inner {
+                AstNodeContents::CellRef(cell_ref) => {
+                    let pos = cell_ref.resolve_from(ctx.pos);
+                    Ok(Some((pos, pos)))
+                }
+                AstNodeContents::Paren(contents) => contents.eval_reference(ctx).await,
+
+                AstNodeContents::ColumnRange(start, end) => {
+                    let x1 = start.resolve_from(ctx.pos.x);
+                    let x2 = end.resolve_from(ctx.pos.x);
+                    let (y1, y2) = column_range_rows(ctx).await;
+                    let min = Pos {
+                        x: x1.min(x2),
+                        y: y1,
+                    };
+                    let max = Pos {
+                        x: x1.max(x2),
+                        y: y2,
+                    };
+                    Ok(Some((min, max)))
+                }
+
+                AstNodeContents::FunctionCall { func, args } if func.inner == ":" => {
+                    // `1:3`
+                    if args
+                        .iter()
+                        .all(|arg| matches!(arg.inner, AstNodeContents::Number(_)))
+                    {
+                        let feature = "row ranges".into();
+                        return Err(FormulaErrorMsg::Unimplemented(feature).with_span(self.span));
                     }
-                    array_of_values.push(row_of_values);
+                    let mut corners = vec![];
+                    for arg in args {
+                        let Some((min, max)) = arg.eval_reference(ctx).await? else {
+                            return Err(FormulaErrorMsg::BadCellReference.with_span(arg.span));
+                        };
+                        corners.extend([min, max]);
+                    }
+                    let min_x = corners.iter().map(|pos| pos.x).min();
+                    let min_y = corners.iter().map(|pos| pos.y).min();
+                    let max_x = corners.iter().map(|pos| pos.x).max();
+                    let max_y = corners.iter().map(|pos| pos.y).max();
+                    let (Some(x1), Some(y1), Some(x2), Some(y2)) = (min_x, min_y, max_x, max_y)
+                    else {
+                        internal_error!("invalid arguments to cell range operator");
+                    };
+                    Ok(Some((Pos { x: x1, y: y1 }, Pos { x: x2, y: y2 })))
+                }
+
+                AstNodeContents::FunctionCall { func, args } if func.inner == "#" => {
+                    let [anchor] = args.as_slice() else {
+                        internal_error!("invalid arguments to spill range operator");
+                    };
+                    let pos = match anchor.eval_reference(ctx).await? {
+                        Some((min, max)) if min == max => min,
+                        _ => return Err(FormulaErrorMsg::BadCellReference.with_span(anchor.span)),
+                    };
+                    match ctx.grid.get_spill_extent(pos).await {
+                        Some(extent) => Ok(Some((pos, extent))),
+                        None => Err(FormulaErrorMsg::BadCellReference.with_span(self.span)),
+                    }
+                }
+
+                AstNodeContents::FunctionCall { func, args }
+                    if func.inner.eq_ignore_ascii_case("index") =>
+                {
+                    let [array, indices @ ..] = args.as_slice() else {
+                        return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+                    };
+                    let Some((min, max)) = array.eval_reference(ctx).await? else {
+                        return Ok(None);
+                    };
+                    let rows = min.y.abs_diff(max.y) as usize + 1;
+                    let cols = min.x.abs_diff(max.x) as usize + 1;
+                    let (row, col) = self.eval_index(ctx, indices, (rows, cols)).await?;
+                    let pos = Pos {
+                        x: min.x + col as i64,
+                        y: min.y + row as i64,
+                    };
+                    Ok(Some((pos, pos)))
                 }
-                Value::Array(array_of_values)
+
+                _ => Ok(None),
             }
+        }
+        .boxed_local()
+    }
 
-            AstNodeContents::CellRef(cell_ref) => self.get_cell(grid, pos, *cell_ref).await?,
+    /// Returns the columns and rows in the cell range between two corners.
+    /// Each cell in the range counts as one evaluation step.
+    fn cell_range_bounds(
+        &self,
+        ctx: &mut Ctx<'_>,
+        min: Pos,
+        max: Pos,
+    ) -> FormulaResult<(RangeInclusive<i64>, RangeInclusive<i64>)> {
+        // Check the size of the range before allocating anything.
+        let width = min.x.abs_diff(max.x).saturating_add(1);
+        let height = min.y.abs_diff(max.y).saturating_add(1);
+        ctx.take_steps(width.saturating_mul(height))
+            .and_then(|()| ctx.check_array_size(height, width))
+            .map_err(|e| e.with_span(self.span))?;
 
-            AstNodeContents::String(s) => Value::String(s.clone()),
+        Ok((min.x..=max.x, min.y..=max.y))
+    }
 
-            AstNodeContents::Number(n) => Value::Number(*n),
+    /// Checks the size of an array literal before evaluating its elements.
+    fn check_array_literal_size(&self, ctx: &Ctx<'_>, a: &[Vec<AstNode>]) -> FormulaResult<()> {
+        let cols = a.iter().map(|row| row.len()).max().unwrap_or(0);
+        ctx.check_array_size(a.len() as u64, cols as u64)
+            .map_err(|e| e.with_span(self.span))
+    }
+
+    /// Evaluates the row and column arguments to `INDEX` for an array of the
+    /// given size, returning zero-based indices. If the column is omitted and
+    /// the array has only one row, the row argument selects a column instead.
+    async fn eval_index(
+        &self,
+        ctx: &mut Ctx<'_>,
+        indices: &[AstNode],
+        (rows, cols): (usize, usize),
+    ) -> FormulaResult<(usize, usize)> {
+        let mut values = vec![];
+        for index in indices {
+            let value = index.eval(ctx).await?;
+            values.push((value.to_integer()?, value.span));
+        }
+        let (row, col) = match values.as_slice() {
+            [col] if rows == 1 => ((1, self.span), *col),
+            [row] => (*row, (1, self.span)),
+            [row, col] => (*row, *col),
+            _ => return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span)),
+        };
+        let check = |(index, span): (i64, Span), len: usize| {
+            if (1..=len as i64).contains(&index) {
+                Ok(index as usize - 1)
+            } else {
+                Err(FormulaErrorMsg::IndexOutOfBounds.with_span(span))
+            }
         };
+        Ok((check(row, rows)?, check(col, cols)?))
+    }
 
-        Ok(Spanned {
-            span: self.span,
-            inner: value,
-        })
+    /// Evaluates `INDEX` when its first argument is not a reference.
+    async fn index_array(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [array, indices @ ..] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let array = array.eval(ctx).await?;
+        let size = array.inner.array_size().unwrap_or((1, 1));
+        let (row, col) = self.eval_index(ctx, indices, size).await?;
+        Ok(array.get_array_value(row, col)?.inner)
     }
 
-    /// Fetches the contents of the cell at `ref_pos` evaluated at `base_pos`,
-    /// or returns an error in the case of a circular reference.
-    async fn get_cell(
+    /// Evaluates `SUBTOTAL(function_num, ref1, ...)`.
+    ///
+    /// Cells in the ranges that contain a `SUBTOTAL` formula themselves are
+    /// skipped, so that nested subtotals are not counted twice. Function
+    /// numbers 101 to 111 also skip rows that the user has hidden.
+    async fn subtotal(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [function_num, refs @ ..] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        if refs.is_empty() {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        }
+        let function_num = function_num.eval(ctx).await?;
+        let (aggregation, ignore_hidden) = match function_num.to_integer()? {
+            n @ 1..=11 => (Aggregation::from_number(n), false),
+            n @ 101..=111 => (Aggregation::from_number(n - 100), true),
+            _ => (None, false),
+        };
+        let Some(aggregation) = aggregation else {
+            return Err(FormulaErrorMsg::BadNumber.with_span(function_num.span));
+        };
+
+        let values = total_values(ctx, refs, ignore_hidden, &["subtotal"]).await?;
+        let values = values.iter().map(|v| &v.inner).collect_vec();
+        let result = aggregation
+            .apply(&values, None)
+            .map_err(|e| e.with_span(self.span))?;
+        Ok(Value::Number(result))
+    }
+
+    /// Evaluates `AGGREGATE(function_num, options, ref1, ...)`, or
+    /// `AGGREGATE(function_num, options, array, k)` for functions that take
+    /// `k`.
+    ///
+    /// Options 0 to 3 skip cells that contain a `SUBTOTAL` or `AGGREGATE`
+    /// formula, odd options skip rows that the user has hidden, and options
+    /// 2, 3, 6, and 7 skip errors.
+    async fn aggregate(&self, ctx: &mut Ctx<'_>, args: &[AstNode]) -> FormulaResult<Value> {
+        let [function_num, options, refs @ ..] = args else {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span));
+        };
+        let function_num = function_num.eval(ctx).await?;
+        let function = Aggregation::from_number(function_num.to_integer()?)
+            .ok_or_else(|| FormulaErrorMsg::BadNumber.with_span(function_num.span))?;
+        let options = options.eval(ctx).await?;
+        let options_num = options.to_integer()?;
+        if !(0..=7).contains(&options_num) {
+            return Err(FormulaErrorMsg::BadNumber.with_span(options.span));
+        }
+        let ignore_nested = options_num <= 3;
+        let ignore_hidden = options_num % 2 == 1;
+        let ignore_errors = matches!(options_num, 2 | 3 | 6 | 7);
+
+        let (refs, k) = match (function.takes_k(), refs) {
+            (true, [array, k]) => {
+                let k = k.eval(ctx).await?.to_number()?;
+                (std::slice::from_ref(array), Some(k))
+            }
+            (false, [_, ..]) => (refs, None),
+            _ => return Err(FormulaErrorMsg::BadArgumentCount.with_span(self.span)),
+        };
+
+        let nested: &[&str] = match ignore_nested {
+            true => &["subtotal", "aggregate"],
+            false => &[],
+        };
+        let mut values = vec![];
+        for v in total_values(ctx, refs, ignore_hidden, nested).await? {
+            match v.inner {
+                Value::Error(_) if ignore_errors => (),
+                Value::Error(e) => return Err(e.with_span(v.span)),
+                other => values.push(other),
+            }
+        }
+        let values = values.iter().collect_vec();
+        let result = function
+            .apply(&values, k)
+            .map_err(|e| e.with_span(self.span))?;
+        Ok(Value::Number(result))
+    }
+
+    /// Returns whether this expression calls the function `name`, ignoring
+    /// case, anywhere inside it.
+    fn calls_function(&self, name: &str) -> bool {
+        match &self.inner {
+            AstNodeContents::FunctionCall { func, args } => {
+                func.inner.eq_ignore_ascii_case(name)
+                    || args.iter().any(|arg| arg.calls_function(name))
+            }
+            AstNodeContents::Paren(contents) => contents.calls_function(name),
+            AstNodeContents::Array(a) => a.iter().flatten().any(|v| v.calls_function(name)),
+            AstNodeContents::CellRef(_)
+            | AstNodeContents::ColumnRange(..)
+            | AstNodeContents::Name(_)
+            | AstNodeContents::String(_)
+            | AstNodeContents::Number(_)
+            | AstNodeContents::Error(_)
+            | AstNodeContents::Omitted => false,
+        }
+    }
+
+    /// Warns about each operand of an operator that is a range of several
+    /// cells, where spreadsheets without dynamic arrays would have used
+    /// implicit intersection instead of applying the operator to every cell.
+    fn warn_implicit_intersection(
         &self,
-        grid: &mut impl GridProxy,
-        base_pos: Pos,
-        ref_pos: CellRef,
+        ctx: &mut Ctx<'_>,
+        func: &Spanned<String>,
+        args: &[AstNode],
+        arg_values: &[Spanned<Value>],
+    ) {
+        if !ctx.is_collecting_warnings()
+            || func.inner.starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            return;
+        }
+        for (arg, value) in args.iter().zip(arg_values) {
+            if arg.is_cell_reference() && matches!(&value.inner, Value::Array(a) if a.len() > 1) {
+                ctx.warn(FormulaWarningMsg::ImplicitIntersection.with_span(arg.span));
+            }
+        }
+    }
+
+    /// Returns the single value from `arg` on the same row or column as
+    /// `ctx.pos`.
+    ///
+    /// If `arg` is a cell range, only the intersecting cell is fetched. If it
+    /// is some other array, the top-left value is returned.
+    async fn implicit_intersection(
+        &self,
+        ctx: &mut Ctx<'_>,
+        arg: &AstNode,
     ) -> FormulaResult<Value> {
-        let ref_pos = ref_pos.resolve_from(base_pos);
-        if ref_pos == base_pos {
+        if let Some((corner1, corner2)) = arg.eval_reference(ctx).await? {
+            let intersect = |a: i64, b: i64, base: i64| {
+                let (lo, hi) = (std::cmp::min(a, b), std::cmp::max(a, b));
+                if lo == hi {
+                    Some(lo)
+                } else {
+                    (lo..=hi).contains(&base).then_some(base)
+                }
+            };
+            let (Some(x), Some(y)) = (
+                intersect(corner1.x, corner2.x, ctx.pos.x),
+                intersect(corner1.y, corner2.y, ctx.pos.y),
+            ) else {
+                return Err(FormulaErrorMsg::NoImplicitIntersection.with_span(self.span));
+            };
+            return self.get_cell(ctx, CellRef::absolute(Pos { x, y })).await;
+        }
+
+        match arg.eval(ctx).await?.inner {
+            Value::Array(a) => match a.first() {
+                Some(v) => Ok(v.clone()),
+                None => Err(FormulaErrorMsg::NoImplicitIntersection.with_span(self.span)),
+            },
+            other => Ok(other),
+        }
+    }
+
+    /// Fetches the contents of the cell at `ref_pos` evaluated at `ctx.pos`,
+    /// or returns an error in the case of a circular reference.
+    async fn get_cell(&self, ctx: &mut Ctx<'_>, ref_pos: CellRef) -> FormulaResult<Value> {
+        let ref_pos = ref_pos.resolve_from(ctx.pos);
+        if ref_pos == ctx.pos {
             return Err(FormulaErrorMsg::CircularReference.with_span(self.span));
         }
-        Ok(Value::String(grid.get(ref_pos).await.unwrap_or_default()))
+        let contents = ctx.grid.get(ref_pos).await;
+        Ok(cell_value(ctx, ref_pos, contents).await)
     }
 
     /// Fetches the contents of the cell at `(x, y)`, but fetches an array of cells
     /// if either `x` or `y` is an array.
     fn array_mapped_get_cell(
         &self,
-        grid: &mut impl GridProxy,
-        base_pos: Pos,
+        ctx: &mut Ctx<'_>,
         args: Spanned<Vec<Spanned<Value>>>,
     ) -> FormulaResult<Value> {
         functions::array_map(args, move |[x, y]| {
@@ -223,7 +1564,179 @@ impl AstNode {
             // Can't have this be async because it needs to mutate `grid` and
             // Rust isn't happy about moving a mutable reference to `grid` into
             // the closure.
-            pollster::block_on(self.get_cell(grid, base_pos, CellRef::absolute(pos)))
+            pollster::block_on(self.get_cell(ctx, CellRef::absolute(pos)))
         })
     }
 }
+
+/// Serializes an error literal as its Excel error code, such as `"#N/A"`.
+mod error_code {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::FormulaErrorMsg;
+
+    pub fn serialize<S: Serializer>(e: &FormulaErrorMsg, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(e.excel_code().unwrap_or("#ERROR!"))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FormulaErrorMsg, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        FormulaErrorMsg::from_excel_code(&code)
+            .ok_or_else(|| de::Error::custom(format!("unknown error code {code:?}")))
+    }
+}
+
+/// Returns the value of the cell at `pos`, given its contents. This is called
+/// right after fetching the contents, since it also checks the cell for an
+/// error. A cell with no contents is [`Value::Blank`].
+///
+/// A pending cell is read as blank and recorded in `ctx`, which makes the
+/// outcome of the whole formula pending regardless of its value.
+async fn cell_value(ctx: &mut Ctx<'_>, pos: Pos, contents: Option<String>) -> Value {
+    if ctx.grid.is_pending(pos).await {
+        ctx.mark_pending(pos);
+        return Value::Blank;
+    }
+    if let Some(source) = ctx.grid.get_error(pos).await {
+        return Value::Error(FormulaErrorMsg::UpstreamError {
+            source: source.into(),
+        });
+    }
+    contents_value(ctx, contents)
+}
+
+/// Converts the contents of a cell to a value, like `cell_value()` but
+/// without asking the grid whether the cell is pending or has an error.
+fn contents_value(ctx: &Ctx<'_>, contents: Option<String>) -> Value {
+    let Some(contents) = contents.filter(|s| !s.is_empty()) else {
+        return Value::Blank;
+    };
+    if let Some(e) = parse_error_literal(&contents) {
+        return Value::Error(e);
+    }
+    if ctx.coercion().reads_cells_as_numbers() {
+        // Read cells that look like numbers as numbers, since they can't be
+        // coerced later.
+        if let Some(n) = Value::String(contents.clone()).as_number() {
+            return Value::Number(n);
+        }
+    }
+    Value::String(contents)
+}
+
+/// Replaces text that looks like a number with the number, in the value of a
+/// cell or range. Cells read as numbers with strict coercion are already
+/// numbers.
+fn read_numbers_in_cells(value: &mut Value) {
+    if let Value::Array(a) = value {
+        a.values_mut().iter_mut().for_each(read_numbers_in_cells);
+    } else if let (Value::String(_), Some(n)) = (&*value, value.as_number()) {
+        *value = Value::Number(n);
+    }
+}
+
+/// Returns the first and last rows of a column range: the rows of the grid's
+/// used region, extended to include the row of the formula, whose own cell is
+/// not blank.
+async fn column_range_rows(ctx: &mut Ctx<'_>) -> (i64, i64) {
+    let y = ctx.pos.y;
+    match ctx.grid.used_region().await {
+        Some((min, max)) => (min.y.min(y), max.y.max(y)),
+        None => (y, y),
+    }
+}
+
+/// Returns the values in `refs` for `SUBTOTAL` or `AGGREGATE`, each with the
+/// span of the argument it came from. Cells in hidden rows are skipped if
+/// `ignore_hidden` is true, and cells whose formula calls one of the functions
+/// in `nested` are skipped.
+async fn total_values(
+    ctx: &mut Ctx<'_>,
+    refs: &[AstNode],
+    ignore_hidden: bool,
+    nested: &[&str],
+) -> FormulaResult<Vec<Spanned<Value>>> {
+    let mut values = vec![];
+    for arg in refs {
+        let span = arg.span;
+        let Some((min, max)) = arg.eval_reference(ctx).await? else {
+            // Not a reference, so there are no hidden rows or nested totals.
+            match arg.eval(ctx).await?.inner {
+                Value::Array(a) => {
+                    values.extend(
+                        a.into_values()
+                            .into_iter()
+                            .map(|inner| Spanned { span, inner }),
+                    );
+                }
+                inner => values.push(Spanned { span, inner }),
+            }
+            continue;
+        };
+        let (xs, ys) = arg.cell_range_bounds(ctx, min, max)?;
+        for y in ys {
+            if ignore_hidden && ctx.grid.is_row_hidden(y).await {
+                continue;
+            }
+            for x in xs.clone() {
+                let pos = Pos { x, y };
+                if is_nested_total_cell(ctx, pos, nested).await {
+                    continue;
+                }
+                let inner = arg.get_cell(ctx, CellRef::absolute(pos)).await?;
+                values.push(Spanned { span, inner });
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Returns whether the cell at `pos` contains a formula that calls one of the
+/// functions in `nested`.
+async fn is_nested_total_cell(ctx: &mut Ctx<'_>, pos: Pos, nested: &[&str]) -> bool {
+    if nested.is_empty() {
+        return false;
+    }
+    match ctx.grid.get_formula(pos).await {
+        Some(source) => parse_formula(&source, pos)
+            .is_ok_and(|f| nested.iter().any(|&name| f.ast.calls_function(name))),
+        None => false,
+    }
+}
+
+/// Turns an error from coercing an argument of a named function, such as
+/// "Expected number, got \"abc\"", into a [`FormulaErrorMsg::TypeMismatch`]
+/// that says which argument of which function has the wrong type. Other errors
+/// are returned unchanged.
+fn with_argument_type_info(e: FormulaError, func: &str, arg_spans: &[Span]) -> FormulaError {
+    // Operators such as `+` don't have meaningful argument numbers.
+    if !func.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return e;
+    }
+    let FormulaErrorMsg::Expected {
+        expected,
+        got: Some(got),
+    } = &e.msg
+    else {
+        return e;
+    };
+    let arg_index = arg_spans.iter().position(|&span| Some(span) == e.span);
+    let expected = ValueType::from_description(expected);
+    // Text that can't be coerced is quoted, such as `"abc"`.
+    let got = if got.starts_with('"') {
+        Some(ValueType::Text)
+    } else {
+        ValueType::from_description(got)
+    };
+    match (arg_index, expected, got) {
+        (Some(arg_index), Some(expected), Some(got)) => FormulaErrorMsg::TypeMismatch {
+            func: func.to_ascii_uppercase().into(),
+            arg_index,
+            expected,
+            got,
+        }
+        .with_span(arg_spans[arg_index]),
+        _ => e,
+    }
+}
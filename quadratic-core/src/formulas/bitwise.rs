@@ -0,0 +1,44 @@
+//! Bitwise operations, for engineering functions such as `BITAND` and
+//! `BITLSHIFT`.
+//!
+//! Like Excel, operands must be integers from 0 to 2^48-1, shift amounts
+//! must be integers from -53 to 53, and results must fit in 48 bits.
+
+use super::*;
+
+/// Largest number that a bitwise function accepts or returns.
+const MAX_VALUE: u64 = (1 << 48) - 1;
+/// Largest magnitude of a shift amount.
+const MAX_SHIFT: f64 = 53.0;
+
+/// Reads an operand of a bitwise function.
+pub fn operand(value: &Spanned<Value>) -> FormulaResult<u64> {
+    let n = value.to_number()?;
+    if n.fract() != 0.0 || !(0.0..=MAX_VALUE as f64).contains(&n) {
+        return Err(FormulaErrorMsg::BadNumber.with_span(value.span));
+    }
+    Ok(n as u64)
+}
+
+/// Shifts a number left by some number of bits, or right if the amount is
+/// negative. If `right` is true, the direction is reversed.
+pub fn shift(number: &Spanned<Value>, amount: &Spanned<Value>, right: bool) -> FormulaResult<u64> {
+    let n = operand(number)?;
+    let mut amount_value = amount.to_number()?;
+    if amount_value.fract() != 0.0 || amount_value.abs() > MAX_SHIFT {
+        return Err(FormulaErrorMsg::BadNumber.with_span(amount.span));
+    }
+    if right {
+        amount_value = -amount_value;
+    }
+    let amount_value = amount_value as i32;
+    if amount_value < 0 {
+        return Ok(n >> -amount_value);
+    }
+    // `n` has at most 48 bits, so this can't overflow.
+    let ret = (n as u128) << amount_value;
+    if ret > MAX_VALUE as u128 {
+        return Err(FormulaErrorMsg::BadNumber.with_span(number.span));
+    }
+    Ok(ret as u64)
+}
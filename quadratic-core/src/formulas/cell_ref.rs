@@ -4,7 +4,29 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
-use super::Pos;
+use super::{FormulaError, FormulaErrorMsg, Pos};
+
+/// Converts an A1-style reference, such as `B$3` or `A1:C5`, to RC-style
+/// notation, such as `R3C[1]`. Relative coordinates in the result are relative
+/// to `base`.
+///
+/// Returns [`FormulaErrorMsg::BadCellReference`] if `a1` is not a cell
+/// reference or a range of cells, columns, or rows.
+pub fn a1_to_rc(a1: &str, base: Pos) -> Result<String, FormulaError> {
+    RangeRef::parse_a1(a1, base)
+        .map(|range| range.to_string())
+        .ok_or(FormulaErrorMsg::BadCellReference.without_span())
+}
+/// Converts an RC-style reference, such as `R3C[1]` or `C[0]:C2`, to A1-style
+/// notation. This is the inverse of [`a1_to_rc()`].
+///
+/// Returns [`FormulaErrorMsg::BadCellReference`] if `rc` is not a cell
+/// reference or a range of cells, columns, or rows.
+pub fn rc_to_a1(rc: &str, base: Pos) -> Result<String, FormulaError> {
+    RangeRef::parse_rc(rc)
+        .map(|range| range.a1_string(base))
+        .ok_or(FormulaErrorMsg::BadCellReference.without_span())
+}
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum RangeRef {
@@ -40,6 +62,52 @@ impl RangeRef {
             RangeRef::Cell(cell) => cell.a1_string(base),
         }
     }
+
+    /// Parses an A1-style reference to a cell or a range of cells, columns,
+    /// or rows, such as `A1`, `A1:$B$2`, `A:C`, or `$1:3`, relative to a given
+    /// location.
+    pub fn parse_a1(s: &str, base: Pos) -> Option<RangeRef> {
+        let Some((start, end)) = s.split_once(':') else {
+            return CellRef::parse_a1(s, base).map(RangeRef::Cell);
+        };
+        if let (Some(start), Some(end)) =
+            (CellRef::parse_a1(start, base), CellRef::parse_a1(end, base))
+        {
+            return Some(RangeRef::CellRange(start, end));
+        }
+        if let (Some(start), Some(end)) = (
+            CellRefCoord::parse_a1_col(start, base.x),
+            CellRefCoord::parse_a1_col(end, base.x),
+        ) {
+            return Some(RangeRef::ColRange(start, end));
+        }
+        Some(RangeRef::RowRange(
+            CellRefCoord::parse_a1_row(start, base.y)?,
+            CellRefCoord::parse_a1_row(end, base.y)?,
+        ))
+    }
+
+    /// Parses an RC-style reference in the format produced by `Display`, such
+    /// as `R[1]C2`, `R1C1:R[2]C[0]`, `C[0]:C[3]`, or `R2:R4`.
+    pub fn parse_rc(s: &str) -> Option<RangeRef> {
+        let Some((start, end)) = s.split_once(':') else {
+            return CellRef::parse_rc(s).map(RangeRef::Cell);
+        };
+        if let (Some(start), Some(end)) = (CellRef::parse_rc(start), CellRef::parse_rc(end)) {
+            return Some(RangeRef::CellRange(start, end));
+        }
+        let coords = |prefix: char| {
+            Some((
+                start.strip_prefix(prefix)?.parse().ok()?,
+                end.strip_prefix(prefix)?.parse().ok()?,
+            ))
+        };
+        if let Some((start, end)) = coords('C') {
+            return Some(RangeRef::ColRange(start, end));
+        }
+        let (start, end) = coords('R')?;
+        Some(RangeRef::RowRange(start, end))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -74,7 +142,7 @@ impl CellRef {
     /// A1-style notation.
     pub fn a1_string(self, base: Pos) -> String {
         let col = self.x.col_string(base.x);
-        let row = self.y.col_string(base.y);
+        let row = self.y.row_string(base.y);
         format!("{col}{row}")
     }
 
@@ -122,6 +190,16 @@ impl CellRef {
             y: row_ref,
         })
     }
+
+    /// Parses an RC-style cell reference in the format produced by
+    /// `Display`, such as `R[1]C2` or `Rn3C[-1]`.
+    pub fn parse_rc(s: &str) -> Option<CellRef> {
+        let (y, x) = s.strip_prefix('R')?.split_once('C')?;
+        Some(CellRef {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -156,14 +234,54 @@ impl FromStr for CellRefCoord {
         let maybe_relative = (|| s.strip_prefix('[')?.strip_suffix(']')?.parse().ok())();
         if let Some(rel) = maybe_relative {
             Ok(Self::Relative(rel))
-        } else if let Ok(abs) = s.parse() {
-            Ok(Self::Absolute(abs))
+        } else if let Some(abs) = s.strip_prefix('n').filter(|s| is_ascii_digits(s)) {
+            Ok(Self::Absolute(-abs.parse::<i64>().map_err(|_| ())?))
+        } else if is_ascii_digits(s) {
+            Ok(Self::Absolute(s.parse().map_err(|_| ())?))
         } else {
             Err(())
         }
     }
 }
 impl CellRefCoord {
+    /// Parses an A1-style column name, such as `B` or `$nC`, relative to a
+    /// given column.
+    pub fn parse_a1_col(s: &str, base_x: i64) -> Option<Self> {
+        let (is_absolute, column_name) = match s.strip_prefix('$') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let col = crate::util::column_from_name(column_name)?;
+        Some(if is_absolute {
+            CellRefCoord::Absolute(col)
+        } else {
+            CellRefCoord::Relative(col - base_x)
+        })
+    }
+
+    /// Parses an A1-style row number, such as `3` or `$n2`, relative to a
+    /// given row.
+    pub fn parse_a1_row(s: &str, base_y: i64) -> Option<Self> {
+        let (is_absolute, row_number) = match s.strip_prefix('$') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (is_negative, digits) = match row_number.strip_prefix('n') {
+            Some(rest) => (true, rest),
+            None => (false, row_number),
+        };
+        if !is_ascii_digits(digits) {
+            return None;
+        }
+        let row = digits.parse::<i64>().ok()?;
+        let row = if is_negative { -row } else { row };
+        Some(if is_absolute {
+            CellRefCoord::Absolute(row)
+        } else {
+            CellRefCoord::Relative(row - base_y)
+        })
+    }
+
     /// Resolves the reference to an absolute coordinate, given the cell
     /// coordinate where evaluation is taking place.
     pub fn resolve_from(self, base: i64) -> i64 {
@@ -190,6 +308,72 @@ impl CellRefCoord {
     /// a row coordinate.
     fn row_string(self, base: i64) -> String {
         let row = self.resolve_from(base);
-        format!("{}{row}", self.prefix())
+        let sign = if row < 0 { "n" } else { "" };
+        format!("{}{sign}{}", self.prefix(), row.unsigned_abs())
+    }
+}
+
+/// Returns whether a string is a nonempty sequence of ASCII digits, without a
+/// sign.
+fn is_ascii_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_reference_style_conversion() {
+        let base = Pos::new(2, 3);
+        for (a1, rc) in [
+            ("C3", "R[0]C[0]"),
+            ("A1", "R[-2]C[-2]"),
+            ("$B$7", "R7C1"),
+            ("nA$n2", "Rn2C[-3]"),
+            ("D$0:$E9", "R0C[1]:R[6]C4"),
+            ("B:$D", "C[-1]:C3"),
+            ("$1:n1", "R1:R[-4]"),
+        ] {
+            assert_eq!(rc, a1_to_rc(a1, base).unwrap(), "{a1:?}");
+            assert_eq!(a1, rc_to_a1(rc, base).unwrap(), "{rc:?}");
+        }
+
+        for bad in ["", "A", "1", "A1:", "A1:B", "A:1", "a1", "A+1", "R1C1"] {
+            let err = a1_to_rc(bad, base).unwrap_err();
+            assert_eq!(FormulaErrorMsg::BadCellReference, err.msg, "{bad:?}");
+        }
+        for bad in ["", "R1", "C[1]", "R[1]C", "RC", "R+1C1", "R1C1:C1", "A1"] {
+            let err = rc_to_a1(bad, base).unwrap_err();
+            assert_eq!(FormulaErrorMsg::BadCellReference, err.msg, "{bad:?}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_a1_to_rc_round_trip(
+            x in -1000..1000_i64,
+            y in -1000..1000_i64,
+            x_is_absolute: bool,
+            y_is_absolute: bool,
+            base_x in -100..100_i64,
+            base_y in -100..100_i64,
+        ) {
+            let col = crate::util::column_name(x);
+            let row = match y {
+                ..0 => format!("n{}", -y),
+                _ => y.to_string(),
+            };
+            let dollar = |is_absolute| if is_absolute { "$" } else { "" };
+            let a1 = format!("{}{col}{}{row}", dollar(x_is_absolute), dollar(y_is_absolute));
+            let base = Pos::new(base_x, base_y);
+
+            let rc = a1_to_rc(&a1, base).unwrap();
+            prop_assert_eq!(&a1, &rc_to_a1(&rc, base).unwrap());
+            let range = format!("{a1}:{a1}");
+            prop_assert_eq!(&range, &rc_to_a1(&a1_to_rc(&range, base).unwrap(), base).unwrap());
+        }
     }
 }
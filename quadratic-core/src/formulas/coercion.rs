@@ -0,0 +1,155 @@
+//! Coercion of values by operators, which depends on the evaluation settings.
+//!
+//! By default, values are coerced like in Excel: text that looks like a number
+//! is read as a number, blank is zero, and values of any types can be compared
+//! for equality. Operators coerce their operands only through [`Coercion`], so
+//! that the stricter settings apply to all of them the same way.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use super::order::{SortKey, TextCompareMode};
+use super::*;
+
+/// How strictly operators coerce their operands.
+///
+/// Only arithmetic operators (`+`, `-`, `*`, `/`, `^`, and `%`) and comparison
+/// operators are affected. Functions and other coercions, such as text passed
+/// to `SUM`, are the same at every level.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Strictness {
+    /// Coerce values like Excel does.
+    #[default]
+    Off,
+    /// Reject non-blank text where a number is expected, as in `"5" + 3`, and
+    /// in ordered comparisons (`<`, `>`, `<=`, and `>=`) with a number. The
+    /// contents of cells that look like numbers are read as numbers instead.
+    Text,
+    /// Reject all silent coercions. This does everything that
+    /// [`Strictness::Text`] does, except that text where a number is expected
+    /// is a [`FormulaErrorMsg::NotANumber`] error. It also rejects blank
+    /// values where a number is expected, as in `A1 + 3` where `A1` is blank,
+    /// and comparisons of values of different types, such as `1 = "1"` or
+    /// `TRUE() > 0`.
+    All,
+}
+
+/// How operators coerce their operands, according to the evaluation settings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Coercion {
+    /// Whether non-blank text is rejected where a number is expected, which
+    /// every level of [`Strictness`] except `Off` does.
+    reject_text: bool,
+    /// Whether blank is rejected where a number is expected, and values of
+    /// different types are rejected in comparisons, which only
+    /// [`Strictness::All`] does.
+    strict: bool,
+    tolerance: Option<FloatTolerance>,
+    text_compare: TextCompareMode,
+}
+impl Coercion {
+    pub fn new(settings: &EvalSettings) -> Self {
+        Self {
+            reject_text: settings.strictness != Strictness::Off,
+            strict: settings.strictness == Strictness::All,
+            tolerance: settings.float_tolerance,
+            text_compare: settings.text_compare,
+        }
+    }
+
+    /// Returns whether the contents of cells that look like numbers should be
+    /// read as numbers, since they can't be coerced later.
+    pub fn reads_cells_as_numbers(self) -> bool {
+        self.reject_text
+    }
+
+    /// Coerces an operand to a number.
+    pub fn to_number(self, value: &Spanned<impl Borrow<Value>>) -> FormulaResult<f64> {
+        let msg = match value.inner.borrow() {
+            // Blank, or text that is only whitespace
+            v if matches!(v, Value::Blank)
+                || matches!(v, Value::String(s) if s.trim().is_empty()) =>
+            {
+                if !self.strict {
+                    return value.to_number();
+                }
+                FormulaErrorMsg::Expected {
+                    expected: "a number".into(),
+                    got: Some("blank".into()),
+                }
+            }
+            Value::String(s) if self.strict => FormulaErrorMsg::NotANumber(format!("{s:?}").into()),
+            Value::String(_) if self.reject_text => FormulaErrorMsg::Expected {
+                expected: "a number".into(),
+                got: Some("text".into()),
+            },
+            _ => return value.to_number(),
+        };
+        Err(msg.with_span(value.span))
+    }
+
+    /// Returns an error if two operands can't be compared with each other. In
+    /// strict mode, they must have the same type, except that blank can be
+    /// compared with anything; otherwise, anything can be compared.
+    pub fn check_comparable(self, a: &Spanned<Value>, b: &Spanned<Value>) -> FormulaResult<()> {
+        let either_blank = matches!(a.inner, Value::Blank) || matches!(b.inner, Value::Blank);
+        if self.strict
+            && !either_blank
+            && std::mem::discriminant(&a.inner) != std::mem::discriminant(&b.inner)
+        {
+            return Err(FormulaErrorMsg::Expected {
+                expected: a.inner.type_name().into(),
+                got: Some(b.inner.type_name().into()),
+            }
+            .with_span(b.span));
+        }
+        Ok(())
+    }
+
+    /// Compares two operands in the order described in [`super::order`]. A
+    /// blank operand is coerced to the type of the other one, so blank equals
+    /// `0`, `""`, and `FALSE`. Numbers within the float tolerance are equal,
+    /// and text is compared according to the text comparison mode.
+    ///
+    /// Returns an error if text that looks like a number is compared with a
+    /// number while text is rejected where a number is expected.
+    pub fn compare(self, a: &Spanned<Value>, b: &Spanned<Value>) -> FormulaResult<Ordering> {
+        #[cfg(feature = "datetime")]
+        if let Some((a, b)) = super::datetime::comparison_numbers(&a.inner, &b.inner) {
+            return Ok(self.cmp_numbers(a, b));
+        }
+        if self.reject_text {
+            for (x, y) in [(a, b), (b, a)] {
+                if matches!(x.inner, Value::String(_)) && matches!(y.inner, Value::Number(_)) {
+                    self.to_number(x)?;
+                }
+            }
+        }
+        let a = SortKey::with_mode(&a.inner, self.text_compare);
+        let b = SortKey::with_mode(&b.inner, self.text_compare);
+        Ok(match (a.blank_as(&b), b.blank_as(&a)) {
+            (SortKey::Number(a), SortKey::Number(b)) => self.cmp_numbers(a, b),
+            (a, b) => a.cmp(&b),
+        })
+    }
+    /// Returns whether two values are equal when compared with
+    /// [`Coercion::compare()`], treating an error as unequal.
+    pub fn values_eq(self, a: &Spanned<Value>, b: &Spanned<Value>) -> bool {
+        self.compare(a, b).is_ok_and(Ordering::is_eq)
+    }
+    /// Compares two numbers, which are equal if they are within the tolerance.
+    fn cmp_numbers(self, a: f64, b: f64) -> Ordering {
+        match self.numbers_eq(a, b) {
+            true => Ordering::Equal,
+            false => SortKey::Number(a).cmp(&SortKey::Number(b)),
+        }
+    }
+    /// Returns whether two numbers are equal, within the tolerance if there is
+    /// one.
+    pub fn numbers_eq(self, a: f64, b: f64) -> bool {
+        match self.tolerance {
+            Some(tolerance) => tolerance.approx_eq(a, b),
+            None => a == b,
+        }
+    }
+}
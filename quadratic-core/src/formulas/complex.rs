@@ -0,0 +1,174 @@
+//! Complex numbers, written as text the same way as Excel, such as `"3+4i"`.
+
+use std::borrow::Borrow;
+use std::fmt;
+
+use super::*;
+
+/// Complex number, along with the suffix used to write its imaginary part.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+    /// Either `'i'` or `'j'`.
+    pub suffix: char,
+}
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Complex { re, im, suffix } = *self;
+        if im == 0.0 {
+            return write!(f, "{re}");
+        }
+        if re != 0.0 {
+            write!(f, "{re}")?;
+            if im > 0.0 {
+                write!(f, "+")?;
+            }
+        }
+        match im {
+            1.0 => write!(f, "{suffix}"),
+            -1.0 => write!(f, "-{suffix}"),
+            _ => write!(f, "{im}{suffix}"),
+        }
+    }
+}
+impl Complex {
+    /// Reads the suffix argument of `COMPLEX`, which must be `"i"` or `"j"`.
+    /// A blank suffix means `"i"`.
+    pub fn parse_suffix(suffix: &Spanned<Value>) -> FormulaResult<char> {
+        suffix.check_error()?;
+        match suffix.inner.to_string().as_str() {
+            "" | "i" => Ok('i'),
+            "j" => Ok('j'),
+            other => Err(FormulaErrorMsg::Expected {
+                expected: "\"i\" or \"j\"".into(),
+                got: Some(format!("{other:?}").into()),
+            }
+            .with_span(suffix.span)),
+        }
+    }
+
+    /// Reads a complex number from a formula value. Numbers and blank strings
+    /// are real.
+    pub fn from_value(value: &Spanned<impl Borrow<Value>>) -> FormulaResult<Self> {
+        let real = |re| Self {
+            re,
+            im: 0.0,
+            suffix: 'i',
+        };
+        match value.inner.borrow() {
+            Value::Number(n) => Ok(real(*n)),
+            Value::Blank => Ok(real(0.0)),
+            Value::String(s) if s.is_empty() => Ok(real(0.0)),
+            Value::String(s) => Self::parse(s).ok_or_else(|| {
+                FormulaErrorMsg::NotANumber(format!("{s:?}").into()).with_span(value.span)
+            }),
+            Value::Error(e) => Err(e.clone().with_span(value.span)),
+            other => Err(FormulaErrorMsg::Expected {
+                expected: "complex number".into(),
+                got: Some(other.type_name().into()),
+            }
+            .with_span(value.span)),
+        }
+    }
+
+    /// Parses a complex number such as `"3+4i"`, `"-2.5j"`, `"i"`, or `"7"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let Some(body) = s.strip_suffix(['i', 'j']) else {
+            return Some(Self {
+                re: parse_real(s)?,
+                im: 0.0,
+                suffix: 'i',
+            });
+        };
+        let suffix = s.chars().last()?;
+        // Find the sign that separates the real and imaginary parts, ignoring
+        // a leading sign and signs in exponents.
+        let split = body
+            .char_indices()
+            .rev()
+            .find(|&(i, c)| (c == '+' || c == '-') && i > 0 && !body[..i].ends_with(['e', 'E']))
+            .map(|(i, _)| i);
+        let (re, im) = match split {
+            Some(i) => (parse_real(&body[..i])?, &body[i..]),
+            None => (0.0, body),
+        };
+        let im = match im {
+            "" | "+" => 1.0,
+            "-" => -1.0,
+            _ => parse_real(im)?,
+        };
+        Some(Self { re, im, suffix })
+    }
+
+    /// Returns the magnitude of the number.
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+    /// Returns the sum of two numbers, or an error if their suffixes differ.
+    pub fn add(self, other: Self) -> FormulaResult<Self> {
+        Ok(Self {
+            re: self.re + other.re,
+            im: self.im + other.im,
+            suffix: common_suffix(self, other)?,
+        })
+    }
+    /// Returns the product of two numbers, or an error if their suffixes
+    /// differ.
+    pub fn mul(self, other: Self) -> FormulaResult<Self> {
+        Ok(Self {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+            suffix: common_suffix(self, other)?,
+        })
+    }
+}
+
+/// Returns the suffix to use for the result of an operation on two numbers.
+/// Real numbers don't need a suffix, so they can be combined with either.
+fn common_suffix(a: Complex, b: Complex) -> FormulaResult<char> {
+    match (a.im == 0.0, b.im == 0.0) {
+        (true, _) => Ok(b.suffix),
+        (_, true) => Ok(a.suffix),
+        _ if a.suffix == b.suffix => Ok(a.suffix),
+        _ => Err(FormulaErrorMsg::InvalidArgument.into()),
+    }
+}
+
+/// Parses a real number, which may have a sign but no surrounding whitespace.
+fn parse_real(s: &str) -> Option<f64> {
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None; // Reject "inf", "NaN", etc.
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complex_round_trip() {
+        for (s, re, im) in [
+            ("3+4i", 3.0, 4.0),
+            ("3-4i", 3.0, -4.0),
+            ("-3.5+i", -3.5, 1.0),
+            ("-i", 0.0, -1.0),
+            ("2.5i", 0.0, 2.5),
+            ("7", 7.0, 0.0),
+            ("0", 0.0, 0.0),
+        ] {
+            let c = Complex::parse(s).unwrap();
+            assert_eq!((re, im), (c.re, c.im), "{s}");
+            assert_eq!(s, c.to_string());
+        }
+        let c = Complex::parse("1e-3+2E+3j").unwrap();
+        assert_eq!((0.001, 2000.0, 'j'), (c.re, c.im, c.suffix));
+        for s in [
+            "", "i+", "3+4", "3+4k", "++4i", "3 + 4i", "infi", "1+NaNi", "ij",
+        ] {
+            assert_eq!(None, Complex::parse(s), "{s}");
+        }
+    }
+}
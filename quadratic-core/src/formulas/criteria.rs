@@ -0,0 +1,363 @@
+//! Criteria for conditional functions such as `SUMIFS` and `MAXIFS`.
+//!
+//! A criterion is a value that may start with a comparison operator (`=`,
+//! `<>`, `<`, `>`, `<=`, or `>=`), such as `">=10"` or `"<>apple"`. If there
+//! is no operator, `=` is assumed. Text comparisons ignore case unless
+//! [`TextCompareMode::CaseSensitive`] is used, and equality
+//! comparisons on text support wildcards: `?` matches any single character, `*`
+//! matches any sequence of characters, and `~` escapes the next character.
+//!
+//! Criteria that are dates or times, such as `">=2021-03-14"`, are compared
+//! as date serial numbers, so they work on ranges of dates.
+
+use regex::{Regex, RegexBuilder};
+
+use super::order::TextKey;
+use super::*;
+
+/// Condition that a value may or may not satisfy.
+#[derive(Debug, Clone)]
+pub struct Criterion {
+    /// Comparison to perform.
+    op: CompareOp,
+    /// Value to compare against.
+    rhs: CriterionValue,
+    /// How text is compared.
+    text_compare: TextCompareMode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CompareOp {
+    Eql,
+    Neq,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+}
+impl CompareOp {
+    /// Splits a leading comparison operator off a string.
+    fn strip_prefix(s: &str) -> (Self, &str) {
+        // Check two-character operators first.
+        for (prefix, op) in [
+            ("<>", Self::Neq),
+            ("<=", Self::Lte),
+            (">=", Self::Gte),
+            ("=", Self::Eql),
+            ("<", Self::Lt),
+            (">", Self::Gt),
+        ] {
+            if let Some(rest) = s.strip_prefix(prefix) {
+                return (op, rest);
+            }
+        }
+        (Self::Eql, s)
+    }
+
+    fn compare<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Self::Eql => lhs == rhs,
+            Self::Neq => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Gt => lhs > rhs,
+            Self::Lte => lhs <= rhs,
+            Self::Gte => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CriterionValue {
+    /// Number to compare against numeric values.
+    Number(f64),
+    /// Text to compare against text values.
+    String(TextKey),
+    /// Pattern to match against text values.
+    Wildcard(Regex),
+}
+
+impl Criterion {
+    /// Constructs a criterion from a formula value. Dates are converted to
+    /// serial numbers in `system`, and text is compared according to
+    /// `text_compare`.
+    pub fn try_from_value(
+        value: &Spanned<Value>,
+        system: DateSystem,
+        text_compare: TextCompareMode,
+    ) -> FormulaResult<Self> {
+        let equal_to = |rhs| Self {
+            op: CompareOp::Eql,
+            rhs,
+            text_compare,
+        };
+        match &value.inner {
+            Value::Number(n) => Ok(equal_to(CriterionValue::Number(*n))),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(dt) => Ok(equal_to(CriterionValue::Number(
+                super::datetime::to_serial(dt),
+            ))),
+            Value::Bool(b) => Ok(equal_to(CriterionValue::String(
+                text_compare.key(&Value::Bool(*b).to_string()),
+            ))),
+            Value::String(s) => Ok(Self::parse(s, system, text_compare)),
+            Value::Blank => Ok(Self::parse("", system, text_compare)),
+            Value::Error(e) => Err(e.clone().with_span(value.span)),
+            Value::Array(_) => Self::try_from_value(&value.to_scalar()?, system, text_compare),
+            Value::Lambda(_) => Err(FormulaErrorMsg::Expected {
+                expected: "criterion".into(),
+                got: Some("lambda".into()),
+            }
+            .with_span(value.span)),
+        }
+    }
+
+    /// Parses a criterion from a string such as `">=10"` or `"<>a*"`. Dates
+    /// are converted to serial numbers in `system`, and text is compared
+    /// according to `text_compare`.
+    pub fn parse(s: &str, system: DateSystem, text_compare: TextCompareMode) -> Self {
+        let (op, rest) = CompareOp::strip_prefix(s);
+        let rhs = if let Some(n) = parse_number(rest) {
+            CriterionValue::Number(n)
+        } else if let Some(n) = parse_date_or_time(rest, system) {
+            CriterionValue::Number(n)
+        } else if matches!(op, CompareOp::Eql | CompareOp::Neq) && has_wildcards(rest) {
+            CriterionValue::Wildcard(wildcard_regex(rest, text_compare))
+        } else {
+            CriterionValue::String(text_compare.key(&unescape_wildcards(rest)))
+        };
+        Self {
+            op,
+            rhs,
+            text_compare,
+        }
+    }
+
+    /// Returns whether a value satisfies the criterion.
+    pub fn matches(&self, value: &Value) -> bool {
+        match &self.rhs {
+            CriterionValue::Number(rhs) => match value.as_number() {
+                Some(lhs) => self.op.compare(lhs, *rhs),
+                // Non-numeric values are never equal to a number.
+                None => self.op == CompareOp::Neq,
+            },
+            CriterionValue::String(rhs) => match self.op {
+                CompareOp::Eql | CompareOp::Neq => {
+                    let lhs = self.text_compare.key(&value.to_string());
+                    self.op.compare(&lhs, rhs)
+                }
+                // Numbers are never ordered relative to text.
+                _ if value.as_number().is_some() => false,
+                _ => self
+                    .op
+                    .compare(&self.text_compare.key(&value.to_string()), rhs),
+            },
+            CriterionValue::Wildcard(regex) => {
+                let is_match = regex.is_match(&value.to_string());
+                is_match == (self.op == CompareOp::Eql)
+            }
+        }
+    }
+}
+
+/// Arrangement of the arguments to a conditional aggregation function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CriteriaArgs {
+    /// `range, criterion, [eval_range]`, as in `SUMIF`. If `eval_range` is
+    /// omitted, the values are taken from `range`.
+    Single,
+    /// `eval_range, criteria_range, criterion, ...`, as in `SUMIFS`.
+    Multiple,
+    /// `criteria_range, criterion, ...`, as in `COUNTIFS`. The values are
+    /// taken from the first criteria range.
+    MultipleWithoutEvalRange,
+}
+
+/// Returns each value to aggregate for a conditional aggregation function,
+/// along with whether every criterion matches the corresponding value in its
+/// criteria range. Every criteria range must be the same size as the range of
+/// values.
+///
+/// All conditional aggregation functions should use this, so that they agree
+/// on which values are included.
+pub fn values_with_criteria(
+    args: &Spanned<Vec<Spanned<Value>>>,
+    layout: CriteriaArgs,
+    system: DateSystem,
+    text_compare: TextCompareMode,
+) -> FormulaResult<Vec<(&Value, bool)>> {
+    let args_slice = args.inner.as_slice();
+    let (eval_range, criteria) = match layout {
+        CriteriaArgs::Single if args_slice.len() <= 3 => (
+            args_slice.get(2).or(args_slice.first()),
+            args_slice.get(..2),
+        ),
+        CriteriaArgs::Single => (None, None),
+        CriteriaArgs::Multiple => (args_slice.first(), args_slice.get(1..)),
+        CriteriaArgs::MultipleWithoutEvalRange => (args_slice.first(), Some(args_slice)),
+    };
+    let (Some(eval_range), Some(criteria)) = (eval_range, criteria) else {
+        return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+    };
+    if criteria.is_empty() || criteria.len() % 2 != 0 {
+        return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+    }
+
+    let size = array_size_of(&eval_range.inner);
+    let mut included = vec![true; size.0 * size.1];
+    for pair in criteria.chunks(2) {
+        let [criteria_range, criterion] = pair else {
+            internal_error!("criteria must come in pairs");
+        };
+        let criteria_range_size = array_size_of(&criteria_range.inner);
+        if criteria_range_size != size {
+            return Err(FormulaErrorMsg::ArraySizeMismatch {
+                expected: size,
+                got: criteria_range_size,
+            }
+            .with_span(criteria_range.span));
+        }
+        let criterion = Criterion::try_from_value(criterion, system, text_compare)?;
+        for (inc, value) in included.iter_mut().zip(flat_values(&criteria_range.inner)) {
+            *inc &= criterion.matches(value);
+        }
+    }
+    Ok(flat_values(&eval_range.inner).zip(included).collect())
+}
+
+/// Returns the size of an array, treating non-array values as 1x1 arrays.
+fn array_size_of(value: &Value) -> (usize, usize) {
+    value.array_size().unwrap_or((1, 1))
+}
+/// Iterates over the values in an array in row-major order, treating non-array
+/// values as 1x1 arrays.
+fn flat_values(value: &Value) -> Box<dyn '_ + Iterator<Item = &'_ Value>> {
+    match value {
+        Value::Array(a) => Box::new(a.iter()),
+        other => Box::new(std::iter::once(other)),
+    }
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parses a date, such as `2021-03-14`, as a serial number in `system`, or a
+/// time of day, such as `12:00`, as a fraction of a day.
+fn parse_date_or_time(s: &str, system: DateSystem) -> Option<f64> {
+    let s = s.trim();
+    match parse_date(s) {
+        Some(serial) => Some(system.from_1900(serial) as f64),
+        None => parse_time(s),
+    }
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?'])
+}
+/// Removes `~` escapes from a string.
+fn unescape_wildcards(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => ret.extend(chars.next()),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+/// Constructs a regex that matches a whole string against a wildcard pattern,
+/// ignoring case unless `text_compare` is case-sensitive.
+pub(crate) fn wildcard_regex(pattern: &str, text_compare: TextCompareMode) -> Regex {
+    let mut regex_str = "^".to_string();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex_str.push_str(".*"),
+            '?' => regex_str.push('.'),
+            '~' => {
+                if let Some(next) = chars.next() {
+                    regex_str.push_str(&regex::escape(&next.to_string()));
+                }
+            }
+            c => regex_str.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex_str.push('$');
+    RegexBuilder::new(&regex_str)
+        .case_insensitive(text_compare == TextCompareMode::CaseInsensitive)
+        .dot_matches_new_line(true)
+        .build()
+        .expect("escaped wildcard pattern should be a valid regex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(criterion: &str, value: Value) -> bool {
+        let text_compare = TextCompareMode::default();
+        Criterion::parse(criterion, DateSystem::default(), text_compare).matches(&value)
+    }
+    fn s(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_criteria() {
+        assert!(matches("5", Value::Number(5.0)));
+        assert!(matches("5", s("5")));
+        assert!(!matches("5", s("five")));
+        assert!(matches(">=5", s("7")));
+        assert!(!matches(">=5", s("4")));
+        assert!(!matches(">=5", s("apple")));
+        assert!(matches("<>5", s("apple")));
+
+        assert!(matches("apple", s("APPLE")));
+        assert!(matches("<>apple", s("pear")));
+        assert!(matches("<b", s("apple")));
+        assert!(!matches("<b", s("1")));
+
+        assert!(matches("a*", s("apple")));
+        assert!(!matches("a*", s("pear")));
+        assert!(matches("<>a*", s("pear")));
+        assert!(matches("?ear", s("pear")));
+        assert!(!matches("?ear", s("spear")));
+        assert!(matches("what~?", s("what?")));
+        assert!(!matches("what~?", s("whats")));
+
+        assert!(matches("", s("")));
+        assert!(matches("=", s("")));
+        assert!(!matches("=", s("x")));
+        assert!(matches("<>", s("x")));
+
+        // Dates and times compare as numbers.
+        assert!(matches(">=2021-01-01", Value::Number(44197.0)));
+        assert!(!matches(">=2021-01-01", Value::Number(44196.0)));
+        assert!(matches("1/1/2021", s("44197")));
+        assert!(matches("<12:00", Value::Number(0.25)));
+        assert!(!matches(">=2021-01-01", s("2021-01-02")));
+        let text_compare = TextCompareMode::default();
+        let criterion = Criterion::parse(">=2021-01-01", DateSystem::Excel1904, text_compare);
+        assert!(criterion.matches(&Value::Number(44197.0 - 1462.0)));
+        assert!(!criterion.matches(&Value::Number(44196.0 - 1462.0)));
+
+        // Case-sensitive criteria.
+        let matches_exactly = |criterion: &str, value: Value| {
+            let text_compare = TextCompareMode::CaseSensitive;
+            Criterion::parse(criterion, DateSystem::default(), text_compare).matches(&value)
+        };
+        assert!(matches_exactly("apple", s("apple")));
+        assert!(!matches_exactly("apple", s("APPLE")));
+        assert!(matches_exactly("<>apple", s("APPLE")));
+        assert!(matches_exactly("a*", s("apple")));
+        assert!(!matches_exactly("a*", s("Apple")));
+        assert!(matches_exactly("<b", s("Apple")));
+        assert!(matches_exactly("<apple", s("Apple")));
+        assert!(matches_exactly("5", s("5")));
+    }
+}
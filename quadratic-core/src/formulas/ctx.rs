@@ -0,0 +1,338 @@
+use super::lambda::Binding;
+use super::*;
+
+/// Default maximum number of evaluation steps for a single formula.
+pub const DEFAULT_MAX_EVAL_STEPS: u64 = 10_000_000;
+/// Default maximum number of elements in an array produced by a formula.
+pub const DEFAULT_MAX_ARRAY_SIZE: u64 = 5_000_000;
+/// Default maximum length of text produced by a formula, in bytes. This is
+/// the maximum number of characters in a cell in Excel.
+pub const DEFAULT_MAX_TEXT_LENGTH: u64 = 32_767;
+/// Default maximum depth of nested expressions during evaluation. This is
+/// more than the parser allows, since a chain of operators such as `1+2+3`
+/// nests without parentheses.
+pub const DEFAULT_MAX_EVAL_DEPTH: usize = 512;
+/// Maximum number of nested calls to `LAMBDA`s. Each call uses more stack
+/// space than other expressions, so a recursive `LAMBDA` is limited more
+/// strictly than the evaluation depth.
+const MAX_CALL_DEPTH: usize = 64;
+
+/// Formula evaluation context.
+pub struct Ctx<'ctx> {
+    /// Grid to read cell values from.
+    pub grid: &'ctx mut dyn GridProxy,
+    /// Position of the cell where the formula is being evaluated.
+    pub pos: Pos,
+    /// Settings that affect evaluation.
+    pub settings: EvalSettings,
+    /// Functions defined by the host application, which formulas can call
+    /// in addition to the built-in functions.
+    pub custom_functions: Option<&'ctx CustomFunctions>,
+
+    /// Number of evaluation steps taken so far.
+    steps: u64,
+    /// Number of expressions being evaluated that contain the current one.
+    depth: usize,
+    /// Number of calls to `LAMBDA`s that contain the current expression.
+    call_depth: usize,
+    /// Value of each sub-expression evaluated so far, if tracing is enabled.
+    trace: Option<Vec<(Span, Value)>>,
+    /// Warnings about the formula so far, if collecting warnings is enabled.
+    warnings: Option<Vec<FormulaWarning>>,
+    /// Random number generator for functions such as `RANDARRAY`.
+    rng: Rng,
+    /// Names bound by `LET` and `LAMBDA` where the expression being evaluated
+    /// is, innermost last.
+    pub(crate) bindings: Vec<Binding>,
+    /// Cells read so far that the grid reported as pending, in the order they
+    /// were first read.
+    pending_cells: Vec<Pos>,
+}
+impl<'ctx> Ctx<'ctx> {
+    /// Constructs a context for evaluating a formula at `pos` in `grid`, using
+    /// the default settings.
+    pub fn new(grid: &'ctx mut dyn GridProxy, pos: Pos) -> Self {
+        Self::with_settings(grid, pos, EvalSettings::default())
+    }
+    /// Constructs a context for evaluating a formula at `pos` in `grid`.
+    pub fn with_settings(grid: &'ctx mut dyn GridProxy, pos: Pos, settings: EvalSettings) -> Self {
+        Ctx {
+            grid,
+            pos,
+            settings,
+            custom_functions: None,
+
+            steps: 0,
+            depth: 0,
+            call_depth: 0,
+            trace: None,
+            warnings: None,
+            rng: match settings.random_seed {
+                Some(seed) => Rng::from_seed(seed),
+                None => Rng::from_entropy(),
+            },
+            bindings: vec![],
+            pending_cells: vec![],
+        }
+    }
+
+    /// Returns the number of evaluation steps taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    /// Returns the random number generator.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Starts recording the value of each sub-expression as it is evaluated,
+    /// discarding anything recorded so far.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(vec![]);
+    }
+    /// Stops recording sub-expression values and returns what was recorded,
+    /// in the order that evaluation finished. Returns an empty list if
+    /// tracing was not enabled.
+    pub fn take_trace(&mut self) -> Vec<(Span, Value)> {
+        self.trace.take().unwrap_or_default()
+    }
+    /// Records the result of evaluating a sub-expression, if tracing is
+    /// enabled. An error is recorded only where it first occurred, not again
+    /// for each expression it propagates through.
+    pub(crate) fn trace_result(&mut self, span: Span, result: &FormulaResult<Spanned<Value>>) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        match result {
+            Ok(value) => trace.push((span, value.inner.clone())),
+            Err(e) => {
+                let entry = (e.span.unwrap_or(span), Value::Error(e.msg.clone()));
+                if trace.last() != Some(&entry) {
+                    trace.push(entry);
+                }
+            }
+        }
+    }
+
+    /// Starts collecting warnings, discarding any collected so far.
+    pub fn start_warnings(&mut self) {
+        self.warnings = Some(vec![]);
+    }
+    /// Stops collecting warnings and returns what was collected. Returns an
+    /// empty list if collecting warnings was not enabled.
+    pub fn take_warnings(&mut self) -> Vec<FormulaWarning> {
+        self.warnings.take().unwrap_or_default()
+    }
+    /// Returns whether warnings are being collected, so that checks for them
+    /// can be skipped otherwise.
+    pub(crate) fn is_collecting_warnings(&self) -> bool {
+        self.warnings.is_some()
+    }
+    /// Records a warning, if collecting warnings is enabled. A warning that
+    /// has already been recorded is not recorded again.
+    pub(crate) fn warn(&mut self, warning: FormulaWarning) {
+        if let Some(warnings) = &mut self.warnings {
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+    }
+
+    /// Returns the cells read so far that the grid reported as pending.
+    pub fn pending_cells(&self) -> &[Pos] {
+        &self.pending_cells
+    }
+    /// Records that a pending cell was read.
+    pub(crate) fn mark_pending(&mut self, pos: Pos) {
+        if !self.pending_cells.contains(&pos) {
+            self.pending_cells.push(pos);
+        }
+    }
+
+    /// Returns the innermost binding of a name, ignoring case.
+    pub(crate) fn lookup_name(&self, name: &str) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|binding| binding.has_name(name))
+    }
+    /// Returns whether the innermost binding of a name is a lambda.
+    pub(crate) fn is_lambda_name(&self, name: &str) -> bool {
+        self.lookup_name(name)
+            .is_some_and(|binding| matches!(binding.value, Value::Lambda(_)))
+    }
+
+    /// Returns how operators should coerce their operands.
+    pub(crate) fn coercion(&self) -> Coercion {
+        Coercion::new(&self.settings)
+    }
+
+    /// Records `n` evaluation steps, returning an error if this exceeds the
+    /// maximum.
+    pub fn take_steps(&mut self, n: u64) -> FormulaResult<()> {
+        let limit = self.settings.max_eval_steps;
+        self.steps = self.steps.saturating_add(n);
+        if self.steps > limit {
+            return Err(FormulaErrorMsg::EvaluationLimitExceeded { limit }.into());
+        }
+        Ok(())
+    }
+    /// Records that evaluation is one expression deeper, returning an error
+    /// if this exceeds the maximum depth. Each successful call must be
+    /// followed by a call to `exit_nested()`.
+    pub(crate) fn enter_nested(&mut self) -> FormulaResult<()> {
+        if self.depth >= self.settings.max_eval_depth {
+            return Err(FormulaErrorMsg::NestingTooDeep.into());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    /// Records that evaluation of an expression has finished.
+    pub(crate) fn exit_nested(&mut self) {
+        self.depth -= 1;
+    }
+    /// Records a call to a `LAMBDA`, returning an error if calls are nested
+    /// too deeply. Each successful call must be followed by a call to
+    /// `exit_call()`.
+    pub(crate) fn enter_call(&mut self) -> FormulaResult<()> {
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(FormulaErrorMsg::NestingTooDeep.into());
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+    /// Records that a call to a `LAMBDA` has returned.
+    pub(crate) fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+    /// Returns an error if an array with the given number of rows and
+    /// columns would have more elements than the maximum. This should be
+    /// checked before the array is allocated.
+    pub fn check_array_size(&self, rows: u64, cols: u64) -> FormulaResult<()> {
+        let limit = self.settings.max_array_size;
+        if rows.saturating_mul(cols) > limit {
+            return Err(FormulaErrorMsg::ArrayTooLarge { limit }.into());
+        }
+        Ok(())
+    }
+    /// Returns an error if text with the given length in bytes would be
+    /// longer than the maximum. This should be checked before the text is
+    /// built, and again as it grows if its length isn't known in advance.
+    pub fn check_text_length(&self, len: usize) -> FormulaResult<()> {
+        let limit = self.settings.max_text_length;
+        if len as u64 > limit {
+            return Err(FormulaErrorMsg::TextTooLong { limit }.into());
+        }
+        Ok(())
+    }
+}
+
+/// Settings that affect formula evaluation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EvalSettings {
+    /// Maximum number of evaluation steps before giving up.
+    ///
+    /// Each AST node evaluated counts as one step, and each cell in a range
+    /// counts as one step.
+    pub max_eval_steps: u64,
+    /// Maximum number of elements in an array, including cell ranges, spill
+    /// ranges, and array literals.
+    pub max_array_size: u64,
+    /// Maximum length in bytes of text produced by an operator or function,
+    /// such as `&` or `REPT`.
+    pub max_text_length: u64,
+    /// Maximum depth of nested expressions during evaluation, including
+    /// chains of operators and calls to `LAMBDA`s. Deeper expressions fail
+    /// with [`FormulaErrorMsg::NestingTooDeep`] instead of overflowing the
+    /// stack.
+    pub max_eval_depth: usize,
+    /// Tolerance to use when comparing numbers, or `None` to compare numbers
+    /// exactly.
+    ///
+    /// This is `None` by default, so `0.1+0.2=0.3` is `FALSE`. Use
+    /// `Some(FloatTolerance::EXCEL)` to match Excel's behavior of comparing
+    /// numbers to 15 significant digits.
+    pub float_tolerance: Option<FloatTolerance>,
+    /// Whether comparison operators, lookups such as `XMATCH`, and criteria
+    /// such as those of `COUNTIF` distinguish text that differs only in case.
+    /// `EXACT` is always case-sensitive.
+    pub text_compare: TextCompareMode,
+    /// Which coercions operators reject, rather than silently coercing
+    /// values. This is [`Strictness::Off`] by default.
+    pub strictness: Strictness,
+    /// Whether `SUM`, `AVERAGE`, and other functions that ignore blanks read
+    /// text in ranges and arrays as a number when it looks like one, like
+    /// Google Sheets, rather than ignoring it, like Excel.
+    ///
+    /// This is `false` by default, so `SUM({"5", 1})` is 1. Either way, cells
+    /// that contain a number are read as numbers, and text passed directly as
+    /// an argument is read as a number, as in `SUM("5", 1)`. With this
+    /// enabled, text in a range that isn't a number is an error.
+    pub coerce_numeric_text: bool,
+    /// Seed for random functions such as `RANDARRAY`, or `None` to use an
+    /// unpredictable seed.
+    ///
+    /// With a seed, evaluating the same formula always produces the same
+    /// random numbers, which is useful for tests.
+    pub random_seed: Option<u64>,
+    /// Date system that date serial numbers are in, for workbooks that use
+    /// the 1904 date system.
+    pub date_system: DateSystem,
+    /// Whether to record the names of the functions that an error passes
+    /// through in [`FormulaError::call_trace`], for debugging deeply nested
+    /// formulas.
+    pub trace_function_calls: bool,
+    /// Unit that string functions such as `LEN` and `MID` count characters
+    /// in. This also determines how `PROPER` finds words.
+    pub text_unit: TextUnit,
+    /// Currency symbol and separators that `DOLLAR` and `FIXED` use.
+    pub number_locale: NumberLocale,
+}
+impl Default for EvalSettings {
+    fn default() -> Self {
+        Self {
+            max_eval_steps: DEFAULT_MAX_EVAL_STEPS,
+            max_array_size: DEFAULT_MAX_ARRAY_SIZE,
+            max_text_length: DEFAULT_MAX_TEXT_LENGTH,
+            max_eval_depth: DEFAULT_MAX_EVAL_DEPTH,
+            float_tolerance: None,
+            text_compare: TextCompareMode::default(),
+            strictness: Strictness::default(),
+            coerce_numeric_text: false,
+            random_seed: None,
+            date_system: DateSystem::default(),
+            trace_function_calls: false,
+            text_unit: TextUnit::default(),
+            number_locale: NumberLocale::default(),
+        }
+    }
+}
+
+/// Tolerance for comparing floating-point numbers. Two numbers are considered
+/// equal if they are within _either_ the absolute tolerance or the relative
+/// tolerance of each other.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FloatTolerance {
+    /// Maximum absolute difference between two numbers considered equal.
+    pub absolute: f64,
+    /// Maximum difference between two numbers considered equal, relative to
+    /// the larger magnitude of the two.
+    pub relative: f64,
+}
+impl FloatTolerance {
+    /// Tolerance that approximates Excel's behavior of comparing numbers to 15
+    /// significant digits.
+    pub const EXCEL: Self = Self {
+        absolute: 0.0,
+        relative: 1e-15,
+    };
+
+    /// Returns whether two numbers are equal within this tolerance.
+    pub fn approx_eq(self, a: f64, b: f64) -> bool {
+        if a == b {
+            return true; // handles infinities
+        }
+        let diff = (a - b).abs();
+        diff <= self.absolute || diff <= self.relative * f64::max(a.abs(), b.abs())
+    }
+}
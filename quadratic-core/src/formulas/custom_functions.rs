@@ -0,0 +1,103 @@
+//! Functions defined by the application embedding the formula engine, rather
+//! than built in.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::*;
+
+/// Function defined by the host application, which takes its arguments along
+/// with their spans.
+type CustomFn = dyn Fn(&[Spanned<Value>]) -> FormulaResult<Value> + Send + Sync;
+
+/// Set of functions defined by the host application, which formulas can call
+/// when evaluated with a [`Ctx`] that has them.
+///
+/// Names are case-insensitive. Built-in functions always take precedence, so
+/// a custom function cannot have the same name as a built-in function. Names
+/// bound to a `LAMBDA` take precedence over both.
+///
+/// Functions must be `Send` and `Sync` so that one set of custom functions can
+/// be shared by formulas evaluated on different threads. They are only ever
+/// called with a shared reference, so any mutable state needs its own
+/// synchronization.
+#[derive(Default)]
+pub struct CustomFunctions {
+    functions: HashMap<String, CustomFunction>,
+}
+impl fmt::Debug for CustomFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
+impl CustomFunctions {
+    /// Constructs an empty set of custom functions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function that takes at least `min_args` arguments and at
+    /// most `max_args` arguments, if there is a maximum. Replaces any custom
+    /// function with the same name.
+    ///
+    /// Returns `false` without registering the function if there is a
+    /// built-in function with the same name.
+    ///
+    /// The function can use [`FormulaErrorMsg::with_span()`] to report an error
+    /// for one of its arguments. Errors without a span are reported for the
+    /// whole function call.
+    pub fn register(
+        &mut self,
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        f: impl 'static + Fn(&[Spanned<Value>]) -> FormulaResult<Value> + Send + Sync,
+    ) -> bool {
+        if function_signature(name).is_some() {
+            return false;
+        }
+        let function = CustomFunction {
+            name: name.to_string(),
+            min_args,
+            max_args,
+            f: Box::new(f),
+        };
+        self.functions.insert(name.to_ascii_lowercase(), function);
+        true
+    }
+
+    /// Returns whether there is a custom function with the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the names of the custom functions, as they were registered.
+    pub fn names(&self) -> impl '_ + Iterator<Item = &str> {
+        self.functions
+            .values()
+            .map(|function| function.name.as_str())
+    }
+
+    /// Calls the custom function with the given name, or returns `None` if
+    /// there is none.
+    pub(crate) fn call(
+        &self,
+        name: &str,
+        args: &Spanned<Vec<Spanned<Value>>>,
+    ) -> Option<FormulaResult<Value>> {
+        let function = self.functions.get(&name.to_ascii_lowercase())?;
+        let n = args.inner.len();
+        if n < function.min_args || function.max_args.is_some_and(|max| n > max) {
+            return Some(Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span)));
+        }
+        Some((function.f)(&args.inner).map_err(|e| e.with_span(args.span)))
+    }
+}
+
+struct CustomFunction {
+    /// Name as it was registered.
+    name: String,
+    min_args: usize,
+    max_args: Option<usize>,
+    f: Box<CustomFn>,
+}
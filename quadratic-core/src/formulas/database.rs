@@ -0,0 +1,96 @@
+//! Database functions such as `DSUM` and `DGET`.
+//!
+//! A database is a range whose first row has the name of each field, and
+//! whose other rows are records. The criteria are also a range whose first
+//! row has field names, and whose other rows each have a criterion under some
+//! of those names, in the same form as for `SUMIF`. A record matches a row of
+//! criteria if it matches every criterion in that row, and it is included if
+//! it matches any row. A blank criterion matches everything.
+
+use super::criteria::Criterion;
+use super::functions::exact_args;
+use super::*;
+
+/// Evaluates the arguments `database, field, criteria` of a database
+/// function, returning the value of the field in each record that matches the
+/// criteria. The field is a field name, ignoring case, or a column number
+/// starting at 1. Field names that aren't in the database are bad references.
+pub fn matching_values(
+    args: Spanned<Vec<Spanned<Value>>>,
+    system: DateSystem,
+    text_compare: TextCompareMode,
+) -> FormulaResult<Vec<Value>> {
+    let [database, field, criteria] = exact_args(args)?;
+    let (headers, records) = split_headers(&database)?;
+    let column = field_index(&headers, &field)?;
+
+    // Each row of criteria becomes a list of conditions on columns of the
+    // database. Blank criteria are left out, since they match anything, and so
+    // are columns of criteria without a field name.
+    let (criteria_headers, criteria_rows) = split_headers(&criteria)?;
+    let mut conditions = vec![vec![]; criteria_rows.len()];
+    for (i, header) in criteria_headers.iter().enumerate() {
+        if header.is_blank_or_empty() {
+            continue;
+        }
+        let column = column_named(&headers, &header.to_string())
+            .ok_or_else(|| FormulaErrorMsg::BadCellReference.with_span(criteria.span))?;
+        for (row, row_conditions) in std::iter::zip(&criteria_rows, &mut conditions) {
+            if !row[i].is_blank_or_empty() {
+                let criterion = Spanned {
+                    span: criteria.span,
+                    inner: row[i].clone(),
+                };
+                let criterion = Criterion::try_from_value(&criterion, system, text_compare)?;
+                row_conditions.push((column, criterion));
+            }
+        }
+    }
+
+    Ok(records
+        .into_iter()
+        .filter(|record| {
+            conditions.iter().any(|row_conditions| {
+                row_conditions
+                    .iter()
+                    .all(|(column, criterion)| criterion.matches(&record[*column]))
+            })
+        })
+        .map(|mut record| record.swap_remove(column))
+        .collect())
+}
+
+/// Splits a range into its first row and the rest of its rows. Returns an
+/// error if it has no rows other than the first.
+fn split_headers(range: &Spanned<Value>) -> FormulaResult<(Vec<Value>, Vec<Vec<Value>>)> {
+    let mut rows = match &range.inner {
+        Value::Array(a) => a.clone().into_rows().into_iter(),
+        _ => return Err(FormulaErrorMsg::InvalidArgument.with_span(range.span)),
+    };
+    match rows.next() {
+        Some(headers) if rows.len() > 0 => Ok((headers, rows.collect())),
+        _ => Err(FormulaErrorMsg::InvalidArgument.with_span(range.span)),
+    }
+}
+
+/// Returns the index of the column for a field, which is either a field name
+/// or a column number starting at 1. Returns an error like that of a bad
+/// reference if there is no field with the name.
+fn field_index(headers: &[Value], field: &Spanned<Value>) -> FormulaResult<usize> {
+    field.check_error()?;
+    match &field.inner {
+        Value::Number(n) => Some(n.trunc() as usize)
+            .filter(|&n| (1..=headers.len()).contains(&n))
+            .map(|n| n - 1)
+            .ok_or_else(|| FormulaErrorMsg::InvalidArgument.with_span(field.span)),
+        other => column_named(headers, &other.to_string())
+            .ok_or_else(|| FormulaErrorMsg::BadCellReference.with_span(field.span)),
+    }
+}
+
+/// Returns the index of the column with a field name, ignoring case.
+fn column_named(headers: &[Value], name: &str) -> Option<usize> {
+    headers
+        .iter()
+        .position(|header| header.to_string().eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,823 @@
+//! Date serial numbers.
+//!
+//! Like Excel, dates are represented as the number of days since 1899-12-31,
+//! so that 1 is 1900-01-01. For compatibility with Lotus 1-2-3, Excel treats
+//! 1900 as a leap year, and we replicate this so that serial numbers match
+//! Excel's exactly:
+//!
+//! - 1 through 59 are 1900-01-01 through 1900-02-28.
+//! - 60 is the nonexistent date 1900-02-29. It is accepted as a date, such as
+//!   by `DATEVALUE("1900-02-29")` and `DATE(1900, 2, 29)`, and `YEAR`,
+//!   `MONTH`, and `DAY` return its parts.
+//! - 61 is 1900-03-01, and every later date is one day later than it would
+//!   be without the bug.
+//! - 0 is 1900-01-00, which `DATE(1900, 1, 0)` returns and which formats as
+//!   1900-01-00.
+//!
+//! Because of this, days of the week are also off by one before 1900-03-01;
+//! 1900-01-01 is treated as a Sunday.
+//!
+//! Workbooks can instead use the 1904 date system, where 0 is 1904-01-01 and
+//! there is no leap year bug, since it has no dates before 1904. Every date
+//! in the 1904 system is therefore exactly 1462 days earlier than in the 1900
+//! system, so converting between them is a constant offset.
+//!
+//! Functions in this module work with serial numbers in the 1900 system;
+//! `to_serial()` and `serial_value()` convert from and to the date system of
+//! the formula, which is the same throughout an evaluation.
+
+use itertools::Itertools;
+
+use super::*;
+
+/// Serial number of the nonexistent date 1900-02-29.
+const LEAP_BUG_SERIAL: i64 = 60;
+/// Largest valid serial number, which is 9999-12-31.
+pub const MAX_SERIAL: i64 = 2_958_465;
+
+/// Date system, which determines which date each serial number represents.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DateSystem {
+    /// Serial number 1 is 1900-01-01, and 1900 is treated as a leap year.
+    /// This is Excel's default.
+    #[default]
+    Excel1900,
+    /// Serial number 0 is 1904-01-01. This was the default in old versions of
+    /// Excel for Mac.
+    Excel1904,
+}
+impl DateSystem {
+    /// Returns the serial number of the first day in this date system, in the
+    /// 1900 system.
+    fn epoch(self) -> i64 {
+        match self {
+            DateSystem::Excel1900 => 0,
+            // 1904-01-01
+            DateSystem::Excel1904 => 1462,
+        }
+    }
+    /// Returns the largest valid serial number in this date system, which is
+    /// 9999-12-31.
+    pub fn max_serial(self) -> i64 {
+        MAX_SERIAL - self.epoch()
+    }
+    /// Converts a serial number in this date system to the 1900 system.
+    pub fn to_1900(self, serial: i64) -> i64 {
+        serial + self.epoch()
+    }
+    /// Converts a serial number in the 1900 system to this date system.
+    pub fn from_1900(self, serial: i64) -> i64 {
+        serial - self.epoch()
+    }
+}
+
+/// Calendar date in the proleptic Gregorian calendar.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Date {
+    pub year: i64,
+    /// Month from 1 to 12.
+    pub month: u32,
+    /// Day of the month from 1 to 31.
+    pub day: u32,
+}
+impl Date {
+    /// Constructs a date from its components.
+    pub fn new(year: i64, month: u32, day: u32) -> Self {
+        Self { year, month, day }
+    }
+
+    /// Returns the date represented by a serial number. Serial number 0 is
+    /// 1899-12-31.
+    pub fn from_serial(serial: i64) -> Self {
+        match serial {
+            LEAP_BUG_SERIAL => Self::new(1900, 2, 29),
+            s if s < LEAP_BUG_SERIAL => Self::from_days(s + EPOCH_BEFORE_LEAP_BUG),
+            s => Self::from_days(s + EPOCH_AFTER_LEAP_BUG),
+        }
+    }
+    /// Returns the serial number of the date.
+    pub fn to_serial(self) -> i64 {
+        if (self.year, self.month, self.day) == (1900, 2, 29) {
+            return LEAP_BUG_SERIAL;
+        }
+        let serial = self.to_days() - EPOCH_BEFORE_LEAP_BUG;
+        if serial < LEAP_BUG_SERIAL {
+            serial
+        } else {
+            self.to_days() - EPOCH_AFTER_LEAP_BUG
+        }
+    }
+
+    /// Returns the date `months` months later, keeping the same day of the
+    /// month unless that month is too short, in which case the last day of
+    /// the month is used instead.
+    pub fn add_months(self, months: i64) -> Self {
+        let month_index = self.year * 12 + self.month as i64 - 1 + months;
+        let year = month_index.div_euclid(12);
+        let month = month_index.rem_euclid(12) as u32 + 1;
+        let day = self.day.min(days_in_month(year, month));
+        Self { year, month, day }
+    }
+    /// Returns the last day of the month.
+    pub fn end_of_month(self) -> Self {
+        let day = days_in_month(self.year, self.month);
+        Self { day, ..self }
+    }
+    /// Returns whether this is the last day of the month.
+    pub fn is_end_of_month(self) -> bool {
+        self.day == days_in_month(self.year, self.month)
+    }
+
+    /// Returns the number of days since 1970-01-01.
+    fn to_days(self) -> i64 {
+        // Algorithm from http://howardhinnant.github.io/date_algorithms.html
+        let y = self.year - (self.month <= 2) as i64;
+        let era = y.div_euclid(400);
+        let yoe = y.rem_euclid(400);
+        let m = self.month as i64;
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+    /// Returns the date that is a number of days since 1970-01-01.
+    fn from_days(days: i64) -> Self {
+        // Algorithm from http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Self { year, month, day }
+    }
+}
+
+/// Returns the serial number in the 1900 system of a date given as a year,
+/// month, and day, like `DATE`. A month outside 1 to 12 rolls over into other
+/// years, and a day outside the month rolls over into other months, so day 0
+/// is the last day of the previous month.
+///
+/// Days are counted from the first of the month, so `(1900, 2, 29)` is the
+/// nonexistent 1900-02-29 and `(1900, 2, 30)` is 1900-03-01, like in Excel.
+pub fn serial_from_parts(year: i64, month: i64, day: i64) -> i64 {
+    let first_of_month = Date::new(year, 1, 1).add_months(month - 1);
+    first_of_month.to_serial() + day - 1
+}
+
+/// Days from 1970-01-01 to 1899-12-31, which is serial number 0.
+const EPOCH_BEFORE_LEAP_BUG: i64 = -25568;
+/// Days from 1970-01-01 to 1899-12-30, which is where serial numbers after
+/// the leap year bug count from.
+const EPOCH_AFTER_LEAP_BUG: i64 = -25569;
+
+/// Parses a date, returning its serial number, or `None` if the string is not
+/// a valid date from 1900-01-01 to 9999-12-31. Leading and trailing whitespace
+/// is ignored. The accepted formats are:
+///
+/// - `YYYY-MM-DD`, such as `2021-03-14` (ISO 8601)
+/// - `M/D/YYYY`, such as `3/14/2021` (US)
+/// - `D-Mon-YYYY`, such as `14-Mar-2021`, where the month is either an English
+///   month name or its three-letter abbreviation, ignoring case
+///
+/// Months and days may have one or two digits, and years must have four
+/// digits. Other formats, such as `14/3/2021` and `03-14-2021`, are rejected
+/// because they are ambiguous.
+pub fn parse_date(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (year, month, day) = if let [a, b, c] = *s.split('-').collect_vec() {
+        match digits(b, 1..=2) {
+            Some(month) => (digits(a, 4..=4)?, month, digits(c, 1..=2)?),
+            None => (digits(c, 4..=4)?, month_from_name(b)?, digits(a, 1..=2)?),
+        }
+    } else if let [m, d, y] = *s.split('/').collect_vec() {
+        (digits(y, 4..=4)?, digits(m, 1..=2)?, digits(d, 1..=2)?)
+    } else {
+        return None;
+    };
+
+    let is_leap_bug_date = (year, month, day) == (1900, 2, 29);
+    let is_valid_day =
+        (1..=12).contains(&month) && (1..=days_in_month(year as i64, month)).contains(&day);
+    if year < 1900 || !(is_valid_day || is_leap_bug_date) {
+        return None;
+    }
+    Some(Date::new(year as i64, month, day).to_serial())
+}
+/// Parses a time of day, returning it as a fraction of a day from 0 to 1, or
+/// `None` if the string is not a valid time. Leading and trailing whitespace
+/// is ignored. The accepted formats are:
+///
+/// - `H:MM`, such as `18:30`
+/// - `H:MM:SS`, such as `18:30:05`
+/// - `H:MM:SS.sss`, with any number of digits after the decimal point
+///
+/// Hours may have one or two digits, and must be from 0 to 23 unless followed
+/// by `AM` or `PM` (ignoring case and whitespace), in which case they must be
+/// from 1 to 12.
+pub fn parse_time(s: &str) -> Option<f64> {
+    let mut s = s.trim();
+    let mut is_pm = None;
+    for (suffix, pm) in [("am", false), ("pm", true)] {
+        let len = s.len().saturating_sub(suffix.len());
+        if s.get(len..)
+            .is_some_and(|end| end.eq_ignore_ascii_case(suffix))
+        {
+            s = s[..len].trim_end();
+            is_pm = Some(pm);
+            break;
+        }
+    }
+
+    let (hours, minutes, seconds) = match *s.split(':').collect_vec() {
+        [h, m] => (digits(h, 1..=2)?, digits(m, 2..=2)?, 0.0),
+        [h, m, sec] => {
+            let (whole, frac) = sec.split_once('.').unwrap_or((sec, "0"));
+            digits(whole, 2..=2)?;
+            if frac.is_empty() || !frac.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            (digits(h, 1..=2)?, digits(m, 2..=2)?, sec.parse().ok()?)
+        }
+        _ => return None,
+    };
+
+    let hours = match is_pm {
+        None if hours < 24 => hours,
+        Some(pm) if (1..=12).contains(&hours) => hours % 12 + if pm { 12 } else { 0 },
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60.0 {
+        return None;
+    }
+    Some((hours as f64 * 3600.0 + minutes as f64 * 60.0 + seconds) / 86400.0)
+}
+/// Parses a string of ASCII digits whose length is in `len`.
+fn digits(s: &str, len: std::ops::RangeInclusive<usize>) -> Option<u32> {
+    if !len.contains(&s.len()) || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+/// English month names.
+pub const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+/// English names of the days of the week, starting with Sunday.
+pub const WEEKDAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Returns the month number for an English month name or abbreviation.
+fn month_from_name(s: &str) -> Option<u32> {
+    let i = MONTH_NAMES.iter().position(|name| {
+        name.eq_ignore_ascii_case(s) || (s.len() == 3 && name[..3].eq_ignore_ascii_case(s))
+    })?;
+    Some(i as u32 + 1)
+}
+
+/// Returns whether a year has 366 days.
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Returns the number of days in a month.
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the day of the week of a serial number, from 0 (Sunday) to 6
+/// (Saturday). Like Excel, this treats 1900-01-01 as a Sunday.
+pub fn weekday(serial: i64) -> u32 {
+    (serial - 1).rem_euclid(7) as u32
+}
+
+/// Converts a value to a date serial number in the 1900 system, discarding
+/// any time of day. Returns an error if the value is not a valid date in
+/// `system`.
+pub fn to_serial(value: &Spanned<Value>, system: DateSystem) -> FormulaResult<i64> {
+    // Date-times always convert to serial numbers in the 1900 system.
+    #[cfg(feature = "datetime")]
+    if let Value::DateTime(dt) = &value.inner {
+        return Ok(super::datetime::to_serial(dt).floor() as i64);
+    }
+    let n = value.to_number()?.floor();
+    if !(0.0..=system.max_serial() as f64).contains(&n) {
+        return Err(FormulaErrorMsg::InvalidDate.with_span(value.span));
+    }
+    Ok(system.to_1900(n as i64))
+}
+
+/// Converts a number of months to add to a date, returning an error if the
+/// result would certainly be out of range.
+pub fn to_months(value: &Spanned<Value>) -> FormulaResult<i64> {
+    let n = value.to_number()?.trunc();
+    // There are fewer than 10,000 years of valid dates.
+    if n.abs() > 12.0 * 10_000.0 {
+        return Err(FormulaErrorMsg::InvalidDate.with_span(value.span));
+    }
+    Ok(n as i64)
+}
+/// Returns a date serial number in the 1900 system as a value in `system`, or
+/// an error if it is out of range.
+pub fn serial_value(serial: i64, system: DateSystem, span: Span) -> FormulaResult<Value> {
+    let serial = system.from_1900(serial);
+    if !(0..=system.max_serial()).contains(&serial) {
+        return Err(FormulaErrorMsg::InvalidDate.with_span(span));
+    }
+    Ok(Value::Number(serial as f64))
+}
+
+/// Days of the week that are not business days.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Weekend {
+    /// Whether each day is a weekend day, indexed by `weekday()`.
+    days: [bool; 7],
+}
+impl Default for Weekend {
+    fn default() -> Self {
+        Self::from_weekdays(&[0, 6])
+    }
+}
+impl Weekend {
+    fn from_weekdays(weekdays: &[u32]) -> Self {
+        let mut days = [false; 7];
+        for &d in weekdays {
+            days[d as usize] = true;
+        }
+        Self { days }
+    }
+
+    /// Constructs a weekend from a formula value, which is either a string of
+    /// seven `0`s and `1`s for Monday through Sunday, where `1` is a weekend
+    /// day, or a number code like Excel's. Codes 1 through 7 are pairs of
+    /// days starting with Saturday and Sunday, and codes 11 through 17 are
+    /// single days starting with Sunday.
+    pub fn try_from_value(value: &Spanned<Value>) -> FormulaResult<Self> {
+        let invalid = || FormulaErrorMsg::InvalidArgument.with_span(value.span);
+
+        if let Value::String(s) = &value.inner {
+            if s.len() == 7 && s.chars().all(|c| c == '0' || c == '1') {
+                if !s.contains('0') {
+                    // There must be at least one business day.
+                    return Err(invalid());
+                }
+                let mut days = [false; 7];
+                for (i, c) in s.chars().enumerate() {
+                    // The string starts on Monday.
+                    days[(i + 1) % 7] = c == '1';
+                }
+                return Ok(Self { days });
+            }
+        }
+
+        Ok(match value.to_integer()? {
+            // Pairs of days, starting with Saturday and Sunday.
+            n @ 1..=7 => Self::from_weekdays(&[(n as u32 + 5) % 7, (n as u32 + 6) % 7]),
+            // Single days, starting with Sunday.
+            n @ 11..=17 => Self::from_weekdays(&[(n - 11) as u32]),
+            _ => return Err(invalid()),
+        })
+    }
+
+    /// Returns whether a date falls on the weekend.
+    pub fn contains(self, serial: i64) -> bool {
+        self.days[weekday(serial) as usize]
+    }
+    /// Returns the number of business days in each week.
+    fn workdays_per_week(self) -> i64 {
+        self.days.iter().filter(|&&is_weekend| !is_weekend).count() as i64
+    }
+}
+
+/// Returns the number of business days from `start` to `end`, inclusive. If
+/// `end` is before `start`, the result is negative. `holidays` must be sorted.
+///
+/// Business days are counted a week at a time, so this takes time
+/// proportional to the number of holidays rather than the number of days.
+pub fn workdays_between(start: i64, end: i64, weekend: Weekend, holidays: &[i64]) -> i64 {
+    if end < start {
+        return -workdays_between(end, start, weekend, holidays);
+    }
+    let days = end - start + 1;
+    let weeks = days / 7;
+    let rest_start = start + weeks * 7;
+    let rest = (rest_start..=end).filter(|&d| !weekend.contains(d)).count() as i64;
+    let holidays = holidays
+        .iter()
+        .dedup()
+        .filter(|&&h| (start..=end).contains(&h) && !weekend.contains(h))
+        .count() as i64;
+    weeks * weekend.workdays_per_week() + rest - holidays
+}
+/// Returns the date that is `days` business days after `start`, or `None` if
+/// that is out of range. `holidays` must be sorted.
+///
+/// Like `workdays_between()`, this takes time proportional to the number of
+/// holidays rather than the number of days.
+pub fn add_workdays(start: i64, days: i64, weekend: Weekend, holidays: &[i64]) -> Option<i64> {
+    // Every business day is a different date.
+    if days.unsigned_abs() > MAX_SERIAL as u64 {
+        return None;
+    }
+    let mut serial = add_weekdays(start, days, weekend);
+    // Each holiday that was passed over moves the result one more business
+    // day along, which may pass over later holidays.
+    if days > 0 {
+        for &h in holidays.iter().dedup() {
+            if start < h && h <= serial && !weekend.contains(h) {
+                serial = add_weekdays(serial, 1, weekend);
+            }
+        }
+    } else {
+        for &h in holidays.iter().rev().dedup() {
+            if serial <= h && h < start && !weekend.contains(h) {
+                serial = add_weekdays(serial, -1, weekend);
+            }
+        }
+    }
+    (0..=MAX_SERIAL).contains(&serial).then_some(serial)
+}
+/// Returns the date that is `days` days after `start`, not counting days on
+/// the weekend.
+fn add_weekdays(start: i64, days: i64, weekend: Weekend) -> i64 {
+    let step = days.signum();
+    let mut remaining = days.abs();
+    // Every 7 days have the same number of business days, so skip whole
+    // weeks at a time. Leave at least one day so that the result is a
+    // business day.
+    let weeks = (remaining - 1).max(0) / weekend.workdays_per_week();
+    let mut serial = start + step * weeks * 7;
+    remaining -= weeks * weekend.workdays_per_week();
+    while remaining > 0 {
+        serial += step;
+        if !weekend.contains(serial) {
+            remaining -= 1;
+        }
+    }
+    serial
+}
+
+/// Returns the week number of a date, where week 1 is the week containing
+/// January 1st and weeks begin on `week_start` (0 = Sunday, 6 = Saturday).
+pub fn week_number(serial: i64, week_start: u32) -> u32 {
+    let year_start = Date::new(Date::from_serial(serial).year, 1, 1).to_serial();
+    let offset = (weekday(year_start) + 7 - week_start) % 7;
+    ((serial - year_start) as u32 + offset) / 7 + 1
+}
+
+/// Returns the ISO 8601 week number of a date, where weeks begin on Monday and
+/// week 1 is the week containing the first Thursday of the year.
+pub fn iso_week_number(serial: i64) -> u32 {
+    // The week belongs to whichever year its Thursday is in.
+    let days_since_monday = (weekday(serial) + 6) % 7;
+    let thursday = serial - days_since_monday as i64 + 3;
+    let year_start = Date::new(Date::from_serial(thursday).year, 1, 1).to_serial();
+    ((thursday - year_start) / 7 + 1) as u32
+}
+
+/// Returns the number of days between two dates in a 360-day year of twelve
+/// 30-day months, as in `DAYS360`.
+///
+/// With the US method, a start date on the last day of February or the 31st
+/// becomes the 30th, and an end date on the 31st becomes the 30th only if
+/// the start date is on or after the 30th. With the European method, any
+/// date on the 31st becomes the 30th.
+pub fn days_360(start: i64, end: i64, european: bool) -> i64 {
+    let start = Date::from_serial(start);
+    let end = Date::from_serial(end);
+    let mut start_day = start.day;
+    let mut end_day = end.day;
+    if european {
+        start_day = start_day.min(30);
+        end_day = end_day.min(30);
+    } else {
+        if start_day == 31 || (start.month == 2 && start.is_end_of_month()) {
+            start_day = 30;
+        }
+        if end_day == 31 && start_day >= 30 {
+            end_day = 30;
+        }
+    }
+    days_360_between(start, start_day, end, end_day)
+}
+
+/// Returns the number of days between two dates given the adjusted days of
+/// the month, in a 360-day year.
+fn days_360_between(start: Date, start_day: u32, end: Date, end_day: u32) -> i64 {
+    (end.year - start.year) * 360
+        + (end.month as i64 - start.month as i64) * 30
+        + (end_day as i64 - start_day as i64)
+}
+
+/// Convention for counting the days and years between two dates, for
+/// `YEARFRAC` and financial functions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DayCountBasis {
+    /// US (NASD) 30/360.
+    Us30360,
+    /// Actual days over the actual length of the years.
+    ActualActual,
+    /// Actual days over a 360-day year.
+    Actual360,
+    /// Actual days over a 365-day year.
+    Actual365,
+    /// European 30/360.
+    European30360,
+}
+impl DayCountBasis {
+    /// Constructs a basis from Excel's code from 0 to 4, returning an error
+    /// for any other number.
+    pub fn try_from_value(value: &Spanned<Value>) -> FormulaResult<Self> {
+        Ok(match value.to_integer()? {
+            0 => Self::Us30360,
+            1 => Self::ActualActual,
+            2 => Self::Actual360,
+            3 => Self::Actual365,
+            4 => Self::European30360,
+            _ => return Err(FormulaErrorMsg::BadNumber.with_span(value.span)),
+        })
+    }
+
+    /// Returns the number of years between two dates, which is never
+    /// negative.
+    pub fn year_fraction(self, start: i64, end: i64) -> f64 {
+        let (start, end) = (start.min(end), start.max(end));
+        let days = (end - start) as f64;
+        match self {
+            Self::Us30360 => us_30_360_yearfrac_days(start, end) as f64 / 360.0,
+            Self::ActualActual => days / actual_year_length(start, end),
+            Self::Actual360 => days / 360.0,
+            Self::Actual365 => days / 365.0,
+            Self::European30360 => days_360(start, end, true) as f64 / 360.0,
+        }
+    }
+}
+
+/// Returns the number of days between two dates for `YEARFRAC` with the US
+/// 30/360 basis, which adjusts the end of February differently from
+/// `DAYS360`. `start` must not be after `end`.
+fn us_30_360_yearfrac_days(start: i64, end: i64) -> i64 {
+    let start = Date::from_serial(start);
+    let end = Date::from_serial(end);
+    let start_is_end_of_feb = start.month == 2 && start.is_end_of_month();
+    let end_is_end_of_feb = end.month == 2 && end.is_end_of_month();
+    let (start_day, end_day) = match (start.day, end.day) {
+        (31, 31) | (30, 31) => (30, 30),
+        (31, d) => (30, d),
+        _ if start_is_end_of_feb && end_is_end_of_feb => (30, 30),
+        (_, d) if start_is_end_of_feb => (30, d),
+        (s, d) => (s, d),
+    };
+    days_360_between(start, start_day, end, end_day)
+}
+
+/// Returns the length of a year for the actual/actual basis. `start` must not
+/// be after `end`.
+///
+/// For dates at most a year apart, this is 366 if there is a February 29th
+/// between them, and 365 otherwise. For dates further apart, this is the
+/// average length of the years from the start year to the end year.
+fn actual_year_length(start: i64, end: i64) -> f64 {
+    let start_date = Date::from_serial(start);
+    let end_date = Date::from_serial(end);
+    let (y1, y2) = (start_date.year, end_date.year);
+    let at_most_a_year = y1 == y2
+        || (y2 == y1 + 1 && (start_date.month, start_date.day) >= (end_date.month, end_date.day));
+    if at_most_a_year {
+        let includes_leap_day = if y1 == y2 {
+            is_leap_year(y1)
+        } else {
+            (is_leap_year(y1) && start_date.month <= 2)
+                || (is_leap_year(y2) && (end_date.month, end_date.day) >= (2, 29))
+        };
+        if includes_leap_day {
+            366.0
+        } else {
+            365.0
+        }
+    } else {
+        let days = Date::new(y2 + 1, 1, 1).to_serial() - Date::new(y1, 1, 1).to_serial();
+        days as f64 / (y2 - y1 + 1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_serial_round_trip() {
+        for (serial, date) in [
+            (0, Date::new(1899, 12, 31)),
+            (1, Date::new(1900, 1, 1)),
+            (59, Date::new(1900, 2, 28)),
+            (60, Date::new(1900, 2, 29)),
+            (61, Date::new(1900, 3, 1)),
+            (25569, Date::new(1970, 1, 1)),
+            (36526, Date::new(2000, 1, 1)),
+            (44197, Date::new(2021, 1, 1)),
+            (MAX_SERIAL, Date::new(9999, 12, 31)),
+        ] {
+            assert_eq!(date, Date::from_serial(serial));
+            assert_eq!(serial, date.to_serial());
+        }
+        for serial in 0..=100_000 {
+            assert_eq!(serial, Date::from_serial(serial).to_serial());
+        }
+
+        let system = DateSystem::Excel1904;
+        assert_eq!(Date::new(1904, 1, 1), Date::from_serial(system.to_1900(0)));
+        let max = system.to_1900(system.max_serial());
+        assert_eq!(Date::new(9999, 12, 31), Date::from_serial(max));
+    }
+
+    #[test]
+    fn test_add_months() {
+        let d = Date::new(2021, 1, 31);
+        assert_eq!(Date::new(2021, 2, 28), d.add_months(1));
+        assert_eq!(Date::new(2020, 2, 29), d.add_months(-11));
+        assert_eq!(Date::new(2022, 3, 31), d.add_months(14));
+        assert_eq!(Date::new(2020, 12, 31), d.add_months(-1));
+        assert_eq!(Date::new(2000, 2, 29), Date::new(2000, 2, 1).end_of_month());
+        assert_eq!(Date::new(1900, 2, 28), Date::new(1900, 2, 1).end_of_month());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        for s in [
+            "2021-03-14",
+            "3/14/2021",
+            "03/14/2021",
+            "14-Mar-2021",
+            " 14-MARCH-2021 ",
+        ] {
+            assert_eq!(Some(44269), parse_date(s), "{s:?}");
+        }
+        assert_eq!(Some(60), parse_date("1900-02-29"));
+        assert_eq!(Some(MAX_SERIAL), parse_date("12/31/9999"));
+
+        for s in [
+            "03-14-2021",  // ambiguous order
+            "14/3/2021",   // day-first
+            "3/14/21",     // two-digit year
+            "2021-02-29",  // not a leap year
+            "2021-13-01",  // bad month
+            "1899-12-31",  // too early
+            "14-Mrz-2021", // bad month name
+            "2021-03-14 12:00",
+            "",
+        ] {
+            assert_eq!(None, parse_date(s), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(Some(0.5), parse_time("12:00"));
+        assert_eq!(Some(0.5), parse_time("12:00 PM"));
+        assert_eq!(Some(0.0), parse_time("12:00am"));
+        assert_eq!(Some(0.75), parse_time("6:00 pm"));
+        assert_eq!(Some(0.75), parse_time("18:00:00"));
+        assert_eq!(Some(1.5 / 86400.0), parse_time("0:00:01.5"));
+
+        for s in [
+            "24:00",
+            "13:00 PM",
+            "0:00 AM",
+            "12:60",
+            "12:00:60",
+            "12",
+            "1:2",
+            "12:00:01.",
+            "noon",
+        ] {
+            assert_eq!(None, parse_time(s), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(0, weekday(1)); // 1900-01-01, which Excel thinks is Sunday
+        assert_eq!(4, weekday(61)); // 1900-03-01
+        assert_eq!(5, weekday(44197)); // 2021-01-01
+    }
+
+    #[test]
+    fn test_day_count_basis() {
+        let serial = |y, m, d| Date::new(y, m, d).to_serial();
+
+        for (start, end, us, european) in [
+            (serial(2011, 1, 30), serial(2011, 2, 28), 28, 28),
+            (serial(2011, 2, 28), serial(2011, 3, 31), 30, 32),
+            (serial(2011, 1, 15), serial(2011, 1, 31), 16, 15),
+            (serial(2011, 1, 31), serial(2011, 3, 31), 60, 60),
+            (serial(2011, 1, 30), serial(2011, 12, 31), 330, 330),
+            (serial(2012, 2, 29), serial(2012, 3, 31), 30, 31),
+        ] {
+            assert_eq!(us, days_360(start, end, false), "{start} to {end}");
+            assert_eq!(european, days_360(start, end, true), "{start} to {end}");
+        }
+
+        let start = serial(2012, 1, 1);
+        let end = serial(2012, 7, 30);
+        for (basis, expected) in [
+            (DayCountBasis::Us30360, 209.0 / 360.0),
+            (DayCountBasis::ActualActual, 211.0 / 366.0),
+            (DayCountBasis::Actual360, 211.0 / 360.0),
+            (DayCountBasis::Actual365, 211.0 / 365.0),
+            (DayCountBasis::European30360, 209.0 / 360.0),
+        ] {
+            assert_eq!(expected, basis.year_fraction(start, end), "{basis:?}");
+            assert_eq!(expected, basis.year_fraction(end, start), "{basis:?}");
+        }
+        assert_eq!(0.0, DayCountBasis::ActualActual.year_fraction(end, end));
+    }
+
+    #[test]
+    fn test_workdays() {
+        // Count one day at a time, to check the faster implementations.
+        let is_workday = |serial, weekend: Weekend, holidays: &[i64]| {
+            !weekend.contains(serial) && !holidays.contains(&serial)
+        };
+        let naive_between = |start: i64, end: i64, weekend, holidays: &[i64]| {
+            let count = (start.min(end)..=start.max(end))
+                .filter(|&d| is_workday(d, weekend, holidays))
+                .count() as i64;
+            if end < start {
+                -count
+            } else {
+                count
+            }
+        };
+        let naive_add = |start: i64, days: i64, weekend, holidays: &[i64]| {
+            let mut serial = start;
+            let mut remaining = days.abs();
+            while remaining > 0 {
+                serial += days.signum();
+                if is_workday(serial, weekend, holidays) {
+                    remaining -= 1;
+                }
+            }
+            serial
+        };
+
+        let weekends = [
+            Weekend::default(),
+            Weekend::from_weekdays(&[0]),
+            Weekend::from_weekdays(&[1, 3, 4, 5, 6]),
+            Weekend::from_weekdays(&[]),
+        ];
+        // Holidays are sorted, but may repeat or fall on the weekend.
+        let holidays = [1000, 1003, 1003, 1004, 1005, 1006, 1010, 1020, 1021];
+        for weekend in weekends {
+            for start in 990..1030 {
+                for n in -40..40 {
+                    assert_eq!(
+                        naive_between(start, start + n, weekend, &holidays),
+                        workdays_between(start, start + n, weekend, &holidays),
+                        "{weekend:?} {start} {n}",
+                    );
+                    assert_eq!(
+                        Some(naive_add(start, n, weekend, &holidays)),
+                        add_workdays(start, n, weekend, &holidays),
+                        "{weekend:?} {start} {n}",
+                    );
+                }
+            }
+        }
+
+        // The whole range of dates.
+        let weekend = Weekend::default();
+        let total = workdays_between(0, MAX_SERIAL, weekend, &holidays);
+        assert_eq!(naive_between(0, MAX_SERIAL, weekend, &holidays), total);
+        let last = naive_add(MAX_SERIAL + 1, -1, weekend, &holidays);
+        assert_eq!(Some(last), add_workdays(0, total, weekend, &holidays));
+        assert_eq!(None, add_workdays(0, total + 1, weekend, &holidays));
+        assert_eq!(None, add_workdays(1, i64::MIN, weekend, &holidays));
+    }
+}
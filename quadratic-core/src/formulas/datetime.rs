@@ -0,0 +1,172 @@
+//! Timezone-aware date-times, enabled by the `datetime` feature.
+//!
+//! A [`Value::DateTime`] is an instant together with the UTC offset it was
+//! written in, such as `2024-03-05T10:30:00+02:00`. Where a number is
+//! expected, it converts to the date serial number of its local date and time
+//! in the 1900 date system, so functions such as `YEAR` work on it. The
+//! conversion keeps millisecond precision, so converting back with the same
+//! offset gives the same date-time.
+//!
+//! Adding or subtracting a number moves a date-time by that many days, and
+//! subtracting two date-times gives the number of days between them. Two
+//! date-times are compared by the instant they represent, regardless of their
+//! offsets.
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+};
+
+use super::date::{Date, MAX_SERIAL};
+use super::functions::array_map;
+use super::*;
+
+const MILLIS_PER_DAY: f64 = 86_400_000.0;
+
+/// Parses a date-time in the RFC 3339 profile of ISO 8601, which requires a
+/// date, a time, and a UTC offset, such as `2024-03-05T10:30:00Z`.
+pub fn parse_datetime(s: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(s.trim()).ok()
+}
+
+/// Returns the date serial number of the local date and time, in the 1900
+/// date system.
+pub fn to_serial(dt: &DateTime<FixedOffset>) -> f64 {
+    naive_to_serial(dt.naive_local())
+}
+/// Returns the date serial number of the date and time in UTC, which orders
+/// date-times by the instant they represent.
+fn instant_serial(dt: &DateTime<FixedOffset>) -> f64 {
+    naive_to_serial(dt.naive_utc())
+}
+fn naive_to_serial(naive: NaiveDateTime) -> f64 {
+    let date = naive.date();
+    let days = Date::new(date.year() as i64, date.month(), date.day()).to_serial();
+    let millis = (naive.time() - NaiveTime::MIN).num_milliseconds();
+    days as f64 + millis as f64 / MILLIS_PER_DAY
+}
+
+/// Returns the date-time with the given offset whose local date and time is a
+/// date serial number in the 1900 date system, rounded to the nearest
+/// millisecond. Returns `None` if the serial number is out of range or is the
+/// nonexistent date 1900-02-29.
+pub fn from_serial(serial: f64, offset: FixedOffset) -> Option<DateTime<FixedOffset>> {
+    if !(0.0..(MAX_SERIAL + 1) as f64).contains(&serial) {
+        return None;
+    }
+    let days = serial.floor();
+    let millis = ((serial - days) * MILLIS_PER_DAY).round() as i64;
+    let date = Date::from_serial(days as i64);
+    let midnight =
+        NaiveDate::from_ymd_opt(date.year as i32, date.month, date.day)?.and_time(NaiveTime::MIN);
+    offset
+        .from_local_datetime(&(midnight + Duration::milliseconds(millis)))
+        .single()
+}
+
+/// Returns the date-time `days` days later, keeping the same offset.
+fn add_days(dt: &DateTime<FixedOffset>, days: f64, span: Span) -> FormulaResult<Value> {
+    from_serial(to_serial(dt) + days, *dt.offset())
+        .map(Value::DateTime)
+        .ok_or_else(|| FormulaErrorMsg::InvalidDate.with_span(span))
+}
+
+/// Implements the binary `+` and `-` operators, which also work on
+/// date-times.
+pub fn add_or_sub(
+    ctx: &Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    subtract: bool,
+) -> FormulaResult<Value> {
+    let coercion = ctx.coercion();
+    let span = args.span;
+    let sign = if subtract { -1.0 } else { 1.0 };
+    array_map(args, |[a, b]| match (&a.inner, &b.inner) {
+        (Value::DateTime(a), Value::DateTime(b)) if subtract => {
+            let millis = (*a - *b).num_milliseconds();
+            Ok(Value::Number(millis as f64 / MILLIS_PER_DAY))
+        }
+        (Value::DateTime(dt), _) => add_days(dt, sign * coercion.to_number(&b)?, span),
+        (_, Value::DateTime(dt)) if !subtract => add_days(dt, coercion.to_number(&a)?, span),
+        _ => {
+            let (a, b) = (coercion.to_number(&a)?, coercion.to_number(&b)?);
+            let result = match subtract {
+                false => arithmetic::add(a, b),
+                true => arithmetic::sub(a, b),
+            };
+            Ok(Value::Number(result))
+        }
+    })
+}
+
+/// Returns the numbers to compare two values by, if either is a date-time.
+/// Two date-times are compared by instant, and a date-time and a number by
+/// the date-time's serial number.
+pub fn comparison_numbers(a: &Value, b: &Value) -> Option<(f64, f64)> {
+    match (a, b) {
+        (Value::DateTime(a), Value::DateTime(b)) => Some((instant_serial(a), instant_serial(b))),
+        (Value::DateTime(_), _) | (_, Value::DateTime(_)) => Some((a.as_number()?, b.as_number()?)),
+        _ => None,
+    }
+}
+
+/// Converts a value to a date-time for `DATETIME`, which accepts a date-time
+/// or text in the format accepted by [`parse_datetime()`].
+pub fn to_datetime(value: &Spanned<Value>) -> FormulaResult<Value> {
+    match &value.inner {
+        Value::DateTime(dt) => Ok(Value::DateTime(*dt)),
+        Value::Error(e) => Err(e.clone().with_span(value.span)),
+        other => parse_datetime(&other.to_string())
+            .map(Value::DateTime)
+            .ok_or_else(|| FormulaErrorMsg::InvalidDate.with_span(value.span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datetime_serial_round_trip() {
+        for s in [
+            "1899-12-31T00:00:00Z",
+            "1900-01-01T12:00:00+01:00",
+            "1900-02-28T23:59:59.999-05:00",
+            "1900-03-01T00:00:00Z",
+            "2024-02-29T10:30:15.250+05:30",
+            "2024-03-05T00:00:00.001-11:00",
+            "9999-12-31T23:59:59.999+14:00",
+        ] {
+            let dt = parse_datetime(s).unwrap();
+            let serial = to_serial(&dt);
+            assert_eq!(Some(dt), from_serial(serial, *dt.offset()), "{s}");
+            assert_eq!(
+                s,
+                from_serial(serial, *dt.offset())
+                    .unwrap()
+                    .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true,)
+            );
+        }
+
+        // Serial numbers match the date functions, which use the local date.
+        let dt = parse_datetime("2024-03-05T18:00:00-08:00").unwrap();
+        assert_eq!(
+            Date::new(2024, 3, 5).to_serial() as f64 + 0.75,
+            to_serial(&dt)
+        );
+        for serial in [0.0, 1.5, 59.25, 61.0, 45356.125, MAX_SERIAL as f64] {
+            let offset = FixedOffset::east_opt(3600).unwrap();
+            let dt = from_serial(serial, offset).unwrap();
+            assert_eq!(serial, to_serial(&dt), "{serial}");
+        }
+
+        // The leap year bug's nonexistent date and out-of-range serials have
+        // no date-time.
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(None, from_serial(60.5, utc));
+        assert_eq!(None, from_serial(-1.0, utc));
+        assert_eq!(None, from_serial(MAX_SERIAL as f64 + 1.0, utc));
+
+        assert_eq!(None, parse_datetime("2024-03-05"));
+        assert_eq!(None, parse_datetime("2024-03-05T10:30:00"));
+    }
+}
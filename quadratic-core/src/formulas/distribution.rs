@@ -0,0 +1,307 @@
+//! Probability distributions for statistical functions such as `NORM.DIST`.
+//!
+//! The normal distribution is computed from the complementary error function,
+//! which uses a power series for small arguments and a continued fraction for
+//! large ones, so that tail probabilities keep their relative precision. The inverse
+//! starts from a rational approximation and is refined with Halley's method.
+//! Acklam's approximation alone has a relative error of about 1e-9; after
+//! refinement, the cumulative distribution and its inverse both have a
+//! relative error below 1e-12, even far into the tails.
+//!
+//! Discrete distributions are computed in log space from the logarithm of the
+//! gamma function, so that binomial coefficients of large counts don't
+//! overflow even when the probabilities themselves are representable. The
+//! logarithm of the gamma function has a relative error of about 1e-15 for
+//! all positive arguments, so probabilities computed from it lose about as
+//! much relative precision as the size of the logarithms involved; for counts
+//! up to a million, they have a relative error of at most about 1e-8.
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Largest argument for which the complementary error function uses the
+/// power series of the error function.
+const ERF_SERIES_MAX: f64 = 2.5;
+
+/// Complementary error function, `1 - erf(x)`, which is accurate even where
+/// it is tiny.
+pub fn erfc(x: f64) -> f64 {
+    if x.is_nan() {
+        x
+    } else if x < 0.0 {
+        2.0 - erfc(-x)
+    } else if x < ERF_SERIES_MAX {
+        1.0 - erf_series(x)
+    } else {
+        erfc_continued_fraction(x)
+    }
+}
+
+/// Computes `erf(x)` as `2/√π · exp(-x²) · Σ 2ⁿ x²ⁿ⁺¹ / (1·3·…·(2n+1))`,
+/// whose terms are all positive.
+fn erf_series(x: f64) -> f64 {
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 0.0;
+    while term.abs() > f64::EPSILON * sum.abs() {
+        n += 1.0;
+        term *= 2.0 * x * x / (2.0 * n + 1.0);
+        sum += term;
+    }
+    2.0 / PI.sqrt() * (-x * x).exp() * sum
+}
+
+/// Computes `erfc(x)` for positive `x` from the continued fraction
+/// `exp(-x²)/√π / (x + (1/2)/(x + 1/(x + (3/2)/(x + …))))`, using Lentz's
+/// method.
+fn erfc_continued_fraction(x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut f = x;
+    let mut c = x;
+    let mut d = 0.0;
+    for n in 1..500 {
+        let a = n as f64 / 2.0;
+        d = 1.0 / (x + a * d).max(TINY);
+        c = (x + a / c).max(TINY);
+        let delta = c * d;
+        f *= delta;
+        if (delta - 1.0).abs() < f64::EPSILON {
+            break;
+        }
+    }
+    (-x * x).exp() / PI.sqrt() / f
+}
+
+/// Probability density of the standard normal distribution.
+pub fn std_normal_pdf(z: f64) -> f64 {
+    (-z * z / 2.0).exp() / (2.0 * PI).sqrt()
+}
+
+/// Cumulative distribution function of the standard normal distribution.
+pub fn std_normal_cdf(z: f64) -> f64 {
+    erfc(-z * FRAC_1_SQRT_2) / 2.0
+}
+
+/// Inverse of [`std_normal_cdf()`]. Returns `None` unless `0 < p < 1`.
+pub fn std_normal_inv(p: f64) -> Option<f64> {
+    if !(p > 0.0 && p < 1.0) {
+        return None;
+    }
+
+    // Rational approximation by Peter Acklam, with a relative error of
+    // about 1e-9.
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    let poly = |coefficients: &[f64], x: f64| coefficients.iter().fold(0.0, |acc, c| acc * x + c);
+    let tail = |p: f64| {
+        let q = (-2.0 * p.ln()).sqrt();
+        poly(&C, q) / (poly(&D, q) * q + 1.0)
+    };
+    let mut z = if p < P_LOW {
+        tail(p)
+    } else if p > 1.0 - P_LOW {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        poly(&A, r) * q / (poly(&B, r) * r + 1.0)
+    };
+
+    // Refine with Halley's method.
+    for _ in 0..2 {
+        let error = std_normal_cdf(z) - p;
+        let u = error / std_normal_pdf(z);
+        if !u.is_finite() {
+            break;
+        }
+        z -= u / (1.0 + z * u / 2.0);
+    }
+    Some(z)
+}
+
+/// Returns `x` scaled to the standard normal distribution, or `None` if the
+/// standard deviation is not positive.
+pub fn standardize(x: f64, mean: f64, std_dev: f64) -> Option<f64> {
+    (std_dev > 0.0).then(|| (x - mean) / std_dev)
+}
+
+/// Natural logarithm of the gamma function for positive `x`, using the
+/// Lanczos approximation, which has a relative error of about 1e-15.
+pub fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x == 1.0 || x == 2.0 {
+        // Γ(1) = Γ(2) = 1 exactly, which the approximation is slightly off
+        // from.
+        return 0.0;
+    }
+    if x < 0.5 {
+        // Reflection formula, since the approximation is only accurate for
+        // `x >= 0.5`.
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .zip(1..)
+        .fold(COEFFICIENTS[0], |acc, (c, i)| acc + c / (x + i as f64));
+    let t = x + G + 0.5;
+    (2.0 * PI).sqrt().ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Natural logarithm of the binomial coefficient `n` choose `k`, for
+/// integers `0 <= k <= n`.
+pub fn ln_choose(n: f64, k: f64) -> f64 {
+    ln_gamma(n + 1.0) - ln_gamma(k + 1.0) - ln_gamma(n - k + 1.0)
+}
+
+/// Largest `n` for which every binomial coefficient `n` choose `k` fits in an
+/// `f64`.
+const MAX_EXACT_CHOOSE_N: f64 = 1029.0;
+
+/// Binomial coefficient `n` choose `k`, for integers `0 <= k <= n <=`
+/// [`MAX_EXACT_CHOOSE_N`]. This is exact as long as the result fits in the
+/// 53 bits of an `f64`'s mantissa.
+fn choose(n: f64, k: f64) -> f64 {
+    let k = k.min(n - k);
+    // Each partial product is itself a binomial coefficient, so it is an
+    // integer.
+    (1..=k as u64).fold(1.0, |acc, i| acc * (n - k + i as f64) / i as f64)
+}
+
+/// Probability of exactly `k` successes in `n` independent trials that each
+/// succeed with probability `p`, for integers `0 <= k <= n` and `0 <= p <= 1`.
+pub fn binomial_pmf(k: f64, n: f64, p: f64) -> f64 {
+    // The logarithm of zero would make `0 * ln(0)` undefined.
+    if p == 0.0 {
+        return (k == 0.0) as u8 as f64;
+    }
+    if p == 1.0 {
+        return (k == n) as u8 as f64;
+    }
+    // Multiplying directly gives exact results for simple cases, such as
+    // `0.5` for one success in two trials, where logarithms would be off in
+    // the last digit. It only works if no factor overflows or underflows.
+    if n <= MAX_EXACT_CHOOSE_N {
+        let ret = choose(n, k) * p.powf(k) * (1.0 - p).powf(n - k);
+        if ret.is_normal() {
+            return ret;
+        }
+    }
+    (ln_choose(n, k) + k * p.ln() + (n - k) * (-p).ln_1p()).exp()
+}
+
+/// Probability of exactly `k` events in a Poisson distribution with the
+/// given mean, for an integer `k >= 0` and `mean >= 0`.
+pub fn poisson_pmf(k: f64, mean: f64) -> f64 {
+    if mean == 0.0 {
+        return (k == 0.0) as u8 as f64;
+    }
+    (k * mean.ln() - mean - ln_gamma(k + 1.0)).exp()
+}
+
+/// Probability of exactly `k` successes in a sample of size `n` drawn
+/// without replacement from a population of size `population` with
+/// `successes` successes, for integers in the range where this is possible.
+pub fn hypergeometric_pmf(k: f64, n: f64, successes: f64, population: f64) -> f64 {
+    let ln_p = ln_choose(successes, k) + ln_choose(population - successes, n - k)
+        - ln_choose(population, n);
+    ln_p.exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erfc() {
+        // Values from Abramowitz and Stegun, table 7.1.
+        for (x, expected) in [
+            (0.0, 0.0),
+            (0.1, 0.1124629160),
+            (0.5, 0.5204998778),
+            (1.0, 0.8427007929),
+            (1.5, 0.9661051465),
+            (2.0, 0.9953222650),
+            (2.5, 0.9995930480),
+            (3.0, 0.9999779095),
+        ] {
+            assert!((erfc(x) - (1.0 - expected)).abs() < 1e-10, "erfc({x})");
+            assert!((erfc(-x) - (1.0 + expected)).abs() < 1e-10, "erfc(-{x})");
+        }
+        // The complementary function keeps its precision in the tail.
+        let expected = 1.537459794428035e-12;
+        assert!((erfc(5.0) / expected - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_ln_gamma() {
+        // Γ(n) = (n-1)!
+        let mut factorial = 1.0_f64;
+        for n in 1..=30 {
+            let expected = factorial.ln();
+            assert!(
+                (ln_gamma(n as f64) - expected).abs() < 1e-13 * expected.max(1.0),
+                "{n}"
+            );
+            factorial *= n as f64;
+        }
+        // Γ(1/2) = √π
+        assert!((ln_gamma(0.5) - PI.sqrt().ln()).abs() < 1e-14);
+        assert!((ln_gamma(1e-10) - 23.025850929882736).abs() < 1e-12);
+        // Large arguments don't overflow.
+        assert!((ln_gamma(1e10) / 220258509288.81058 - 1.0).abs() < 1e-14);
+    }
+
+    #[test]
+    fn test_std_normal_inv_round_trips() {
+        for p in [
+            1e-300, 1e-12, 0.001, 0.02425, 0.1, 0.5, 0.9, 0.975, 0.999999,
+        ] {
+            let z = std_normal_inv(p).unwrap();
+            assert!((std_normal_cdf(z) / p - 1.0).abs() < 1e-12, "{p}");
+        }
+        for p in [0.0, 1.0, -0.5, 1.5, f64::NAN] {
+            assert_eq!(None, std_normal_inv(p), "{p}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! State that is kept across formula evaluations, such as parsed formulas.
+//!
+//! Parsing only depends on the source and the location of a formula, so
+//! parsed formulas never need to be invalidated. The location is part of the
+//! key because cell references are stored relative to it.
+//!
+//! This means the cache only helps when the same cell is recalculated. Cells
+//! filled down from one formula each have a different source and location,
+//! such as `A1 + 1` in `B1` and `A2 + 1` in `B2`, so each is parsed once.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use super::*;
+
+/// Default number of parsed formulas that a [`FormulaEngine`] keeps.
+pub const DEFAULT_PARSE_CACHE_CAPACITY: usize = 4096;
+
+/// Source and location of a formula.
+type ParseKey = (String, Pos);
+
+/// Parses formulas, remembering the most recently used ones so that parsing
+/// the same formula in the same cell again is cheap, such as when a cell is
+/// recalculated after a cell that it references changes.
+#[derive(Debug, Clone)]
+pub struct FormulaEngine {
+    /// Maximum number of parsed formulas to keep. Zero disables the cache.
+    capacity: usize,
+    /// Parsed formulas, along with when each was last used.
+    parsed: HashMap<ParseKey, (FormulaResult<Arc<Formula>>, u64)>,
+    /// Keys of `parsed` by when they were last used, oldest first.
+    recently_used: BTreeMap<u64, ParseKey>,
+    /// Counter that increases every time a formula is used.
+    clock: u64,
+}
+impl Default for FormulaEngine {
+    fn default() -> Self {
+        Self::new(DEFAULT_PARSE_CACHE_CAPACITY)
+    }
+}
+impl FormulaEngine {
+    /// Constructs an engine that keeps up to `capacity` parsed formulas.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            parsed: HashMap::new(),
+            recently_used: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the number of parsed formulas being kept.
+    pub fn cached_len(&self) -> usize {
+        self.parsed.len()
+    }
+
+    /// Parses a formula at a location, reusing the result from an earlier call
+    /// with the same source and location if it is still cached. The result is
+    /// always identical to calling `parse_formula()`.
+    pub fn parse(&mut self, source: &str, loc: Pos) -> FormulaResult<Arc<Formula>> {
+        if self.capacity == 0 {
+            return parse_formula(source, loc).map(Arc::new);
+        }
+
+        self.clock += 1;
+        let key = (source.to_string(), loc);
+        if let Some((result, last_used)) = self.parsed.get_mut(&key) {
+            let key = self.recently_used.remove(last_used).unwrap_or(key);
+            *last_used = self.clock;
+            self.recently_used.insert(self.clock, key);
+            return result.clone();
+        }
+
+        if self.parsed.len() >= self.capacity {
+            if let Some((_, oldest)) = self.recently_used.pop_first() {
+                self.parsed.remove(&oldest);
+            }
+        }
+        let result = parse_formula(source, loc).map(Arc::new);
+        self.parsed
+            .insert(key.clone(), (result.clone(), self.clock));
+        self.recently_used.insert(self.clock, key);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cache() {
+        let mut engine = FormulaEngine::new(2);
+        let a = Pos::new(0, 1);
+        let b = Pos::new(3, 4);
+
+        for (source, loc) in [("SUM(A1:A3)", a), ("SUM(A1:A3)", b), ("1 +", a)] {
+            for _ in 0..2 {
+                match (parse_formula(source, loc), engine.parse(source, loc)) {
+                    (Ok(expected), Ok(got)) => assert_eq!(expected, *got, "{source:?}"),
+                    (Err(expected), Err(got)) => assert_eq!(expected.msg, got.msg, "{source:?}"),
+                    (expected, got) => panic!("for {source:?}: expected {expected:?}, got {got:?}"),
+                }
+            }
+        }
+        assert_eq!(2, engine.cached_len());
+
+        // Using a formula keeps it from being the next one evicted.
+        let first = engine.parse("'x'", a).unwrap();
+        assert!(Arc::ptr_eq(&first, &engine.parse("'x'", a).unwrap()));
+        engine.parse("'y'", a).unwrap();
+        assert!(Arc::ptr_eq(&first, &engine.parse("'x'", a).unwrap()));
+        engine.parse("'z'", a).unwrap();
+        engine.parse("'y'", a).unwrap();
+        assert!(!Arc::ptr_eq(&first, &engine.parse("'x'", a).unwrap()));
+        assert_eq!(2, engine.cached_len());
+
+        // With no capacity, nothing is kept.
+        let mut engine = FormulaEngine::new(0);
+        let first = engine.parse("'x'", a).unwrap();
+        assert!(!Arc::ptr_eq(&first, &engine.parse("'x'", a).unwrap()));
+        assert_eq!(0, engine.cached_len());
+    }
+}
@@ -4,7 +4,23 @@ use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
 
-use super::Span;
+use strum_macros::{EnumString, IntoStaticStr};
+
+use super::{Span, ValueType};
+
+/// Parses the contents of a cell, such as `#REF!`, as an error value. This
+/// ignores case and surrounding whitespace, and is the reverse of
+/// [`FormulaErrorMsg::excel_code()`]. Any other text returns `None`, and should
+/// be treated as text.
+///
+/// ```
+/// # use quadratic_core::formulas::{parse_error_literal, FormulaErrorMsg};
+/// assert_eq!(Some(FormulaErrorMsg::NotAvailable), parse_error_literal(" #n/a "));
+/// assert_eq!(None, parse_error_literal("#FOO!"));
+/// ```
+pub fn parse_error_literal(s: &str) -> Option<FormulaErrorMsg> {
+    FormulaErrorMsg::from_excel_code(&s.trim().to_ascii_uppercase())
+}
 
 /// Error message and accompanying span.
 #[derive(Debug, Clone)]
@@ -13,17 +29,38 @@ pub struct FormulaError {
     pub span: Option<Span>,
     /// Type of error.
     pub msg: FormulaErrorMsg,
+    /// Names of the functions that the error passed through on its way out,
+    /// innermost first. This is only recorded if
+    /// [`EvalSettings::trace_function_calls`] is enabled.
+    ///
+    /// [`EvalSettings::trace_function_calls`]: super::EvalSettings::trace_function_calls
+    pub call_trace: Vec<String>,
+    /// What the user may have meant instead, such as the name of a function
+    /// with a similar name to one that doesn't exist.
+    pub suggestion: Option<String>,
 }
 impl fmt::Display for FormulaError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.span {
-            Some(span) => write!(f, "column {} to {}: {}", span.start, span.end, self.msg),
-            None => write!(f, "{}", self.msg),
+        if let Some(span) = self.span {
+            write!(f, "column {} to {}: ", span.start, span.end)?;
         }
+        self.fmt_message(f)
     }
 }
 impl Error for FormulaError {}
 impl FormulaError {
+    /// Writes the call trace and message, without the span.
+    fn fmt_message(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        for func in self.call_trace.iter().rev() {
+            write!(f, "in {func} → ")?;
+        }
+        write!(f, "{}", self.msg)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean {suggestion}?)")?;
+        }
+        Ok(())
+    }
+
     /// Attaches a span to this FormulaError, if it does not already have one.
     pub fn with_span(mut self, span: impl Into<Span>) -> Self {
         if self.span.is_none() {
@@ -33,11 +70,50 @@ impl FormulaError {
     }
 }
 
+/// Renders an error for a terminal: the line of the formula containing the
+/// error, a row of `^` under the span, and the message below. Formulas with
+/// more than one line start with the line number. Errors without a span only
+/// show the message.
+///
+/// ```
+/// # use quadratic_core::formulas::{render_with_source, FormulaErrorMsg, Span};
+/// let err = FormulaErrorMsg::BadFunctionName.with_span(Span { start: 4, end: 8 });
+/// assert_eq!(
+///     "1 + FOOD(2)\n    ^^^^\nThere is no function with this name",
+///     render_with_source(&err, "1 + FOOD(2)"),
+/// );
+/// ```
+pub fn render_with_source(err: &FormulaError, src: &str) -> String {
+    let mut ret = String::new();
+    if let Some(span) = err.span {
+        let (line_index, col) = span.line_col(src);
+        let line = src.split('\n').nth(line_index).unwrap_or_default();
+        if src.contains('\n') {
+            ret.push_str(&format!("line {}:\n", line_index + 1));
+        }
+        // Underline to the end of the span or the line, whichever comes first,
+        // counting characters rather than bytes so that the carets line up.
+        let underlined = &line[col..(col + span.end - span.start).min(line.len())];
+        let indent = line[..col].chars().count();
+        let width = underlined.chars().count().max(1);
+        ret.push_str(line.trim_end_matches('\r'));
+        ret.push('\n');
+        ret.push_str(&" ".repeat(indent));
+        ret.push_str(&"^".repeat(width));
+        ret.push('\n');
+    }
+    // Writing to a `String` can't fail.
+    let _ = err.fmt_message(&mut ret);
+    ret
+}
+
 /// Information about the type of error that occurred.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, EnumString, IntoStaticStr)]
 pub enum FormulaErrorMsg {
     // Miscellaneous errors
-    Unimplemented,
+    /// Syntax or feature that is recognized but not supported yet, such as
+    /// sheet references.
+    Unimplemented(Cow<'static, str>),
     UnknownError,
     InternalError(Cow<'static, str>),
 
@@ -53,9 +129,46 @@ pub enum FormulaErrorMsg {
     },
     NonRectangularArray,
     BadArgumentCount,
+    /// Array with more than one value where a single value is required. The
+    /// size is `(rows, columns)`.
+    ///
+    /// Most functions are applied to each value of an array separately, but
+    /// these arguments must be single values:
+    /// - the function number of `AGGREGATE` and `SUBTOTAL`, and the options
+    ///   and `k` of `AGGREGATE`
+    /// - the row and column of `INDEX`
+    /// - the criteria of `SUMIF`, `COUNTIF`, and the other conditional
+    ///   aggregation functions
+    /// - `x`, `n`, and `m` of `SERIESSUM`
+    /// - the size of `MUNIT`
+    /// - `const` and `stats` of `LINEST` and `LOGEST`, and `const` of `TREND`
+    ///   and `GROWTH`
+    /// - the dates, number of days, and weekend of `WORKDAY`, `NETWORKDAYS`,
+    ///   and their `.INTL` versions
+    /// - `ignore_empty` of `TEXTSPLIT`, and the format of `ARRAYTOTEXT` and
+    ///   `VALUETOTEXT`
+    /// - the arguments of `RANDARRAY`
+    /// - the arguments of `REGEXEXTRACT`
+    ExpectedScalar {
+        got_size: (u32, u32),
+    },
+    /// Argument to a function has the wrong type. `arg_index` starts at 0.
+    TypeMismatch {
+        func: Cow<'static, str>,
+        arg_index: usize,
+        expected: ValueType,
+        got: ValueType,
+    },
     BadFunctionName,
+    /// Name that is not bound by `LET` or `LAMBDA`.
+    BadName,
     BadCellReference,
     BadNumber,
+    BadErrorCode,
+    NestingTooDeep,
+    /// Function that can only be used inside the body of `LAMBDA`, such as
+    /// `ISOMITTED`.
+    NotInLambda,
 
     // Runtime errors
     CircularReference,
@@ -63,12 +176,43 @@ pub enum FormulaErrorMsg {
     DivideByZero,
     NegativeExponent,
     IndexOutOfBounds,
+    /// Ranges that have no cells in common, as `#NULL!` in a formula.
+    NoIntersection,
+    /// Range used with the `@` operator that doesn't contain the row or
+    /// column of the formula cell.
+    NoImplicitIntersection,
+    InvalidArgument,
+    InvalidDate,
+    NotANumber(Cow<'static, str>),
+    NotAvailable,
+    /// Unit of measurement that `CONVERT` doesn't know.
+    UnknownUnit(Cow<'static, str>),
+    /// Pattern for a function such as `REGEXMATCH` that is not a valid
+    /// regular expression. `msg` is the message from the regex engine.
+    BadRegex {
+        msg: Cow<'static, str>,
+    },
+    EvaluationLimitExceeded {
+        limit: u64,
+    },
+    ArrayTooLarge {
+        limit: u64,
+    },
+    /// Text that would be longer than the maximum length in bytes.
+    TextTooLong {
+        limit: u64,
+    },
+    /// Cell that the formula refers to contains code, such as Python, that
+    /// failed to run. `source` is the error message from that cell.
+    UpstreamError {
+        source: Cow<'static, str>,
+    },
 }
 impl fmt::Display for FormulaErrorMsg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Unimplemented => {
-                write!(f, "This feature is unimplemented")
+            Self::Unimplemented(feature) => {
+                write!(f, "This feature is unimplemented: {feature}")
             }
             Self::UnknownError => {
                 write!(f, "(unknown error)")
@@ -90,6 +234,22 @@ impl fmt::Display for FormulaErrorMsg {
             Self::NonRectangularArray => {
                 write!(f, "Array must be rectangular")
             }
+            Self::ExpectedScalar { got_size } => {
+                let (rows, cols) = got_size;
+                write!(f, "Expected a single value, got a {rows}x{cols} array")
+            }
+            Self::TypeMismatch {
+                func,
+                arg_index,
+                expected,
+                got,
+            } => {
+                let n = arg_index + 1;
+                write!(
+                    f,
+                    "Argument {n} of {func} expected {expected} but got {got}"
+                )
+            }
             Self::BadArgumentCount => {
                 // TODO: give a nicer error message that says what the arguments
                 // should be
@@ -98,11 +258,23 @@ impl fmt::Display for FormulaErrorMsg {
             Self::BadFunctionName => {
                 write!(f, "There is no function with this name")
             }
+            Self::BadName => {
+                write!(f, "There is no LET or LAMBDA that defines this name")
+            }
             Self::BadCellReference => {
                 write!(f, "Bad cell reference")
             }
             Self::BadNumber => {
-                write!(f, "Bad numeric literal")
+                write!(f, "Bad number")
+            }
+            Self::BadErrorCode => {
+                write!(f, "There is no error with this code")
+            }
+            Self::NestingTooDeep => {
+                write!(f, "Formula is nested too deeply")
+            }
+            Self::NotInLambda => {
+                write!(f, "This function can only be used inside LAMBDA")
             }
 
             Self::CircularReference => {
@@ -120,15 +292,107 @@ impl fmt::Display for FormulaErrorMsg {
             Self::IndexOutOfBounds => {
                 write!(f, "Index out of bounds")
             }
+            Self::NoIntersection => {
+                write!(f, "Ranges do not intersect")
+            }
+            Self::NoImplicitIntersection => {
+                write!(f, "Range does not intersect this cell's row or column")
+            }
+            Self::InvalidArgument => {
+                write!(f, "Invalid argument")
+            }
+            Self::InvalidDate => {
+                write!(f, "Invalid date")
+            }
+            Self::NotANumber(s) => {
+                write!(f, "Number is invalid or out of range: {s}")
+            }
+            Self::NotAvailable => {
+                write!(f, "No value is available")
+            }
+            Self::UnknownUnit(s) => {
+                write!(f, "Unknown unit: {s}")
+            }
+            Self::BadRegex { msg } => {
+                write!(f, "Invalid regular expression: {msg}")
+            }
+            Self::EvaluationLimitExceeded { limit } => {
+                write!(
+                    f,
+                    "Formula is too complex (exceeded limit of {limit} steps)"
+                )
+            }
+            Self::ArrayTooLarge { limit } => {
+                write!(f, "Array is too large (exceeded limit of {limit} elements)")
+            }
+            Self::TextTooLong { limit } => {
+                write!(f, "Text is too long (exceeded limit of {limit} bytes)")
+            }
+            Self::UpstreamError { source } => {
+                write!(f, "Error in referenced cell: {source}")
+            }
         }
     }
 }
 impl FormulaErrorMsg {
+    /// Returns a short, stable name for the type of error, such as
+    /// `"DivideByZero"`, that does not depend on any details of the error.
+    pub fn short_name(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Returns the code that Excel displays in a cell for this type of error,
+    /// such as `"#DIV/0!"`, or `None` if Excel has no equivalent.
+    pub fn excel_code(&self) -> Option<&'static str> {
+        match self {
+            Self::DivideByZero => Some("#DIV/0!"),
+            Self::NotAvailable | Self::UnknownUnit(_) => Some("#N/A"),
+            Self::BadFunctionName | Self::BadName | Self::BadErrorCode => Some("#NAME?"),
+            Self::NoIntersection => Some("#NULL!"),
+            Self::BadNumber
+            | Self::Overflow
+            | Self::NegativeExponent
+            | Self::InvalidDate
+            | Self::NotANumber(_) => Some("#NUM!"),
+            Self::BadCellReference | Self::IndexOutOfBounds => Some("#REF!"),
+            Self::Expected { .. }
+            | Self::ArraySizeMismatch { .. }
+            | Self::ExpectedScalar { .. }
+            | Self::TypeMismatch { .. }
+            | Self::TextTooLong { .. }
+            | Self::BadRegex { .. }
+            | Self::NoImplicitIntersection
+            | Self::InvalidArgument => Some("#VALUE!"),
+            _ => None,
+        }
+    }
+    /// Returns the error for an Excel error code, such as `"#DIV/0!"`.
+    pub(crate) fn from_excel_code(code: &str) -> Option<Self> {
+        match code {
+            "#DIV/0!" => Some(Self::DivideByZero),
+            "#N/A" => Some(Self::NotAvailable),
+            "#NAME?" => Some(Self::BadFunctionName),
+            "#NULL!" => Some(Self::NoIntersection),
+            "#NUM!" => Some(Self::BadNumber),
+            "#REF!" => Some(Self::BadCellReference),
+            "#VALUE!" => Some(Self::InvalidArgument),
+            _ => None,
+        }
+    }
+
+    /// Returns the error with a given short name, as returned by
+    /// `short_name()`. Any details of the error are left empty.
+    pub(crate) fn from_short_name(name: &str) -> Option<Self> {
+        name.parse().ok()
+    }
+
     /// Attaches a span to this error message, returning a FormulaError.
     pub fn with_span(self, span: impl Into<Span>) -> FormulaError {
         FormulaError {
             span: Some(span.into()),
             msg: self,
+            call_trace: vec![],
+            suggestion: None,
         }
     }
     /// Returns a FormulaError from this error message, without a span.
@@ -136,6 +400,8 @@ impl FormulaErrorMsg {
         FormulaError {
             span: None,
             msg: self,
+            call_trace: vec![],
+            suggestion: None,
         }
     }
 }
@@ -146,6 +412,72 @@ impl<T: Into<FormulaErrorMsg>> From<T> for FormulaError {
     }
 }
 
+/// Non-fatal problem with a formula, such as text that was read as a number,
+/// and accompanying span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormulaWarning {
+    /// Location of the source code that the warning is about (if any).
+    pub span: Option<Span>,
+    /// Type of warning.
+    pub msg: FormulaWarningMsg,
+}
+impl fmt::Display for FormulaWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "column {} to {}: {}", span.start, span.end, self.msg),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+/// Information about the type of warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormulaWarningMsg {
+    /// Text in an array was read as a number by a function that ignores
+    /// blanks, such as `SUM`.
+    TextCoercedToNumber(String),
+    /// The divisor of a division is always zero.
+    DivideByZero,
+    /// Arguments are never evaluated, because a condition before them is
+    /// always the same.
+    UnreachableArgument,
+    /// An index is out of bounds of an array whose size is known without
+    /// evaluating the formula.
+    IndexOutOfBounds,
+    /// A range of several cells is an operand of an operator, which is applied
+    /// to every cell. Spreadsheets without dynamic arrays would have used only
+    /// the cell on the same row or column as the formula, like `@` does.
+    ImplicitIntersection,
+}
+impl fmt::Display for FormulaWarningMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TextCoercedToNumber(s) => {
+                write!(f, "Text {s:?} was read as a number")
+            }
+            Self::DivideByZero => write!(f, "Divide by zero"),
+            Self::UnreachableArgument => {
+                write!(f, "Never evaluated, because the condition is constant")
+            }
+            Self::IndexOutOfBounds => write!(f, "Index out of bounds"),
+            Self::ImplicitIntersection => write!(
+                f,
+                "Every cell in the range is used; \
+                 use @ for only the cell in the same row or column",
+            ),
+        }
+    }
+}
+impl FormulaWarningMsg {
+    /// Attaches a span to this warning message, returning a FormulaWarning.
+    pub fn with_span(self, span: impl Into<Span>) -> FormulaWarning {
+        FormulaWarning {
+            span: Some(span.into()),
+            msg: self,
+        }
+    }
+}
+
 /// Handles internal errors. Panics in debug mode for the stack trace, but
 /// returns a nice error message in release mode or on web.
 ///
@@ -158,7 +490,7 @@ macro_rules! internal_error_value {
     ( $msg:expr ) => {{
         // Panic in a debug build (for stack trace).
         #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
-        #[allow(unused)]
+        #[allow(unused, clippy::diverging_sub_expression)]
         let ret: crate::formulas::FormulaError = panic!("{}", $msg);
         // Give nice error message for user in release build.
         #[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
@@ -174,7 +506,7 @@ macro_rules! internal_error_value {
     ( $( $args:expr ),+ $(,)? ) => {{
         // Panic in a debug build (for stack trace).
         #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
-        #[allow(unused)]
+        #[allow(unused, clippy::diverging_sub_expression)]
         let ret: crate::formulas::FormulaError = panic!($( $args ),+);
         // Give nice error message for user in release build.
         #[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
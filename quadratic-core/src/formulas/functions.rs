@@ -1,12 +1,28 @@
 use itertools::Itertools;
-use smallvec::SmallVec;
+use std::cmp::Ordering;
 
+use super::aggregate::Aggregation;
+use super::arithmetic;
+use super::bitwise;
+use super::complex::Complex;
+use super::criteria::CriteriaArgs;
+use super::database;
+use super::date::{Date, DateSystem, DayCountBasis, Weekend};
+#[cfg(feature = "datetime")]
+use super::datetime;
+use super::distribution;
+use super::lookup::{self, MatchMode, SearchMode};
+use super::matrix::Matrix;
+use super::pattern::{self, RegexCache};
+use super::radix::Radix;
+use super::regression::{LinearFit, PairStats};
+use super::units::Unit;
 use super::*;
 
 /// Produces a constant function that takes no arguments.
 macro_rules! constant_function {
     ($value:expr) => {
-        |args| {
+        |_ctx, args| {
             if !args.inner.is_empty() {
                 return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
             }
@@ -18,62 +34,118 @@ macro_rules! constant_function {
 /// Constructs a thunk that calls `array_mapped()`.
 macro_rules! array_mapped {
     ($closure:expr) => {
-        |args| array_map(args, $closure)
+        |_ctx, args| array_map(args, $closure)
     };
 }
 
-pub fn pure_function_from_name(
-    s: &str,
-) -> Option<fn(Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value>> {
-    // When adding new functions, also update the code editor completions list.
+/// Function that takes spanned arguments and returns a value.
+pub type FormulaFn = fn(&mut Ctx<'_>, Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value>;
+
+/// Returns whether a function may return a different result each time it is
+/// evaluated, even if its arguments and the cells it reads are unchanged.
+///
+/// This includes functions that read cells computed at runtime, since which
+/// cells those are isn't known until the function is evaluated.
+///
+/// The only such functions so far are `RANDARRAY` and `CELL`. Other volatile
+/// functions in spreadsheets, such as `NOW`, `RAND`, and `OFFSET`, should be
+/// added here when they are implemented.
+pub fn is_volatile(name: &str) -> bool {
+    matches!(name.to_ascii_lowercase().as_str(), "randarray") || has_dynamic_references(name)
+}
+
+/// Returns whether a function reads cells whose locations are computed from
+/// its arguments, such as `CELL`, so that the cells a formula depends on
+/// can't be found without evaluating it.
+pub fn has_dynamic_references(name: &str) -> bool {
+    matches!(name.to_ascii_lowercase().as_str(), "cell" | "c")
+}
+
+/// Returns whether a function gives the same result if blank cells are
+/// removed from its arguments, so that a column range such as `A:A` only
+/// needs to fetch its nonblank cells.
+pub fn ignores_blanks(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "sum" | "product" | "average" | "count" | "min" | "max" | "sumsq",
+    )
+}
+
+pub fn pure_function_from_name(s: &str) -> Option<FormulaFn> {
+    // When adding new functions, also update the code editor completions list
+    // and the list of signatures in `signatures.rs`.
     Some(match s.to_ascii_lowercase().as_str() {
         // Comparison operators
-        "=" | "==" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_string() == b.to_string()))),
-        "<>" | "!=" => {
-            array_mapped!(|[a, b]| Ok(Value::Bool(a.to_string() != b.to_string())))
-        }
-        "<" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_number()? < b.to_number()?))),
-        ">" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_number()? > b.to_number()?))),
-        "<=" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_number()? <= b.to_number()?))),
-        ">=" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_number()? >= b.to_number()?))),
+        "=" | "==" => |ctx, args| comparison(ctx, args, Ordering::is_eq),
+        "<>" | "!=" => |ctx, args| comparison(ctx, args, Ordering::is_ne),
+        "<" => |ctx, args| comparison(ctx, args, Ordering::is_lt),
+        ">" => |ctx, args| comparison(ctx, args, Ordering::is_gt),
+        "<=" => |ctx, args| comparison(ctx, args, Ordering::is_le),
+        ">=" => |ctx, args| comparison(ctx, args, Ordering::is_ge),
 
         // Mathematical operators
-        "sum" => |args| sum(&args.inner).map(Value::Number),
-        "+" => |args| match args.inner.len() {
-            1 => array_map(args, |[a]| Ok(Value::Number(a.to_number()?))),
-            _ => array_map(args, |[a, b]| {
-                Ok(Value::Number(a.to_number()? + b.to_number()?))
-            }),
+        "sum" => |ctx, args| {
+            warn_text_in_ranges(ctx, &args.inner);
+            let coerce_text = ctx.settings.coerce_numeric_text;
+            let result = sum(&args.inner, coerce_text).map_err(|e| e.with_span(args.span))?;
+            Ok(Value::Number(result))
         },
-        "-" => |args| match args.inner.len() {
-            1 => array_map(args, |[a]| Ok(Value::Number(-a.to_number()?))),
-            _ => array_map(args, |[a, b]| {
-                Ok(Value::Number(a.to_number()? - b.to_number()?))
-            }),
+        "+" => |ctx, args| match args.inner.len() {
+            1 => numeric_op(ctx, args, |[a]| Ok(a)),
+            #[cfg(feature = "datetime")]
+            _ => datetime::add_or_sub(ctx, args, false),
+            #[cfg(not(feature = "datetime"))]
+            _ => numeric_op(ctx, args, |[a, b]| Ok(arithmetic::add(a, b))),
+        },
+        "-" => |ctx, args| match args.inner.len() {
+            1 => numeric_op(ctx, args, |[a]| Ok(-a)),
+            #[cfg(feature = "datetime")]
+            _ => datetime::add_or_sub(ctx, args, true),
+            #[cfg(not(feature = "datetime"))]
+            _ => numeric_op(ctx, args, |[a, b]| Ok(arithmetic::sub(a, b))),
+        },
+        "product" => |ctx, args| {
+            warn_text_in_ranges(ctx, &args.inner);
+            let coerce_text = ctx.settings.coerce_numeric_text;
+            let result = product(&args.inner, coerce_text).map_err(|e| e.with_span(args.span))?;
+            Ok(Value::Number(result))
+        },
+        "sumx2my2" => |_ctx, args| sum_of_pairs(args, |x, y| x * x - y * y),
+        "sumx2py2" => |_ctx, args| sum_of_pairs(args, |x, y| x * x + y * y),
+        "sumxmy2" => |_ctx, args| sum_of_pairs(args, |x, y| (x - y) * (x - y)),
+        "seriessum" => |_ctx, args| {
+            let [x, n, m, coefficients] = exact_args(args)?;
+            let (x, n, m) = (x.to_number()?, n.to_number()?, m.to_number()?);
+            let mut ret = 0.0;
+            for (i, a) in coefficients.to_numbers()?.into_iter().enumerate() {
+                ret += a * x.powf(n + i as f64 * m);
+            }
+            Ok(Value::Number(ret))
         },
-        "product" => |args| product(&args.inner).map(Value::Number),
-        "*" => array_mapped!(|[a, b]| Ok(Value::Number(a.to_number()? * b.to_number()?))),
-        "/" => array_mapped!(|[a, b]| Ok(Value::Number(a.to_number()? / b.to_number()?))),
-        "^" | "**" => {
-            array_mapped!(|[a, b]| Ok(Value::Number(a.to_number()?.powf(b.to_number()?))))
+        "round" => array_mapped!(|[n, digits]| {
+            let digits = digits.to_number()?.trunc() as i64;
+            Ok(Value::Number(arithmetic::round(n.to_number()?, digits)))
+        }),
+        "floor" => |_ctx, args| round_to_multiple(args, RoundingDirection::Down, false),
+        "ceiling" => |_ctx, args| round_to_multiple(args, RoundingDirection::Up, false),
+        "floor.precise" => |_ctx, args| round_to_multiple(args, RoundingDirection::Down, true),
+        "ceiling.precise" | "iso.ceiling" => {
+            |_ctx, args| round_to_multiple(args, RoundingDirection::Up, true)
         }
-        "%" => array_mapped!(|[n]| Ok(Value::Number(n.to_number()? / 100.0))),
+        "randarray" => randarray,
+        "sequence" => sequence,
+        "*" => |ctx, args| numeric_op(ctx, args, |[a, b]| Ok(arithmetic::mul(a, b))),
+        "/" => |ctx, args| numeric_op(ctx, args, |[a, b]| Ok(arithmetic::div(a, b))),
+        "^" | "**" => |ctx, args| numeric_op(ctx, args, |[a, b]| Ok(a.powf(b))),
+        "%" => |ctx, args| numeric_op(ctx, args, |[n]| Ok(arithmetic::div(n, 100.0))),
 
-        // Logic functions (non-short-circuiting)
+        // Logic functions. `IF`, `AND`, and `OR` only evaluate the arguments
+        // they need, which is handled when evaluating the AST; `IF` is only
+        // called here when its condition is an array.
         "true" => constant_function!(Ok(Value::Bool(true))),
         "false" => constant_function!(Ok(Value::Bool(false))),
         "not" => array_mapped!(|[a]| Ok(Value::Bool(!a.to_bool()?))),
-        "and" => |args| {
-            flat_iter_bools(&args.inner)
-                .try_fold(true, |ret, next| FormulaResult::Ok(ret & next?))
-                .map(Value::Bool)
-        },
-        "or" => |args| {
-            flat_iter_bools(&args.inner)
-                .try_fold(false, |ret, next| FormulaResult::Ok(ret | next?))
-                .map(Value::Bool)
-        },
-        "xor" => |args| {
+        "xor" => |_ctx, args| {
             flat_iter_bools(&args.inner)
                 .try_fold(false, |ret, next| FormulaResult::Ok(ret ^ next?))
                 .map(Value::Bool)
@@ -82,54 +154,1999 @@ pub fn pure_function_from_name(
             array_mapped!(|[cond, t, f]| { Ok(if cond.to_bool()? { t.inner } else { f.inner }) })
         }
 
+        // Error functions
+        "na" => constant_function!(Ok(Value::Error(FormulaErrorMsg::NotAvailable))),
+        "error" => array_mapped!(|[name]| {
+            let s = name.inner.to_string();
+            FormulaErrorMsg::from_excel_code(&s.to_ascii_uppercase())
+                .or_else(|| FormulaErrorMsg::from_short_name(&s))
+                .map(Value::Error)
+                .ok_or_else(|| FormulaErrorMsg::BadErrorCode.with_span(name.span))
+        }),
+        "isblank" => array_mapped!(|[value]| Ok(Value::Bool(value.inner == Value::Blank))),
+
         // Statistics functions
         // TODO: many of these have strange behavior when given zero arguments
-        "average" => |args| Ok(Value::Number(sum(&args.inner)? / count(&args.inner) as f64)),
-        "count" => |args| Ok(Value::Number(count(&args.inner) as f64)),
-        "min" => |args| {
+        "average" => |ctx, args| {
+            warn_text_in_ranges(ctx, &args.inner);
+            let coerce_text = ctx.settings.coerce_numeric_text;
+            let count = match coerce_text {
+                true => count(&args.inner),
+                false => count_ignoring_text_in_arrays(&args.inner),
+            };
+            Ok(Value::Number(sum(&args.inner, coerce_text)? / count as f64))
+        },
+        "count" => |_ctx, args| Ok(Value::Number(count(&args.inner) as f64)),
+        "countunique" => |ctx, args| {
+            if args.inner.is_empty() {
+                return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+            }
+            // Values are compared the same way as `=`. Errors are ignored
+            // like blanks, rather than counted.
+            let coercion = ctx.coercion();
+            let mut distinct: Vec<Spanned<Value>> = vec![];
+            for value in flat_iter_values(&args.inner) {
+                if value.inner.is_blank_or_empty() || value.inner.is_error() {
+                    continue;
+                }
+                if !distinct.iter().any(|v| coercion.values_eq(v, &value)) {
+                    distinct.push(value);
+                }
+            }
+            Ok(Value::Number(distinct.len() as f64))
+        },
+        "countblank" => |_ctx, args| {
+            // Unlike `ISBLANK`, this also counts empty strings.
+            let [range] = exact_args(args)?;
+            let blanks = flat_iter_values(std::slice::from_ref(&range))
+                .filter(|v| v.inner.is_blank_or_empty())
+                .count();
+            Ok(Value::Number(blanks as f64))
+        },
+        "min" => |ctx, args| {
+            warn_text_in_ranges(ctx, &args.inner);
             Ok(Value::Number(
-                flat_iter_numbers(&args.inner).try_fold(f64::INFINITY, |ret, next| {
-                    FormulaResult::Ok(f64::min(ret, next?))
-                })?,
+                flat_iter_numbers(&args.inner, ctx.settings.coerce_numeric_text)
+                    .try_fold(f64::INFINITY, |ret, next| {
+                        FormulaResult::Ok(f64::min(ret, next?))
+                    })?,
             ))
         },
-        "max" => |args| {
+        "max" => |ctx, args| {
+            warn_text_in_ranges(ctx, &args.inner);
+            Ok(Value::Number(
+                flat_iter_numbers(&args.inner, ctx.settings.coerce_numeric_text)
+                    .try_fold(-f64::INFINITY, |ret, next| {
+                        FormulaResult::Ok(f64::max(ret, next?))
+                    })?,
+            ))
+        },
+        "sumsq" => |_ctx, args| {
+            let numbers = numbers_ignoring_text(&args)?;
+            let result = numbers
+                .iter()
+                .fold(0.0, |sum, x| arithmetic::add(sum, x * x));
+            Ok(Value::Number(result))
+        },
+        "devsq" => |_ctx, args| {
+            let numbers = numbers_ignoring_text(&args)?;
+            let mean = mean(&numbers).map_err(|e| e.with_span(args.span))?;
             Ok(Value::Number(
-                flat_iter_numbers(&args.inner).try_fold(-f64::INFINITY, |ret, next| {
-                    FormulaResult::Ok(f64::max(ret, next?))
-                })?,
+                numbers.iter().map(|x| (x - mean).powi(2)).sum(),
             ))
         },
+        "avedev" => |_ctx, args| {
+            let numbers = numbers_ignoring_text(&args)?;
+            let mean = mean(&numbers).map_err(|e| e.with_span(args.span))?;
+            let deviations = numbers.iter().map(|x| (x - mean).abs()).collect_vec();
+            Ok(Value::Number(
+                deviations.iter().sum::<f64>() / deviations.len() as f64,
+            ))
+        },
+        "geomean" => |_ctx, args| {
+            let numbers = numbers_ignoring_text(&args)?;
+            if numbers.iter().any(|&x| x <= 0.0) {
+                return Err(FormulaErrorMsg::BadNumber.with_span(args.span));
+            }
+            let logs = numbers.iter().map(|x| x.ln()).collect_vec();
+            let mean_log = mean(&logs).map_err(|e| e.with_span(args.span))?;
+            Ok(Value::Number(mean_log.exp()))
+        },
+        "harmean" => |_ctx, args| {
+            let numbers = numbers_ignoring_text(&args)?;
+            if numbers.iter().any(|&x| x < 0.0) {
+                return Err(FormulaErrorMsg::BadNumber.with_span(args.span));
+            }
+            if numbers.contains(&0.0) {
+                return Err(FormulaErrorMsg::DivideByZero.with_span(args.span));
+            }
+            let reciprocals = numbers.iter().map(|x| 1.0 / x).collect_vec();
+            let mean_reciprocal = mean(&reciprocals).map_err(|e| e.with_span(args.span))?;
+            Ok(Value::Number(1.0 / mean_reciprocal))
+        },
+        "frequency" => |_ctx, args| {
+            let [data, bins] = exact_args(args)?;
+            frequency(&data, &bins)
+        },
 
-        // String functions
-        "&" => {
-            array_mapped!(|[a, b]| Ok(Value::String(a.to_string() + &b.to_string())))
+        // Conditional statistics functions
+        // These return zero if no values match, like Excel, except for
+        // `AVERAGEIF` and `AVERAGEIFS`.
+        "sumif" => {
+            |ctx, args| conditional_aggregate(ctx, &args, CriteriaArgs::Single, Aggregation::Sum)
         }
-        "concat" => |args| {
-            Ok(Value::String(
-                flat_iter_strings(&args.inner)
-                    .try_fold(String::new(), |ret, next| FormulaResult::Ok(ret + &next?))?,
-            ))
+        "sumifs" => {
+            |ctx, args| conditional_aggregate(ctx, &args, CriteriaArgs::Multiple, Aggregation::Sum)
+        }
+        "countif" => |ctx, args| {
+            // Unlike `SUMIF`, there is no separate range of values.
+            if args.inner.len() > 2 {
+                return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+            }
+            conditional_count(ctx, &args, CriteriaArgs::Single)
+        },
+        "countifs" => {
+            |ctx, args| conditional_count(ctx, &args, CriteriaArgs::MultipleWithoutEvalRange)
+        }
+        "averageif" => |ctx, args| {
+            conditional_aggregate(ctx, &args, CriteriaArgs::Single, Aggregation::Average)
         },
+        "averageifs" => |ctx, args| {
+            conditional_aggregate(ctx, &args, CriteriaArgs::Multiple, Aggregation::Average)
+        },
+        "maxifs" => {
+            |ctx, args| conditional_aggregate(ctx, &args, CriteriaArgs::Multiple, Aggregation::Max)
+        }
+        "minifs" => {
+            |ctx, args| conditional_aggregate(ctx, &args, CriteriaArgs::Multiple, Aggregation::Min)
+        }
+
+        // Matrix functions
+        "mmult" => |ctx, args| {
+            let span = args.span;
+            let [a, b] = exact_args(args)?;
+            let (a_matrix, b_matrix) = (Matrix::from_value(&a)?, Matrix::from_value(&b)?);
+            let (rows, cols) = (a_matrix.rows() as u64, b_matrix.cols() as u64);
+            ctx.check_array_size(rows, cols)
+                .and_then(|()| {
+                    let inner = a_matrix.cols() as u64;
+                    ctx.take_steps(rows.saturating_mul(cols).saturating_mul(inner))
+                })
+                .map_err(|e| e.with_span(span))?;
+            match a_matrix.matmul(&b_matrix) {
+                Some(product) => Ok(product.to_value()),
+                None => Err(FormulaErrorMsg::ArraySizeMismatch {
+                    expected: (a_matrix.cols(), b_matrix.cols()),
+                    got: b_matrix.size(),
+                }
+                .with_span(b.span)),
+            }
+        },
+        "minverse" => |ctx, args| {
+            let [m] = exact_args(args)?;
+            let matrix = Matrix::from_square_value(&m)?;
+            take_decomposition_steps(ctx, &matrix).map_err(|e| e.with_span(m.span))?;
+            match matrix.inverse() {
+                Some(inverse) => Ok(inverse.to_value()),
+                None => Err(FormulaErrorMsg::BadNumber.with_span(m.span)),
+            }
+        },
+        "mdeterm" => |ctx, args| {
+            let [m] = exact_args(args)?;
+            let matrix = Matrix::from_square_value(&m)?;
+            take_decomposition_steps(ctx, &matrix).map_err(|e| e.with_span(m.span))?;
+            Ok(Value::Number(matrix.determinant()))
+        },
+        "munit" => |ctx, args| {
+            let [n] = exact_args(args)?;
+            let size = n.to_integer()?;
+            if size < 1 {
+                return Err(FormulaErrorMsg::InvalidArgument.with_span(n.span));
+            }
+            ctx.take_steps((size as u64).saturating_mul(size as u64))
+                .and_then(|()| ctx.check_array_size(size as u64, size as u64))
+                .map_err(|e| e.with_span(n.span))?;
+            Ok(Matrix::identity(size as usize).to_value())
+        },
+
+        // Regression functions
+        "slope" => |_ctx, args| pair_stat(args, PairStats::slope),
+        "intercept" => |_ctx, args| pair_stat(args, PairStats::intercept),
+        "correl" => |_ctx, args| pair_stat(args, PairStats::correlation),
+        "rsq" => |_ctx, args| pair_stat(args, |stats| Some(stats.correlation()?.powi(2))),
+        "linest" => |_ctx, args| linest(args, false),
+        "logest" => |_ctx, args| linest(args, true),
+        "trend" => |_ctx, args| trend(args, false),
+        "growth" => |_ctx, args| trend(args, true),
+
+        // Probability distributions
+        "norm.dist" | "normdist" => array_mapped!(|[x, mean, std_dev, cumulative]| {
+            normal_dist(&x, &mean, &std_dev, &cumulative)
+        }),
+        "norm.inv" | "norminv" => {
+            array_mapped!(|[p, mean, std_dev]| normal_inv(&p, &mean, &std_dev))
+        }
+        "norm.s.dist" => array_mapped!(|[z, cumulative]| {
+            let z = z.to_number()?;
+            Ok(Value::Number(match cumulative.to_bool()? {
+                true => distribution::std_normal_cdf(z),
+                false => distribution::std_normal_pdf(z),
+            }))
+        }),
+        // `NORMSDIST` is the older form of `NORM.S.DIST`, which is always
+        // cumulative.
+        "normsdist" => {
+            array_mapped!(|[z]| { Ok(Value::Number(distribution::std_normal_cdf(z.to_number()?))) })
+        }
+        "norm.s.inv" | "normsinv" => array_mapped!(|[p]| {
+            let z = distribution::std_normal_inv(p.to_number()?)
+                .ok_or_else(|| FormulaErrorMsg::BadNumber.with_span(p.span))?;
+            Ok(Value::Number(z))
+        }),
+        "phi" => array_mapped!(|[x]| {
+            let x = x.to_number()?;
+            Ok(Value::Number(distribution::std_normal_pdf(x)))
+        }),
+        "gauss" => array_mapped!(|[z]| {
+            let z = z.to_number()?;
+            Ok(Value::Number(distribution::std_normal_cdf(z) - 0.5))
+        }),
+        "binom.dist" | "binomdist" => |ctx, args| {
+            array_map(args, |[successes, trials, probability, cumulative]| {
+                let n = distribution_count(&trials)?;
+                let k = distribution_count(&successes)?;
+                if k > n {
+                    return Err(FormulaErrorMsg::BadNumber.with_span(successes.span));
+                }
+                let p = distribution_probability(&probability)?;
+                let pmf = |i| distribution::binomial_pmf(i, n, p);
+                distribution_value(ctx, 0.0, k, &cumulative, pmf)
+            })
+        },
+        "poisson.dist" | "poisson" => |ctx, args| {
+            array_map(args, |[x, mean, cumulative]| {
+                let k = distribution_count(&x)?;
+                let mean_number = mean.to_number()?;
+                if mean_number.is_nan() || mean_number < 0.0 {
+                    return Err(FormulaErrorMsg::BadNumber.with_span(mean.span));
+                }
+                let pmf = |i| distribution::poisson_pmf(i, mean_number);
+                distribution_value(ctx, 0.0, k, &cumulative, pmf)
+            })
+        },
+        "hypgeom.dist" => |ctx, args| {
+            array_map(
+                args,
+                |[sample_s, number_sample, population_s, number_pop, cumulative]| {
+                    let population = distribution_count(&number_pop)?;
+                    let n = distribution_count(&number_sample)?;
+                    let successes = distribution_count(&population_s)?;
+                    let k = distribution_count(&sample_s)?;
+                    for (count, arg) in [(n, &number_sample), (successes, &population_s)] {
+                        if count > population {
+                            return Err(FormulaErrorMsg::BadNumber.with_span(arg.span));
+                        }
+                    }
+                    // The sample can't have more successes than there are in the
+                    // sample or the population, or fewer than the number of its
+                    // elements that aren't failures.
+                    let min = (n - (population - successes)).max(0.0);
+                    if k < min || k > n.min(successes) {
+                        return Err(FormulaErrorMsg::BadNumber.with_span(sample_s.span));
+                    }
+                    let pmf = |i| distribution::hypergeometric_pmf(i, n, successes, population);
+                    distribution_value(ctx, min, k, &cumulative, pmf)
+                },
+            )
+        },
+        "expon.dist" | "expondist" => array_mapped!(|[x, lambda, cumulative]| {
+            let x_number = x.to_number()?;
+            if x_number.is_nan() || x_number < 0.0 {
+                return Err(FormulaErrorMsg::BadNumber.with_span(x.span));
+            }
+            let rate = lambda.to_number()?;
+            if rate.is_nan() || rate <= 0.0 {
+                return Err(FormulaErrorMsg::BadNumber.with_span(lambda.span));
+            }
+            Ok(Value::Number(match cumulative.to_bool()? {
+                true => -(-rate * x_number).exp_m1(),
+                false => rate * (-rate * x_number).exp(),
+            }))
+        }),
+        "gammaln" | "gammaln.precise" => array_mapped!(|[x]| {
+            let n = x.to_number()?;
+            if n.is_nan() || n <= 0.0 {
+                return Err(FormulaErrorMsg::BadNumber.with_span(x.span));
+            }
+            Ok(Value::Number(distribution::ln_gamma(n)))
+        }),
+
+        // Database functions
+        "dsum" => |ctx, args| database_aggregate(ctx, args, Aggregation::Sum),
+        "daverage" => |ctx, args| database_aggregate(ctx, args, Aggregation::Average),
+        "dcount" => |ctx, args| database_aggregate(ctx, args, Aggregation::Count),
+        "dmax" => |ctx, args| database_aggregate(ctx, args, Aggregation::Max),
+        "dmin" => |ctx, args| database_aggregate(ctx, args, Aggregation::Min),
+        "dget" => |ctx, args| {
+            let span = args.span;
+            match database::matching_values(
+                args,
+                ctx.settings.date_system,
+                ctx.settings.text_compare,
+            )?
+            .as_slice()
+            {
+                [value] => Ok(value.clone()),
+                [] => Err(FormulaErrorMsg::NotAvailable.with_span(span)),
+                _ => Err(FormulaErrorMsg::BadNumber.with_span(span)),
+            }
+        },
+
+        // Date functions
+        "date" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            let span = args.span;
+            array_map(args, |[year, month, day]| {
+                // Like Excel, years before 1900 are relative to 1900.
+                let year_number = match year.to_number()?.trunc() {
+                    y if (0.0..1900.0).contains(&y) => y + 1900.0,
+                    y if (1900.0..10_000.0).contains(&y) => y,
+                    _ => return Err(FormulaErrorMsg::InvalidDate.with_span(year.span)),
+                };
+                let days = day.to_number()?.trunc();
+                // There are fewer than 10,000 years of valid dates.
+                if days.abs() > 366.0 * 10_000.0 {
+                    return Err(FormulaErrorMsg::InvalidDate.with_span(day.span));
+                }
+                let serial = date::serial_from_parts(
+                    year_number as i64,
+                    date::to_months(&month)?,
+                    days as i64,
+                );
+                date::serial_value(serial, system, span)
+            })
+        },
+        "weeknum" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            match args.inner.len() {
+                1 => array_map(args, |[date]| {
+                    let week = date::week_number(date::to_serial(&date, system)?, 0);
+                    Ok(Value::Number(week as f64))
+                }),
+                _ => array_map(args, |[date, return_type]| {
+                    let serial = date::to_serial(&date, system)?;
+                    let week = match return_type.to_integer()? {
+                        1 => date::week_number(serial, 0),
+                        2 => date::week_number(serial, 1),
+                        // 11 through 17 start the week on Monday through Sunday.
+                        n @ 11..=17 => date::week_number(serial, (n - 10) as u32 % 7),
+                        21 => date::iso_week_number(serial),
+                        _ => {
+                            return Err(FormulaErrorMsg::InvalidArgument.with_span(return_type.span))
+                        }
+                    };
+                    Ok(Value::Number(week as f64))
+                }),
+            }
+        },
+        "isoweeknum" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[date]| {
+                let week = date::iso_week_number(date::to_serial(&date, system)?);
+                Ok(Value::Number(week as f64))
+            })
+        },
+
+        "edate" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[start, months]| {
+                let date = Date::from_serial(date::to_serial(&start, system)?);
+                let result = date.add_months(date::to_months(&months)?);
+                date::serial_value(result.to_serial(), system, start.span)
+            })
+        },
+        "eomonth" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[start, months]| {
+                let date = Date::from_serial(date::to_serial(&start, system)?);
+                let result = date.add_months(date::to_months(&months)?).end_of_month();
+                date::serial_value(result.to_serial(), system, start.span)
+            })
+        },
+        "year" => |ctx, args| date_part(ctx, args, |date| date.year),
+        "month" => |ctx, args| date_part(ctx, args, |date| date.month as i64),
+        "day" => |ctx, args| date_part(ctx, args, |date| date.day as i64),
+        #[cfg(feature = "datetime")]
+        "datetime" => array_mapped!(|[value]| datetime::to_datetime(&value)),
+        "datevalue" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[text]| match date::parse_date(&text.to_string()) {
+                Some(serial) => date::serial_value(serial, system, text.span),
+                None => Err(FormulaErrorMsg::Expected {
+                    expected: "date".into(),
+                    got: Some(format!("{:?}", text.to_string()).into()),
+                }
+                .with_span(text.span)),
+            })
+        },
+        "timevalue" => array_mapped!(|[text]| match date::parse_time(&text.to_string()) {
+            Some(time) => Ok(Value::Number(time)),
+            None => Err(FormulaErrorMsg::Expected {
+                expected: "time".into(),
+                got: Some(format!("{:?}", text.to_string()).into()),
+            }
+            .with_span(text.span)),
+        }),
+        "workday" => |ctx, args| workday(ctx, args, false),
+        "workday.intl" => |ctx, args| workday(ctx, args, true),
+        "networkdays" => |ctx, args| networkdays(ctx, args, false),
+        "networkdays.intl" => |ctx, args| networkdays(ctx, args, true),
+        "days" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[end, start]| {
+                let days = date::to_serial(&end, system)? - date::to_serial(&start, system)?;
+                Ok(Value::Number(days as f64))
+            })
+        },
+        "days360" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            let days_360 = |start: &Spanned<Value>, end: &Spanned<Value>, european| {
+                let start = date::to_serial(start, system)?;
+                let end = date::to_serial(end, system)?;
+                Ok(Value::Number(date::days_360(start, end, european) as f64))
+            };
+            match args.inner.len() {
+                2 => array_map(args, |[start, end]| days_360(&start, &end, false)),
+                _ => array_map(args, |[start, end, method]| {
+                    days_360(&start, &end, method.to_bool()?)
+                }),
+            }
+        },
+        "yearfrac" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            let yearfrac = |start: &Spanned<Value>, end: &Spanned<Value>, basis: DayCountBasis| {
+                let start = date::to_serial(start, system)?;
+                let end = date::to_serial(end, system)?;
+                Ok(Value::Number(basis.year_fraction(start, end)))
+            };
+            match args.inner.len() {
+                2 => array_map(args, |[start, end]| {
+                    yearfrac(&start, &end, DayCountBasis::Us30360)
+                }),
+                _ => array_map(args, |[start, end, basis]| {
+                    yearfrac(&start, &end, DayCountBasis::try_from_value(&basis)?)
+                }),
+            }
+        },
+
+        // String functions
+        "&" => |ctx, args| {
+            let span = args.span;
+            array_map(args, |[a, b]| {
+                let (a, b) = (a.to_string(), b.to_string());
+                ctx.check_text_length(a.len() + b.len())
+                    .map_err(|e| e.with_span(span))?;
+                Ok(Value::String(a + &b))
+            })
+        },
+        "len" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            array_map(args, |[text]| {
+                Ok(Value::Number(unit.split(&text.to_string()).len() as f64))
+            })
+        },
+        "left" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            match args.inner.len() {
+                1 => array_map(args, |[text]| left_or_right(unit, &text, None, false)),
+                _ => array_map(args, |[text, n]| {
+                    left_or_right(unit, &text, Some(&n), false)
+                }),
+            }
+        },
+        "right" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            match args.inner.len() {
+                1 => array_map(args, |[text]| left_or_right(unit, &text, None, true)),
+                _ => array_map(args, |[text, n]| left_or_right(unit, &text, Some(&n), true)),
+            }
+        },
+        "mid" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            array_map(args, |[text, start, n]| {
+                let start = start_index(&start)?;
+                let n = char_count(&n)?;
+                let s = text.to_string();
+                let chars = unit.split(&s);
+                let start = start.min(chars.len());
+                let end = start.saturating_add(n).min(chars.len());
+                Ok(Value::String(chars[start..end].concat()))
+            })
+        },
+        "substitute" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            let span = args.span;
+            let substitute = |[text, old, new]: [Spanned<Value>; 3], instance| {
+                let (text, old, new) = (text.to_string(), old.to_string(), new.to_string());
+                let s = unit.substitute(&text, &old, &new, instance);
+                ctx.check_text_length(s.len())
+                    .map_err(|e| e.with_span(span))?;
+                Ok(Value::String(s))
+            };
+            match args.inner.len() {
+                3 => array_map(args, |[text, old, new]| substitute([text, old, new], None)),
+                _ => array_map(args, |[text, old, new, instance]| {
+                    let n = match instance.to_number()?.trunc() {
+                        n if n >= 1.0 => n as usize,
+                        _ => return Err(FormulaErrorMsg::BadNumber.with_span(instance.span)),
+                    };
+                    substitute([text, old, new], Some(n))
+                }),
+            }
+        },
+        "replace" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            let span = args.span;
+            array_map(args, |[text, start, n, new]| {
+                let start = start_index(&start)?;
+                let n = char_count(&n)?;
+                let s = text.to_string();
+                let chars = unit.split(&s);
+                let start = start.min(chars.len());
+                let end = start.saturating_add(n).min(chars.len());
+                let new = new.to_string();
+                let ret = chars[..start].concat() + &new + &chars[end..].concat();
+                ctx.check_text_length(ret.len())
+                    .map_err(|e| e.with_span(span))?;
+                Ok(Value::String(ret))
+            })
+        },
+        "proper" => |ctx, args| {
+            let unit = ctx.settings.text_unit;
+            array_map(args, |[text]| {
+                Ok(Value::String(unit.proper(&text.to_string())))
+            })
+        },
+        "exact" => array_mapped!(|[a, b]| Ok(Value::Bool(a.to_string() == b.to_string()))),
+        "concat" => |ctx, args| {
+            let mut ret = String::new();
+            for next in flat_iter_strings(&args.inner) {
+                let next = next?;
+                ctx.check_text_length(ret.len() + next.len())
+                    .map_err(|e| e.with_span(args.span))?;
+                ret += &next;
+            }
+            Ok(Value::String(ret))
+        },
+        "rept" => |ctx, args| {
+            let span = args.span;
+            array_map(args, |[text, times]| {
+                let n = times.to_number()?.trunc();
+                if n < 0.0 {
+                    return Err(FormulaErrorMsg::InvalidArgument.with_span(times.span));
+                }
+                // Check the length before allocating anything.
+                let text = text.to_string();
+                ctx.check_text_length(text.len().saturating_mul(n as usize))
+                    .map_err(|e| e.with_span(span))?;
+                Ok(Value::String(text.repeat(n as usize)))
+            })
+        },
+        "text" => |ctx, args| {
+            let system = ctx.settings.date_system;
+            array_map(args, |[value, format]| {
+                let fmt = NumberFormat::parse(&format.to_string())
+                    .ok_or_else(|| FormulaErrorMsg::InvalidArgument.with_span(format.span))?
+                    .with_date_system(system);
+                value.check_error()?;
+                // Text that looks like a number is formatted as a number.
+                let n = match &value.inner {
+                    Value::Number(n) => Some(*n),
+                    Value::String(s) if !s.trim().is_empty() => value.to_number().ok(),
+                    _ => None,
+                };
+                match n {
+                    Some(n) => match fmt.format_number(n) {
+                        Some(s) => Ok(Value::String(s)),
+                        None => Err(FormulaErrorMsg::InvalidDate.with_span(value.span)),
+                    },
+                    None => Ok(Value::String(value.inner.format(&fmt))),
+                }
+            })
+        },
+        "dollar" => |ctx, args| {
+            let locale = ctx.settings.number_locale;
+            match args.inner.len() {
+                1 => array_map(args, |[n]| format_fixed(&n, None, true, true, locale)),
+                _ => array_map(args, |[n, decimals]| {
+                    format_fixed(&n, Some(&decimals), true, true, locale)
+                }),
+            }
+        },
+        "fixed" => |ctx, args| {
+            let locale = ctx.settings.number_locale;
+            match args.inner.len() {
+                1 => array_map(args, |[n]| format_fixed(&n, None, true, false, locale)),
+                2 => array_map(args, |[n, decimals]| {
+                    format_fixed(&n, Some(&decimals), true, false, locale)
+                }),
+                _ => array_map(args, |[n, decimals, no_commas]| {
+                    let commas = !no_commas.to_bool()?;
+                    format_fixed(&n, Some(&decimals), commas, false, locale)
+                }),
+            }
+        },
+        "numbervalue" => |_ctx, args| match args.inner.len() {
+            1 => array_map(args, |[text]| number_value(&text, None, None)),
+            2 => array_map(args, |[text, decimal_sep]| {
+                number_value(&text, Some(&decimal_sep), None)
+            }),
+            _ => array_map(args, |[text, decimal_sep, group_sep]| {
+                number_value(&text, Some(&decimal_sep), Some(&group_sep))
+            }),
+        },
+        "textsplit" => |_ctx, args| text_split(args),
+        "arraytotext" => |_ctx, args| {
+            let [Some(array), format] = optional_args(args, 1)? else {
+                internal_error!("missing required argument");
+            };
+            let strict = is_strict_text_format(format.as_ref())?;
+            let text = match &array.inner {
+                Value::Array(_) if strict => array.inner.to_formula_literal(),
+                Value::Array(a) => a.iter().join(", "),
+                other if strict => format!("{{{}}}", other.to_formula_literal()),
+                other => other.to_string(),
+            };
+            Ok(Value::String(text))
+        },
+        "valuetotext" => |_ctx, args| {
+            let [Some(value), format] = optional_args(args, 1)? else {
+                internal_error!("missing required argument");
+            };
+            let strict = is_strict_text_format(format.as_ref())?;
+            let to_text = |v: &Value| match strict {
+                true => Value::String(v.to_formula_literal()),
+                false => Value::String(v.to_string()),
+            };
+            Ok(match &value.inner {
+                Value::Array(a) => Value::Array(a.map(to_text)),
+                other => to_text(other),
+            })
+        },
+        "encodeurl" => array_mapped!(|[text]| Ok(Value::String(encode_url(&text.to_string())))),
+        "code" => array_mapped!(|[text]| char_code(&text, true)),
+        "unicode" => array_mapped!(|[text]| char_code(&text, false)),
+        "char" => array_mapped!(|[code]| char_from_code(&code, true)),
+        "unichar" => array_mapped!(|[code]| char_from_code(&code, false)),
+        "regexmatch" => |_ctx, args| {
+            let mut regexes = RegexCache::default();
+            array_map(args, |[text, pattern]| {
+                let regex = regexes.get(&pattern)?;
+                Ok(Value::Bool(regex.is_match(&text.to_string())))
+            })
+        },
+        "regexextract" => regex_extract,
+        "regexreplace" => |ctx, args| {
+            let span = args.span;
+            let mut regexes = RegexCache::default();
+            array_map(args, |[text, pattern, replacement]| {
+                let regex = regexes.get(&pattern)?;
+                let (text, replacement) = (text.to_string(), replacement.to_string());
+                pattern::replace_all(ctx, regex, &text, &replacement)
+                    .map(Value::String)
+                    .map_err(|e| e.with_span(span))
+            })
+        },
+
+        // Engineering functions
+        "bin2dec" => |_ctx, args| convert_base(args, Radix::Bin, Radix::Dec),
+        "bin2oct" => |_ctx, args| convert_base(args, Radix::Bin, Radix::Oct),
+        "bin2hex" => |_ctx, args| convert_base(args, Radix::Bin, Radix::Hex),
+        "oct2dec" => |_ctx, args| convert_base(args, Radix::Oct, Radix::Dec),
+        "oct2bin" => |_ctx, args| convert_base(args, Radix::Oct, Radix::Bin),
+        "oct2hex" => |_ctx, args| convert_base(args, Radix::Oct, Radix::Hex),
+        "dec2bin" => |_ctx, args| convert_base(args, Radix::Dec, Radix::Bin),
+        "dec2oct" => |_ctx, args| convert_base(args, Radix::Dec, Radix::Oct),
+        "dec2hex" => |_ctx, args| convert_base(args, Radix::Dec, Radix::Hex),
+        "hex2dec" => |_ctx, args| convert_base(args, Radix::Hex, Radix::Dec),
+        "hex2bin" => |_ctx, args| convert_base(args, Radix::Hex, Radix::Bin),
+        "hex2oct" => |_ctx, args| convert_base(args, Radix::Hex, Radix::Oct),
+        "bitand" => array_mapped!(|[a, b]| bitwise_op(&a, &b, |a, b| a & b)),
+        "bitor" => array_mapped!(|[a, b]| bitwise_op(&a, &b, |a, b| a | b)),
+        "bitxor" => array_mapped!(|[a, b]| bitwise_op(&a, &b, |a, b| a ^ b)),
+        "bitlshift" => array_mapped!(|[n, amount]| {
+            Ok(Value::Number(bitwise::shift(&n, &amount, false)? as f64))
+        }),
+        "bitrshift" => array_mapped!(|[n, amount]| {
+            Ok(Value::Number(bitwise::shift(&n, &amount, true)? as f64))
+        }),
+        "convert" => array_mapped!(|[number, from, to]| {
+            let n = number.to_number()?;
+            let unit = |arg: &Spanned<Value>| {
+                let s = arg.inner.to_string();
+                Unit::parse(&s)
+                    .ok_or_else(|| FormulaErrorMsg::UnknownUnit(format!("{s:?}").into()))
+                    .map_err(|e| e.with_span(arg.span))
+            };
+            let (from_unit, to_unit) = (unit(&from)?, unit(&to)?);
+            match from_unit.convert(n, to_unit) {
+                Some(n) => Ok(Value::Number(n)),
+                None => Err(FormulaErrorMsg::NotAvailable.with_span(to.span)),
+            }
+        }),
+        "complex" => |_ctx, args| match args.inner.len() {
+            3 => array_map(args, |[re, im, suffix]| {
+                let suffix = Complex::parse_suffix(&suffix)?;
+                complex_value(re.to_number()?, im.to_number()?, suffix)
+            }),
+            _ => array_map(args, |[re, im]| {
+                complex_value(re.to_number()?, im.to_number()?, 'i')
+            }),
+        },
+        "imreal" => array_mapped!(|[c]| Ok(Value::Number(Complex::from_value(&c)?.re))),
+        "imaginary" => array_mapped!(|[c]| Ok(Value::Number(Complex::from_value(&c)?.im))),
+        "imabs" => array_mapped!(|[c]| Ok(Value::Number(Complex::from_value(&c)?.abs()))),
+        "imsum" => |_ctx, args| complex_fold(args, 0.0, Complex::add),
+        "improduct" => |_ctx, args| complex_fold(args, 1.0, Complex::mul),
+
+        // Lookup functions
+        "xmatch" => |ctx, args| {
+            let span = args.span;
+            let [needle, haystack, match_mode, search_mode] = optional_args(args, 2)?;
+            let (needle, haystack) = (needle.unwrap(), haystack.unwrap());
+            let text_compare = ctx.settings.text_compare;
+            match lookup_index(&needle, &haystack, match_mode, search_mode, text_compare)? {
+                Some(i) => Ok(Value::Number((i + 1) as f64)),
+                None => Err(FormulaErrorMsg::NotAvailable.with_span(span)),
+            }
+        },
+        "xlookup" => xlookup,
+        "match" => |ctx, args| {
+            let span = args.span;
+            let [needle, haystack, match_type] = optional_args(args, 2)?;
+            let (needle, haystack) = (needle.unwrap(), haystack.unwrap());
+            let needle = needle.to_scalar()?;
+            let (haystack, _) = lookup::lookup_vector(&haystack)?;
+            let (match_mode, search_mode) = match match_type.as_ref() {
+                None => (MatchMode::ExactOrNextSmaller, SearchMode::BinaryAscending),
+                Some(arg) => match arg.to_integer()?.signum() {
+                    1 => (MatchMode::ExactOrNextSmaller, SearchMode::BinaryAscending),
+                    0 => (MatchMode::Wildcard, SearchMode::FirstToLast),
+                    _ => (MatchMode::ExactOrNextLarger, SearchMode::BinaryDescending),
+                },
+            };
+            let text_compare = ctx.settings.text_compare;
+            match lookup::search(
+                &needle.inner,
+                &haystack,
+                match_mode,
+                search_mode,
+                text_compare,
+            ) {
+                Some(i) => Ok(Value::Number((i + 1) as f64)),
+                None => Err(FormulaErrorMsg::NotAvailable.with_span(span)),
+            }
+        },
+        "sort" => sort,
+        "unique" => unique,
+        "wraprows" => |ctx, args| wrap(ctx, args, false),
+        "wrapcols" => |ctx, args| wrap(ctx, args, true),
+        "tocol" => |ctx, args| flatten(ctx, args, true),
+        "torow" => |ctx, args| flatten(ctx, args, false),
 
         _ => return None,
     })
 }
 
-fn sum(args: &[Spanned<Value>]) -> FormulaResult<f64> {
-    flat_iter_numbers(args).try_fold(0.0, |sum, next| FormulaResult::Ok(sum + next?))
+/// Maps an operator over arguments that may be arrays, coercing them to
+/// numbers first.
+fn numeric_op<const N: usize>(
+    ctx: &Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    op: impl Fn([f64; N]) -> FormulaResult<f64>,
+) -> FormulaResult<Value> {
+    let coercion = ctx.coercion();
+    let span = args.span;
+    array_map(args, |values: [Spanned<Value>; N]| {
+        let mut numbers = [0.0; N];
+        for (n, v) in numbers.iter_mut().zip(&values) {
+            *n = coercion.to_number(v)?;
+        }
+        let result = op(numbers).map_err(|e| e.with_span(span))?;
+        Ok(Value::Number(result))
+    })
+}
+/// Maps a comparison over arguments that may be arrays. `cmp` is given the
+/// order of the two values, as returned by [`Coercion::compare()`].
+fn comparison(
+    ctx: &Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    cmp: fn(Ordering) -> bool,
+) -> FormulaResult<Value> {
+    let coercion = ctx.coercion();
+    array_map(args, |[a, b]| {
+        coercion.check_comparable(&a, &b)?;
+        Ok(Value::Bool(cmp(coercion.compare(&a, &b)?)))
+    })
+}
+
+/// Applies an aggregation to the values that match the criteria, ignoring
+/// non-numeric values.
+fn conditional_aggregate(
+    ctx: &Ctx<'_>,
+    args: &Spanned<Vec<Spanned<Value>>>,
+    layout: CriteriaArgs,
+    aggregation: Aggregation,
+) -> FormulaResult<Value> {
+    let values = criteria::values_with_criteria(
+        args,
+        layout,
+        ctx.settings.date_system,
+        ctx.settings.text_compare,
+    )?
+    .into_iter()
+    .filter(|(_, included)| *included)
+    .map(|(value, _)| value)
+    .collect_vec();
+    let result = aggregation
+        .apply(&values, None)
+        .map_err(|e| e.with_span(args.span))?;
+    Ok(Value::Number(result))
+}
+/// Applies an aggregation to the field of the database records that match the
+/// criteria, ignoring non-numeric values.
+fn database_aggregate(
+    ctx: &Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    aggregation: Aggregation,
+) -> FormulaResult<Value> {
+    let span = args.span;
+    let values =
+        database::matching_values(args, ctx.settings.date_system, ctx.settings.text_compare)?;
+    let result = aggregation
+        .apply(&values.iter().collect_vec(), None)
+        .map_err(|e| e.with_span(span))?;
+    Ok(Value::Number(result))
+}
+/// Counts the values that match the criteria, including non-numeric values.
+fn conditional_count(
+    ctx: &Ctx<'_>,
+    args: &Spanned<Vec<Spanned<Value>>>,
+    layout: CriteriaArgs,
+) -> FormulaResult<Value> {
+    let values = criteria::values_with_criteria(
+        args,
+        layout,
+        ctx.settings.date_system,
+        ctx.settings.text_compare,
+    )?;
+    let count = values.iter().filter(|(_, included)| *included).count();
+    Ok(Value::Number(count as f64))
+}
+
+/// Implements `NORM.DIST`, which returns the cumulative distribution or the
+/// probability density of a normal distribution. Returns an error if the
+/// standard deviation is not positive.
+fn normal_dist(
+    x: &Spanned<Value>,
+    mean: &Spanned<Value>,
+    std_dev: &Spanned<Value>,
+    cumulative: &Spanned<Value>,
+) -> FormulaResult<Value> {
+    let sd = std_dev.to_number()?;
+    let z = distribution::standardize(x.to_number()?, mean.to_number()?, sd)
+        .ok_or_else(|| FormulaErrorMsg::BadNumber.with_span(std_dev.span))?;
+    Ok(Value::Number(match cumulative.to_bool()? {
+        true => distribution::std_normal_cdf(z),
+        false => distribution::std_normal_pdf(z) / sd,
+    }))
+}
+/// Implements `NORM.INV`, the inverse of the cumulative distribution of a
+/// normal distribution. Returns an error if the standard deviation is not
+/// positive or the probability is not strictly between 0 and 1.
+fn normal_inv(
+    p: &Spanned<Value>,
+    mean: &Spanned<Value>,
+    std_dev: &Spanned<Value>,
+) -> FormulaResult<Value> {
+    let sd = std_dev.to_number()?;
+    if sd.is_nan() || sd <= 0.0 {
+        return Err(FormulaErrorMsg::BadNumber.with_span(std_dev.span));
+    }
+    let z = distribution::std_normal_inv(p.to_number()?)
+        .ok_or_else(|| FormulaErrorMsg::BadNumber.with_span(p.span))?;
+    Ok(Value::Number(mean.to_number()? + z * sd))
+}
+
+/// Returns a count for a discrete distribution, truncated to an integer.
+/// Returns an error if it is negative.
+fn distribution_count(value: &Spanned<Value>) -> FormulaResult<f64> {
+    let n = value.to_number()?.trunc();
+    if n.is_nan() || n < 0.0 {
+        return Err(FormulaErrorMsg::BadNumber.with_span(value.span));
+    }
+    Ok(n)
+}
+/// Returns a probability for a discrete distribution. Returns an error if it
+/// is not between 0 and 1.
+fn distribution_probability(value: &Spanned<Value>) -> FormulaResult<f64> {
+    let p = value.to_number()?;
+    if !(0.0..=1.0).contains(&p) {
+        return Err(FormulaErrorMsg::BadNumber.with_span(value.span));
+    }
+    Ok(p)
+}
+/// Returns the probability mass function of a discrete distribution at `k`,
+/// or if `cumulative` is true, the sum of it from `min` to `k`. Each term of
+/// the sum is one evaluation step.
+fn distribution_value(
+    ctx: &mut Ctx<'_>,
+    min: f64,
+    k: f64,
+    cumulative: &Spanned<Value>,
+    pmf: impl Fn(f64) -> f64,
+) -> FormulaResult<Value> {
+    if !cumulative.to_bool()? {
+        return Ok(Value::Number(pmf(k)));
+    }
+    ctx.take_steps((k - min + 1.0) as u64)?;
+    let mut sum = 0.0;
+    let mut i = min;
+    while i <= k {
+        sum += pmf(i);
+        i += 1.0;
+    }
+    // Rounding errors shouldn't make the probability greater than 1.
+    Ok(Value::Number(sum.min(1.0)))
+}
+
+/// Implements `WORKDAY` and `WORKDAY.INTL`, which takes a weekend argument
+/// before the holidays.
+fn workday(
+    ctx: &mut Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    intl: bool,
+) -> FormulaResult<Value> {
+    let span = args.span;
+    let system = ctx.settings.date_system;
+    let (start, days, weekend, holidays) = business_day_args(args, intl, system)?;
+    let start = date::to_serial(&start, system)?;
+    let days = days.to_number()?.trunc() as i64;
+    match date::add_workdays(start, days, weekend, &holidays) {
+        Some(serial) => date::serial_value(serial, system, span),
+        None => Err(FormulaErrorMsg::InvalidDate.with_span(span)),
+    }
 }
-fn product(args: &[Spanned<Value>]) -> FormulaResult<f64> {
-    flat_iter_numbers(args).try_fold(1.0, |prod, next| FormulaResult::Ok(prod * next?))
+/// Implements `NETWORKDAYS` and `NETWORKDAYS.INTL`, which takes a weekend
+/// argument before the holidays.
+fn networkdays(
+    ctx: &mut Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    intl: bool,
+) -> FormulaResult<Value> {
+    let system = ctx.settings.date_system;
+    let (start, end, weekend, holidays) = business_day_args(args, intl, system)?;
+    let start = date::to_serial(&start, system)?;
+    let end = date::to_serial(&end, system)?;
+    let days = date::workdays_between(start, end, weekend, &holidays);
+    Ok(Value::Number(days as f64))
+}
+/// Returns the arguments to a business day function: two required
+/// arguments, an optional weekend (only if `intl` is true), and optional
+/// holidays.
+#[allow(clippy::type_complexity)]
+fn business_day_args(
+    args: Spanned<Vec<Spanned<Value>>>,
+    intl: bool,
+    system: DateSystem,
+) -> FormulaResult<(Spanned<Value>, Spanned<Value>, Weekend, Vec<i64>)> {
+    let max_args = if intl { 4 } else { 3 };
+    if !(2..=max_args).contains(&args.inner.len()) {
+        return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+    }
+    let mut args = args.inner.into_iter();
+    let (a, b) = (args.next().unwrap(), args.next().unwrap());
+    let weekend = if intl { args.next() } else { None };
+    let weekend = match weekend {
+        Some(weekend) => Weekend::try_from_value(&weekend)?,
+        None => Weekend::default(),
+    };
+    let holidays = match args.next() {
+        Some(holidays) => holiday_serials(&holidays, system)?,
+        None => vec![],
+    };
+    Ok((a, b, weekend, holidays))
+}
+/// Returns the sorted date serial numbers in a list of holidays, in the 1900
+/// date system.
+fn holiday_serials(holidays: &Spanned<Value>, system: DateSystem) -> FormulaResult<Vec<i64>> {
+    let mut ret = holidays
+        .to_numbers()?
+        .into_iter()
+        .map(|n| system.to_1900(n.floor() as i64))
+        .collect_vec();
+    ret.sort_unstable();
+    Ok(ret)
+}
+
+/// Sums `f(x, y)` over corresponding pairs of values in two arrays, which
+/// must be the same size. Like Excel, pairs where either value is non-numeric
+/// are skipped.
+fn sum_of_pairs(
+    args: Spanned<Vec<Spanned<Value>>>,
+    f: fn(f64, f64) -> f64,
+) -> FormulaResult<Value> {
+    let pairs = numeric_pairs(args)?;
+    Ok(Value::Number(pairs.into_iter().map(|(x, y)| f(x, y)).sum()))
+}
+/// Computes a statistic for corresponding pairs of values in two arrays,
+/// using the first array as the y-values and the second as the x-values.
+/// Returns a divide-by-zero error if the statistic is undefined.
+fn pair_stat(
+    args: Spanned<Vec<Spanned<Value>>>,
+    f: fn(PairStats) -> Option<f64>,
+) -> FormulaResult<Value> {
+    let span = args.span;
+    let pairs = numeric_pairs(args)?
+        .into_iter()
+        .map(|(y, x)| (x, y))
+        .collect_vec();
+    match PairStats::new(&pairs).and_then(f) {
+        Some(n) => Ok(Value::Number(n)),
+        None => Err(FormulaErrorMsg::DivideByZero.with_span(span)),
+    }
+}
+/// Returns corresponding pairs of values in two arrays, which must be the
+/// same size. Like Excel, pairs where either value is non-numeric are
+/// skipped.
+fn numeric_pairs(args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Vec<(f64, f64)>> {
+    let [xs, ys] = exact_args(args)?;
+    let xs_size = xs.inner.array_size().unwrap_or((1, 1));
+    let ys_size = ys.inner.array_size().unwrap_or((1, 1));
+    if xs_size != ys_size {
+        return Err(FormulaErrorMsg::ArraySizeMismatch {
+            expected: xs_size,
+            got: ys_size,
+        }
+        .with_span(ys.span));
+    }
+    let mut ret = vec![];
+    for row in 0..xs_size.0 {
+        for col in 0..xs_size.1 {
+            let x = xs.get_array_value(row, col)?.inner.as_number();
+            let y = ys.get_array_value(row, col)?.inner.as_number();
+            if let (Some(x), Some(y)) = (x, y) {
+                ret.push((x, y));
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Implements `LINEST` and `LOGEST`, which differ only in whether the model
+/// is fit to the logarithms of the y-values.
+fn linest(args: Spanned<Vec<Spanned<Value>>>, log_y: bool) -> FormulaResult<Value> {
+    let [Some(known_y), known_x, has_intercept, stats] = optional_args(args, 1)? else {
+        internal_error!("missing required argument");
+    };
+    let has_intercept = has_intercept.map_or(Ok(true), |v| v.to_bool())?;
+    let stats = stats.map_or(Ok(false), |v| v.to_bool())?;
+    LinearFit::new(&known_y, known_x.as_ref(), has_intercept, log_y)?.coefficients(stats)
+}
+
+/// Implements `TREND` and `GROWTH`, which differ only in whether the model is
+/// fit to the logarithms of the y-values.
+fn trend(args: Spanned<Vec<Spanned<Value>>>, log_y: bool) -> FormulaResult<Value> {
+    let [Some(known_y), known_x, new_x, has_intercept] = optional_args(args, 1)? else {
+        internal_error!("missing required argument");
+    };
+    let has_intercept = has_intercept.map_or(Ok(true), |v| v.to_bool())?;
+    let fit = LinearFit::new(&known_y, known_x.as_ref(), has_intercept, log_y)?;
+    fit.predict(new_x.as_ref())
+}
+
+/// Implements the functions that convert between number bases, such as
+/// `HEX2DEC` and `DEC2BIN`. Conversions to decimal take no `places` argument.
+fn convert_base(
+    args: Spanned<Vec<Spanned<Value>>>,
+    from: Radix,
+    to: Radix,
+) -> FormulaResult<Value> {
+    let convert = |number: Spanned<Value>, places: Option<Spanned<Value>>| {
+        let n = from.parse(&number)?;
+        if to == Radix::Dec {
+            return Ok(Value::Number(n as f64));
+        }
+        let not_a_number = |v: &Spanned<Value>| {
+            FormulaErrorMsg::NotANumber(v.inner.to_string().into()).with_span(v.span)
+        };
+        let digits = to.format(n).ok_or_else(|| not_a_number(&number))?;
+        match places {
+            // Negative numbers always use all 10 digits.
+            Some(places) if n >= 0 => {
+                let width = places.to_number()?.trunc();
+                if !(1.0..=10.0).contains(&width) || (digits.len() as f64) > width {
+                    return Err(not_a_number(&places));
+                }
+                Ok(Value::String(format!("{digits:0>0$}", width as usize)))
+            }
+            _ => Ok(Value::String(digits)),
+        }
+    };
+    match args.inner.len() {
+        2 if to != Radix::Dec => array_map(args, |[number, places]| convert(number, Some(places))),
+        _ => array_map(args, |[number]| convert(number, None)),
+    }
+}
+
+/// Applies a bitwise operation to two numbers.
+fn bitwise_op(
+    a: &Spanned<Value>,
+    b: &Spanned<Value>,
+    op: fn(u64, u64) -> u64,
+) -> FormulaResult<Value> {
+    let (a, b) = (bitwise::operand(a)?, bitwise::operand(b)?);
+    Ok(Value::Number(op(a, b) as f64))
+}
+
+/// Returns the text form of a complex number.
+fn complex_value(re: f64, im: f64, suffix: char) -> FormulaResult<Value> {
+    Ok(Value::String(Complex { re, im, suffix }.to_string()))
+}
+
+/// Implements `IMSUM` and `IMPRODUCT`, which combine any number of complex
+/// numbers or arrays of complex numbers, starting from `identity`. Blank
+/// values are skipped.
+fn complex_fold(
+    args: Spanned<Vec<Spanned<Value>>>,
+    identity: f64,
+    op: fn(Complex, Complex) -> FormulaResult<Complex>,
+) -> FormulaResult<Value> {
+    if args.inner.is_empty() {
+        return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+    }
+    let mut ret = Complex {
+        re: identity,
+        im: 0.0,
+        suffix: 'i',
+    };
+    for arg in &args.inner {
+        for c in arg.to_complexes()? {
+            ret = op(ret, c).map_err(|e| e.with_span(arg.span))?;
+        }
+    }
+    Ok(Value::String(ret.to_string()))
+}
+
+/// Implements `DOLLAR` and `FIXED`, which round a number to some number of
+/// decimal places (2 by default) and format it as text, optionally with
+/// thousands separators. Negative `decimals` round to the left of the decimal
+/// point. The currency symbol and separators come from `locale`. Like Excel in
+/// the US, `DOLLAR` puts negative amounts in parentheses and `FIXED` gives
+/// them a minus sign.
+fn format_fixed(
+    n: &Spanned<Value>,
+    decimals: Option<&Spanned<Value>>,
+    commas: bool,
+    currency: bool,
+    locale: NumberLocale,
+) -> FormulaResult<Value> {
+    let decimals = match decimals {
+        Some(decimals) => match decimals.to_number()?.trunc() {
+            d if d > 127.0 => return Err(FormulaErrorMsg::BadNumber.with_span(decimals.span)),
+            d => d.max(-308.0) as i64,
+        },
+        None => 2,
+    };
+    let rounded = arithmetic::round(n.to_number()?, decimals);
+
+    let mut format = if commas { "#,##0" } else { "0" }.to_string();
+    if decimals > 0 {
+        format.push('.');
+        format.push_str(&"0".repeat(decimals as usize));
+    }
+    if currency {
+        format = format!("${format};(${format})");
+    }
+    let Some(s) = NumberFormat::parse(&format).and_then(|fmt| fmt.format_number(rounded)) else {
+        internal_error!("bad number format {format:?}");
+    };
+    Ok(Value::String(locale.localize(&s)))
+}
+
+/// Implements `TEXTSPLIT`, which splits text into columns and optionally rows.
+/// Rows with fewer columns are padded with `#N/A`.
+fn text_split(args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [Some(text), Some(col_delimiter), row_delimiter, ignore_empty] = optional_args(args, 2)?
+    else {
+        internal_error!("missing required argument");
+    };
+    let text = text.to_string();
+    let col_delimiter = col_delimiter.to_string();
+    let row_delimiter = row_delimiter.map_or(String::new(), |d| d.to_string());
+    let ignore_empty = ignore_empty.map_or(Ok(false), |v| v.to_bool())?;
+    if col_delimiter.is_empty() && row_delimiter.is_empty() {
+        return Err(FormulaErrorMsg::Expected {
+            expected: "delimiter".into(),
+            got: Some("empty string".into()),
+        }
+        .with_span(span));
+    }
+
+    fn split<'a>(s: &'a str, delimiter: &str, ignore_empty: bool) -> Vec<&'a str> {
+        let parts = match delimiter {
+            "" => vec![s],
+            _ => s.split(delimiter).collect(),
+        };
+        parts
+            .into_iter()
+            .filter(|part| !(ignore_empty && part.is_empty()))
+            .collect()
+    }
+    let rows = split(&text, &row_delimiter, ignore_empty)
+        .into_iter()
+        .map(|row| split(row, &col_delimiter, ignore_empty))
+        .filter(|row| !row.is_empty())
+        .collect_vec();
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if width == 0 {
+        return Ok(Value::String(String::new()));
+    }
+    Value::array(
+        rows.into_iter()
+            .map(|row| {
+                let mut row = row.into_iter().map(Value::from).collect_vec();
+                row.resize(width, Value::Error(FormulaErrorMsg::NotAvailable));
+                row
+            })
+            .collect(),
+    )
+}
+
+/// Implements `REGEXEXTRACT`, which returns the first match of a pattern, or
+/// a column of every match if `return_mode` is 1. If the pattern has capture
+/// groups, each match is a row of the text of each group instead. Returns an
+/// error if there is no match.
+fn regex_extract(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [Some(text), Some(pattern), return_mode] = optional_args(args, 2)? else {
+        internal_error!("missing required argument");
+    };
+    let text = text.to_scalar()?;
+    text.check_error()?;
+    let regex = pattern::compile(&pattern.to_scalar()?)?;
+    let all = match return_mode {
+        None => false,
+        Some(mode) => match mode.to_scalar()?.to_integer()? {
+            0 => false,
+            1 => true,
+            _ => return Err(FormulaErrorMsg::InvalidArgument.with_span(mode.span)),
+        },
+    };
+
+    let text = text.inner.to_string();
+    let mut rows = vec![];
+    for captures in regex.captures_iter(&text) {
+        let row = pattern::extracted(&captures);
+        ctx.take_steps(1)
+            .and_then(|()| ctx.check_array_size(rows.len() as u64 + 1, row.len() as u64))
+            .map_err(|e| e.with_span(span))?;
+        rows.push(row);
+        if !all {
+            break;
+        }
+    }
+    match rows.as_slice() {
+        [] => Err(FormulaErrorMsg::NotAvailable.with_span(span)),
+        [row] if !all && row.len() == 1 => Ok(row[0].clone()),
+        _ => Value::array(rows),
+    }
+}
+
+/// Reads the `format` argument of `ARRAYTOTEXT` and `VALUETOTEXT`, returning
+/// whether it selects the strict format, which can be parsed as a formula,
+/// rather than the concise one.
+fn is_strict_text_format(format: Option<&Spanned<Value>>) -> FormulaResult<bool> {
+    let Some(format) = format else {
+        return Ok(false);
+    };
+    match format.to_number()?.trunc() {
+        0.0 => Ok(false),
+        1.0 => Ok(true),
+        _ => Err(FormulaErrorMsg::BadNumber.with_span(format.span)),
+    }
+}
+
+/// Percent-encodes every byte of a string except unreserved URL characters.
+fn encode_url(s: &str) -> String {
+    let mut ret = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                ret.push(b as char)
+            }
+            _ => ret.push_str(&format!("%{b:02X}")),
+        }
+    }
+    ret
+}
+
+/// Direction to round a number to a multiple of a significance.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RoundingDirection {
+    Down,
+    Up,
+}
+impl RoundingDirection {
+    /// Rounds a quotient to an integer in this direction. Quotients within
+    /// floating-point error of an integer are treated as that integer, so
+    /// that `0.3 / 0.1` rounds down to 3 rather than 2.
+    fn round(self, q: f64) -> f64 {
+        let nearest = q.round();
+        if (q - nearest).abs() <= nearest.abs().max(1.0) * 1e-12 {
+            return nearest;
+        }
+        match self {
+            RoundingDirection::Down => q.floor(),
+            RoundingDirection::Up => q.ceil(),
+        }
+    }
+}
+
+/// Implements `FLOOR`, `CEILING`, and their `.PRECISE` variants, which round
+/// a number to a multiple of a significance.
+///
+/// The `.PRECISE` variants ignore the sign of the significance, which is 1 by
+/// default, and always round toward negative or positive infinity. A
+/// significance of zero returns zero.
+///
+/// The classic functions require a significance with the same sign as the
+/// number, so a negative number rounds toward zero for `FLOOR` and away from
+/// zero for `CEILING`. A significance of zero is a division by zero for
+/// `FLOOR` and returns zero for `CEILING`.
+fn round_to_multiple(
+    args: Spanned<Vec<Spanned<Value>>>,
+    direction: RoundingDirection,
+    precise: bool,
+) -> FormulaResult<Value> {
+    if precise && args.inner.len() == 1 {
+        return array_map(args, |[n]| {
+            Ok(Value::Number(direction.round(n.to_number()?)))
+        });
+    }
+    let span = args.span;
+    array_map(args, |[n, significance]| {
+        let number = n.to_number()?;
+        let mut multiple = significance.to_number()?;
+        if precise {
+            multiple = multiple.abs();
+        } else if number == 0.0 || multiple == 0.0 && direction == RoundingDirection::Up {
+            return Ok(Value::Number(0.0));
+        } else if multiple == 0.0 {
+            return Err(FormulaErrorMsg::DivideByZero.with_span(significance.span));
+        } else if (number < 0.0) != (multiple < 0.0) {
+            return Err(FormulaErrorMsg::BadNumber.with_span(significance.span));
+        }
+        if multiple == 0.0 {
+            return Ok(Value::Number(0.0));
+        }
+        let quotient = arithmetic::div(number, multiple);
+        let result = arithmetic::mul(direction.round(quotient), multiple);
+        // A tiny significance can make the quotient overflow.
+        if !result.is_finite() {
+            return Err(FormulaErrorMsg::Overflow.with_span(span));
+        }
+        Ok(Value::Number(result))
+    })
+}
+
+/// Records the steps to decompose a square matrix, which takes time
+/// proportional to the cube of its size.
+fn take_decomposition_steps(ctx: &mut Ctx<'_>, matrix: &Matrix) -> FormulaResult<()> {
+    ctx.take_steps((matrix.rows() as u64).saturating_pow(3))
+}
+
+/// Returns the number of rows or columns for a function that constructs an
+/// array, which defaults to 1 if the argument is missing.
+fn array_dimension(arg: Option<Spanned<Value>>) -> FormulaResult<u64> {
+    match arg {
+        None => Ok(1),
+        Some(arg) => match arg.to_number()?.trunc() {
+            n if n >= 1.0 => Ok(n as u64),
+            _ => Err(FormulaErrorMsg::BadNumber.with_span(arg.span)),
+        },
+    }
+}
+
+/// Implements `RANDARRAY`, which returns an array of random numbers that are
+/// at least `min` and less than `max`, or of random integers from `min` to
+/// `max` inclusive. In integer mode, `min` is rounded up and `max` is rounded
+/// down.
+fn randarray(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [rows, cols, min, max, integer] = optional_args(args, 0)?;
+    let (rows, cols) = (array_dimension(rows)?, array_dimension(cols)?);
+    let bound_span = max.as_ref().or(min.as_ref()).map_or(span, |arg| arg.span);
+    let mut min = min.map_or(Ok(0.0), |arg| arg.to_number())?;
+    let mut max = max.map_or(Ok(1.0), |arg| arg.to_number())?;
+    let integer = integer.map_or(Ok(false), |arg| arg.to_bool())?;
+    if integer {
+        (min, max) = (min.ceil(), max.floor());
+    }
+    if min > max {
+        return Err(FormulaErrorMsg::BadNumber.with_span(bound_span));
+    }
+
+    // Each value counts as a step, so a huge array fails before it is
+    // allocated.
+    ctx.take_steps(rows.saturating_mul(cols))
+        .and_then(|()| ctx.check_array_size(rows, cols))
+        .map_err(|e| e.with_span(span))?;
+
+    let rng = ctx.rng();
+    let mut random = || {
+        let x = rng.next_f64();
+        Value::Number(if integer {
+            min + (x * (max - min + 1.0)).floor()
+        } else {
+            min + x * (max - min)
+        })
+    };
+    let (rows, cols) = (rows as usize, cols as usize);
+    Ok(Value::Array(Array::from_fn(rows, cols, |_, _| random())))
+}
+
+/// Implements `SEQUENCE`, which returns an array of evenly spaced numbers
+/// filled in row by row.
+fn sequence(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [rows, cols, start, step] = optional_args(args, 1)?;
+    let (rows, cols) = (array_dimension(rows)?, array_dimension(cols)?);
+    let start = start.map_or(Ok(1.0), |arg| arg.to_number())?;
+    let step = step.map_or(Ok(1.0), |arg| arg.to_number())?;
+
+    ctx.take_steps(rows.saturating_mul(cols))
+        .and_then(|()| ctx.check_array_size(rows, cols))
+        .map_err(|e| e.with_span(span))?;
+
+    let (rows, cols) = (rows as usize, cols as usize);
+    Ok(Value::Array(Array::from_fn(rows, cols, |row, col| {
+        Value::Number(start + (row * cols + col) as f64 * step)
+    })))
+}
+
+/// Implements `YEAR`, `MONTH`, and `DAY`, which return a part of a date.
+fn date_part(
+    ctx: &Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    part: fn(Date) -> i64,
+) -> FormulaResult<Value> {
+    let system = ctx.settings.date_system;
+    array_map(args, |[date]| {
+        let serial = date::to_serial(&date, system)?;
+        Ok(Value::Number(part(Date::from_serial(serial)) as f64))
+    })
+}
+
+/// Searches a one-dimensional array for a value, for `XMATCH` and `XLOOKUP`.
+/// Returns the index of the match, or `None` if there is none.
+fn lookup_index(
+    needle: &Spanned<Value>,
+    haystack: &Spanned<Value>,
+    match_mode: Option<Spanned<Value>>,
+    search_mode: Option<Spanned<Value>>,
+    text_compare: TextCompareMode,
+) -> FormulaResult<Option<usize>> {
+    let needle = needle.to_scalar()?;
+    let (haystack, _) = lookup::lookup_vector(haystack)?;
+    let match_mode = MatchMode::try_from_arg(match_mode.as_ref())?;
+    let search_mode = SearchMode::try_from_arg(search_mode.as_ref())?;
+    Ok(lookup::search(
+        &needle.inner,
+        &haystack,
+        match_mode,
+        search_mode,
+        text_compare,
+    ))
+}
+
+/// Returns an array whose rows are the rows of a value, or its columns if
+/// `by_col` is true, without copying it. A value that is not an array is a
+/// single row containing one value.
+fn array_by_lines(value: Value, by_col: bool) -> Array {
+    let array = match value {
+        Value::Array(a) => a,
+        other => Array::from_row(vec![other]),
+    };
+    match by_col {
+        false => array,
+        true => array.transpose(),
+    }
+}
+/// Returns the rows of an array, or its columns if `by_col` is true. A value
+/// that is not an array is a single row containing one value.
+fn array_lines(value: Value, by_col: bool) -> Vec<Vec<Value>> {
+    array_by_lines(value, by_col).into_rows()
+}
+/// Constructs an array from its rows, or its columns if `by_col` is true. This
+/// is the inverse of `array_lines()`.
+fn array_from_lines(lines: Vec<Vec<Value>>, by_col: bool) -> Value {
+    let (count, len) = (lines.len(), lines.first().map_or(0, Vec::len));
+    let mut values = Vec::with_capacity(count * len);
+    lines.into_iter().for_each(|line| values.extend(line));
+    let array = Array::from_row_major(count, len, values);
+    Value::Array(match by_col {
+        false => array,
+        true => array.transpose(),
+    })
+}
+
+/// Implements `SORT`, which sorts the rows or columns of an array by one of
+/// its columns or rows. Rows with equal values keep their original order, and
+/// blanks are always last.
+fn sort(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [array, sort_index, sort_order, by_col] = optional_args(args, 1)?;
+    let by_col = by_col.map_or(Ok(false), |v| v.to_bool())?;
+    let mut lines = array_lines(array.unwrap().inner, by_col);
+    let line_len = lines.first().map_or(0, Vec::len);
+
+    let index = match &sort_index {
+        Some(arg) => match arg.to_integer()? {
+            i @ 1.. if (i as usize) <= line_len => i as usize - 1,
+            _ => return Err(FormulaErrorMsg::IndexOutOfBounds.with_span(arg.span)),
+        },
+        None => 0,
+    };
+    let descending = match &sort_order {
+        Some(arg) => match arg.to_integer()? {
+            1 => false,
+            -1 => true,
+            _ => return Err(FormulaErrorMsg::InvalidArgument.with_span(arg.span)),
+        },
+        None => false,
+    };
+
+    ctx.take_steps(lines.len() as u64)
+        .map_err(|e| e.with_span(span))?;
+    let is_blank = Value::is_blank_or_empty;
+    lines.sort_by(|a, b| {
+        let (a, b) = (&a[index], &b[index]);
+        match descending && !is_blank(a) && !is_blank(b) {
+            true => b.total_cmp(a),
+            false => a.total_cmp(b),
+        }
+    });
+    Ok(array_from_lines(lines, by_col))
+}
+
+/// Implements `UNIQUE`, which returns the distinct rows or columns of an
+/// array in the order they first appear. Values are compared in the same
+/// order as `SORT`, so text is compared ignoring case.
+fn unique(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [array, by_col, exactly_once] = optional_args(args, 1)?;
+    let by_col = by_col.map_or(Ok(false), |v| v.to_bool())?;
+    let exactly_once = exactly_once.map_or(Ok(false), |v| v.to_bool())?;
+    let lines = array_lines(array.unwrap().inner, by_col);
+    ctx.take_steps(lines.len() as u64)
+        .map_err(|e| e.with_span(span))?;
+
+    // Sort the indices so that equal lines are next to each other. The sort
+    // is stable, so the first index in each group is the first occurrence.
+    let cmp_lines = |a: &Vec<Value>, b: &Vec<Value>| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.total_cmp(b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    };
+    let mut indices = (0..lines.len()).collect_vec();
+    indices.sort_by(|&a, &b| cmp_lines(&lines[a], &lines[b]));
+    let mut kept = indices
+        .chunk_by(|&a, &b| cmp_lines(&lines[a], &lines[b]).is_eq())
+        .filter(|group| !exactly_once || group.len() == 1)
+        .map(|group| group[0])
+        .collect_vec();
+    kept.sort_unstable();
+
+    if kept.is_empty() {
+        return Err(FormulaErrorMsg::NotAvailable.with_span(span));
+    }
+    let mut lines = lines.into_iter().map(Some).collect_vec();
+    let unique_lines = kept.into_iter().filter_map(|i| lines[i].take()).collect();
+    Ok(array_from_lines(unique_lines, by_col))
+}
+
+/// Implements `WRAPROWS` and `WRAPCOLS`, which split a row or column into rows,
+/// or columns if `by_col` is true, of `wrap_count` values each. The last one
+/// is padded with `pad_with`, or `#N/A` by default.
+fn wrap(
+    ctx: &mut Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    by_col: bool,
+) -> FormulaResult<Value> {
+    let span = args.span;
+    let [vector, wrap_count, pad_with] = optional_args(args, 2)?;
+    let (vector, wrap_count) = (vector.unwrap(), wrap_count.unwrap());
+    let (values, _) = lookup::lookup_vector(&vector)?;
+    let line_len = match wrap_count.to_integer()? {
+        n @ 1.. => n as usize,
+        _ => return Err(FormulaErrorMsg::BadNumber.with_span(wrap_count.span)),
+    };
+    let pad_with = match pad_with {
+        Some(arg) => arg.to_scalar()?.inner,
+        None => Value::Error(FormulaErrorMsg::NotAvailable),
+    };
+
+    let line_count = values.len().div_ceil(line_len);
+    let (rows, cols) = match by_col {
+        false => (line_count, line_len),
+        true => (line_len, line_count),
+    };
+    ctx.take_steps((rows as u64).saturating_mul(cols as u64))
+        .and_then(|()| ctx.check_array_size(rows as u64, cols as u64))
+        .map_err(|e| e.with_span(span))?;
+
+    let lines = values
+        .chunks(line_len)
+        .map(|chunk| {
+            let mut line = chunk.iter().map(|&v| v.clone()).collect_vec();
+            line.resize(line_len, pad_with.clone());
+            line
+        })
+        .collect();
+    Ok(array_from_lines(lines, by_col))
+}
+
+/// Implements `TOCOL` and `TOROW`, which flatten an array into a column if
+/// `to_col` is true or a row otherwise. The array is read row by row, or
+/// column by column if `scan_by_col` is true. `ignore` is 1 to leave out
+/// blanks, 2 to leave out errors, or 3 to leave out both.
+fn flatten(
+    ctx: &mut Ctx<'_>,
+    args: Spanned<Vec<Spanned<Value>>>,
+    to_col: bool,
+) -> FormulaResult<Value> {
+    let span = args.span;
+    let [array, ignore, scan_by_col] = optional_args(args, 1)?;
+    let (ignore_blanks, ignore_errors) = match &ignore {
+        Some(arg) if !arg.inner.is_blank_or_empty() => match arg.to_integer()? {
+            0 => (false, false),
+            1 => (true, false),
+            2 => (false, true),
+            3 => (true, true),
+            _ => return Err(FormulaErrorMsg::InvalidArgument.with_span(arg.span)),
+        },
+        _ => (false, false),
+    };
+    let scan_by_col = scan_by_col.map_or(Ok(false), |v| v.to_bool())?;
+    let array = array_by_lines(array.unwrap().inner, scan_by_col);
+    ctx.take_steps(array.len() as u64)
+        .map_err(|e| e.with_span(span))?;
+
+    let values = array
+        .iter()
+        .filter(|v| !(ignore_blanks && v.is_blank_or_empty() || ignore_errors && v.is_error()))
+        .cloned()
+        .collect_vec();
+    if values.is_empty() {
+        return Err(FormulaErrorMsg::NotAvailable.with_span(span));
+    }
+    Ok(array_from_lines(vec![values], to_col))
+}
+
+/// Implements `XLOOKUP`, which returns the row or column of `return_array`
+/// corresponding to where a value is found in `lookup_array`.
+fn xlookup(ctx: &mut Ctx<'_>, args: Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Value> {
+    let span = args.span;
+    let [needle, haystack, returns, if_not_found, match_mode, search_mode] =
+        optional_args(args, 3)?;
+    let (needle, haystack, returns) = (needle.unwrap(), haystack.unwrap(), returns.unwrap());
+
+    let text_compare = ctx.settings.text_compare;
+    let Some(i) = lookup_index(&needle, &haystack, match_mode, search_mode, text_compare)? else {
+        return match if_not_found {
+            Some(value) => Ok(value.inner),
+            None => Err(FormulaErrorMsg::NotAvailable.with_span(span)),
+        };
+    };
+
+    // If the lookup array is a column, return a row of the return array;
+    // otherwise return a column.
+    let (lookup_values, is_column) = lookup::lookup_vector(&haystack)?;
+    let array = match returns.inner {
+        Value::Array(a) => a,
+        other => Array::from_row(vec![other]),
+    };
+    let (row_count, col_count) = array.size();
+    let result = if is_column && row_count == lookup_values.len() {
+        array.row(i)
+    } else if !is_column && col_count == lookup_values.len() {
+        array.column(i)
+    } else {
+        return Err(FormulaErrorMsg::InvalidArgument.with_span(returns.span));
+    };
+    Ok(match (result.size(), result.first()) {
+        ((1, 1), Some(v)) => v.clone(),
+        _ => Value::Array(result),
+    })
+}
+
+/// Implements `LEFT` and `RIGHT`, which return the first or last `n`
+/// characters of some text. `n` defaults to 1.
+fn left_or_right(
+    unit: TextUnit,
+    text: &Spanned<Value>,
+    n: Option<&Spanned<Value>>,
+    from_end: bool,
+) -> FormulaResult<Value> {
+    let n = n.map_or(Ok(1), char_count)?;
+    let s = text.to_string();
+    let chars = unit.split(&s);
+    let n = n.min(chars.len());
+    let chars = match from_end {
+        false => &chars[..n],
+        true => &chars[chars.len() - n..],
+    };
+    Ok(Value::String(chars.concat()))
+}
+/// Implements `NUMBERVALUE`, which parses text as a number using the given
+/// decimal and group separators, which default to `.` and `,`. Whitespace is
+/// ignored, group separators may appear anywhere before the decimal
+/// separator, and each trailing `%` divides the number by 100.
+fn number_value(
+    text: &Spanned<Value>,
+    decimal_sep: Option<&Spanned<Value>>,
+    group_sep: Option<&Spanned<Value>>,
+) -> FormulaResult<Value> {
+    // Only the first character of each separator is used, like in Excel. A
+    // blank separator is the default.
+    let separator = |arg: Option<&Spanned<Value>>, default| {
+        arg.and_then(|arg| arg.inner.to_string().chars().next())
+            .unwrap_or(default)
+    };
+    let (decimal, group) = (separator(decimal_sep, '.'), separator(group_sep, ','));
+    if decimal == group {
+        let span = group_sep.or(decimal_sep).map_or(text.span, |arg| arg.span);
+        return Err(FormulaErrorMsg::InvalidArgument.with_span(span));
+    }
+
+    let s = text.inner.to_string();
+    let not_a_number = || FormulaErrorMsg::NotANumber(format!("{s:?}").into()).with_span(text.span);
+    let chars = s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let digits = chars.trim_end_matches('%');
+    let percent_signs = chars.len() - digits.len();
+    if digits.is_empty() {
+        return match percent_signs {
+            0 => Ok(Value::Number(0.0)),
+            _ => Err(not_a_number()),
+        };
+    }
+
+    let (integer_part, fraction_part) = match digits.split_once(decimal) {
+        Some((_, fraction)) if fraction.contains([decimal, group]) => {
+            return Err(not_a_number());
+        }
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let mut normalized = integer_part.replace(group, "");
+    if let Some(fraction) = fraction_part {
+        normalized.push('.');
+        normalized.push_str(fraction);
+    }
+    // Rule out words such as `inf` that Rust parses as numbers.
+    let is_numeric = |c: char| c.is_ascii_digit() || "+-.eE".contains(c);
+    if !normalized.chars().all(is_numeric) {
+        return Err(not_a_number());
+    }
+    let n: f64 = normalized.parse().map_err(|_| not_a_number())?;
+    Ok(Value::Number(n / 100_f64.powi(percent_signs as i32)))
+}
+
+/// Returns a number of characters for a string function, which must not be
+/// negative.
+fn char_count(n: &Spanned<Value>) -> FormulaResult<usize> {
+    match n.to_number()?.trunc() {
+        n if n >= 0.0 => Ok(n as usize),
+        _ => Err(FormulaErrorMsg::InvalidArgument.with_span(n.span)),
+    }
+}
+/// Returns the zero-based index of a one-based character position for a
+/// string function, which must be at least 1.
+fn start_index(start: &Spanned<Value>) -> FormulaResult<usize> {
+    match start.to_number()?.trunc() {
+        n if n >= 1.0 => Ok(n as usize - 1),
+        _ => Err(FormulaErrorMsg::InvalidArgument.with_span(start.span)),
+    }
+}
+
+/// Implements `CODE` and `UNICODE`, which return the code point of the first
+/// character of some text. `CODE` only supports ASCII characters.
+fn char_code(text: &Spanned<Value>, ascii: bool) -> FormulaResult<Value> {
+    let Some(c) = text.inner.to_string().chars().next() else {
+        return Err(FormulaErrorMsg::NotAvailable.with_span(text.span));
+    };
+    if ascii && !c.is_ascii() {
+        return Err(FormulaErrorMsg::InvalidArgument.with_span(text.span));
+    }
+    Ok(Value::Number(c as u32 as f64))
+}
+/// Implements `CHAR` and `UNICHAR`, which return the character with a code
+/// point. `CHAR` only supports ASCII characters. Code points that are not
+/// Unicode scalar values, such as surrogates, are an error.
+fn char_from_code(code: &Spanned<Value>, ascii: bool) -> FormulaResult<Value> {
+    let n = code.to_number()?.trunc();
+    let max = if ascii { 0x7F } else { char::MAX as u32 };
+    (1.0..=max as f64)
+        .contains(&n)
+        .then(|| char::from_u32(n as u32))
+        .flatten()
+        .map(|c| Value::String(c.to_string()))
+        .ok_or_else(|| FormulaErrorMsg::BadNumber.with_span(code.span))
+}
+
+fn sum(args: &[Spanned<Value>], coerce_text: bool) -> FormulaResult<f64> {
+    flat_iter_numbers(args, coerce_text).try_fold(0.0, |sum, next| {
+        FormulaResult::Ok(arithmetic::add(sum, next?))
+    })
+}
+fn product(args: &[Spanned<Value>], coerce_text: bool) -> FormulaResult<f64> {
+    flat_iter_numbers(args, coerce_text).try_fold(1.0, |prod, next| {
+        FormulaResult::Ok(arithmetic::mul(prod, next?))
+    })
 }
 fn count(args: &[Spanned<Value>]) -> usize {
     args.iter().map(|v| v.inner.count()).sum()
 }
+/// Counts the values that `flat_iter_numbers()` returns when it ignores text.
+fn count_ignoring_text_in_arrays(args: &[Spanned<Value>]) -> usize {
+    args.iter()
+        .map(|arg| match &arg.inner {
+            Value::Array(a) => a
+                .iter()
+                .filter(|v| !matches!(v, Value::String(_)))
+                .map(Value::count)
+                .sum(),
+            other => other.count(),
+        })
+        .sum()
+}
 
+/// Warns about text in an array argument that is read as a number, such as
+/// `"$5"` in a range passed to `SUM`. The contents of cells are text, so text
+/// that looks exactly like a number is only reported when operators reject
+/// text, since then such cells have already been read as numbers.
+///
+/// This does nothing unless warnings are being collected and text is being
+/// read as numbers at all.
+fn warn_text_in_ranges(ctx: &mut Ctx<'_>, args: &[Spanned<Value>]) {
+    if !ctx.is_collecting_warnings() || !ctx.settings.coerce_numeric_text {
+        return;
+    }
+    let strict = ctx.coercion().reads_cells_as_numbers();
+    for arg in args {
+        let Value::Array(a) = &arg.inner else {
+            continue;
+        };
+        let coerced_text = a.iter().find_map(|v| match v {
+            Value::String(s) if !s.trim().is_empty() && (strict || v.as_number().is_none()) => {
+                let is_number = Spanned {
+                    span: arg.span,
+                    inner: v,
+                }
+                .to_number()
+                .is_ok();
+                is_number.then_some(s)
+            }
+            _ => None,
+        });
+        if let Some(s) = coerced_text {
+            ctx.warn(FormulaWarningMsg::TextCoercedToNumber(s.clone()).with_span(arg.span));
+        }
+    }
+}
+
+/// Returns the numbers in the arguments, flattening arrays and ignoring values
+/// that aren't numbers, such as text and blanks, the same way as `AGGREGATE`.
+/// Error values are returned as errors.
+fn numbers_ignoring_text(args: &Spanned<Vec<Spanned<Value>>>) -> FormulaResult<Vec<f64>> {
+    let mut numbers = vec![];
+    for value in flat_iter_values(&args.inner) {
+        value.check_error()?;
+        numbers.extend(value.inner.as_number());
+    }
+    Ok(numbers)
+}
+/// Returns the arithmetic mean of some numbers, or an error if there are
+/// none.
+fn mean(numbers: &[f64]) -> FormulaResult<f64> {
+    if numbers.is_empty() {
+        return Err(FormulaErrorMsg::DivideByZero.into());
+    }
+    Ok(numbers.iter().sum::<f64>() / numbers.len() as f64)
+}
+
+/// Implements `FREQUENCY`, which counts how many numbers in `data` fall into
+/// each bin. Each bin counts the numbers greater than the next smaller bin and
+/// at most the bin itself. The counts are returned as a column in the same
+/// order as `bins`, followed by the count of numbers greater than every bin.
+fn frequency(data: &Spanned<Value>, bins: &Spanned<Value>) -> FormulaResult<Value> {
+    let mut bin_values = vec![];
+    for bin in flat_iter_values(std::slice::from_ref(bins)) {
+        bin.check_error()?;
+        if bin.inner.is_blank_or_empty() {
+            continue;
+        }
+        let n = bin.inner.as_number().ok_or_else(|| {
+            FormulaErrorMsg::NotANumber(format!("{:?}", bin.inner.to_string()).into())
+                .with_span(bin.span)
+        })?;
+        bin_values.push(n);
+    }
+    // Indices of the bins in ascending order, so that each number can be
+    // counted in the smallest bin that is at least as large.
+    let order = (0..bin_values.len())
+        .sorted_by(|&a, &b| bin_values[a].total_cmp(&bin_values[b]))
+        .collect_vec();
+
+    let mut counts = vec![0_usize; bin_values.len() + 1];
+    for value in flat_iter_values(std::slice::from_ref(data)) {
+        value.check_error()?;
+        let Some(n) = value.inner.as_number() else {
+            continue;
+        };
+        let i = order.partition_point(|&bin| bin_values[bin] < n);
+        counts[order.get(i).copied().unwrap_or(bin_values.len())] += 1;
+    }
+
+    Ok(Value::Array(Array::from_column(
+        counts
+            .into_iter()
+            .map(|count| Value::Number(count as f64))
+            .collect(),
+    )))
+}
+
+/// Iterates over every value in the arguments, flattening arrays.
+fn flat_iter_values(args: &[Spanned<Value>]) -> impl '_ + Iterator<Item = Spanned<Value>> {
+    args.iter().flat_map(|arg| match &arg.inner {
+        Value::Array(a) => a
+            .iter()
+            .map(|v| Spanned {
+                span: arg.span,
+                inner: v.clone(),
+            })
+            .collect_vec(),
+        _ => vec![arg.clone()],
+    })
+}
+/// Returns the numbers in the arguments, flattening arrays. Text in an array
+/// is read as a number if `coerce_text` is true, and is otherwise ignored; see
+/// [`EvalSettings::coerce_numeric_text`].
 fn flat_iter_numbers<'a>(
     args: &'a [Spanned<Value>],
+    coerce_text: bool,
 ) -> impl 'a + Iterator<Item = FormulaResult<f64>> {
-    args.iter().map(|v| v.to_numbers()).flatten_ok()
+    args.iter()
+        .map(move |arg| match &arg.inner {
+            Value::Array(a) if !coerce_text => a
+                .iter()
+                .filter(|v| !matches!(v, Value::String(_)))
+                .map(|v| {
+                    let v = Spanned {
+                        span: arg.span,
+                        inner: v,
+                    };
+                    v.check_error()?;
+                    v.to_number()
+                })
+                .collect(),
+            _ => arg.to_numbers(),
+        })
+        .flatten_ok()
 }
 fn flat_iter_bools<'a>(
     args: &'a [Spanned<Value>],
@@ -142,6 +2159,29 @@ fn flat_iter_strings<'a>(
     args.iter().map(|v| v.to_strings()).flatten_ok()
 }
 
+/// Returns `N` arguments, of which the first `required` must be present, or an
+/// error if there are too few or too many arguments. Missing arguments are
+/// `None`.
+pub fn optional_args<const N: usize>(
+    args: Spanned<Vec<Spanned<Value>>>,
+    required: usize,
+) -> FormulaResult<[Option<Spanned<Value>>; N]> {
+    if !(required..=N).contains(&args.inner.len()) {
+        return Err(FormulaErrorMsg::BadArgumentCount.with_span(args.span));
+    }
+    let mut args = args.inner.into_iter();
+    Ok(std::array::from_fn(|_| args.next()))
+}
+/// Returns exactly `N` arguments, or an error if there are a different number
+/// of arguments.
+pub fn exact_args<const N: usize>(
+    args: Spanned<Vec<Spanned<Value>>>,
+) -> FormulaResult<[Spanned<Value>; N]> {
+    args.inner
+        .try_into()
+        .map_err(|_| FormulaErrorMsg::BadArgumentCount.with_span(args.span))
+}
+
 /// Maps a fixed-argument-count function over arguments that may be arrays.
 pub fn array_map<const N: usize>(
     args: Spanned<Vec<Spanned<Value>>>,
@@ -151,39 +2191,42 @@ pub fn array_map<const N: usize>(
     match array_size {
         // Compute the results. If any argument is not an array, pretend it's an
         // array of one element repeated with the right size.
+        // The result is the same size as the arrays, which were already
+        // checked against the maximum array size when they were constructed.
         Some((rows, cols)) => {
-            let mut output_array = Vec::with_capacity(rows);
-            for row in 0..rows {
-                let mut output_row = SmallVec::with_capacity(cols);
-                for col in 0..cols {
-                    let output_value = op(args
-                        .iter()
-                        .map(|arg| arg.get_array_value(row, col))
-                        .collect::<FormulaResult<Vec<_>>>()?
-                        .try_into()
-                        .unwrap())?;
-                    output_row.push(output_value);
-                }
-                output_array.push(output_row);
-            }
+            let output_array = Array::try_from_fn(rows, cols, |row, col| {
+                let values = try_array(std::array::from_fn(|i| args[i].get_array_value(row, col)))?;
+                values.iter().try_for_each(|v| v.check_error())?;
+                op(values)
+            })?;
             Ok(Value::Array(output_array))
         }
 
         // No operands are arrays, so just do the operation once.
-        None => op(args),
+        None => {
+            args.iter().try_for_each(|v| v.check_error())?;
+            op(args)
+        }
+    }
+}
+
+/// Returns the values from an array of results, or the first error. Unlike
+/// collecting into a `Vec`, this doesn't allocate.
+fn try_array<T, const N: usize>(results: [FormulaResult<T>; N]) -> FormulaResult<[T; N]> {
+    if let Some(Err(e)) = results.iter().find(|r| r.is_err()) {
+        return Err(e.clone());
     }
+    Ok(results.map(|r| r.unwrap_or_else(|_| unreachable!())))
 }
 
 /// Returns the common `(rows, cols)` of several arguments, or `None` if no
 /// arguments are arrays.
+#[allow(clippy::type_complexity)]
 pub fn args_with_common_array_size<const N: usize>(
     args: Spanned<Vec<Spanned<Value>>>,
 ) -> FormulaResult<([Spanned<Value>; N], Option<(usize, usize)>)> {
     // Check argument count.
-    let args: [Spanned<Value>; N] = args
-        .inner
-        .try_into()
-        .map_err(|_| FormulaErrorMsg::BadArgumentCount.with_span(args.span))?;
+    let args: [Spanned<Value>; N] = exact_args(args)?;
 
     let mut array_sizes_iter = args
         .iter()
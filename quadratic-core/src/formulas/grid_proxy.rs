@@ -13,4 +13,110 @@ pub trait GridProxy {
     /// Fetches the contents of the cell at `pos`, not checking whether it
     /// results in a circular reference.
     async fn get(&mut self, pos: Pos) -> Option<String>;
+
+    /// Returns the error message if the cell at `pos` contains code, such as
+    /// Python, that failed to run. Formulas that read the cell get a
+    /// [`FormulaErrorMsg::UpstreamError`] with this message instead of its
+    /// contents.
+    ///
+    /// This is always called right after `get()` or `iter_range()` returns
+    /// the cell. The default implementation returns `None`.
+    ///
+    /// [`FormulaErrorMsg::UpstreamError`]: super::FormulaErrorMsg::UpstreamError
+    async fn get_error(&mut self, _pos: Pos) -> Option<String> {
+        None
+    }
+
+    /// Returns whether the cell at `pos` is still being computed by an
+    /// external process, so its contents aren't known yet. A formula that
+    /// reads a pending cell has a pending outcome instead of a value or an
+    /// error, so the host can evaluate it again once the cell is computed; see
+    /// [`Formula::eval_outcome()`].
+    ///
+    /// This is always called right after `get()` or `iter_range()` returns
+    /// the cell. The default implementation returns `false`.
+    ///
+    /// [`Formula::eval_outcome()`]: super::Formula::eval_outcome
+    async fn is_pending(&mut self, _pos: Pos) -> bool {
+        false
+    }
+
+    /// Fetches the source of the formula in the cell at `pos`, if it contains
+    /// one. This is used by `ISFORMULA` and `FORMULATEXT`, and by `SUBTOTAL`
+    /// to skip nested subtotals.
+    ///
+    /// The default implementation returns `None`.
+    async fn get_formula(&mut self, _pos: Pos) -> Option<String> {
+        None
+    }
+
+    /// Returns whether row `y` has been hidden by the user. This is used by
+    /// `SUBTOTAL` with function numbers 101 to 111.
+    ///
+    /// The default implementation returns `false`.
+    async fn is_row_hidden(&mut self, _y: i64) -> bool {
+        false
+    }
+
+    /// Returns the bottom-right corner of the array that spilled from the
+    /// cell at `pos`, or `None` if no array spilled from that cell. This is
+    /// used by the spill range operator, as in `A1#`.
+    ///
+    /// The default implementation returns `None`.
+    async fn get_spill_extent(&mut self, _pos: Pos) -> Option<Pos> {
+        None
+    }
+
+    /// Returns the top-left and bottom-right corners of the smallest
+    /// rectangle that contains every nonblank cell, or `None` if there are
+    /// none. This bounds the rows of a column range, as in `A:A`.
+    ///
+    /// The default implementation returns `None`, so column ranges only
+    /// contain the row of the formula.
+    async fn used_region(&mut self) -> Option<(Pos, Pos)> {
+        None
+    }
+
+    /// Returns the names of the sheets in the order they appear, including the
+    /// sheet of the formula. A 3-D reference, as in `Sheet1:Sheet3!A1`,
+    /// includes every sheet from the first one named to the last.
+    ///
+    /// The default implementation returns no sheets, so every 3-D reference
+    /// refers to an unknown sheet.
+    async fn sheet_names(&mut self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Fetches the contents of the cell at `pos` on the sheet named `sheet`,
+    /// which is one of the names returned by `sheet_names()`.
+    ///
+    /// The default implementation returns `None`.
+    async fn get_on_sheet(&mut self, _sheet: &str, _pos: Pos) -> Option<String> {
+        None
+    }
+
+    /// Returns the contents of the nonblank cells in the rectangle from `min`
+    /// to `max`, in row-major order. Blank cells are included only if
+    /// `get_error()` returns an error for them or `is_pending()` returns
+    /// `true`. Functions that ignore blank cells, such as `SUM`, use this to
+    /// read column ranges without fetching every blank cell.
+    ///
+    /// The default implementation calls `get()`, `get_error()`, and
+    /// `is_pending()` for each cell in the rectangle.
+    async fn iter_range(&mut self, min: Pos, max: Pos) -> Box<dyn Iterator<Item = (Pos, String)>> {
+        let mut cells = vec![];
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = Pos { x, y };
+                let contents = self.get(pos).await.unwrap_or_default();
+                if !contents.is_empty()
+                    || self.get_error(pos).await.is_some()
+                    || self.is_pending(pos).await
+                {
+                    cells.push((pos, contents));
+                }
+            }
+        }
+        Box::new(cells.into_iter())
+    }
 }
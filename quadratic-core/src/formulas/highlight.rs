@@ -0,0 +1,198 @@
+//! Tokenization for syntax highlighting in the code editor.
+
+use super::lexer::{self, Token};
+use super::{Span, Spanned};
+
+/// Coarse kind of a token, for syntax highlighting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Number,
+    String,
+    /// Name of a function, not including the `(` after it.
+    FunctionName,
+    /// Cell reference, such as `A1` or `$B$2`.
+    Reference,
+    /// Name bound by `LET` or `LAMBDA`, such as `x`.
+    Name,
+    /// Operator or separator, such as `+`, `:`, or `,`.
+    Operator,
+    /// Parenthesis, bracket, or brace.
+    Paren,
+    /// Error literal, such as `#N/A`.
+    ErrorLiteral,
+    Comment,
+    Whitespace,
+    /// Text that can't be part of a valid formula, such as an unknown symbol or
+    /// an unterminated string.
+    Error,
+}
+
+/// Splits a formula into tokens with their spans, for syntax highlighting.
+///
+/// Unlike parsing, this never fails. The spans cover the whole source without
+/// gaps or overlap, including whitespace and comments. An unterminated string
+/// or block comment is an error token that covers the rest of the source.
+pub fn tokenize(src: &str) -> Vec<(Span, TokenKind)> {
+    let mut ret = vec![];
+    for Spanned { span, inner } in lexer::tokenize(src) {
+        let kind = match inner {
+            Token::LParen
+            | Token::LBracket
+            | Token::LBrace
+            | Token::RParen
+            | Token::RBracket
+            | Token::RBrace => TokenKind::Paren,
+
+            Token::ArgSep
+            | Token::RowSep
+            | Token::Eql
+            | Token::Neq
+            | Token::Lt
+            | Token::Gt
+            | Token::Lte
+            | Token::Gte
+            | Token::Plus
+            | Token::Minus
+            | Token::Mult
+            | Token::Div
+            | Token::Power
+            | Token::ShiftLeft
+            | Token::ShiftRight
+            | Token::Concat
+            | Token::RangeOp
+            | Token::Percent
+            | Token::CellRangeOp
+            | Token::ImplicitIntersection
+            | Token::SpillRangeOp
+            | Token::SheetRefOp => TokenKind::Operator,
+
+            // The token includes the `(`, which is highlighted separately.
+            Token::FunctionCall => {
+                let name_end = span.end - 1;
+                ret.push((
+                    Span {
+                        start: span.start,
+                        end: name_end,
+                    },
+                    TokenKind::FunctionName,
+                ));
+                ret.push((
+                    Span {
+                        start: name_end,
+                        end: span.end,
+                    },
+                    TokenKind::Paren,
+                ));
+                continue;
+            }
+
+            // The lexer only consumes the opening quote or `/*`, but
+            // everything after it is part of the string or comment.
+            Token::UnterminatedStringLiteral | Token::UnterminatedBlockComment => {
+                ret.push((
+                    Span {
+                        start: span.start,
+                        end: src.len(),
+                    },
+                    TokenKind::Error,
+                ));
+                break;
+            }
+
+            Token::Comment => TokenKind::Comment,
+            Token::StringLiteral => TokenKind::String,
+            Token::NumericLiteral => TokenKind::Number,
+            Token::ErrorLiteral => TokenKind::ErrorLiteral,
+            Token::CellRef | Token::ColumnRange => TokenKind::Reference,
+            Token::Name => TokenKind::Name,
+            Token::Whitespace => TokenKind::Whitespace,
+            Token::Unknown => TokenKind::Error,
+        };
+        ret.push((span, kind));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that the spans of the tokens cover the whole source in order.
+    fn assert_tiles(src: &str, tokens: &[(Span, TokenKind)]) {
+        let mut pos = 0;
+        for (span, _) in tokens {
+            assert_eq!(pos, span.start, "gap or overlap in {src:?}: {tokens:?}");
+            assert!(span.start < span.end, "empty token in {src:?}: {tokens:?}");
+            pos = span.end;
+        }
+        assert_eq!(src.len(), pos, "tokens don't reach the end of {src:?}");
+    }
+
+    #[test]
+    fn test_tokenize_for_highlighting() {
+        use TokenKind::*;
+
+        let src = "SUM(A1:$B$2, 3.5) & 'x' // done";
+        let tokens = tokenize(src);
+        assert_tiles(src, &tokens);
+        let kinds = tokens
+            .iter()
+            .map(|&(span, kind)| (span.of_str(src), kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("SUM", FunctionName),
+                ("(", Paren),
+                ("A1", Reference),
+                (":", Operator),
+                ("$B$2", Reference),
+                (",", Operator),
+                (" ", Whitespace),
+                ("3.5", Number),
+                (")", Paren),
+                (" ", Whitespace),
+                ("&", Operator),
+                (" ", Whitespace),
+                ("'x'", String),
+                (" ", Whitespace),
+                ("// done", Comment),
+            ],
+            kinds,
+        );
+
+        for src in [
+            "",
+            "  =IF(A1 >= 2, {1, 2; 3, 4}, #N/A)  ",
+            "NETWORKDAYS.INTL(1, 10, \"0000011\")\n",
+            "1 /* nested /* comment */ */ + é ~ 2",
+            "A1# + @B2:C3",
+            "LET(x_1, 2, x_1 * A1)",
+            "SUM($A:C) + B:B",
+        ] {
+            assert_tiles(src, &tokenize(src));
+        }
+
+        // Unterminated strings and comments take the rest of the source.
+        for (src, start) in [
+            ("CONCAT('abc, 1)", 7),
+            ("1 + \"it's", 4),
+            ("1 /* unterminated", 2),
+        ] {
+            let tokens = tokenize(src);
+            assert_tiles(src, &tokens);
+            assert_eq!(
+                Some(&(
+                    Span {
+                        start,
+                        end: src.len()
+                    },
+                    Error
+                )),
+                tokens.last()
+            );
+        }
+
+        assert_eq!(Error, tokenize("~")[0].1);
+        assert_eq!(ErrorLiteral, tokenize("#DIV/0!")[0].1);
+    }
+}
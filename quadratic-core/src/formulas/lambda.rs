@@ -0,0 +1,71 @@
+//! Names bound by `LET` and `LAMBDA`, and the functions that `LAMBDA` creates.
+//!
+//! Names are looked up from the innermost binding outward, ignoring case.
+//! Something that looks like a cell reference, such as `A1`, is always a cell
+//! reference, so it can't be used as a name. A name bound to a lambda that is
+//! called, as in `f(1)`, takes precedence over a function with the same name.
+
+use std::fmt;
+
+use itertools::Itertools;
+
+use super::*;
+
+/// Value bound to a name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    /// Name, as it was written where it was bound.
+    pub name: String,
+    pub value: Value,
+    /// For a `LAMBDA` parameter, whether the argument was omitted from the
+    /// call. This is `None` for a name bound by `LET`.
+    pub omitted: Option<bool>,
+}
+impl Binding {
+    /// Returns whether this binding is for `name`, ignoring case.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Function created by `LAMBDA`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    /// Names of the parameters.
+    pub params: Vec<String>,
+    /// Expression that computes the result.
+    pub body: AstNode,
+    /// Names that were bound where the `LAMBDA` was evaluated, which the body
+    /// can still use when it is called from elsewhere.
+    pub captured: Vec<Binding>,
+}
+impl fmt::Display for Lambda {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LAMBDA(")?;
+        for param in &self.params {
+            write!(f, "{param}, ")?;
+        }
+        write!(f, "{})", self.body)
+    }
+}
+impl Lambda {
+    /// Returns the bindings to evaluate the body with: the captured bindings,
+    /// followed by the parameters bound to `args`. Arguments that are `None`
+    /// or missing at the end are omitted, and are bound as blank.
+    pub fn bindings_for_call(
+        &self,
+        args: Vec<Option<Value>>,
+        span: Span,
+    ) -> FormulaResult<Vec<Binding>> {
+        if args.len() > self.params.len() {
+            return Err(FormulaErrorMsg::BadArgumentCount.with_span(span));
+        }
+        let args = args.into_iter().chain(std::iter::repeat(None));
+        let params = self.params.iter().zip(args).map(|(name, arg)| Binding {
+            name: name.clone(),
+            omitted: Some(arg.is_none()),
+            value: arg.unwrap_or_default(),
+        });
+        Ok(self.captured.iter().cloned().chain(params).collect_vec())
+    }
+}
@@ -7,7 +7,15 @@ use strum_macros::Display;
 use super::{Span, Spanned};
 
 pub fn tokenize<'a>(input_str: &'a str) -> impl 'a + Iterator<Item = Spanned<Token>> {
-    let mut token_start = 0;
+    tokenize_from(input_str, 0)
+}
+/// Tokenizes the part of a string starting at `start`, which must be the start
+/// of a token.
+pub fn tokenize_from<'a>(
+    input_str: &'a str,
+    start: usize,
+) -> impl 'a + Iterator<Item = Spanned<Token>> {
+    let mut token_start = start;
     std::iter::from_fn(move || {
         Token::consume_from_input(input_str, token_start).map(|(token, token_end)| {
             let span = Span {
@@ -25,8 +33,13 @@ fn new_fullmatch_regex(s: &str) -> Regex {
 }
 
 /// Function call consisting of a letter or underscore followed by any letters,
-/// digits, and/or underscores terminated with a `(`.
-const FUNCTION_CALL_PATTERN: &str = r#"[A-Za-z_][A-Za-z_\d]*\("#;
+/// digits, and/or underscores terminated with a `(`. Dots may separate parts
+/// of the name, as in `NETWORKDAYS.INTL(`.
+const FUNCTION_CALL_PATTERN: &str = r#"[A-Za-z_][A-Za-z_\d]*(\.[A-Za-z_\d]+)*\("#;
+
+/// Name bound by `LET` or `LAMBDA`, with the same form as a function name but
+/// not followed by a `(`.
+const NAME_PATTERN: &str = r#"[A-Za-z_][A-Za-z_\d]*(\.[A-Za-z_\d]+)*"#;
 
 /// A1-style cell reference.
 ///
@@ -37,6 +50,14 @@ const FUNCTION_CALL_PATTERN: &str = r#"[A-Za-z_][A-Za-z_\d]*\("#;
 ///                 \d+       digits
 const A1_CELL_REFERENCE_PATTERN: &str = r#"\$?n?[A-Z]+\$?n?\d+"#;
 
+/// Range of whole columns, such as `A:A` or `$B:D`.
+///
+/// \$?n?[A-Z]+:\$?n?[A-Z]+
+/// \$?n?[A-Z]+              first column
+///            :             `:`
+///             \$?n?[A-Z]+  last column
+const COLUMN_RANGE_PATTERN: &str = r#"\$?n?[A-Z]+:\$?n?[A-Z]+"#;
+
 /// Floating-point or integer number, without leading sign.
 ///
 /// (\d+(\.\d*)?|\.\d+)([eE][+-]?\d+)?
@@ -59,6 +80,11 @@ const DOUBLE_QUOTE_STRING_LITERAL_PATTERN: &str = r#""([^"\\]|\\[\s\S])*""#;
 /// Unterminated string literal.
 const UNTERMINATED_STRING_LITERAL_PATTERN: &str = r#"["']"#;
 
+/// Error code, such as `#N/A` or `#DIV/0!`. Known codes are listed first,
+/// since they may contain characters that others can't.
+const ERROR_LITERAL_PATTERN: &str =
+    r#"(?i:#(DIV/0!|N/A|NAME\?|NULL!|NUM!|REF!|VALUE!)|#[A-Z_\d]+[!?]?)"#;
+
 /// List of token patterns, arranged roughly from least to most general.
 const TOKEN_PATTERNS: &[&str] = &[
     // Comparison operators `==`, `!=`, `<=`, and `>=`.
@@ -75,8 +101,16 @@ const TOKEN_PATTERNS: &[&str] = &[
     NUMERIC_LITERAL_PATTERN,
     // Function call.
     FUNCTION_CALL_PATTERN,
+    // Reference to a range of columns, which must come before cell references
+    // and names so that its first column isn't taken as a name.
+    COLUMN_RANGE_PATTERN,
     // Reference to a cell.
     A1_CELL_REFERENCE_PATTERN,
+    // Name, which must come after cell references so that they take
+    // precedence.
+    NAME_PATTERN,
+    // Error code.
+    ERROR_LITERAL_PATTERN,
     // Whitespace.
     r#"\s+"#,
     // Any other single Unicode character.
@@ -97,6 +131,14 @@ lazy_static! {
     pub static ref A1_CELL_REFERENCE_REGEX: Regex =
         new_fullmatch_regex(A1_CELL_REFERENCE_PATTERN);
 
+    /// Regex that matches a range of whole columns.
+    pub static ref COLUMN_RANGE_REGEX: Regex =
+        new_fullmatch_regex(COLUMN_RANGE_PATTERN);
+
+    /// Regex that matches a name.
+    pub static ref NAME_REGEX: Regex =
+        new_fullmatch_regex(NAME_PATTERN);
+
     /// Regex that matches all valid numeric literals and some invalid ones.
     pub static ref NUMERIC_LITERAL_REGEX: Regex =
         new_fullmatch_regex(NUMERIC_LITERAL_PATTERN);
@@ -108,6 +150,10 @@ lazy_static! {
             DOUBLE_QUOTE_STRING_LITERAL_PATTERN,
         ].join("|"));
 
+    /// Regex that matches an error code.
+    pub static ref ERROR_LITERAL_REGEX: Regex =
+        new_fullmatch_regex(ERROR_LITERAL_PATTERN);
+
     /// Regex that matches an unterminated string literal.
     pub static ref UNTERMINATED_STRING_LITERAL_REGEX: Regex =
         new_fullmatch_regex(UNTERMINATED_STRING_LITERAL_PATTERN);
@@ -174,6 +220,12 @@ pub enum Token {
     Percent, // %
     #[strum(to_string = "cell range operator")]
     CellRangeOp, // :
+    #[strum(to_string = "implicit intersection operator")]
+    ImplicitIntersection, // @
+    #[strum(to_string = "spill range operator")]
+    SpillRangeOp, // #
+    #[strum(to_string = "sheet reference operator")]
+    SheetRefOp, // !
 
     // Comments
     #[strum(to_string = "comment")]
@@ -190,8 +242,14 @@ pub enum Token {
     UnterminatedStringLiteral,
     #[strum(to_string = "numeric literal")]
     NumericLiteral,
+    #[strum(to_string = "error literal")]
+    ErrorLiteral,
     #[strum(to_string = "RC-style cell reference")]
     CellRef,
+    #[strum(to_string = "column range")]
+    ColumnRange,
+    #[strum(to_string = "name")]
+    Name,
     #[strum(to_string = "whitespace")]
     Whitespace,
     #[strum(to_string = "unknown symbol")]
@@ -231,6 +289,9 @@ impl Token {
                 ".." => Self::RangeOp,
                 "%" => Self::Percent,
                 ":" => Self::CellRangeOp,
+                "@" => Self::ImplicitIntersection,
+                "#" => Self::SpillRangeOp,
+                "!" => Self::SheetRefOp,
 
                 // Match a line comment.
                 s if s.starts_with("//") => Self::Comment,
@@ -265,9 +326,14 @@ impl Token {
                 // Match anything else.
                 s if FUNCTION_CALL_REGEX.is_match(s) => Self::FunctionCall,
                 s if STRING_LITERAL_REGEX.is_match(s) => Self::StringLiteral,
-                s if UNTERMINATED_STRING_LITERAL_REGEX.is_match(s) => Self::StringLiteral,
+                s if UNTERMINATED_STRING_LITERAL_REGEX.is_match(s) => {
+                    Self::UnterminatedStringLiteral
+                }
                 s if NUMERIC_LITERAL_REGEX.is_match(s) => Self::NumericLiteral,
                 s if A1_CELL_REFERENCE_REGEX.is_match(s) => Self::CellRef,
+                s if COLUMN_RANGE_REGEX.is_match(s) => Self::ColumnRange,
+                s if NAME_REGEX.is_match(s) => Self::Name,
+                s if ERROR_LITERAL_REGEX.is_match(s) => Self::ErrorLiteral,
                 s if s.trim().is_empty() => Self::Whitespace,
 
                 // Give up.
@@ -305,6 +371,22 @@ mod tests {
         test_block_comment(false, "/* /*");
         test_block_comment(false, "/*/");
     }
+
+    #[test]
+    fn test_lex_column_range() {
+        let tokens = |s: &str| tokenize(s).map(|t| t.inner).collect_vec();
+        assert_eq!(vec![Token::ColumnRange], tokens("A:A"));
+        assert_eq!(vec![Token::ColumnRange], tokens("$B:nAC"));
+        // A range of cells is still a cell range operator between cells.
+        assert_eq!(
+            vec![Token::CellRef, Token::CellRangeOp, Token::CellRef],
+            tokens("A1:B2"),
+        );
+        assert_eq!(
+            vec![Token::Name, Token::CellRangeOp, Token::Name],
+            tokens("a:b"),
+        );
+    }
     fn test_block_comment(expected_to_end: bool, s: &str) {
         let tokens = tokenize(s).collect_vec();
         if expected_to_end {
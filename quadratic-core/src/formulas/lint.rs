@@ -0,0 +1,225 @@
+//! Checking a formula for likely mistakes without evaluating it, for tooling
+//! such as the formula editor.
+//!
+//! Only the formula itself is checked, so these only find mistakes that don't
+//! depend on any cells: dividing by a literal zero, an argument that is never
+//! evaluated because a condition before it is constant, and an `INDEX` that is
+//! out of bounds of an array literal or a range of a known size.
+
+use super::ast::AstNodeContents;
+use super::*;
+
+/// Returns warnings about likely mistakes in a formula, found without
+/// evaluating it. Warnings about an expression come before warnings about the
+/// expressions inside it.
+pub fn lint(formula: &Formula) -> Vec<FormulaWarning> {
+    let mut warnings = vec![];
+    lint_node(&formula.ast, &mut warnings);
+    warnings
+}
+
+fn lint_node(node: &AstNode, warnings: &mut Vec<FormulaWarning>) {
+    match &node.inner {
+        AstNodeContents::FunctionCall { func, args } => {
+            lint_call(func, args, warnings);
+            for arg in args {
+                lint_node(arg, warnings);
+            }
+        }
+        AstNodeContents::Paren(contents) => lint_node(contents, warnings),
+        AstNodeContents::Array(a) => {
+            for elem in a.iter().flatten() {
+                lint_node(elem, warnings);
+            }
+        }
+        AstNodeContents::CellRef(_)
+        | AstNodeContents::ColumnRange(..)
+        | AstNodeContents::Name(_)
+        | AstNodeContents::String(_)
+        | AstNodeContents::Number(_)
+        | AstNodeContents::Error(_)
+        | AstNodeContents::Omitted => (),
+    }
+}
+
+fn lint_call(func: &Spanned<String>, args: &[AstNode], warnings: &mut Vec<FormulaWarning>) {
+    match (func.inner.to_ascii_lowercase().as_str(), args) {
+        ("/", [_, divisor]) if constant_number(divisor) == Some(0.0) => {
+            warnings.push(FormulaWarningMsg::DivideByZero.with_span(divisor.span));
+        }
+
+        ("if", [cond, if_true, if_false]) => match constant_bool(cond) {
+            Some(true) => warnings.push(unreachable(if_false, if_false)),
+            Some(false) => warnings.push(unreachable(if_true, if_true)),
+            None => (),
+        },
+
+        // `AND` and `OR` stop at the first argument that decides the result.
+        (name @ ("and" | "or"), _) => {
+            let deciding_value = name == "or";
+            let decided_at = args
+                .iter()
+                .position(|arg| constant_bool(arg) == Some(deciding_value));
+            if let Some(rest) = decided_at.map(|i| &args[i + 1..]) {
+                if let (Some(first), Some(last)) = (rest.first(), rest.last()) {
+                    warnings.push(unreachable(first, last));
+                }
+            }
+        }
+
+        ("index", [array, indices @ ..]) => {
+            let Some((rows, cols)) = constant_size(array) else {
+                return;
+            };
+            let (row, col) = match indices {
+                [col] if rows == 1 => (None, Some(col)),
+                [row] => (Some(row), None),
+                [row, col] => (Some(row), Some(col)),
+                _ => return,
+            };
+            for (index, len) in [(row, rows), (col, cols)] {
+                let Some(index) = index else { continue };
+                let Some(n) = constant_number(index) else {
+                    continue;
+                };
+                if !(1..=len as i64).contains(&(n.round() as i64)) {
+                    warnings.push(FormulaWarningMsg::IndexOutOfBounds.with_span(index.span));
+                }
+            }
+        }
+
+        _ => (),
+    }
+}
+
+/// Returns a warning that the arguments from `first` to `last` are never
+/// evaluated.
+fn unreachable(first: &AstNode, last: &AstNode) -> FormulaWarning {
+    FormulaWarningMsg::UnreachableArgument.with_span(Span::merge(first, last))
+}
+
+/// Returns the number that an expression always evaluates to, if it is a
+/// literal number.
+fn constant_number(node: &AstNode) -> Option<f64> {
+    match &node.inner {
+        AstNodeContents::Number(n) => Some(*n),
+        AstNodeContents::Paren(contents) => constant_number(contents),
+        AstNodeContents::FunctionCall { func, args } => match (func.inner.as_str(), &args[..]) {
+            ("-", [arg]) => constant_number(arg).map(|n| -n),
+            ("+", [arg]) => constant_number(arg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the boolean that a condition always evaluates to, if it is a
+/// literal number, `TRUE()`, or `FALSE()`.
+fn constant_bool(node: &AstNode) -> Option<bool> {
+    match &node.inner {
+        AstNodeContents::Paren(contents) => constant_bool(contents),
+        AstNodeContents::FunctionCall { func, args } if args.is_empty() => {
+            match func.inner.to_ascii_lowercase().as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            }
+        }
+        _ => constant_number(node).map(|n| n != 0.0),
+    }
+}
+
+/// Returns the size `(rows, columns)` of an array literal or a cell range, if
+/// it doesn't depend on where the formula is. A range with one relative and
+/// one absolute end has a different size in different cells.
+fn constant_size(node: &AstNode) -> Option<(usize, usize)> {
+    match &node.inner {
+        AstNodeContents::Paren(contents) => constant_size(contents),
+        AstNodeContents::Array(a) => Some((a.len(), a.first()?.len())),
+        AstNodeContents::FunctionCall { func, args } if func.inner == ":" => {
+            let [start, end] = args.as_slice() else {
+                return None;
+            };
+            let (start, end) = (start.to_cell_ref().ok()?, end.to_cell_ref().ok()?);
+            Some((constant_len(start.y, end.y)?, constant_len(start.x, end.x)?))
+        }
+        _ => None,
+    }
+}
+fn constant_len(start: CellRefCoord, end: CellRefCoord) -> Option<usize> {
+    match (start, end) {
+        (CellRefCoord::Relative(a), CellRefCoord::Relative(b))
+        | (CellRefCoord::Absolute(a), CellRefCoord::Absolute(b)) => {
+            Some(a.abs_diff(b) as usize + 1)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns each warning's message and the source it is about.
+    fn lint_src(src: &str) -> Vec<(FormulaWarningMsg, &str)> {
+        let formula = parse_formula(src, Pos::ORIGIN).unwrap();
+        lint(&formula)
+            .into_iter()
+            .map(|w| (w.msg, w.span.unwrap().of_str(src)))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        use FormulaWarningMsg::*;
+
+        assert_eq!(vec![(DivideByZero, "0")], lint_src("A1 / 0"));
+        assert_eq!(vec![(DivideByZero, "(-0)")], lint_src("SUM(1, 2 / (-0))"));
+        assert!(lint_src("A1 / 0.5").is_empty());
+        assert!(lint_src("A1 / B1").is_empty());
+        assert!(lint_src("0 / A1").is_empty());
+
+        assert_eq!(
+            vec![(UnreachableArgument, "A3")],
+            lint_src("IF(TRUE(), A2, A3)"),
+        );
+        assert_eq!(vec![(UnreachableArgument, "A2")], lint_src("IF(0, A2, A3)"));
+        assert!(lint_src("IF(A1, A2, A3)").is_empty());
+        assert!(lint_src("IF({1, 0}, A2, A3)").is_empty());
+        assert_eq!(
+            vec![(UnreachableArgument, "A2, A3")],
+            lint_src("OR(A1, TRUE(), A2, A3)"),
+        );
+        assert_eq!(
+            vec![(UnreachableArgument, "A2")],
+            lint_src("AND(A1, FALSE(), A2)"),
+        );
+        assert!(lint_src("OR(A1, FALSE(), A2)").is_empty());
+        assert!(lint_src("AND(A1, A2, FALSE())").is_empty());
+
+        assert_eq!(
+            vec![(IndexOutOfBounds, "4")],
+            lint_src("INDEX({1, 2, 3}, 4)"),
+        );
+        assert_eq!(
+            vec![(IndexOutOfBounds, "0")],
+            lint_src("INDEX(B1:C5, 0, 2)"),
+        );
+        assert_eq!(
+            vec![(IndexOutOfBounds, "3")],
+            lint_src("INDEX($B$1:$C$5, 5, 3)"),
+        );
+        assert!(lint_src("INDEX({1, 2, 3}, 3)").is_empty());
+        assert!(lint_src("INDEX({1; 2; 3}, 3)").is_empty());
+        assert!(lint_src("INDEX(B1:C5, 5, 2)").is_empty());
+        // The size of this range depends on where the formula is.
+        assert!(lint_src("INDEX(B1:$C$5, 9, 9)").is_empty());
+        assert!(lint_src("INDEX(B1:C5, A1)").is_empty());
+
+        // Nested expressions are checked too.
+        assert_eq!(
+            vec![(UnreachableArgument, "1 / 0"), (DivideByZero, "0")],
+            lint_src("IF(1, {A1 / 2}, 1 / 0)"),
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Searching arrays for lookup functions such as `XMATCH` and `XLOOKUP`.
+//!
+//! Values are compared in the order described in [`super::order`], and only
+//! match values of the same type. Blanks and errors never match anything.
+//! Text is compared according to a [`TextCompareMode`], including text
+//! matched against a wildcard pattern.
+
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use super::criteria::wildcard_regex;
+use super::order::{SortKey, TextCompareMode};
+use super::*;
+
+/// How a lookup decides whether a value matches, as in the `match_mode`
+/// argument of `XMATCH`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Only an equal value matches.
+    Exact,
+    /// An equal value, or else the largest smaller value of the same type.
+    ExactOrNextSmaller,
+    /// An equal value, or else the smallest larger value of the same type.
+    ExactOrNextLarger,
+    /// Text matches a pattern where `?` matches any single character, `*`
+    /// matches any sequence of characters, and `~` escapes the next
+    /// character.
+    Wildcard,
+}
+impl MatchMode {
+    /// Parses the `match_mode` argument of a lookup function: `0` for exact,
+    /// `-1` for exact or next smaller, `1` for exact or next larger, or `2`
+    /// for wildcard. The default is exact, which is also used if the argument is
+    /// blank.
+    pub fn try_from_arg(arg: Option<&Spanned<Value>>) -> FormulaResult<Self> {
+        let Some(arg) = arg.filter(|arg| !is_blank(arg)) else {
+            return Ok(Self::Exact);
+        };
+        match arg.to_integer()? {
+            0 => Ok(Self::Exact),
+            -1 => Ok(Self::ExactOrNextSmaller),
+            1 => Ok(Self::ExactOrNextLarger),
+            2 => Ok(Self::Wildcard),
+            _ => Err(FormulaErrorMsg::InvalidArgument.with_span(arg.span)),
+        }
+    }
+}
+
+/// Order in which a lookup searches, as in the `search_mode` argument of
+/// `XMATCH`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Returns the first match.
+    FirstToLast,
+    /// Returns the last match.
+    LastToFirst,
+    /// Binary search of values sorted in ascending order.
+    BinaryAscending,
+    /// Binary search of values sorted in descending order.
+    BinaryDescending,
+}
+impl SearchMode {
+    /// Parses the `search_mode` argument of a lookup function: `1` to search
+    /// first to last, `-1` to search last to first, `2` for binary search in
+    /// ascending order, or `-2` for binary search in descending order. The
+    /// default is first to last, which is also used if the argument is blank.
+    pub fn try_from_arg(arg: Option<&Spanned<Value>>) -> FormulaResult<Self> {
+        let Some(arg) = arg.filter(|arg| !is_blank(arg)) else {
+            return Ok(Self::FirstToLast);
+        };
+        match arg.to_integer()? {
+            1 => Ok(Self::FirstToLast),
+            -1 => Ok(Self::LastToFirst),
+            2 => Ok(Self::BinaryAscending),
+            -2 => Ok(Self::BinaryDescending),
+            _ => Err(FormulaErrorMsg::InvalidArgument.with_span(arg.span)),
+        }
+    }
+    fn is_binary(self) -> bool {
+        matches!(self, Self::BinaryAscending | Self::BinaryDescending)
+    }
+}
+
+fn is_blank(arg: &Spanned<Value>) -> bool {
+    arg.inner.is_blank_or_empty()
+}
+
+/// Returns the key to compare a value by, or `None` if it is blank, an error,
+/// or an array, which never match anything.
+fn lookup_key(value: &Value, text_compare: TextCompareMode) -> Option<SortKey> {
+    match SortKey::with_mode(value, text_compare) {
+        key @ (SortKey::Number(_) | SortKey::Text(_) | SortKey::Bool(_)) => Some(key),
+        SortKey::Error(_) | SortKey::Other | SortKey::Blank => None,
+    }
+}
+
+/// Searches `haystack` for `needle`, returning the index of the match or
+/// `None` if there is none.
+///
+/// Binary search modes assume that the values are sorted in the given order,
+/// with blanks last. If they are not sorted, the result is unspecified: it
+/// may be any index or `None`, but no error is returned.
+///
+/// Wildcards are only supported with linear search modes; binary search
+/// modes treat [`MatchMode::Wildcard`] as [`MatchMode::Exact`].
+pub fn search(
+    needle: &Value,
+    haystack: &[&Value],
+    match_mode: MatchMode,
+    search_mode: SearchMode,
+    text_compare: TextCompareMode,
+) -> Option<usize> {
+    let needle_key = lookup_key(needle, text_compare)?;
+    if search_mode.is_binary() {
+        return binary_search(&needle_key, haystack, match_mode, search_mode, text_compare);
+    }
+
+    let indices: Box<dyn Iterator<Item = usize>> = match search_mode {
+        SearchMode::LastToFirst => Box::new((0..haystack.len()).rev()),
+        _ => Box::new(0..haystack.len()),
+    };
+
+    if match_mode == MatchMode::Wildcard {
+        let pattern = match &needle_key {
+            SortKey::Text(_) => Some(wildcard_regex(&needle.to_string(), text_compare)),
+            _ => None,
+        };
+        return indices
+            .into_iter()
+            .find(|&i| wildcard_matches(&needle_key, pattern.as_ref(), haystack[i], text_compare));
+    }
+
+    // Keep the closest value in the right direction, preferring the one found
+    // first if there are several.
+    let mut best: Option<(usize, SortKey)> = None;
+    for i in indices {
+        let Some(key) = lookup_key(haystack[i], text_compare) else {
+            continue;
+        };
+        let Some(ordering) = key.cmp_same_type(&needle_key) else {
+            continue;
+        };
+        let is_candidate = match (ordering, match_mode) {
+            (Ordering::Equal, _) => return Some(i),
+            (Ordering::Less, MatchMode::ExactOrNextSmaller) => true,
+            (Ordering::Greater, MatchMode::ExactOrNextLarger) => true,
+            _ => false,
+        };
+        let is_closer = |best: &SortKey| match match_mode {
+            MatchMode::ExactOrNextSmaller => key > *best,
+            _ => key < *best,
+        };
+        if is_candidate && best.as_ref().is_none_or(|(_, best)| is_closer(best)) {
+            best = Some((i, key));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// Returns whether `value` matches a wildcard lookup. `pattern` is the
+/// pattern if the needle is text.
+fn wildcard_matches(
+    needle: &SortKey,
+    pattern: Option<&Regex>,
+    value: &Value,
+    text_compare: TextCompareMode,
+) -> bool {
+    match (lookup_key(value, text_compare), pattern) {
+        (Some(SortKey::Text(_)), Some(pattern)) => pattern.is_match(&value.to_string()),
+        (Some(key), None) => key == *needle,
+        _ => false,
+    }
+}
+
+fn binary_search(
+    needle: &SortKey,
+    haystack: &[&Value],
+    match_mode: MatchMode,
+    search_mode: SearchMode,
+    text_compare: TextCompareMode,
+) -> Option<usize> {
+    let descending = search_mode == SearchMode::BinaryDescending;
+    // Index of the first value that is not before the needle in the sorted
+    // order. Blanks are sorted last.
+    let i = haystack.partition_point(|value| match lookup_key(value, text_compare) {
+        Some(key) if descending => key > *needle,
+        Some(key) => key < *needle,
+        None => false,
+    });
+    let same_type_at = |i: usize| {
+        let key = lookup_key(haystack.get(i)?, text_compare)?;
+        Some((i, key.cmp_same_type(needle)?))
+    };
+
+    if let Some((i, Ordering::Equal)) = same_type_at(i) {
+        return Some(i);
+    }
+    // The values on either side of `i` are the closest ones in each
+    // direction.
+    let (smaller, larger) = match descending {
+        false => (i.checked_sub(1), Some(i)),
+        true => (Some(i), i.checked_sub(1)),
+    };
+    let candidate = match match_mode {
+        MatchMode::ExactOrNextSmaller => smaller,
+        MatchMode::ExactOrNextLarger => larger,
+        MatchMode::Exact | MatchMode::Wildcard => None,
+    };
+    candidate
+        .and_then(same_type_at)
+        .filter(|&(_, ordering)| ordering != Ordering::Equal)
+        .map(|(i, _)| i)
+}
+
+/// Returns the values of a one-dimensional array, and whether it is a
+/// column rather than a row. Returns an error if it is two-dimensional.
+pub fn lookup_vector(array: &Spanned<Value>) -> FormulaResult<(Vec<&Value>, bool)> {
+    match &array.inner {
+        Value::Array(a) if a.size().0 == 1 => Ok((a.iter().collect(), false)),
+        Value::Array(a) if a.size().0 == 0 || a.size().1 == 1 => Ok((a.iter().collect(), true)),
+        Value::Array(_) => Err(FormulaErrorMsg::InvalidArgument.with_span(array.span)),
+        other => Ok((vec![other], false)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn values(src: &[&str]) -> Vec<Value> {
+        src.iter().map(|s| Value::String(s.to_string())).collect()
+    }
+
+    #[test]
+    fn test_binary_search_matches_linear_search() {
+        use MatchMode::*;
+
+        let ascending = values(&["1", "3", "3", "5", "8", "Apple", "apple", "pear", ""]);
+        let ascending = ascending.iter().collect::<Vec<_>>();
+        let descending = values(&["pear", "apple", "Apple", "8", "5", "3", "3", "1", ""]);
+        let descending = descending.iter().collect::<Vec<_>>();
+
+        let needles = [
+            "0", "1", "2", "3", "4", "8", "9", "a", "apple", "APPLE", "b", "z",
+        ];
+        for (needle, text_compare) in needles.into_iter().cartesian_product([
+            TextCompareMode::CaseInsensitive,
+            TextCompareMode::CaseSensitive,
+        ]) {
+            let needle = Value::String(needle.to_string());
+            // Values that differ only in case may be equal, so compare the
+            // keys of the values found.
+            let key = |value: &Value| SortKey::with_mode(value, text_compare);
+            for match_mode in [Exact, ExactOrNextSmaller, ExactOrNextLarger] {
+                let linear = search(
+                    &needle,
+                    &ascending,
+                    match_mode,
+                    SearchMode::FirstToLast,
+                    text_compare,
+                )
+                .map(|i| key(ascending[i]));
+                for (haystack, search_mode) in [
+                    (&ascending, SearchMode::BinaryAscending),
+                    (&descending, SearchMode::BinaryDescending),
+                ] {
+                    let binary = search(&needle, haystack, match_mode, search_mode, text_compare)
+                        .map(|i| key(haystack[i]));
+                    assert_eq!(
+                        linear, binary,
+                        "{needle:?} {match_mode:?} {search_mode:?} {text_compare:?}",
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! Dense matrices of numbers for linear algebra functions.
+
+use super::*;
+
+/// Rectangular matrix of numbers, stored in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+impl std::ops::Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (row, col): (usize, usize)) -> &f64 {
+        &self.data[row * self.cols + col]
+    }
+}
+impl std::ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut f64 {
+        &mut self.data[row * self.cols + col]
+    }
+}
+impl Matrix {
+    /// Constructs a matrix of zeros.
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+    /// Constructs an `n`x`n` identity matrix.
+    pub fn identity(n: usize) -> Self {
+        let mut ret = Self::zeros(n, n);
+        for i in 0..n {
+            ret[(i, i)] = 1.0;
+        }
+        ret
+    }
+    /// Constructs a matrix from numbers in row-major order.
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        assert_eq!(rows * cols, data.len(), "wrong number of matrix elements");
+        Self { rows, cols, data }
+    }
+    /// Constructs a matrix from a formula value, treating non-array values as
+    /// 1x1 matrices. Returns an error if any value is not a number.
+    pub fn from_value(value: &Spanned<Value>) -> FormulaResult<Self> {
+        let (rows, cols) = value.inner.array_size().unwrap_or((1, 1));
+        let mut data = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                data.push(value.get_array_value(row, col)?.to_number()?);
+            }
+        }
+        Ok(Self { rows, cols, data })
+    }
+    /// Constructs a square matrix from a formula value. Returns an error if
+    /// any value is not a number or if the matrix is not square.
+    pub fn from_square_value(value: &Spanned<Value>) -> FormulaResult<Self> {
+        let ret = Self::from_value(value)?;
+        if ret.rows != ret.cols {
+            return Err(FormulaErrorMsg::ArraySizeMismatch {
+                expected: (ret.rows, ret.rows),
+                got: ret.size(),
+            }
+            .with_span(value.span));
+        }
+        Ok(ret)
+    }
+    /// Converts the matrix to an array value.
+    pub fn to_value(&self) -> Value {
+        Value::Array(Array::from_row_major(
+            self.rows,
+            self.cols,
+            self.data.iter().map(|&n| Value::Number(n)).collect(),
+        ))
+    }
+
+    /// Returns the number of rows.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    /// Returns the number of columns.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+    /// Returns the size `(rows, columns)` of the matrix.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+    /// Returns the elements of the matrix in row-major order.
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    /// Returns the transpose of the matrix.
+    pub fn transpose(&self) -> Self {
+        let mut ret = Self::zeros(self.cols, self.rows);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                ret[(col, row)] = self[(row, col)];
+            }
+        }
+        ret
+    }
+
+    /// Returns the matrix product `self * other`, or `None` if the number of
+    /// columns in `self` does not match the number of rows in `other`.
+    pub fn matmul(&self, other: &Self) -> Option<Self> {
+        if self.cols != other.rows {
+            return None;
+        }
+        let mut ret = Self::zeros(self.rows, other.cols);
+        for row in 0..self.rows {
+            for col in 0..other.cols {
+                ret[(row, col)] = (0..self.cols)
+                    .map(|k| self[(row, k)] * other[(k, col)])
+                    .sum();
+            }
+        }
+        Some(ret)
+    }
+
+    /// Returns the determinant of a square matrix. If the matrix is singular
+    /// to within a tolerance, returns exactly zero.
+    pub fn determinant(&self) -> f64 {
+        self.lu_decomposition().map_or(0.0, |lu| lu.determinant())
+    }
+    /// Returns the inverse of a square matrix, or `None` if the matrix is
+    /// singular to within a tolerance.
+    pub fn inverse(&self) -> Option<Self> {
+        let lu = self.lu_decomposition()?;
+        let n = self.rows;
+        let mut ret = Self::zeros(n, n);
+        for col in 0..n {
+            let unit = (0..n).map(|row| if row == col { 1.0 } else { 0.0 });
+            for (row, x) in lu.solve(unit.collect()).into_iter().enumerate() {
+                ret[(row, col)] = x;
+            }
+        }
+        Some(ret)
+    }
+    /// Computes the LU decomposition `PA = LU` of a square matrix, or returns
+    /// `None` if it is singular to within a tolerance.
+    ///
+    /// This uses partial pivoting: at each step, the row with the largest
+    /// absolute value in the current column is swapped into place, which keeps
+    /// the multipliers in `L` no larger than 1 and avoids dividing by small
+    /// pivots.
+    fn lu_decomposition(&self) -> Option<LuDecomposition> {
+        let n = self.rows;
+        let mut a = self.clone();
+        let max_abs = a.data.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        let tolerance = RANK_TOLERANCE * max_abs;
+
+        let mut permutation = (0..n).collect::<Vec<_>>();
+        let mut sign = 1.0;
+        for j in 0..n {
+            let pivot_row =
+                (j..n).max_by(|&r1, &r2| a[(r1, j)].abs().total_cmp(&a[(r2, j)].abs()))?;
+            let pivot = a[(pivot_row, j)];
+            if pivot.abs() <= tolerance {
+                return None;
+            }
+            if pivot_row != j {
+                a.swap_rows(j, pivot_row);
+                permutation.swap(j, pivot_row);
+                sign = -sign;
+            }
+
+            // Below the diagonal, store the multipliers of `L` in place of
+            // the values they eliminate.
+            for row in j + 1..n {
+                let factor = a[(row, j)] / pivot;
+                a[(row, j)] = factor;
+                for col in j + 1..n {
+                    let x = a[(j, col)];
+                    a[(row, col)] -= factor * x;
+                }
+            }
+        }
+        Some(LuDecomposition {
+            lu: a,
+            permutation,
+            sign,
+        })
+    }
+    fn swap_rows(&mut self, r1: usize, r2: usize) {
+        for col in 0..self.cols {
+            self.data.swap(r1 * self.cols + col, r2 * self.cols + col);
+        }
+    }
+
+    /// Finds `x` minimizing `|self * x - b|` using a Householder QR
+    /// decomposition, which is more numerically stable than solving the
+    /// normal equations. Returns `None` if the columns of the matrix are
+    /// linearly dependent (to within a tolerance) or if there are more columns
+    /// than rows.
+    pub fn least_squares(&self, b: &[f64]) -> Option<LeastSquares> {
+        let (m, n) = self.size();
+        if m < n || b.len() != m {
+            return None;
+        }
+        let mut a = self.clone();
+        let mut b = b.to_vec();
+
+        for j in 0..n {
+            let column_norm = (0..m).map(|i| a[(i, j)].powi(2)).sum::<f64>().sqrt();
+            let norm = (j..m).map(|i| a[(i, j)].powi(2)).sum::<f64>().sqrt();
+            // If nothing is left of the column after removing its components
+            // along previous columns, then it is linearly dependent on them.
+            if norm <= RANK_TOLERANCE * column_norm || norm == 0.0 {
+                return None;
+            }
+
+            // Reflect the column onto the diagonal, choosing the sign that
+            // avoids cancellation.
+            let alpha = if a[(j, j)] > 0.0 { -norm } else { norm };
+            let mut v = (j..m).map(|i| a[(i, j)]).collect::<Vec<_>>();
+            v[0] -= alpha;
+            let v_norm_sq = v.iter().map(|x| x * x).sum::<f64>();
+            let reflect = |x: &mut [f64]| {
+                let dot = x.iter().zip(&v).map(|(xk, vk)| xk * vk).sum::<f64>();
+                let s = 2.0 * dot / v_norm_sq;
+                for (xk, vk) in x.iter_mut().zip(&v) {
+                    *xk -= s * vk;
+                }
+            };
+            for col in j..n {
+                let mut x = (j..m).map(|i| a[(i, col)]).collect::<Vec<_>>();
+                reflect(&mut x);
+                for (i, xi) in (j..m).zip(x) {
+                    a[(i, col)] = xi;
+                }
+            }
+            reflect(&mut b[j..]);
+        }
+
+        // Solve `R * x = Q^T * b` by back substitution.
+        let mut x = vec![0.0; n];
+        for i in (0..n).rev() {
+            let sum = (i + 1..n).map(|k| a[(i, k)] * x[k]).sum::<f64>();
+            x[i] = (b[i] - sum) / a[(i, i)];
+        }
+        let residual_sum_of_squares = b[n..].iter().map(|r| r * r).sum();
+
+        let mut r = Self::zeros(n, n);
+        for i in 0..n {
+            for k in i..n {
+                r[(i, k)] = a[(i, k)];
+            }
+        }
+
+        Some(LeastSquares {
+            solution: x,
+            residual_sum_of_squares,
+            r,
+        })
+    }
+}
+
+/// Relative tolerance for deciding that a matrix is singular.
+const RANK_TOLERANCE: f64 = 1e-10;
+
+/// LU decomposition of a square matrix with partial pivoting.
+#[derive(Debug, Clone)]
+struct LuDecomposition {
+    /// `L` below the diagonal, without its diagonal of ones, and `U` on and
+    /// above the diagonal.
+    lu: Matrix,
+    /// Row of the original matrix that ended up in each row.
+    permutation: Vec<usize>,
+    /// Sign of the permutation: 1 for an even number of swaps, or -1 for an
+    /// odd number.
+    sign: f64,
+}
+impl LuDecomposition {
+    fn determinant(&self) -> f64 {
+        let n = self.lu.rows;
+        self.sign * (0..n).map(|i| self.lu[(i, i)]).product::<f64>()
+    }
+    /// Solves `A * x = b` by forward and back substitution.
+    fn solve(&self, b: Vec<f64>) -> Vec<f64> {
+        let n = self.lu.rows;
+        // Solve `L * y = P * b`.
+        let mut y = self.permutation.iter().map(|&i| b[i]).collect::<Vec<_>>();
+        for i in 0..n {
+            y[i] -= (0..i).map(|k| self.lu[(i, k)] * y[k]).sum::<f64>();
+        }
+        // Solve `U * x = y`.
+        for i in (0..n).rev() {
+            let sum = (i + 1..n).map(|k| self.lu[(i, k)] * y[k]).sum::<f64>();
+            y[i] = (y[i] - sum) / self.lu[(i, i)];
+        }
+        y
+    }
+}
+
+/// Solution to a least-squares problem.
+#[derive(Debug, Clone)]
+pub struct LeastSquares {
+    /// Values of the unknowns that minimize the residual.
+    pub solution: Vec<f64>,
+    /// Sum of the squares of the residuals.
+    pub residual_sum_of_squares: f64,
+    /// Upper triangular factor from the QR decomposition.
+    r: Matrix,
+}
+impl LeastSquares {
+    /// Returns the diagonal of `(A^T A)^-1`, which is proportional to the
+    /// variance of each unknown.
+    pub fn unscaled_variances(&self) -> Vec<f64> {
+        // (A^T A)^-1 = (R^T R)^-1 = R^-1 R^-T, so each diagonal element is the
+        // squared norm of a row of R^-1.
+        let n = self.r.rows;
+        let mut r_inv = Matrix::zeros(n, n);
+        for col in 0..n {
+            for row in (0..=col).rev() {
+                let identity = if row == col { 1.0 } else { 0.0 };
+                let sum = (row + 1..=col)
+                    .map(|k| self.r[(row, k)] * r_inv[(k, col)])
+                    .sum::<f64>();
+                r_inv[(row, col)] = (identity - sum) / self.r[(row, row)];
+            }
+        }
+        (0..n)
+            .map(|row| (0..n).map(|col| r_inv[(row, col)].powi(2)).sum())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_squares() {
+        // Fit y = 1 + 2x exactly.
+        let a = Matrix::from_vec(3, 2, vec![1.0, 0.0, 1.0, 1.0, 1.0, 2.0]);
+        let fit = a.least_squares(&[1.0, 3.0, 5.0]).unwrap();
+        assert!((fit.solution[0] - 1.0).abs() < 1e-12);
+        assert!((fit.solution[1] - 2.0).abs() < 1e-12);
+        assert!(fit.residual_sum_of_squares < 1e-20);
+
+        // (A^T A)^-1 = [[5, -3], [-3, 3]] / 6
+        let variances = fit.unscaled_variances();
+        assert!((variances[0] - 5.0 / 6.0).abs() < 1e-12);
+        assert!((variances[1] - 0.5).abs() < 1e-12);
+
+        // Second column is a multiple of the first.
+        let a = Matrix::from_vec(3, 2, vec![1.0, 2.0, 1.0, 2.0, 1.0, 2.0]);
+        assert!(a.least_squares(&[1.0, 2.0, 3.0]).is_none());
+        // Too few rows.
+        assert!(Matrix::zeros(1, 2).least_squares(&[1.0]).is_none());
+    }
+
+    #[test]
+    fn test_inverse_and_determinant() {
+        // Requires a row swap for the first pivot.
+        let a = Matrix::from_vec(3, 3, vec![0.0, 2.0, 1.0, 1.0, 1.0, 0.0, 3.0, 0.0, 1.0]);
+        assert!((a.determinant() + 5.0).abs() < 1e-12);
+        let product = a.matmul(&a.inverse().unwrap()).unwrap();
+        for (got, expected) in product.data().iter().zip(Matrix::identity(3).data()) {
+            assert!((got - expected).abs() < 1e-12);
+        }
+
+        let singular = Matrix::from_vec(2, 2, vec![1.0, 2.0, 2.0, 4.0 + 1e-14]);
+        assert_eq!(0.0, singular.determinant());
+        assert!(singular.inverse().is_none());
+        assert_eq!(0.0, Matrix::zeros(2, 2).determinant());
+    }
+
+    #[test]
+    fn test_lu_decomposition() {
+        // Both columns need a row swap, so the permutation is even.
+        let a = Matrix::from_vec(3, 3, vec![0.0, 2.0, 1.0, 1.0, 1.0, 0.0, 3.0, 0.0, 1.0]);
+        let lu = a.lu_decomposition().unwrap();
+        assert_eq!(vec![2, 0, 1], lu.permutation);
+        assert_eq!(1.0, lu.sign);
+        let expected = [3.0, 0.0, 1.0, 0.0, 2.0, 1.0, 1.0 / 3.0, 0.5, -5.0 / 6.0];
+        for (got, expected) in lu.lu.data().iter().zip(expected) {
+            assert!((got - expected).abs() < 1e-12);
+        }
+        let x = lu.solve(vec![3.0, 2.0, 4.0]);
+        for (got, expected) in x.iter().zip([1.0, 1.0, 1.0]) {
+            assert!((got - expected).abs() < 1e-12);
+        }
+    }
+}
@@ -1,25 +1,87 @@
 use crate::Pos;
 use ast::AstNode;
+use coercion::Coercion;
 use lexer::Token;
 
 #[macro_use]
 mod errors;
+mod aggregate;
+mod arithmetic;
+mod array;
 mod ast;
+mod bitwise;
 mod cell_ref;
+mod coercion;
+mod complex;
+mod criteria;
+mod ctx;
+mod custom_functions;
+mod database;
+mod date;
+#[cfg(feature = "datetime")]
+mod datetime;
+mod distribution;
+mod engine;
 mod functions;
 mod grid_proxy;
+mod highlight;
+mod lambda;
 mod lexer;
+mod lint;
+mod lookup;
+mod matrix;
+mod number_format;
+mod order;
 mod parser;
+mod pattern;
+mod radix;
+mod random;
+mod regression;
+mod signatures;
 mod span;
+mod text;
+mod units;
 mod value;
 
-pub use ast::Formula;
+pub use array::Array;
+pub use ast::{
+    asts_equivalent, evaluate_with_trace, ArrayResult, EvalOutcome, EvalResult, Formula,
+};
 pub use cell_ref::*;
-pub use errors::{FormulaError, FormulaErrorMsg};
+pub use coercion::Strictness;
+pub use criteria::Criterion;
+pub use ctx::{
+    Ctx, EvalSettings, FloatTolerance, DEFAULT_MAX_ARRAY_SIZE, DEFAULT_MAX_EVAL_DEPTH,
+    DEFAULT_MAX_EVAL_STEPS, DEFAULT_MAX_TEXT_LENGTH,
+};
+pub use custom_functions::CustomFunctions;
+pub use date::{parse_date, parse_time, DateSystem};
+#[cfg(feature = "datetime")]
+pub use datetime::parse_datetime;
+pub use engine::{FormulaEngine, DEFAULT_PARSE_CACHE_CAPACITY};
+pub use errors::{
+    parse_error_literal, render_with_source, FormulaError, FormulaErrorMsg, FormulaWarning,
+    FormulaWarningMsg,
+};
 pub use grid_proxy::GridProxy;
-pub use parser::parse_formula;
+pub use highlight::{tokenize, TokenKind};
+pub use lambda::Lambda;
+pub use lint::lint;
+pub use lookup::{MatchMode, SearchMode};
+pub use number_format::{NumberFormat, NumberLocale};
+pub use order::TextCompareMode;
+pub use parser::{
+    parse_formula, parse_formula_batch, parse_formula_with_max_depth, reparse_formula, TextEdit,
+    DEFAULT_MAX_NESTING_DEPTH,
+};
+pub use random::Rng;
+pub use signatures::{
+    function_signature, function_signatures, similar_function_name, ArgSignature, ArgType,
+    FunctionSignature,
+};
 pub use span::{Span, Spanned};
-pub use value::Value;
+pub use text::TextUnit;
+pub use value::{Value, ValueType};
 
 /// Result of a `FormulaError`.
 pub type FormulaResult<T = Spanned<Value>> = Result<T, FormulaError>;
@@ -0,0 +1,624 @@
+//! Number formats, such as `$#,##0.00` or `yyyy-mm-dd`, used for displaying
+//! values and by the `TEXT` function.
+//!
+//! Like Excel, a format has up to four sections separated by semicolons. With
+//! one section, it is used for all numbers. With two, the second is used for
+//! negative numbers, which are then shown without a minus sign so that the
+//! section can show them some other way, such as in parentheses. A third
+//! section is used for zero, and a fourth for text.
+//!
+//! Within a section:
+//!
+//! - `0` is a digit that is always shown, and `#` or `?` is a digit that is
+//!   shown only if significant.
+//! - `.` is the decimal point, and `,` between digits separates thousands.
+//!   Each `,` after the last digit divides the number by 1000.
+//! - `%` multiplies the number by 100.
+//! - `E+00` or `E-00` shows the number in scientific notation.
+//! - `y`, `m`, `d`, `h`, `s`, `AM/PM`, and `A/P` show parts of a date or time.
+//!   `m` is minutes if it comes just after hours or just before seconds.
+//! - `@` is the text, and `General` is the number in the default format.
+//! - Text in double quotes, or a character after `\`, is shown as-is.
+//!   `[$€]` shows a currency symbol, and other text in square brackets, such
+//!   as a color, is ignored.
+
+use itertools::Itertools;
+
+use super::date::{self, Date, DateSystem, MONTH_NAMES, WEEKDAY_NAMES};
+use super::*;
+
+/// Symbols for formatting numbers that depend on the locale, used by
+/// `DOLLAR` and `FIXED`. Number formats themselves always use `.` and `,`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NumberLocale {
+    /// Symbol shown before an amount of currency.
+    pub currency_symbol: &'static str,
+    /// Symbol between the integer and fractional parts of a number.
+    pub decimal_separator: char,
+    /// Symbol between groups of thousands.
+    pub group_separator: char,
+}
+impl Default for NumberLocale {
+    fn default() -> Self {
+        Self::US
+    }
+}
+impl NumberLocale {
+    /// Conventions in the US, which are the default.
+    pub const US: Self = Self {
+        currency_symbol: "$",
+        decimal_separator: '.',
+        group_separator: ',',
+    };
+
+    /// Replaces the `$`, `.`, and `,` in a formatted number with the symbols
+    /// for this locale.
+    pub fn localize(&self, formatted: &str) -> String {
+        let mut ret = String::with_capacity(formatted.len());
+        for c in formatted.chars() {
+            match c {
+                '$' => ret.push_str(self.currency_symbol),
+                '.' => ret.push(self.decimal_separator),
+                ',' => ret.push(self.group_separator),
+                _ => ret.push(c),
+            }
+        }
+        ret
+    }
+}
+
+/// Parsed number format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    sections: Vec<Section>,
+    /// Date system that date serial numbers are in.
+    date_system: DateSystem,
+}
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            sections: vec![Section::new(vec![Part::General])],
+            date_system: DateSystem::default(),
+        }
+    }
+}
+impl NumberFormat {
+    /// Parses a format string, returning `None` if it has more than four
+    /// sections.
+    pub fn parse(s: &str) -> Option<Self> {
+        let chars = s.chars().collect_vec();
+        let mut sections = vec![];
+        let mut parts = vec![];
+        let mut i = 0;
+        while let Some(&c) = chars.get(i) {
+            i += 1;
+            let rest = &chars[i - 1..];
+            match c {
+                ';' => sections.push(Section::new(std::mem::take(&mut parts))),
+                '"' => {
+                    let len = chars[i..].iter().position(|&c| c == '"');
+                    let end = len.map_or(chars.len(), |len| i + len);
+                    push_literal(&mut parts, &String::from_iter(&chars[i..end]));
+                    i = end + 1;
+                }
+                '\\' => {
+                    if let Some(&c) = chars.get(i) {
+                        push_literal(&mut parts, &c.to_string());
+                        i += 1;
+                    }
+                }
+                // Space as wide as the next character.
+                '_' => {
+                    push_literal(&mut parts, " ");
+                    i += 1;
+                }
+                // Repeating the next character to fill the cell is not
+                // supported.
+                '*' => i += 1,
+                '[' => {
+                    let len = chars[i..].iter().position(|&c| c == ']');
+                    let end = len.map_or(chars.len(), |len| i + len);
+                    let contents = String::from_iter(&chars[i..end]);
+                    if let Some(currency) = contents.strip_prefix('$') {
+                        let symbol = currency.split('-').next().unwrap_or_default();
+                        push_literal(&mut parts, symbol);
+                    }
+                    i = end + 1;
+                }
+                '@' => parts.push(Part::Text),
+                '%' => parts.push(Part::Percent),
+                '0' | '#' | '?' => {
+                    i -= 1;
+                    parts.push(Part::Number(NumberPattern::parse(&chars, &mut i)));
+                }
+                '.' if matches!(chars.get(i), Some('0' | '#' | '?')) => {
+                    i -= 1;
+                    parts.push(Part::Number(NumberPattern::parse(&chars, &mut i)));
+                }
+                _ if starts_with_ignore_case(rest, "general") => {
+                    parts.push(Part::General);
+                    i += "general".len() - 1;
+                }
+                _ if starts_with_ignore_case(rest, "am/pm") => {
+                    parts.push(Part::Date(DatePart::AmPm {
+                        short: false,
+                        lowercase: c == 'a',
+                    }));
+                    i += "am/pm".len() - 1;
+                }
+                _ if starts_with_ignore_case(rest, "a/p") => {
+                    parts.push(Part::Date(DatePart::AmPm {
+                        short: true,
+                        lowercase: c == 'a',
+                    }));
+                    i += "a/p".len() - 1;
+                }
+                'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => {
+                    let len = rest
+                        .iter()
+                        .take_while(|d| d.eq_ignore_ascii_case(&c))
+                        .count();
+                    i += len - 1;
+                    parts.push(Part::Date(match c.to_ascii_lowercase() {
+                        'y' => DatePart::Year(len),
+                        'm' => DatePart::Month(len),
+                        'd' => DatePart::Day(len),
+                        'h' => DatePart::Hour(len),
+                        _ => DatePart::Second(len),
+                    }));
+                }
+                _ => push_literal(&mut parts, &c.to_string()),
+            }
+        }
+        sections.push(Section::new(parts));
+
+        (sections.len() <= 4).then_some(Self {
+            sections,
+            date_system: DateSystem::default(),
+        })
+    }
+
+    /// Returns the format with dates interpreted in a different date system.
+    pub fn with_date_system(self, date_system: DateSystem) -> Self {
+        Self {
+            date_system,
+            ..self
+        }
+    }
+
+    /// Formats a number, or returns `None` if the format is for dates and the
+    /// number is not a valid date.
+    pub fn format_number(&self, n: f64) -> Option<String> {
+        if self.sections[0].is_text_only() {
+            return Self::default().format_number(n);
+        }
+        let system = self.date_system;
+        let section_for_negative = self.sections.get(1).filter(|s| !s.is_text_only());
+        let section_for_zero = self.sections.get(2).filter(|s| !s.is_text_only());
+        match (section_for_negative, section_for_zero) {
+            (_, Some(zero)) if n == 0.0 => zero.format_number(n, false, system),
+            (Some(negative), _) if n < 0.0 => negative.format_number(-n, false, system),
+            (Some(_), _) => self.sections[0].format_number(n, false, system),
+            (None, _) => self.sections[0].format_number(n.abs(), n < 0.0, system),
+        }
+    }
+
+    /// Formats text, which is shown unchanged unless the format has a section
+    /// for text.
+    pub fn format_text(&self, s: &str) -> String {
+        let section = self
+            .sections
+            .get(3)
+            .or_else(|| self.sections.iter().find(|s| s.parts.contains(&Part::Text)));
+        let Some(section) = section else {
+            return s.to_string();
+        };
+        let mut ret = String::new();
+        for part in &section.parts {
+            match part {
+                Part::Literal(literal) => ret.push_str(literal),
+                Part::Percent => ret.push('%'),
+                Part::Text => ret.push_str(s),
+                _ => (),
+            }
+        }
+        ret
+    }
+}
+
+/// Section of a number format, which applies to some kinds of values.
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    parts: Vec<Part>,
+}
+impl Section {
+    fn new(mut parts: Vec<Part>) -> Self {
+        // `m` is minutes, not months, just after hours or just before seconds.
+        let date_parts = parts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, part)| match part {
+                Part::Date(d) => Some((i, *d)),
+                _ => None,
+            })
+            .collect_vec();
+        for (j, &(i, part)) in date_parts.iter().enumerate() {
+            let DatePart::Month(len @ 1..=2) = part else {
+                continue;
+            };
+            let after_hours = j > 0 && matches!(date_parts[j - 1].1, DatePart::Hour(_));
+            let before_seconds = matches!(date_parts.get(j + 1), Some((_, DatePart::Second(_))));
+            if after_hours || before_seconds {
+                parts[i] = Part::Date(DatePart::Minute(len));
+            }
+        }
+        Self { parts }
+    }
+
+    fn is_date(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p, Part::Date(_)))
+    }
+    /// Returns whether the section is only for text, so numbers should use a
+    /// different section.
+    fn is_text_only(&self) -> bool {
+        self.parts.contains(&Part::Text)
+            && !self
+                .parts
+                .iter()
+                .any(|p| matches!(p, Part::Number(_) | Part::General | Part::Date(_)))
+    }
+
+    /// Formats a non-negative number, with a minus sign if `negative` is true.
+    fn format_number(&self, n: f64, negative: bool, system: DateSystem) -> Option<String> {
+        if self.is_date() {
+            return if negative {
+                None
+            } else {
+                self.format_date(n, system)
+            };
+        }
+        let percents = self.parts.iter().filter(|&p| p == &Part::Percent).count();
+        let x = n * 100_f64.powi(percents as i32);
+
+        let mut ret = String::new();
+        if negative {
+            ret.push('-');
+        }
+        let mut number_shown = false;
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => ret.push_str(s),
+                Part::Percent => ret.push('%'),
+                // Only the first group of digit placeholders is used.
+                Part::Number(pattern) if !number_shown => {
+                    ret.push_str(&pattern.format(x));
+                    number_shown = true;
+                }
+                Part::Number(_) => (),
+                Part::General | Part::Text => ret.push_str(&x.to_string()),
+                Part::Date(_) => unreachable!(),
+            }
+        }
+        Some(ret)
+    }
+
+    /// Formats a date serial number, including a time of day as the
+    /// fractional part.
+    fn format_date(&self, n: f64, system: DateSystem) -> Option<String> {
+        if !(0.0..(system.max_serial() + 1) as f64).contains(&n) {
+            return None;
+        }
+        let mut serial = n.floor() as i64;
+        let mut seconds = ((n - n.floor()) * 86400.0).round() as i64;
+        if seconds >= 86400 {
+            serial += 1;
+            seconds -= 86400;
+        }
+        // The rest of the date module uses the 1900 date system.
+        let serial = system.to_1900(serial);
+        let date = Date::from_serial(serial);
+        let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        let has_am_pm = self
+            .parts
+            .iter()
+            .any(|p| matches!(p, Part::Date(DatePart::AmPm { .. })));
+        let pad = |x: i64, len: usize| match len {
+            1 => x.to_string(),
+            _ => format!("{x:02}"),
+        };
+
+        let mut ret = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => ret.push_str(s),
+                Part::Percent => ret.push('%'),
+                Part::Date(DatePart::Year(1..=2)) => ret += &pad(date.year % 100, 2),
+                Part::Date(DatePart::Year(_)) => ret += &date.year.to_string(),
+                Part::Date(DatePart::Month(len @ 1..=2)) => ret += &pad(date.month as i64, *len),
+                Part::Date(DatePart::Month(len)) => {
+                    let name = MONTH_NAMES[date.month as usize - 1];
+                    ret += match len {
+                        3 => &name[..3],
+                        4 => name,
+                        _ => &name[..1],
+                    };
+                }
+                Part::Date(DatePart::Day(len @ 1..=2)) => ret += &pad(date.day as i64, *len),
+                Part::Date(DatePart::Day(len)) => {
+                    let name = WEEKDAY_NAMES[date::weekday(serial) as usize];
+                    ret += if *len == 3 { &name[..3] } else { name };
+                }
+                Part::Date(DatePart::Hour(len)) if has_am_pm => {
+                    ret += &pad((hour + 11) % 12 + 1, *len)
+                }
+                Part::Date(DatePart::Hour(len)) => ret += &pad(hour, *len),
+                Part::Date(DatePart::Minute(len)) => ret += &pad(minute, *len),
+                Part::Date(DatePart::Second(len)) => ret += &pad(second, *len),
+                Part::Date(DatePart::AmPm { short, lowercase }) => {
+                    let s = match (hour < 12, short) {
+                        (true, false) => "AM",
+                        (false, false) => "PM",
+                        (true, true) => "A",
+                        (false, true) => "P",
+                    };
+                    ret += &if *lowercase {
+                        s.to_ascii_lowercase()
+                    } else {
+                        s.to_string()
+                    };
+                }
+                Part::Number(_) | Part::General | Part::Text => (),
+            }
+        }
+        Some(ret)
+    }
+}
+
+/// Piece of a section of a number format.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `%`, which is shown and also multiplies the number by 100.
+    Percent,
+    Number(NumberPattern),
+    Date(DatePart),
+    /// `General`, which shows the number in the default format.
+    General,
+    /// `@`, which shows the text.
+    Text,
+}
+
+/// Part of a date or time, with the number of letters used to write it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DatePart {
+    Year(usize),
+    Month(usize),
+    Day(usize),
+    Hour(usize),
+    Minute(usize),
+    Second(usize),
+    AmPm { short: bool, lowercase: bool },
+}
+
+/// Group of digit placeholders, such as `#,##0.00`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct NumberPattern {
+    /// Number of integer digits that are always shown.
+    min_integer_digits: usize,
+    /// Number of decimal places that are always shown.
+    min_decimals: usize,
+    /// Number of decimal places shown if significant.
+    max_decimals: usize,
+    has_decimal_point: bool,
+    has_thousands_separator: bool,
+    /// Number of times to divide by 1000.
+    thousands_scale: i32,
+    /// Minimum number of digits in the exponent, if in scientific notation.
+    exponent_digits: Option<usize>,
+    /// Whether to show `+` for positive exponents.
+    exponent_plus: bool,
+}
+impl NumberPattern {
+    /// Parses digit placeholders starting at `chars[*i]`, and advances `i`
+    /// past them.
+    fn parse(chars: &[char], i: &mut usize) -> Self {
+        let mut ret = Self::default();
+        let mut commas = 0;
+        while let Some(&c) = chars.get(*i) {
+            match c {
+                '0' | '#' | '?' => {
+                    if ret.has_decimal_point {
+                        ret.max_decimals += 1;
+                        ret.min_decimals += (c == '0') as usize;
+                    } else {
+                        ret.has_thousands_separator |= commas > 0;
+                        ret.min_integer_digits += (c == '0') as usize;
+                    }
+                    commas = 0;
+                }
+                '.' if !ret.has_decimal_point => {
+                    ret.thousands_scale += commas;
+                    commas = 0;
+                    ret.has_decimal_point = true;
+                }
+                ',' => commas += 1,
+                'E' | 'e'
+                    if matches!(chars.get(*i + 1), Some('+' | '-'))
+                        && matches!(chars.get(*i + 2), Some('0' | '#')) =>
+                {
+                    ret.exponent_plus = chars[*i + 1] == '+';
+                    *i += 2;
+                    let digits = chars[*i..]
+                        .iter()
+                        .take_while(|&&c| c == '0' || c == '#')
+                        .count();
+                    ret.exponent_digits = Some(digits);
+                    *i += digits;
+                    break;
+                }
+                _ => break,
+            }
+            *i += 1;
+        }
+        ret.thousands_scale += commas;
+        ret
+    }
+
+    /// Formats a non-negative number.
+    fn format(self, x: f64) -> String {
+        let mut x = x / 1000_f64.powi(self.thousands_scale);
+
+        let mut exponent = String::new();
+        if let Some(exponent_digits) = self.exponent_digits {
+            let integer_digits = self.min_integer_digits.max(1) as i32;
+            let mantissa = |e: i32| round(x / 10_f64.powi(e), self.max_decimals);
+            let mut e = match x {
+                0.0 => 0,
+                _ => x.log10().floor() as i32 - (integer_digits - 1),
+            };
+            if mantissa(e) >= 10_f64.powi(integer_digits) {
+                e += 1; // Rounding added a digit.
+            }
+            x = mantissa(e);
+            let sign = match e {
+                ..0 => "-",
+                _ if self.exponent_plus => "+",
+                _ => "",
+            };
+            exponent = format!("E{sign}{:0exponent_digits$}", e.abs());
+        }
+
+        let digits = format!("{:.*}", self.max_decimals, round(x, self.max_decimals));
+        let (integer, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        let significant_decimals = fraction.trim_end_matches('0').len();
+        let fraction = &fraction[..significant_decimals.max(self.min_decimals)];
+        let integer = integer.trim_start_matches('0');
+        let integer = format!("{integer:0>0$}", self.min_integer_digits);
+
+        let mut ret = String::new();
+        for (i, digit) in integer.chars().enumerate() {
+            let digits_left = integer.len() - i;
+            ret.push(digit);
+            if self.has_thousands_separator && digits_left > 1 && digits_left % 3 == 1 {
+                ret.push(',');
+            }
+        }
+        if self.has_decimal_point {
+            ret.push('.');
+        }
+        ret + fraction + &exponent
+    }
+}
+
+/// Rounds a number to some number of decimal places, with halves rounded away
+/// from zero.
+fn round(x: f64, decimals: usize) -> f64 {
+    arithmetic::round(x, decimals as i64)
+}
+
+/// Appends literal text to a list of parts, combining it with the previous
+/// part if that is also literal text.
+fn push_literal(parts: &mut Vec<Part>, s: &str) {
+    match parts.last_mut() {
+        Some(Part::Literal(literal)) => literal.push_str(s),
+        _ => parts.push(Part::Literal(s.to_string())),
+    }
+}
+
+fn starts_with_ignore_case(chars: &[char], prefix: &str) -> bool {
+    chars.len() >= prefix.len()
+        && chars
+            .iter()
+            .zip(prefix.chars())
+            .all(|(a, b)| a.eq_ignore_ascii_case(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(n: f64, format: &str) -> String {
+        NumberFormat::parse(format)
+            .unwrap()
+            .format_number(n)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_number_format_numbers() {
+        assert_eq!("1234.5", format(1234.5, "General"));
+        assert_eq!("1235", format(1234.5, "0"));
+        assert_eq!("1234.50", format(1234.5, "0.00"));
+        assert_eq!("1,234.50", format(1234.5, "#,##0.00"));
+        assert_eq!("1,234,567", format(1234567.0, "#,##0"));
+        assert_eq!("0.5", format(0.5, "0.##"));
+        assert_eq!(".5", format(0.5, "#.##"));
+        assert_eq!("3.", format(3.0, "0.#"));
+        assert_eq!("007", format(7.0, "000"));
+        assert_eq!("1,235K", format(1234567.0, "#,##0,\"K\""));
+        assert_eq!("-1.2", format(-1.23, "0.0"));
+    }
+
+    #[test]
+    fn test_number_format_currency() {
+        assert_eq!("$1,234.00", format(1234.0, "$#,##0.00"));
+        assert_eq!("-$1,234.00", format(-1234.0, "$#,##0.00"));
+        assert_eq!("€5.00", format(5.0, "[$€-407]0.00"));
+
+        // Negative numbers in parentheses
+        let accounting = "$#,##0.00;($#,##0.00)";
+        assert_eq!("$1,234.00", format(1234.0, accounting));
+        assert_eq!("($1,234.00)", format(-1234.0, accounting));
+        assert_eq!("$0.00", format(0.0, accounting));
+        let with_zero = "#,##0;[Red](#,##0);\"-\"";
+        assert_eq!("(42)", format(-42.0, with_zero));
+        assert_eq!("-", format(0.0, with_zero));
+    }
+
+    #[test]
+    fn test_number_format_percentage() {
+        assert_eq!("25%", format(0.25, "0%"));
+        assert_eq!("12.50%", format(0.125, "0.00%"));
+        assert_eq!("-150.0%", format(-1.5, "0.0%"));
+    }
+
+    #[test]
+    fn test_number_format_scientific() {
+        assert_eq!("1.23E+03", format(1234.0, "0.00E+00"));
+        assert_eq!("1.23E-03", format(0.001234, "0.00E+00"));
+        assert_eq!("1.2E3", format(1234.0, "0.0E-0"));
+        assert_eq!("1.00E+01", format(9.999, "0.00E+00"));
+        assert_eq!("0.00E+00", format(0.0, "0.00E+00"));
+        assert_eq!("-5E+00", format(-5.0, "0E+00"));
+    }
+
+    #[test]
+    fn test_number_format_dates() {
+        // 2021-03-14 18:30:05
+        let n = 44269.0 + (18.0 * 3600.0 + 30.0 * 60.0 + 5.0) / 86400.0;
+        assert_eq!("2021-03-14", format(n, "yyyy-mm-dd"));
+        assert_eq!("3/14/21", format(n, "m/d/yy"));
+        assert_eq!("Sunday, March 14, 2021", format(n, "dddd, mmmm d, yyyy"));
+        assert_eq!("Sun 14-Mar", format(n, "ddd dd-mmm"));
+        assert_eq!("18:30:05", format(n, "hh:mm:ss"));
+        assert_eq!("6:30 PM", format(n, "h:mm AM/PM"));
+        assert_eq!("6:30p", format(n, "h:mma/p"));
+        assert_eq!("30:05", format(n, "mm:ss"));
+
+        let fmt = NumberFormat::parse("yyyy-mm-dd").unwrap();
+        assert_eq!(None, fmt.format_number(-1.0));
+        assert_eq!(None, fmt.format_number(1e9));
+    }
+
+    #[test]
+    fn test_number_format_text() {
+        let fmt = NumberFormat::parse("0.00;-0.00;0;\"Name: \"@").unwrap();
+        assert_eq!("Name: Alice", fmt.format_text("Alice"));
+        assert_eq!("Alice", NumberFormat::default().format_text("Alice"));
+        let fmt = NumberFormat::parse("\"<\"@\">\"").unwrap();
+        assert_eq!("<x>", fmt.format_text("x"));
+        assert_eq!("-3", fmt.format_number(-3.0).unwrap());
+
+        assert_eq!(None, NumberFormat::parse("0;0;0;@;0"));
+    }
+}
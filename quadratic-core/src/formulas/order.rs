@@ -0,0 +1,197 @@
+//! Total order over values, shared by sorting, lookups, and comparison
+//! operators so that they all agree.
+//!
+//! Values are ordered first by type, the way Excel sorts them:
+//!
+//! 1. Numbers, including date-times and text that looks like a number, such
+//!    as the contents of a cell
+//! 2. Text, ignoring case unless [`TextCompareMode::CaseSensitive`] is used,
+//!    in which case text that differs only in case is ordered by comparing it
+//!    exactly
+//! 3. Booleans, with FALSE before TRUE
+//! 4. Errors, by error code
+//! 5. Arrays and lambdas, which are all equal to each other
+//! 6. Blanks and empty strings
+//!
+//! Values that compare equal are not necessarily identical, such as `abc` and
+//! `ABC`, so functions that sort use a stable sort, which keeps them in their
+//! original order.
+
+use std::cmp::Ordering;
+
+use super::*;
+
+/// Whether text comparisons, such as `=`, lookups, and criteria, depend on
+/// case. `EXACT` is always case-sensitive.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TextCompareMode {
+    /// `"abc"` and `"ABC"` are equal, like in Excel.
+    #[default]
+    CaseInsensitive,
+    /// `"abc"` and `"ABC"` are different.
+    CaseSensitive,
+}
+impl TextCompareMode {
+    /// Converts text for comparison.
+    pub fn key(self, s: &str) -> TextKey {
+        TextKey {
+            folded: s.to_lowercase(),
+            exact: (self == Self::CaseSensitive).then(|| s.to_string()),
+        }
+    }
+}
+
+/// Text converted for comparison. Text is compared ignoring case first, and
+/// then exactly if case matters, so that changing the mode never reorders
+/// text that differs by more than case.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextKey {
+    /// Lowercase text.
+    folded: String,
+    /// Original text, if case matters.
+    exact: Option<String>,
+}
+
+/// Value converted for comparison. The order of the variants is the order of
+/// the types.
+#[derive(Debug, Clone)]
+pub enum SortKey {
+    Number(f64),
+    Text(TextKey),
+    Bool(bool),
+    /// Error code, such as `#N/A`.
+    Error(&'static str),
+    /// Array or lambda.
+    Other,
+    Blank,
+}
+impl SortKey {
+    /// Converts a value for comparison, ignoring case.
+    pub fn new(value: &Value) -> Self {
+        Self::with_mode(value, TextCompareMode::CaseInsensitive)
+    }
+    /// Converts a value for comparison, comparing text according to `mode`.
+    pub fn with_mode(value: &Value, mode: TextCompareMode) -> Self {
+        match value {
+            Value::Blank => Self::Blank,
+            Value::String(s) if s.is_empty() => Self::Blank,
+            Value::Number(_) | Value::String(_) => match value.as_number() {
+                Some(n) => Self::Number(n),
+                None => Self::Text(mode.key(&value.to_string())),
+            },
+            Value::Bool(b) => Self::Bool(*b),
+            Value::Error(e) => Self::Error(e.excel_code().unwrap_or("#ERROR!")),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => value.as_number().map_or(Self::Other, Self::Number),
+            Value::Array(_) | Value::Lambda(_) => Self::Other,
+        }
+    }
+
+    /// Returns the key that blank is coerced to when it is compared with
+    /// `other`: `0`, `""`, or `FALSE` depending on its type. Keys other than
+    /// blank are returned unchanged.
+    pub fn blank_as(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Self::Blank, Self::Number(_)) => Self::Number(0.0),
+            (Self::Blank, Self::Text(text)) => Self::Text(TextKey {
+                folded: String::new(),
+                exact: text.exact.as_ref().map(|_| String::new()),
+            }),
+            (Self::Blank, Self::Bool(_)) => Self::Bool(false),
+            _ => self.clone(),
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            Self::Number(_) => 0,
+            Self::Text(_) => 1,
+            Self::Bool(_) => 2,
+            Self::Error(_) => 3,
+            Self::Other => 4,
+            Self::Blank => 5,
+        }
+    }
+
+    /// Compares two keys of the same type, or returns `None` if they have
+    /// different types.
+    pub fn cmp_same_type(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => {
+                Some(a.partial_cmp(b).unwrap_or_else(|| a.total_cmp(b)))
+            }
+            (Self::Text(a), Self::Text(b)) => Some(a.cmp(b)),
+            (Self::Bool(a), Self::Bool(b)) => Some(a.cmp(b)),
+            (Self::Error(a), Self::Error(b)) => Some(a.cmp(b)),
+            (Self::Other, Self::Other) | (Self::Blank, Self::Blank) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_same_type(other)
+            .unwrap_or_else(|| self.type_rank().cmp(&other.type_rank()))
+    }
+}
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for SortKey {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_total_order() {
+        let text = |s: &str| Value::String(s.to_string());
+        let ascending = [
+            Value::Number(-2.0),
+            text("1.5"),
+            Value::Number(10.0),
+            text("apple"),
+            text("Banana"),
+            text("cherry"),
+            Value::Bool(false),
+            Value::Bool(true),
+            Value::Error(FormulaErrorMsg::DivideByZero),
+            Value::Error(FormulaErrorMsg::NotAvailable),
+            Value::Array(Array::default()),
+            text(""),
+        ];
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(i.cmp(&j), a.total_cmp(b), "{a:?} vs {b:?}");
+            }
+        }
+
+        // Equal values keep their original order in a stable sort.
+        let mut values = vec![
+            text("b"),
+            text("A"),
+            text("B"),
+            text("a"),
+            Value::Number(0.0),
+        ];
+        values.sort_by(Value::total_cmp);
+        assert_eq!(
+            vec![
+                Value::Number(0.0),
+                text("A"),
+                text("a"),
+                text("b"),
+                text("B")
+            ],
+            values,
+        );
+        assert_eq!(Ordering::Equal, Value::Number(-0.0).total_cmp(&text("0")));
+    }
+}
@@ -0,0 +1,71 @@
+//! Parsing many formulas at once, such as when loading a sheet.
+//!
+//! Sheets often contain the same formula many times, so each distinct formula
+//! is only parsed once and the result is shared.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::*;
+
+/// Parses each formula at its location, returning the results in the same
+/// order. The result is always identical to calling `parse_formula()` on each
+/// one.
+///
+/// Formulas with the same source and location are only parsed once, and share
+/// the same `Arc`. Parsing doesn't depend on anything else, so the distinct
+/// formulas could be parsed in parallel.
+pub fn parse_formula_batch<'a>(
+    formulas: impl IntoIterator<Item = (&'a str, Pos)>,
+) -> Vec<FormulaResult<Arc<Formula>>> {
+    let mut parsed = HashMap::<(&str, Pos), FormulaResult<Arc<Formula>>>::new();
+    formulas
+        .into_iter()
+        .map(|(source, loc)| {
+            parsed
+                .entry((source, loc))
+                .or_insert_with(|| parse_formula(source, loc).map(Arc::new))
+                .clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formula_batch() {
+        let a = Pos::new(0, 1);
+        let b = Pos::new(3, 4);
+        let formulas = [
+            ("SUM(A1:A3)", a),
+            ("1 +", a),
+            ("SUM(A1:A3)", a),
+            ("SUM(A1:A3)", b),
+            ("1 +", a),
+            ("'x'", b),
+        ];
+        let results = parse_formula_batch(formulas);
+        assert_eq!(formulas.len(), results.len());
+        for ((source, loc), result) in formulas.into_iter().zip(&results) {
+            match (parse_formula(source, loc), result) {
+                (Ok(expected), Ok(got)) => assert_eq!(&expected, got.as_ref(), "{source:?}"),
+                (Err(expected), Err(got)) => {
+                    assert_eq!(expected.msg, got.msg, "{source:?}");
+                    assert_eq!(expected.span, got.span, "{source:?}");
+                }
+                (expected, got) => panic!("for {source:?}: expected {expected:?}, got {got:?}"),
+            }
+        }
+
+        // Identical formulas share the same `Arc`, but the same source at a
+        // different location is parsed separately since its cell references
+        // are relative to the location.
+        let (Ok(first), Ok(same), Ok(elsewhere)) = (&results[0], &results[2], &results[3]) else {
+            panic!("expected formulas to parse: {results:?}");
+        };
+        assert!(Arc::ptr_eq(first, same));
+        assert!(!Arc::ptr_eq(first, elsewhere));
+    }
+}
@@ -0,0 +1,290 @@
+//! Incremental reparsing of a formula after an edit.
+//!
+//! If an edit is entirely inside a bracketed group (a function call,
+//! parenthesized expression, or array literal), the rest of the formula parses
+//! exactly the same as before, so only that group needs to be parsed again.
+
+use super::rules::{ExpressionWithPrecedence, OpPrecedence};
+use super::*;
+
+/// Edit to the source string of a formula. All positions are byte offsets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// Start of the edit, in both the old and new source.
+    pub start: usize,
+    /// End of the replaced text in the old source.
+    pub old_end: usize,
+    /// End of the replacement text in the new source.
+    pub new_end: usize,
+}
+
+/// Parses `new_source` given `old`, which was parsed from the source before
+/// `edit` using the same `loc`. The result is always identical to
+/// `parse_formula(new_source, loc)`.
+///
+/// Only the smallest bracketed group containing the edit is reparsed. If there
+/// is no such group, or the group no longer parses the same way in context,
+/// the whole formula is reparsed.
+pub fn reparse_formula(
+    old: &Formula,
+    edit: TextEdit,
+    new_source: &str,
+    loc: Pos,
+) -> FormulaResult<Formula> {
+    match reparse_group(old, edit, new_source, loc) {
+        Some(formula) => Ok(formula),
+        None => parse_formula(new_source, loc),
+    }
+}
+
+/// Reparses the smallest bracketed group containing `edit`, or returns `None`
+/// if that would not give the same result as reparsing the whole formula.
+fn reparse_group(old: &Formula, edit: TextEdit, new_source: &str, loc: Pos) -> Option<Formula> {
+    let delta = edit.new_end as isize - edit.old_end as isize;
+    let (path, depth) = innermost_group(&old.ast, edit)?;
+    let old_group = node_at_path(&old.ast, &path);
+    let (opener, opener_end) = opener(old_group)?;
+    let group_start = old_group.span.start;
+    let group_end = old_group.span.end.checked_add_signed(delta)?;
+
+    // A token can only extend past the opening bracket of a group if it is a
+    // string literal or comment that was unterminated before the edit, and
+    // `old` could not have parsed successfully with one of those. So the
+    // tokens before the group are unchanged. The tokens after the group are
+    // also unchanged, as long as the group still ends on a token boundary.
+    let mut tokens = vec![];
+    for token in lexer::tokenize_from(new_source, group_start) {
+        if token.span.end > group_end {
+            return None;
+        }
+        if !token.inner.is_skip() {
+            tokens.push(token);
+        }
+        if token.span.end == group_end {
+            break;
+        }
+    }
+    if tokens.first()?.inner != opener || tokens.first()?.span.end != opener_end {
+        return None;
+    }
+    if tokens.last()?.span.end != group_end {
+        return None;
+    }
+
+    // Parse the group at the same nesting depth as in a full parse.
+    let mut p = Parser::new(new_source, &tokens, loc);
+    p.depth = depth;
+    let new_group = p.parse(ExpressionWithPrecedence(OpPrecedence::Atom)).ok()?;
+    if p.next().is_some() {
+        return None;
+    }
+
+    let mut ast = old.ast.clone();
+    shift_spans(&mut ast, old_group.span.end, delta);
+    *node_at_path_mut(&mut ast, &path) = new_group;
+    Some(Formula { ast })
+}
+
+/// Returns the path to the innermost bracketed group whose contents entirely
+/// contain `edit`, along with the parser nesting depth at which that group is
+/// parsed.
+fn innermost_group(root: &AstNode, edit: TextEdit) -> Option<(Vec<usize>, usize)> {
+    let mut ret = None;
+    let mut path = vec![];
+    let mut node = root;
+    // The top-level expression is one level deep.
+    let mut depth = 1;
+    loop {
+        if let Some((_, opener_end)) = opener(node) {
+            let closer_start = node.span.end - 1;
+            if opener_end <= edit.start && edit.old_end <= closer_start {
+                ret = Some((path.clone(), depth));
+            }
+        }
+        if increases_depth(node) {
+            depth += 1;
+        }
+        let Some((i, child)) = children(node)
+            .enumerate()
+            .find(|(_, child)| child.span.start <= edit.start && edit.old_end <= child.span.end)
+        else {
+            return ret;
+        };
+        path.push(i);
+        node = child;
+    }
+}
+
+/// Returns the opening token of a bracketed group and the position where it
+/// ends, or `None` if the node is not a bracketed group.
+fn opener(node: &AstNode) -> Option<(Token, usize)> {
+    match &node.inner {
+        ast::AstNodeContents::FunctionCall { func, .. } if is_function_name(&func.inner) => {
+            Some((Token::FunctionCall, func.span.end))
+        }
+        ast::AstNodeContents::Paren(_) => Some((Token::LParen, node.span.start + 1)),
+        ast::AstNodeContents::Array(_) => Some((Token::LBrace, node.span.start + 1)),
+        _ => None,
+    }
+}
+
+/// Returns whether the parser nests one level deeper when parsing the
+/// children of a node.
+fn increases_depth(node: &AstNode) -> bool {
+    match &node.inner {
+        ast::AstNodeContents::FunctionCall { func, .. } => {
+            is_function_name(&func.inner) || func.inner == "@"
+        }
+        ast::AstNodeContents::Paren(_) | ast::AstNodeContents::Array(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns whether a function call is written using its name, rather than as
+/// an operator.
+fn is_function_name(func: &str) -> bool {
+    func.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+fn children(node: &AstNode) -> Box<dyn '_ + Iterator<Item = &AstNode>> {
+    match &node.inner {
+        ast::AstNodeContents::FunctionCall { args, .. } => Box::new(args.iter()),
+        ast::AstNodeContents::Paren(contents) => Box::new(std::iter::once(&**contents)),
+        ast::AstNodeContents::Array(rows) => Box::new(rows.iter().flatten()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+fn children_mut(node: &mut AstNode) -> Box<dyn '_ + Iterator<Item = &mut AstNode>> {
+    match &mut node.inner {
+        ast::AstNodeContents::FunctionCall { args, .. } => Box::new(args.iter_mut()),
+        ast::AstNodeContents::Paren(contents) => Box::new(std::iter::once(&mut **contents)),
+        ast::AstNodeContents::Array(rows) => Box::new(rows.iter_mut().flatten()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn node_at_path<'a>(mut node: &'a AstNode, path: &[usize]) -> &'a AstNode {
+    for &i in path {
+        node = children(node).nth(i).expect("bad AST path");
+    }
+    node
+}
+fn node_at_path_mut<'a>(mut node: &'a mut AstNode, path: &[usize]) -> &'a mut AstNode {
+    for &i in path {
+        node = children_mut(node).nth(i).expect("bad AST path");
+    }
+    node
+}
+
+/// Moves every span that ends at or after `pos` to account for `delta` bytes
+/// inserted before `pos`. Spans that also start at or after `pos` are moved
+/// entirely; others are extended.
+fn shift_spans(node: &mut AstNode, pos: usize, delta: isize) {
+    shift_span(&mut node.span, pos, delta);
+    if let ast::AstNodeContents::FunctionCall { func, .. } = &mut node.inner {
+        shift_span(&mut func.span, pos, delta);
+    }
+    for child in children_mut(node) {
+        shift_spans(child, pos, delta);
+    }
+}
+fn shift_span(span: &mut Span, pos: usize, delta: isize) {
+    if span.start >= pos {
+        span.start = span.start.wrapping_add_signed(delta);
+    }
+    if span.end >= pos {
+        span.end = span.end.wrapping_add_signed(delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Formulas to edit, chosen to exercise every kind of bracketed group.
+    const FORMULAS: &[&str] = &[
+        "=SUM(1, 2, (3 + 4) * 5)",
+        "IF(A1 > 0, {1, 2; 3, 4}, -@B2:C3)",
+        "CONCAT('a(b', \"c)d\", SUM((1), ((2))))",
+        "=(1 + 2) * (3 + 4) /* comment (with parens) */ + MAX({5, 6})",
+        "SUM(1,\n  2, // (comment\n  3)",
+        "{SUM(1), 2; (3), {4}}",
+        "AVERAGE(A1:B5, 10%, 2^3^4, -3)",
+    ];
+
+    fn check_reparse(old_source: &str, start: usize, old_end: usize, replacement: &str) {
+        let loc = Pos::new(1, 2);
+        let old = parse_formula(old_source, loc).unwrap();
+        let new_source = format!(
+            "{}{replacement}{}",
+            &old_source[..start],
+            &old_source[old_end..],
+        );
+        let edit = TextEdit {
+            start,
+            old_end,
+            new_end: start + replacement.len(),
+        };
+
+        let expected = parse_formula(&new_source, loc);
+        let got = reparse_formula(&old, edit, &new_source, loc);
+        match (expected, got) {
+            (Ok(expected), Ok(got)) => assert_eq!(expected, got, "for {new_source:?}"),
+            (Err(expected), Err(got)) => {
+                assert_eq!(expected.msg, got.msg, "for {new_source:?}");
+                assert_eq!(expected.span, got.span, "for {new_source:?}");
+            }
+            (expected, got) => panic!("for {new_source:?}: expected {expected:?}, got {got:?}"),
+        }
+    }
+
+    #[test]
+    fn test_reparse_reuses_group() {
+        let loc = Pos::ORIGIN;
+        let old = parse_formula("=SUM(1, 2, (3 + 4) * 5)", loc).unwrap();
+        // Replace `4` with `40`.
+        let new_source = "=SUM(1, 2, (3 + 40) * 5)";
+        let edit = TextEdit {
+            start: 16,
+            old_end: 17,
+            new_end: 18,
+        };
+        let (path, depth) = innermost_group(&old.ast, edit).unwrap();
+        assert_eq!(vec![2, 0], path);
+        assert_eq!(2, depth);
+        let reparsed = reparse_group(&old, edit, new_source, loc).unwrap();
+        assert_eq!(parse_formula(new_source, loc).unwrap(), reparsed);
+
+        // Opening a string literal inside the group changes how the rest of
+        // the formula is tokenized.
+        let edit = TextEdit {
+            start: 12,
+            old_end: 12,
+            new_end: 13,
+        };
+        assert!(reparse_group(&old, edit, "=SUM(1, 2, ('3 + 4) * 5)", loc).is_none());
+        check_reparse("=SUM(1, 2, (3 + 4) * 5)", 12, 12, "'");
+        check_reparse("=SUM(1, 2, (3 + 4) * 5)", 12, 12, "/*");
+        check_reparse("=SUM(1, 2, (3 + 4) * 5)", 12, 17, ")(");
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn proptest_reparse_matches_full_parse(
+            formula_index in 0..FORMULAS.len(),
+            start in 0.0..=1.0_f64,
+            len in 0..4_usize,
+            replacement in r#"[ 0-9A-C+*,]{0,4}|[;:@%()'"{}/]|SUM\("#,
+        ) {
+            // Most edits in an editor are small.
+            let source = FORMULAS[formula_index];
+            let start = (start * source.len() as f64) as usize;
+            let end = (start + len).min(source.len());
+            check_reparse(source, start, end, &replacement);
+        }
+    }
+}
@@ -4,26 +4,103 @@ use itertools::Itertools;
 
 #[macro_use]
 mod macros;
+mod batch;
+mod incremental;
 pub mod rules;
 
+pub use batch::parse_formula_batch;
+pub use incremental::{reparse_formula, TextEdit};
+
 use super::*;
 use rules::SyntaxRule;
 
+/// Default maximum nesting depth of expressions in a formula. This is twice
+/// Excel's limit on nested functions, and small enough that parsing and
+/// evaluating a formula nested this deeply fits in a default thread stack.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 128;
+
 pub fn parse_formula(source: &str, loc: Pos) -> FormulaResult<ast::Formula> {
+    parse_formula_with_max_depth(source, loc, DEFAULT_MAX_NESTING_DEPTH)
+}
+
+/// Parses a formula, returning an error if expressions are nested more than
+/// `max_depth` levels deep.
+pub fn parse_formula_with_max_depth(
+    source: &str,
+    loc: Pos,
+    max_depth: usize,
+) -> FormulaResult<ast::Formula> {
     Ok(Formula {
-        ast: parse_exactly_one(source, loc, rules::Expression)?,
+        ast: parse_exactly_one(source, loc, max_depth, rules::Expression)?,
     })
 }
 
-fn parse_exactly_one<R: SyntaxRule>(source: &str, loc: Pos, rule: R) -> FormulaResult<R::Output> {
-    let tokens = lexer::tokenize(source)
+fn parse_exactly_one<R: SyntaxRule>(
+    source: &str,
+    loc: Pos,
+    max_depth: usize,
+    rule: R,
+) -> FormulaResult<R::Output> {
+    let all_tokens = lexer::tokenize(source).collect_vec();
+    let tokens = all_tokens
+        .iter()
         .filter(|t| !t.inner.is_skip())
+        .copied()
         .collect_vec();
     let mut p = Parser::new(source, &tokens, loc);
-    match p.parse(rule) {
-        Ok(_) if p.next().is_some() => p.expected("end of formula"),
+    p.max_depth = max_depth;
+    let result = match p.parse(rule) {
+        Ok(_) if p.peek_next().is_some() => p.expected("end of formula"),
         result => result,
+    };
+    result.map_err(|e| unimplemented_syntax(&all_tokens).unwrap_or(e))
+}
+
+/// Returns an error for syntax from other spreadsheets that isn't supported
+/// yet, if a formula that failed to parse has any. None of this syntax is
+/// valid otherwise, so it's only looked for after a parse error.
+fn unimplemented_syntax(tokens: &[Spanned<Token>]) -> Option<FormulaError> {
+    let is_reference = |t: &Spanned<Token>| matches!(t.inner, Token::CellRef | Token::ColumnRange);
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = tokens[..i].iter().rev().find(|t| !t.inner.is_skip());
+        let next = tokens[i + 1..].iter().find(|t| !t.inner.is_skip());
+        let (start, end) = (prev.unwrap_or(token).span, next.unwrap_or(token).span);
+        let (feature, span) = match token.inner {
+            Token::SheetRefOp => {
+                let mut before = tokens[..i].iter().rev().filter(|t| !t.inner.is_skip());
+                let (last, op, first) = (before.next(), before.next(), before.next());
+                // `Sheet1:Sheet3!A1` is a 3-D reference, which is supported,
+                // so the error is somewhere else.
+                let is_sheet_name = |t: Option<&Spanned<Token>>| {
+                    t.is_some_and(|t| matches!(t.inner, Token::Name | Token::StringLiteral))
+                };
+                if last.is_some_and(|t| t.inner == Token::ColumnRange)
+                    || is_sheet_name(first)
+                        && op.is_some_and(|t| t.inner == Token::CellRangeOp)
+                        && is_sheet_name(last)
+                {
+                    continue;
+                }
+                // `Sheet1!A1`
+                ("sheet references", Span::merge(start, end))
+            }
+            // `Table1[Column]`
+            Token::LBracket if i > 0 && tokens[i - 1].inner == Token::Name => {
+                let closer = tokens[i..].iter().find(|t| t.inner == Token::RBracket);
+                let end = closer.map_or(token.span, |t| t.span);
+                ("structured references", Span::merge(start, end))
+            }
+            // `A1:B2 B1:C2`
+            Token::Whitespace
+                if prev.is_some_and(is_reference) && next.is_some_and(is_reference) =>
+            {
+                ("intersection operator", Span::merge(start, end))
+            }
+            _ => continue,
+        };
+        return Some(FormulaErrorMsg::Unimplemented(feature.into()).with_span(span));
     }
+    None
 }
 
 /// Token parser used to assemble an AST.
@@ -38,6 +115,11 @@ pub struct Parser<'a> {
 
     /// Coordinates of the cell where this formula was entered.
     pub loc: Pos,
+
+    /// Current nesting depth of expressions.
+    pub depth: usize,
+    /// Maximum nesting depth of expressions.
+    pub max_depth: usize,
 }
 impl<'a> Parser<'a> {
     /// Constructs a parser for a file.
@@ -48,6 +130,9 @@ impl<'a> Parser<'a> {
             cursor: None,
 
             loc,
+
+            depth: 0,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
         };
 
         // Skip leading `=`
@@ -145,6 +230,19 @@ impl<'a> Parser<'a> {
         tmp.span()
     }
 
+    /// Runs `f` one level of nesting deeper, returning an error instead if that
+    /// would exceed the maximum nesting depth.
+    pub fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> FormulaResult<T>) -> FormulaResult<T> {
+        // Bail out before we overflow the stack.
+        if self.depth > self.max_depth {
+            return Err(FormulaErrorMsg::NestingTooDeep.with_span(self.peek_next_span()));
+        }
+        self.depth += 1;
+        let ret = f(self);
+        self.depth -= 1;
+        ret
+    }
+
     /// Attempts to apply a syntax rule starting at the cursor, returning an
     /// error if it fails. This should only be used when this syntax rule
     /// represents the only valid parse; if there are other options,
@@ -175,12 +273,24 @@ impl<'a> Parser<'a> {
     }
 
     /// Returns an error describing that `expected` was expected.
+    ///
+    /// If there is a next token, the error spans that token and includes its
+    /// source text. Otherwise, the error is an empty span at the end of the
+    /// last token.
     pub fn expected_err(mut self, expected: impl ToString) -> FormulaError {
-        self.next();
-        FormulaErrorMsg::Expected {
-            expected: expected.to_string().into(),
-            got: None,
+        let end_of_last_token = self.span().end;
+        let expected = expected.to_string().into();
+        match self.next() {
+            Some(_) => FormulaErrorMsg::Expected {
+                expected,
+                got: Some(self.token_str().to_string().into()),
+            }
+            .with_span(self.span()),
+            None => FormulaErrorMsg::Expected {
+                expected,
+                got: Some("end of formula".into()),
+            }
+            .with_span(Span::empty(end_of_last_token)),
         }
-        .with_span(self.span())
     }
 }
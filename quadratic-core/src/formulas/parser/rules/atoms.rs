@@ -8,11 +8,21 @@ impl SyntaxRule for StringLiteral {
     type Output = ast::AstNode;
 
     fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
-        p.next() == Some(Token::StringLiteral)
+        matches!(
+            p.next(),
+            Some(Token::StringLiteral | Token::UnterminatedStringLiteral),
+        )
     }
     fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
-        if p.next() != Some(Token::StringLiteral) {
-            return p.expected(self);
+        match p.next() {
+            Some(Token::StringLiteral) => (),
+            Some(Token::UnterminatedStringLiteral) => {
+                return Err(FormulaErrorMsg::Unterminated("string literal").with_span(p.span()));
+            }
+            _ => {
+                p.prev();
+                return p.expected(self);
+            }
         }
         // Use IIFE for error handling.
         || -> Option<Self::Output> {
@@ -28,16 +38,11 @@ impl SyntaxRule for StringLiteral {
                 }
             }
 
-            if chars.next().is_none() {
-                // End of token, as expected.
-                Some(Spanned {
-                    span: p.span(),
-                    inner: ast::AstNodeContents::String(string_contents),
-                })
-            } else {
-                // Why is there more after the closing quote?
-                None
-            }
+            // Why would there be more after the closing quote?
+            chars.next().is_none().then(|| Spanned {
+                span: p.span(),
+                inner: ast::AstNodeContents::String(string_contents),
+            })
         }()
         .ok_or_else(|| internal_error_value!("error in string literal parsing"))
     }
@@ -64,7 +69,40 @@ impl SyntaxRule for NumericLiteral {
                     inner: ast::AstNodeContents::Number(n),
                 })
             }
-            _ => p.expected(self),
+            _ => {
+                p.prev();
+                p.expected(self)
+            }
+        }
+    }
+}
+
+/// Matches an error literal.
+#[derive(Debug, Copy, Clone)]
+pub struct ErrorLiteral;
+impl_display!(for ErrorLiteral, "error literal, such as '#N/A' or '#DIV/0!'");
+impl SyntaxRule for ErrorLiteral {
+    type Output = AstNode;
+
+    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
+        p.next() == Some(Token::ErrorLiteral)
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        match p.next() {
+            Some(Token::ErrorLiteral) => {
+                let code = p.token_str().to_ascii_uppercase();
+                let Some(e) = FormulaErrorMsg::from_excel_code(&code) else {
+                    return Err(FormulaErrorMsg::BadErrorCode.with_span(p.span()));
+                };
+                Ok(AstNode {
+                    span: p.span(),
+                    inner: ast::AstNodeContents::Error(e),
+                })
+            }
+            _ => {
+                p.prev();
+                p.expected(self)
+            }
         }
     }
 }
@@ -89,3 +127,47 @@ impl SyntaxRule for CellReference {
         })
     }
 }
+
+/// Matches a range of whole columns.
+pub struct ColumnRange;
+impl_display!(for ColumnRange, "column range, such as 'A:A' or '$B:$D'");
+impl SyntaxRule for ColumnRange {
+    type Output = AstNode;
+
+    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
+        p.next() == Some(Token::ColumnRange)
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        p.next();
+        let Some((start, end)) = p.token_str().split_once(':').and_then(|(start, end)| {
+            Some((
+                CellRefCoord::parse_a1_col(start, p.loc.x)?,
+                CellRefCoord::parse_a1_col(end, p.loc.x)?,
+            ))
+        }) else {
+            return Err(FormulaErrorMsg::BadCellReference.with_span(p.span()));
+        };
+        Ok(AstNode {
+            span: p.span(),
+            inner: ast::AstNodeContents::ColumnRange(start, end),
+        })
+    }
+}
+
+/// Matches a name bound by `LET` or `LAMBDA`.
+pub struct Name;
+impl_display!(for Name, "name, such as 'x' or 'total'");
+impl SyntaxRule for Name {
+    type Output = AstNode;
+
+    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
+        p.next() == Some(Token::Name)
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        p.parse(Token::Name)?;
+        Ok(AstNode {
+            span: p.span(),
+            inner: ast::AstNodeContents::Name(p.token_str().to_string()),
+        })
+    }
+}
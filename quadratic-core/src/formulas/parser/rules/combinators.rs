@@ -121,7 +121,7 @@ impl<R: Copy + SyntaxRule> SyntaxRule for List<R> {
                 break; // End of list; empty list, or trailing separator.
             }
             // End the list or consume a separator.
-            if let Some(_) = parse_one_of!(p, [self.sep.map(Some), self.end.map(|_| None)])? {
+            if parse_one_of!(p, [self.sep.map(Some), self.end.map(|_| None)])?.is_some() {
                 continue; // There is a separator.
             } else {
                 break; // End of list, no trailing separator.
@@ -148,20 +148,3 @@ impl SyntaxRule for Epsilon {
         Ok(())
     }
 }
-
-/// Rule that matches end of file and consumes no tokens.
-pub struct EndOfFile;
-impl_display!(for EndOfFile, "end of file");
-impl SyntaxRule for EndOfFile {
-    type Output = ();
-
-    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
-        p.next().is_none()
-    }
-    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
-        match p.next() {
-            Some(_) => Ok(()),
-            None => p.expected(self),
-        }
-    }
-}
@@ -1,18 +1,38 @@
 use super::*;
 
-/// Operator precedence table.
+/// Operator precedence table, from lowest to highest.
+///
+/// | Level        | Operators                                 | Kind   | Associativity |
+/// |--------------|-------------------------------------------|--------|---------------|
+/// | `Comparison` | `=` `==` `<>` `!=` `<` `>` `<=` `>=`      | binary | left          |
+/// | `Concat`     | `&`                                       | binary | left          |
+/// | `AddSub`     | `+` `-`                                   | binary | left          |
+/// | `MulDiv`     | `*` `/`                                   | binary | left          |
+/// | `Pow`        | `^` `**`                                  | binary | right         |
+/// | `Percent`    | `%`                                       | suffix |               |
+/// | `Prefix`     | `+` `-`                                   | prefix |               |
+/// | `CellRange`  | `:`                                       | binary | left          |
+/// | `Spill`      | `#`                                       | suffix |               |
+/// | `Atom`       | literals, references, calls, `(...)`, `@` |        |               |
+///
+/// This matches Excel, so `-2^2` is `4` and `1+2&3*4` is `"312"`, except that
+/// `^` is right-associative like in mathematics, so `2^3^2` is `2^9` rather
+/// than `8^2`. Comparisons are left-associative like in Excel, so `1<2<3`
+/// compares `TRUE` with `3` rather than checking that `2` is between `1` and
+/// `3`. The cell range operator binds tighter than negation, so `-A1:A3`
+/// negates the whole range, and the spill operator binds tighter still, so
+/// `A1:B2#` is a range ending at the spill range of `B2`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OpPrecedence {
     Comparison,
     Concat,
-    Bitshift,
     AddSub,
     MulDiv,
     Pow,
-    Range,
-    CellRange,
+    Percent,
     Prefix,
-    Suffix,
+    CellRange,
+    Spill,
     Atom,
 }
 impl Default for OpPrecedence {
@@ -30,15 +50,14 @@ impl OpPrecedence {
     pub fn next(self) -> Self {
         match self {
             Self::Comparison => Self::Concat,
-            Self::Concat => Self::Bitshift,
-            Self::Bitshift => Self::AddSub,
+            Self::Concat => Self::AddSub,
             Self::AddSub => Self::MulDiv,
             Self::MulDiv => Self::Pow,
-            Self::Pow => Self::Range,
-            Self::Range => Self::CellRange,
-            Self::CellRange => Self::Prefix,
-            Self::Prefix => Self::Suffix,
-            Self::Suffix => Self::Atom,
+            Self::Pow => Self::Percent,
+            Self::Percent => Self::Prefix,
+            Self::Prefix => Self::CellRange,
+            Self::CellRange => Self::Spill,
+            Self::Spill => Self::Atom,
             Self::Atom => panic!("tried to get operator precedence level beyond {:?}", self),
         }
     }
@@ -49,18 +68,13 @@ impl OpPrecedence {
         match self {
             Self::Comparison => &[Eql, Neq, Lt, Gt, Lte, Gte],
             Self::Concat => &[Concat],
-            Self::Bitshift => &[ShiftLeft, ShiftRight],
             Self::AddSub => &[Plus, Minus],
             Self::MulDiv => &[Mult, Div],
             Self::Pow => &[Power],
-            Self::Range => &[RangeOp],
             Self::CellRange => &[CellRangeOp],
-            Self::Prefix => &[],
-            Self::Suffix => &[],
-            Self::Atom => &[],
+            Self::Percent | Self::Prefix | Self::Spill | Self::Atom => &[],
         }
     }
-
     /// Returns a list of unary prefix operators at this precedence level.
     pub fn prefix_ops(self) -> &'static [Token] {
         use Token::*;
@@ -69,23 +83,49 @@ impl OpPrecedence {
             _ => &[],
         }
     }
-
     /// Returns a list of unary suffix operators at this precedence level.
     pub fn suffix_ops(self) -> &'static [Token] {
         use Token::*;
         match self {
-            Self::Suffix => &[Percent],
+            Self::Percent => &[Percent],
+            Self::Spill => &[SpillRangeOp],
             _ => &[],
         }
     }
 
+    /// Returns the precedence level of a binary operator, or `None` if the
+    /// token is not a binary operator.
+    pub fn of_binary_op(token: Token) -> Option<Self> {
+        Self::find(token, Self::binary_ops)
+    }
+    /// Returns the precedence level of a prefix operator, or `None` if the
+    /// token is not a prefix operator.
+    pub fn of_prefix_op(token: Token) -> Option<Self> {
+        Self::find(token, Self::prefix_ops)
+    }
+    /// Returns the precedence level of a suffix operator, or `None` if the
+    /// token is not a suffix operator.
+    pub fn of_suffix_op(token: Token) -> Option<Self> {
+        Self::find(token, Self::suffix_ops)
+    }
+    /// Returns the lowest precedence level where `ops` contains `token`.
+    fn find(token: Token, ops: fn(Self) -> &'static [Token]) -> Option<Self> {
+        let mut precedence = Self::lowest();
+        loop {
+            if ops(precedence).contains(&token) {
+                return Some(precedence);
+            }
+            if precedence == Self::Atom {
+                return None;
+            }
+            precedence = precedence.next();
+        }
+    }
+
     /// Returns whether the binary operators at this precedence level are
     /// right-associative.
     pub fn is_right_associative(self) -> bool {
-        match self {
-            OpPrecedence::Pow => true,
-            _ => false,
-        }
+        matches!(self, OpPrecedence::Pow)
     }
 }
 
@@ -100,14 +140,25 @@ impl SyntaxRule for Expression {
         ExpressionWithPrecedence::default().prefix_matches(p)
     }
     fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
-        p.parse(ExpressionWithPrecedence::default())
+        p.nested(|p| ExpressionWithPrecedence::default().parse_shallow(p))
     }
 }
 
 /// Matches an expression with the given precedence level.
 #[derive(Debug, Default, Copy, Clone)]
-struct ExpressionWithPrecedence(pub OpPrecedence);
+pub struct ExpressionWithPrecedence(pub OpPrecedence);
 impl_display!(for ExpressionWithPrecedence, "expression");
+impl ExpressionWithPrecedence {
+    /// Equivalent to `p.parse(self)`, but uses fewer stack frames, which makes
+    /// a big difference for deeply nested expressions.
+    fn parse_shallow(self, p: &mut Parser<'_>) -> FormulaResult<ast::AstNode> {
+        if self.prefix_matches(*p) {
+            self.consume_match(p)
+        } else {
+            p.expected(self)
+        }
+    }
+}
 impl SyntaxRule for ExpressionWithPrecedence {
     type Output = ast::AstNode;
 
@@ -126,7 +177,7 @@ impl SyntaxRule for ExpressionWithPrecedence {
 
                 Token::Eql | Token::Neq | Token::Lt | Token::Gt | Token::Lte | Token::Gte => false,
 
-                Token::Plus | Token::Minus => true,
+                Token::Plus | Token::Minus | Token::ImplicitIntersection => true,
 
                 Token::Mult
                 | Token::Div
@@ -136,7 +187,9 @@ impl SyntaxRule for ExpressionWithPrecedence {
                 | Token::Concat
                 | Token::RangeOp
                 | Token::Percent
-                | Token::CellRangeOp => false,
+                | Token::CellRangeOp
+                | Token::SpillRangeOp
+                | Token::SheetRefOp => false,
 
                 Token::Comment | Token::UnterminatedBlockComment => false,
 
@@ -144,7 +197,10 @@ impl SyntaxRule for ExpressionWithPrecedence {
                 | Token::StringLiteral
                 | Token::UnterminatedStringLiteral
                 | Token::NumericLiteral
-                | Token::CellRef => true,
+                | Token::ErrorLiteral
+                | Token::CellRef
+                | Token::ColumnRange
+                | Token::Name => true,
 
                 Token::Whitespace => false,
                 Token::Unknown => false,
@@ -154,158 +210,132 @@ impl SyntaxRule for ExpressionWithPrecedence {
         }
     }
     fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
-        // Consume an expression at the given precedence level, which may
-        // consist of expressions with higher precedence.
-        match self.0 {
-            OpPrecedence::Atom => parse_one_of!(
-                p,
-                [
-                    FunctionCall.map(Some),
-                    StringLiteral.map(Some),
-                    NumericLiteral.map(Some),
-                    ArrayLiteral.map(Some),
-                    CellReference.map(Some),
-                    ParenExpression.map(Some),
-                    Epsilon.map(|_| None),
-                ],
-            )
-            .transpose()
-            .unwrap_or_else(|| p.expected(self)),
-
-            prec if !prec.binary_ops().is_empty() => parse_binary_ops_expr(p, prec),
-            prec if !prec.prefix_ops().is_empty() => parse_prefix_ops(p, prec),
-            prec if !prec.suffix_ops().is_empty() => parse_suffix_ops(p, prec),
-            prec => internal_error!("don't know what to do for precedence {:?}", prec),
-        }
+        parse_ops_expr(p, self.0)
     }
 }
 
-/// Parses an expression with any number of binary operators at a specific
+/// Parses an atomic expression, such as a literal or a function call.
+///
+/// This is a separate function so that its locals don't bloat the stack frame
+/// of every precedence level.
+fn parse_atom(p: &mut Parser<'_>) -> FormulaResult<ast::AstNode> {
+    parse_one_of!(
+        p,
+        [
+            FunctionCall.map(Some),
+            SheetRange.map(Some),
+            StringLiteral.map(Some),
+            NumericLiteral.map(Some),
+            ErrorLiteral.map(Some),
+            ArrayLiteral.map(Some),
+            CellReference.map(Some),
+            ColumnRange.map(Some),
+            Name.map(Some),
+            ParenExpression.map(Some),
+            ImplicitIntersection.map(Some),
+            Epsilon.map(|_| None),
+        ],
+    )
+    .transpose()
+    .unwrap_or_else(|| p.expected(Expression))
+}
+
+/// Parses an expression containing only operators at or above a specific
+/// precedence level, according to the table on `OpPrecedence`.
+///
+/// This uses precedence climbing rather than recursing once per precedence
+/// level, so that each level of nesting (e.g., parentheses) uses only a little
+/// bit of stack space.
+///
+/// The work at each step is split into separate functions so that the stack
+/// frame of this function, which stays on the stack while the operands are
+/// parsed, is small even in unoptimized builds.
+fn parse_ops_expr(p: &mut Parser<'_>, min_precedence: OpPrecedence) -> FormulaResult<ast::AstNode> {
+    let lhs = match p.peek_next().and_then(OpPrecedence::of_prefix_op) {
+        Some(precedence) if precedence >= min_precedence => parse_prefix_op(p, precedence),
+        _ => parse_atom(p),
+    };
+    parse_suffix_and_binary_ops(p, lhs?, min_precedence)
+}
+
+/// Parses a prefix operator and its operand, which may have more prefix
+/// operators, as in `--A1`.
+fn parse_prefix_op(p: &mut Parser<'_>, precedence: OpPrecedence) -> FormulaResult<ast::AstNode> {
+    p.next();
+    let op = Spanned {
+        span: p.span(),
+        inner: p.token_str().to_string(),
+    };
+    let operand = p.nested(|p| parse_ops_expr(p, precedence))?;
+    Ok(AstNode {
+        span: Span::merge(op.span, operand.span),
+        inner: ast::AstNodeContents::FunctionCall {
+            func: op,
+            args: vec![operand],
+        },
+    })
+}
+
+/// Parses any suffix and binary operators after `lhs` at or above a specific
 /// precedence level.
-fn parse_binary_ops_expr(
+fn parse_suffix_and_binary_ops(
     p: &mut Parser<'_>,
-    precedence: OpPrecedence,
+    lhs: ast::AstNode,
+    min_precedence: OpPrecedence,
 ) -> FormulaResult<ast::AstNode> {
-    let recursive_expression = ExpressionWithPrecedence(precedence.next());
-
-    let allowed_ops = precedence.binary_ops();
-
-    // First, just make a list of operators and expressions. `ops.len()` should
-    // always be equal to `exprs.len() - 1`.
-    let mut ops: Vec<Spanned<String>> = vec![];
-    let mut exprs: Vec<ast::AstNode> = vec![p.parse(recursive_expression)?];
+    let mut ret = lhs;
     while let Some(tok) = p.peek_next() {
-        if allowed_ops.contains(&tok) {
+        if let Some(precedence) = OpPrecedence::of_suffix_op(tok) {
+            if precedence < min_precedence {
+                break;
+            }
             p.next();
-            ops.push(Spanned {
+            let op = Spanned {
                 span: p.span(),
                 inner: p.token_str().to_string(),
-            });
-            exprs.push(p.parse(recursive_expression)?);
-            continue;
-        } else {
-            break;
-        }
-    }
-
-    let mut ret: ast::AstNode;
-    if precedence.is_right_associative() {
-        // Take out the last/rightmost expression; that's the deepest AST node.
-        ret = exprs.pop().unwrap();
-        // Pair up the remaining operators and expressions, and make a new AST
-        // node using the previous iteration as the right-hand side of the
-        // expression.
-        for (lhs, op) in exprs.into_iter().zip(ops).rev() {
-            ret = AstNode {
-                span: Span::merge(lhs.span, ret.span),
-                inner: ast::AstNodeContents::FunctionCall {
-                    func: op,
-                    args: vec![lhs, ret],
-                },
             };
-        }
-    } else {
-        // Take out the first/leftmost expression; that's the deepest AST node.
-        let mut exprs_iter = exprs.into_iter();
-        ret = exprs_iter.next().unwrap();
-        // Pair up the remaining operators and expressions, and make a new AST
-        // node using the previous iteration as the left-hand side of the
-        // expression.
-        for (op, rhs) in ops.into_iter().zip(exprs_iter) {
             ret = AstNode {
-                span: Span::merge(ret.span, rhs.span),
+                span: Span::merge(ret.span, op.span),
                 inner: ast::AstNodeContents::FunctionCall {
                     func: op,
-                    args: vec![ret, rhs],
+                    args: vec![ret],
                 },
             };
+            continue;
         }
-    }
-    Ok(ret)
-}
-
-/// Parses an expression with any number of prefix operators.
-fn parse_prefix_ops(p: &mut Parser<'_>, precedence: OpPrecedence) -> FormulaResult<ast::AstNode> {
-    let allowed_ops = precedence.prefix_ops();
 
-    // Build a list of operators in the order that they appear in the source
-    // code.
-    let mut ops: Vec<Spanned<String>> = vec![];
-    while let Some(tok) = p.peek_next() {
-        if allowed_ops.contains(&tok) {
-            p.next();
-            ops.push(Spanned {
-                span: p.span(),
-                inner: p.token_str().to_string(),
-            });
-            continue;
-        } else {
+        let Some(precedence) = OpPrecedence::of_binary_op(tok) else {
+            break;
+        };
+        if precedence < min_precedence {
             break;
         }
-    }
-    let mut ret = p.parse(ExpressionWithPrecedence(precedence.next()))?;
-    // Now pop the operators off the list from right to left.
-    for op in ops {
+        p.next();
+        let op = Spanned {
+            span: p.span(),
+            inner: p.token_str().to_string(),
+        };
+
+        // For a right-associative operator, the right-hand side may contain
+        // the same operator, so it counts as nesting. For a left-associative
+        // operator, it must not.
+        let rhs = if precedence.is_right_associative() {
+            p.nested(|p| parse_ops_expr(p, precedence))?
+        } else {
+            parse_ops_expr(p, precedence.next())?
+        };
+
         ret = AstNode {
-            span: Span::merge(op.span, ret.span),
+            span: Span::merge(ret.span, rhs.span),
             inner: ast::AstNodeContents::FunctionCall {
                 func: op,
-                args: vec![ret],
+                args: vec![ret, rhs],
             },
         };
     }
     Ok(ret)
 }
 
-/// Parses an expression with any number of suffix operators.
-fn parse_suffix_ops(p: &mut Parser<'_>, precedence: OpPrecedence) -> FormulaResult<ast::AstNode> {
-    let allowed_ops = precedence.suffix_ops();
-
-    // Parse the initial expression.
-    let mut ret = p.parse(ExpressionWithPrecedence(precedence.next()))?;
-
-    // Repeatedly try to consume a suffix.
-    while let Some(tok) = p.peek_next() {
-        if allowed_ops.contains(&tok) {
-            p.next();
-            let op = Spanned {
-                span: p.span(),
-                inner: p.token_str().to_string(),
-            };
-            ret = AstNode {
-                span: Span::merge(ret.span, op.span),
-                inner: ast::AstNodeContents::FunctionCall {
-                    func: op,
-                    args: vec![ret],
-                },
-            }
-        } else {
-            break;
-        }
-    }
-    Ok(ret)
-}
-
 /// Matches a function call.
 #[derive(Debug, Copy, Clone)]
 pub struct FunctionCall;
@@ -328,7 +358,7 @@ impl SyntaxRule for FunctionCall {
         p.prev();
 
         let spanned_args = p.parse(List {
-            inner: Expression,
+            inner: FunctionArgument,
             sep: Token::ArgSep,
             start: Token::FunctionCall,
             end: Token::RParen,
@@ -343,6 +373,30 @@ impl SyntaxRule for FunctionCall {
     }
 }
 
+/// Matches an argument to a function call, which may be omitted if it is
+/// followed by a comma, as in `IF(A1,,2)`. A trailing comma does not add an
+/// omitted argument.
+#[derive(Debug, Copy, Clone)]
+pub struct FunctionArgument;
+impl_display!(for FunctionArgument, "{}", Expression);
+impl SyntaxRule for FunctionArgument {
+    type Output = ast::AstNode;
+
+    fn prefix_matches(&self, p: Parser<'_>) -> bool {
+        Token::ArgSep.prefix_matches(p) || Expression.prefix_matches(p)
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        if Token::ArgSep.prefix_matches(*p) {
+            let start = p.peek_next_span().start;
+            return Ok(AstNode {
+                span: Span { start, end: start },
+                inner: ast::AstNodeContents::Omitted,
+            });
+        }
+        p.parse(Expression)
+    }
+}
+
 /// Matches a pair of parentheses containing an expression.
 #[derive(Debug, Copy, Clone)]
 pub struct ParenExpression;
@@ -360,6 +414,117 @@ impl SyntaxRule for ParenExpression {
     }
 }
 
+/// Matches the implicit intersection operator `@` applied to an expression.
+///
+/// `@` has lower precedence than the cell range operator, so `@A1:A10` applies
+/// to the whole range, but otherwise acts like an atom so that it can be used
+/// after prefix operators, as in `-@A1:A10`.
+#[derive(Debug, Copy, Clone)]
+pub struct ImplicitIntersection;
+impl_display!(for ImplicitIntersection, "implicit intersection, such as '@A1:A10'");
+impl SyntaxRule for ImplicitIntersection {
+    type Output = AstNode;
+
+    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
+        p.next() == Some(Token::ImplicitIntersection)
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        p.parse(Token::ImplicitIntersection)?;
+        let op = Spanned {
+            span: p.span(),
+            inner: p.token_str().to_string(),
+        };
+        let arg =
+            p.nested(|p| ExpressionWithPrecedence(OpPrecedence::CellRange).parse_shallow(p))?;
+        Ok(AstNode {
+            span: Span::merge(op.span, arg.span),
+            inner: ast::AstNodeContents::FunctionCall {
+                func: op,
+                args: vec![arg],
+            },
+        })
+    }
+}
+
+/// Matches a 3-D reference, which refers to the same cells on each sheet from
+/// one sheet to another, as in `Sheet1:Sheet3!A1:B10`. Sheet names that aren't
+/// valid names or that look like cell references are quoted, as in
+/// `'Jan 2024':'Mar 2024'!A1` or `'Q1':'Q4'!A1`.
+#[derive(Debug, Copy, Clone)]
+pub struct SheetRange;
+impl_display!(for SheetRange, "3-D reference, such as 'Sheet1:Sheet3!A1'");
+impl SyntaxRule for SheetRange {
+    type Output = AstNode;
+
+    fn prefix_matches(&self, mut p: Parser<'_>) -> bool {
+        let is_sheet_name =
+            |t: Option<Token>| matches!(t, Some(Token::Name | Token::StringLiteral));
+        match p.next() {
+            // Uppercase sheet names, as in `JAN:MAR`, look like a column range.
+            Some(Token::ColumnRange) => p.next() == Some(Token::SheetRefOp),
+            first => {
+                is_sheet_name(first)
+                    && p.next() == Some(Token::CellRangeOp)
+                    && is_sheet_name(p.next())
+                    && p.next() == Some(Token::SheetRefOp)
+            }
+        }
+    }
+    fn consume_match(&self, p: &mut Parser<'_>) -> FormulaResult<Self::Output> {
+        let (first, last) = if p.peek_next() == Some(Token::ColumnRange) {
+            p.next();
+            let span = p.span();
+            let (first, last) = p.token_str().split_once(':').unwrap_or_default();
+            let sheet_name = |start: usize, name: &str| AstNode {
+                span: Span {
+                    start,
+                    end: start + name.len(),
+                },
+                inner: ast::AstNodeContents::String(name.to_string()),
+            };
+            (
+                sheet_name(span.start, first),
+                sheet_name(span.end - last.len(), last),
+            )
+        } else {
+            let first = parse_sheet_name(p)?;
+            p.parse(Token::CellRangeOp)?;
+            (first, parse_sheet_name(p)?)
+        };
+        p.parse(Token::SheetRefOp)?;
+        let op = Spanned {
+            span: p.span(),
+            inner: p.token_str().to_string(),
+        };
+        let range =
+            p.nested(|p| ExpressionWithPrecedence(OpPrecedence::CellRange).parse_shallow(p))?;
+        Ok(AstNode {
+            span: Span::merge(first.span, range.span),
+            inner: ast::AstNodeContents::FunctionCall {
+                func: op,
+                args: vec![first, last, range],
+            },
+        })
+    }
+}
+
+/// Parses the name of a sheet in a 3-D reference as a string.
+fn parse_sheet_name(p: &mut Parser<'_>) -> FormulaResult<AstNode> {
+    if let Some(result) = p.try_parse(StringLiteral) {
+        return result;
+    }
+    match p.next() {
+        Some(Token::Name) => Ok(AstNode {
+            span: p.span(),
+            inner: ast::AstNodeContents::String(p.token_str().to_string()),
+        }),
+        _ => {
+            p.prev();
+            p.expected("sheet name")
+        }
+    }
+}
+
 /// Matches an array literal.
 pub struct ArrayLiteral;
 impl_display!(for ArrayLiteral, "array literal, such as '{{1, 2; 3, 4}}'");
@@ -382,6 +547,7 @@ impl SyntaxRule for ArrayLiteral {
                 Some(Token::RowSep) => rows.push(vec![]), // start a new row
                 Some(Token::RBrace) => break,             // end of array
                 _ => {
+                    p.prev();
                     return Err(p.expected_err(crate::util::join_with_conjunction(
                         "or",
                         &[
@@ -389,7 +555,7 @@ impl SyntaxRule for ArrayLiteral {
                             Token::RowSep.to_string(),
                             Token::RBrace.to_string(),
                         ],
-                    )))
+                    )));
                 }
             }
         }
@@ -33,7 +33,7 @@ pub trait SyntaxRule: fmt::Display {
 
 impl<O> std::fmt::Debug for Box<dyn SyntaxRule<Output = O>> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", *self)
+        write!(f, "{}", self)
     }
 }
 impl<O, T: SyntaxRule<Output = O> + ?Sized> SyntaxRule for Box<T> {
@@ -58,6 +58,7 @@ impl SyntaxRule for Token {
         if p.next() == Some(*self) {
             Ok(())
         } else {
+            p.prev();
             p.expected(self)
         }
     }
@@ -0,0 +1,94 @@
+//! Regular expressions for `REGEXMATCH`, `REGEXEXTRACT`, and `REGEXREPLACE`.
+//!
+//! Patterns use the syntax of the `regex` crate, which is nearly the same as
+//! RE2, the engine that Google Sheets uses. Like RE2, it has no
+//! backreferences or lookaround, so matching always takes time linear in the
+//! length of the text and no pattern can take exponential time. Instead, the
+//! size of a compiled pattern is limited, and each match counts as an
+//! evaluation step.
+
+use regex::{Captures, Regex, RegexBuilder};
+
+use super::*;
+
+/// Maximum size in bytes of a compiled pattern.
+pub const MAX_COMPILED_REGEX_SIZE: usize = 1 << 20;
+
+/// Compiles a pattern, returning a [`FormulaErrorMsg::BadRegex`] error with
+/// the span of the pattern if it is invalid or too large.
+pub fn compile(pattern: &Spanned<Value>) -> FormulaResult<Regex> {
+    pattern.check_error()?;
+    RegexBuilder::new(&pattern.inner.to_string())
+        .size_limit(MAX_COMPILED_REGEX_SIZE)
+        .dfa_size_limit(MAX_COMPILED_REGEX_SIZE)
+        .build()
+        .map_err(|e| {
+            // Syntax errors repeat the pattern with the problem underlined,
+            // followed by a line that describes the problem.
+            let msg = e.to_string();
+            let msg = match msg.lines().find_map(|line| line.strip_prefix("error: ")) {
+                Some(line) => line.to_string(),
+                None => msg,
+            };
+            FormulaErrorMsg::BadRegex { msg: msg.into() }.with_span(pattern.span)
+        })
+}
+
+/// Compiles patterns, reusing the last one if the same pattern is used again,
+/// such as when a function is applied to each value of a range.
+#[derive(Debug, Default)]
+pub struct RegexCache {
+    last: Option<(String, Regex)>,
+}
+impl RegexCache {
+    /// Returns the compiled pattern, or an error if it is invalid.
+    pub fn get(&mut self, pattern: &Spanned<Value>) -> FormulaResult<&Regex> {
+        let s = pattern.inner.to_string();
+        let last = match self.last.take() {
+            Some((last, regex)) if last == s => (last, regex),
+            _ => (s, compile(pattern)?),
+        };
+        Ok(&self.last.insert(last).1)
+    }
+}
+
+/// Returns the text of a match for `REGEXEXTRACT`: the whole match if the
+/// pattern has no capture groups, or else the text of each group. Groups that
+/// didn't participate in the match are empty.
+pub fn extracted(captures: &Captures<'_>) -> Vec<Value> {
+    match captures.len() {
+        1 => vec![Value::from(&captures[0])],
+        _ => captures
+            .iter()
+            .skip(1)
+            .map(|group| Value::from(group.map_or("", |m| m.as_str())))
+            .collect(),
+    }
+}
+
+/// Replaces every match of `regex` in `text` for `REGEXREPLACE`. In the
+/// replacement, `$1` or `${1}` is the text of the first capture group,
+/// `${name}` is the text of a named group, and `$$` is a literal `$`.
+///
+/// Each match is one evaluation step, and the length of the result is
+/// checked as it grows.
+pub fn replace_all(
+    ctx: &mut Ctx<'_>,
+    regex: &Regex,
+    text: &str,
+    replacement: &str,
+) -> FormulaResult<String> {
+    let mut ret = String::new();
+    let mut last_end = 0;
+    for captures in regex.captures_iter(text) {
+        ctx.take_steps(1)?;
+        let m = captures.get(0).expect("group 0 is always present");
+        ret.push_str(&text[last_end..m.start()]);
+        captures.expand(replacement, &mut ret);
+        ctx.check_text_length(ret.len())?;
+        last_end = m.end();
+    }
+    ret.push_str(&text[last_end..]);
+    ctx.check_text_length(ret.len())?;
+    Ok(ret)
+}
@@ -0,0 +1,118 @@
+//! Conversions between number bases, for engineering functions such as
+//! `HEX2DEC` and `DEC2BIN`.
+//!
+//! Like Excel, binary, octal, and hexadecimal numbers have at most 10 digits,
+//! and negative numbers are written in two's complement using all 10 digits.
+//! So binary numbers range from -512 to 511, octal numbers from -2^29 to
+//! 2^29-1, and hexadecimal numbers from -2^39 to 2^39-1.
+
+use super::*;
+
+/// Maximum number of digits in a non-decimal number.
+const MAX_DIGITS: usize = 10;
+
+/// Number base.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Radix {
+    Bin,
+    Oct,
+    Dec,
+    Hex,
+}
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Bin => 2,
+            Radix::Oct => 8,
+            Radix::Dec => 10,
+            Radix::Hex => 16,
+        }
+    }
+    /// Returns the number of bits in the two's complement representation, or
+    /// `None` for decimal.
+    fn bits(self) -> Option<u32> {
+        match self {
+            Radix::Dec => None,
+            other => Some(other.base().ilog2() * MAX_DIGITS as u32),
+        }
+    }
+
+    /// Reads a number written in this base. Decimal numbers are truncated to
+    /// integers.
+    pub fn parse(self, value: &Spanned<Value>) -> FormulaResult<i64> {
+        let Some(bits) = self.bits() else {
+            return Ok(value.to_number()?.trunc() as i64);
+        };
+        value.check_error()?;
+        let s = value.inner.to_string();
+        let not_a_number = || FormulaErrorMsg::NotANumber(format!("{s:?}").into());
+        if s.len() > MAX_DIGITS || !s.chars().all(|c| c.is_digit(self.base())) {
+            return Err(not_a_number().with_span(value.span));
+        }
+        if s.is_empty() {
+            return Ok(0);
+        }
+        let n = i64::from_str_radix(&s, self.base()).map_err(|_| not_a_number())?;
+        // Ten digits with the highest bit set is a negative number.
+        if n >= 1 << (bits - 1) {
+            Ok(n - (1 << bits))
+        } else {
+            Ok(n)
+        }
+    }
+
+    /// Writes a number in this base, or returns `None` if it is out of range.
+    /// Negative numbers are written with exactly 10 digits.
+    pub fn format(self, n: i64) -> Option<String> {
+        let Some(bits) = self.bits() else {
+            return Some(n.to_string());
+        };
+        let limit = 1 << (bits - 1);
+        if !(-limit..limit).contains(&n) {
+            return None;
+        }
+        let n = if n < 0 { n + (1 << bits) } else { n };
+        Some(match self {
+            Radix::Bin => format!("{n:b}"),
+            Radix::Oct => format!("{n:o}"),
+            Radix::Dec => n.to_string(),
+            Radix::Hex => format!("{n:X}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(radix: Radix, s: &str) -> FormulaResult<i64> {
+        radix.parse(&Spanned {
+            span: Span::empty(0),
+            inner: Value::String(s.to_string()),
+        })
+    }
+
+    #[test]
+    fn test_radix_twos_complement() {
+        for (radix, min, max) in [
+            (Radix::Bin, "1000000000", "111111111"),
+            (Radix::Oct, "4000000000", "3777777777"),
+            (Radix::Hex, "8000000000", "7FFFFFFFFF"),
+        ] {
+            let bits = radix.bits().unwrap();
+            let limit = 1_i64 << (bits - 1);
+            assert_eq!(-limit, parse(radix, min).unwrap());
+            assert_eq!(limit - 1, parse(radix, max).unwrap());
+            assert_eq!(-1, parse(radix, &radix.format(-1).unwrap()).unwrap());
+            assert_eq!(Some(min.to_string()), radix.format(-limit));
+            assert_eq!(Some(max.to_string()), radix.format(limit - 1));
+            assert_eq!(None, radix.format(-limit - 1));
+            assert_eq!(None, radix.format(limit));
+        }
+        assert_eq!(Some("1111111111".to_string()), Radix::Bin.format(-1));
+        assert_eq!(Some("7777777777".to_string()), Radix::Oct.format(-1));
+        assert_eq!(Some("FFFFFFFFFF".to_string()), Radix::Hex.format(-1));
+        assert_eq!(255, parse(Radix::Hex, "ff").unwrap());
+        assert_eq!(0, parse(Radix::Bin, "").unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+//! Random number generation for functions such as `RANDARRAY`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Small, fast pseudorandom number generator (SplitMix64).
+///
+/// This is not cryptographically secure, but it is plenty for spreadsheet
+/// functions, and it can be seeded so that tests are deterministic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+impl Rng {
+    /// Constructs a generator that always produces the same sequence for the
+    /// same seed.
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    /// Constructs a generator with an unpredictable seed.
+    pub fn from_entropy() -> Self {
+        // `RandomState` is randomly keyed, which saves depending on a crate
+        // just to get a seed.
+        Self::from_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Returns a random 64-bit integer.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// Returns a random number that is at least 0 and less than 1.
+    pub fn next_f64(&mut self) -> f64 {
+        // Use the top 53 bits, which is all that fits in an `f64`.
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_seed() {
+        let mut a = Rng::from_seed(42);
+        let mut b = Rng::from_seed(42);
+        let xs = (0..100).map(|_| a.next_f64()).collect::<Vec<_>>();
+        assert_eq!(xs, (0..100).map(|_| b.next_f64()).collect::<Vec<_>>());
+        assert!(xs.iter().all(|x| (0.0..1.0).contains(x)));
+        let mut c = Rng::from_seed(43);
+        assert_ne!(xs, (0..100).map(|_| c.next_f64()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,310 @@
+//! Linear regression for statistics functions such as `LINEST` and `TREND`,
+//! and exponential regression for `LOGEST` and `GROWTH`.
+
+use super::matrix::{LeastSquares, Matrix};
+use super::*;
+
+/// Arrangement of observations in an array of known or new x-values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Layout {
+    /// Each value is an observation of a single variable.
+    Flat,
+    /// Each row is an observation and each column is a variable.
+    Rows,
+    /// Each column is an observation and each row is a variable.
+    Columns,
+}
+
+/// Linear model `y = m_1*x_1 + ... + m_k*x_k + b` fit to known values using
+/// least squares, or the exponential model `y = b * m_1^x_1 * ... * m_k^x_k`
+/// if fit using the logarithms of the y-values.
+#[derive(Debug, Clone)]
+pub struct LinearFit {
+    layout: Layout,
+    /// Known x-values, in their original layout.
+    known_x: Matrix,
+    /// Whether the model was fit to `ln(y)`.
+    log_y: bool,
+    /// Whether the intercept `b` was fit, rather than forced to zero.
+    has_intercept: bool,
+
+    /// Coefficients `m_1` through `m_k`, followed by `b` if `has_intercept`.
+    least_squares: LeastSquares,
+    /// Number of observations.
+    n: usize,
+    /// Total sum of squares, around the mean if `has_intercept` or else around
+    /// zero.
+    total_sum_of_squares: f64,
+}
+impl LinearFit {
+    /// Fits a model to `known_y` given `known_x`, which defaults to the
+    /// sequence 1, 2, 3, etc.
+    ///
+    /// If `known_y` is a single column, then each column of `known_x` is a
+    /// separate variable; if `known_y` is a single row, then each row of
+    /// `known_x` is a separate variable. Otherwise `known_x` must be the same
+    /// size as `known_y`.
+    pub fn new(
+        known_y: &Spanned<Value>,
+        known_x: Option<&Spanned<Value>>,
+        has_intercept: bool,
+        log_y: bool,
+    ) -> FormulaResult<Self> {
+        let mut y = Matrix::from_value(known_y)?.data().to_vec();
+        if log_y {
+            if y.iter().any(|&y| y <= 0.0) {
+                return Err(FormulaErrorMsg::BadNumber.with_span(known_y.span));
+            }
+            y.iter_mut().for_each(|y| *y = y.ln());
+        }
+        let y_size = known_y.inner.array_size().unwrap_or((1, 1));
+
+        let (layout, known_x) = match known_x {
+            None => {
+                let sequence = (1..=y.len()).map(|i| i as f64).collect();
+                (Layout::Flat, Matrix::from_vec(y_size.0, y_size.1, sequence))
+            }
+            Some(value) => {
+                let x = Matrix::from_value(value)?;
+                let layout = if x.size() == y_size {
+                    Layout::Flat
+                } else if y_size.1 == 1 && x.rows() == y_size.0 {
+                    Layout::Rows
+                } else if y_size.0 == 1 && x.cols() == y_size.1 {
+                    Layout::Columns
+                } else {
+                    return Err(FormulaErrorMsg::ArraySizeMismatch {
+                        expected: y_size,
+                        got: x.size(),
+                    }
+                    .with_span(value.span));
+                };
+                (layout, x)
+            }
+        };
+
+        let x = observations(layout, &known_x);
+        let k = x.cols();
+        let n = x.rows();
+        let mut design = Matrix::zeros(n, k + has_intercept as usize);
+        for i in 0..n {
+            for j in 0..k {
+                design[(i, j)] = x[(i, j)];
+            }
+            if has_intercept {
+                design[(i, k)] = 1.0;
+            }
+        }
+        let least_squares = design
+            .least_squares(&y)
+            .ok_or(FormulaErrorMsg::NotAvailable.with_span(known_y.span))?;
+
+        let center = if has_intercept {
+            y.iter().sum::<f64>() / n as f64
+        } else {
+            0.0
+        };
+        let total_sum_of_squares = y.iter().map(|y| (y - center).powi(2)).sum();
+
+        Ok(Self {
+            layout,
+            known_x,
+            log_y,
+            has_intercept,
+
+            least_squares,
+            n,
+            total_sum_of_squares,
+        })
+    }
+
+    /// Returns the number of x-variables.
+    fn k(&self) -> usize {
+        self.least_squares.solution.len() - self.has_intercept as usize
+    }
+    /// Returns the coefficients `m_1` through `m_k`.
+    fn slopes(&self) -> &[f64] {
+        &self.least_squares.solution[..self.k()]
+    }
+    /// Returns the intercept `b`.
+    fn intercept(&self) -> f64 {
+        match self.has_intercept {
+            true => self.least_squares.solution[self.k()],
+            false => 0.0,
+        }
+    }
+
+    /// Returns the y-values predicted by the model for `new_x`, which
+    /// defaults to the known x-values. `new_x` must have the same number of
+    /// variables as the known x-values, arranged in the same way.
+    pub fn predict(&self, new_x: Option<&Spanned<Value>>) -> FormulaResult<Value> {
+        let new_x = match new_x {
+            Some(value) => {
+                let x = Matrix::from_value(value)?;
+                let is_compatible = match self.layout {
+                    Layout::Flat => true,
+                    Layout::Rows => x.cols() == self.known_x.cols(),
+                    Layout::Columns => x.rows() == self.known_x.rows(),
+                };
+                if !is_compatible {
+                    return Err(FormulaErrorMsg::ArraySizeMismatch {
+                        expected: self.known_x.size(),
+                        got: x.size(),
+                    }
+                    .with_span(value.span));
+                }
+                x
+            }
+            None => self.known_x.clone(),
+        };
+
+        let x = observations(self.layout, &new_x);
+        let predictions = (0..x.rows()).map(|i| {
+            let y = (0..x.cols())
+                .map(|j| self.slopes()[j] * x[(i, j)])
+                .sum::<f64>()
+                + self.intercept();
+            if self.log_y {
+                y.exp()
+            } else {
+                y
+            }
+        });
+        let predictions = predictions.collect::<Vec<_>>();
+
+        let (rows, cols) = match self.layout {
+            Layout::Flat => new_x.size(),
+            Layout::Rows => (predictions.len(), 1),
+            Layout::Columns => (1, predictions.len()),
+        };
+        Ok(Matrix::from_vec(rows, cols, predictions).to_value())
+    }
+
+    /// Returns the coefficients of the model in the format returned by
+    /// `LINEST` and `LOGEST`: a row containing `m_k` through `m_1` followed
+    /// by `b`.
+    ///
+    /// If `stats` is true, four more rows are added containing the standard
+    /// error of each coefficient; the coefficient of determination and the
+    /// standard error of the y estimate; the F statistic and the degrees of
+    /// freedom; and the regression and residual sums of squares. Unused cells
+    /// are blank. For an exponential model, these statistics are for the
+    /// linear fit to `ln(y)`, like in Excel.
+    pub fn coefficients(&self, stats: bool) -> FormulaResult<Value> {
+        let k = self.k();
+        let mut row = self.slopes().iter().rev().copied().collect::<Vec<_>>();
+        row.push(self.intercept());
+        if self.log_y {
+            row.iter_mut().for_each(|c| *c = c.exp());
+        }
+        if !stats {
+            return Ok(Matrix::from_vec(1, k + 1, row).to_value());
+        }
+
+        let p = k + self.has_intercept as usize;
+        let degrees_of_freedom = self.n - p;
+        if degrees_of_freedom == 0 {
+            return Err(FormulaErrorMsg::DivideByZero.into());
+        }
+        let ss_resid = self.least_squares.residual_sum_of_squares;
+        let ss_total = self.total_sum_of_squares;
+        let ss_reg = ss_total - ss_resid;
+        let r_squared = if ss_total == 0.0 {
+            1.0
+        } else {
+            ss_reg / ss_total
+        };
+        let se_y = (ss_resid / degrees_of_freedom as f64).sqrt();
+        let f = (ss_reg / k as f64) / (ss_resid / degrees_of_freedom as f64);
+
+        let mut std_errors = self
+            .least_squares
+            .unscaled_variances()
+            .into_iter()
+            .map(|v| Value::Number(se_y * v.sqrt()))
+            .collect::<Vec<_>>();
+        std_errors[..k].reverse();
+        if !self.has_intercept {
+            std_errors.push(Value::default());
+        }
+
+        let number_row = |values: &[f64]| {
+            let mut row = values.iter().map(|&n| Value::Number(n)).collect::<Vec<_>>();
+            row.resize(k + 1, Value::default());
+            row
+        };
+        Value::array(vec![
+            number_row(&row),
+            std_errors,
+            number_row(&[r_squared, se_y]),
+            number_row(&[f, degrees_of_freedom as f64]),
+            number_row(&[ss_reg, ss_resid]),
+        ])
+    }
+}
+
+/// Returns a matrix where each row is an observation and each column is a
+/// variable.
+fn observations(layout: Layout, x: &Matrix) -> Matrix {
+    match layout {
+        Layout::Flat => Matrix::from_vec(x.data().len(), 1, x.data().to_vec()),
+        Layout::Rows => x.clone(),
+        Layout::Columns => x.transpose(),
+    }
+}
+
+/// Means and sums of squared deviations for two variables.
+#[derive(Debug, Copy, Clone)]
+pub struct PairStats {
+    pub mean_x: f64,
+    pub mean_y: f64,
+    /// Sum of `(x - mean_x)^2`.
+    pub sxx: f64,
+    /// Sum of `(y - mean_y)^2`.
+    pub syy: f64,
+    /// Sum of `(x - mean_x) * (y - mean_y)`.
+    pub sxy: f64,
+}
+impl PairStats {
+    /// Computes statistics for `(x, y)` pairs, or returns `None` if there
+    /// are none.
+    pub fn new(pairs: &[(f64, f64)]) -> Option<Self> {
+        if pairs.is_empty() {
+            return None;
+        }
+        let n = pairs.len() as f64;
+        let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+        for (x, y) in pairs {
+            let (dx, dy) = (x - mean_x, y - mean_y);
+            sxx += dx * dx;
+            syy += dy * dy;
+            sxy += dx * dy;
+        }
+        Some(Self {
+            mean_x,
+            mean_y,
+            sxx,
+            syy,
+            sxy,
+        })
+    }
+
+    /// Returns the slope of the least-squares line, or `None` if all the
+    /// x-values are the same.
+    pub fn slope(self) -> Option<f64> {
+        (self.sxx != 0.0).then(|| self.sxy / self.sxx)
+    }
+    /// Returns the intercept of the least-squares line, or `None` if all the
+    /// x-values are the same.
+    pub fn intercept(self) -> Option<f64> {
+        Some(self.mean_y - self.slope()? * self.mean_x)
+    }
+    /// Returns the Pearson correlation coefficient, or `None` if either
+    /// variable is constant.
+    pub fn correlation(self) -> Option<f64> {
+        let denominator = (self.sxx * self.syy).sqrt();
+        (denominator != 0.0).then(|| self.sxy / denominator)
+    }
+}
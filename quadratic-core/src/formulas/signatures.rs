@@ -0,0 +1,1267 @@
+//! Signatures of formula functions, for autocomplete and parameter hints.
+
+use std::fmt;
+
+/// Kind of value expected for a function argument.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArgType {
+    /// Any value.
+    Any,
+    /// Number, or anything that can be coerced to one.
+    Number,
+    /// Boolean, or the text `TRUE` or `FALSE`.
+    Bool,
+    /// Text.
+    Text,
+    /// Date serial number.
+    Date,
+    /// Array or cell range.
+    Array,
+}
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgType::Any => write!(f, "any"),
+            ArgType::Number => write!(f, "number"),
+            ArgType::Bool => write!(f, "boolean"),
+            ArgType::Text => write!(f, "text"),
+            ArgType::Date => write!(f, "date"),
+            ArgType::Array => write!(f, "array"),
+        }
+    }
+}
+
+/// Argument in a function signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArgSignature {
+    pub name: &'static str,
+    pub ty: ArgType,
+    /// Whether the argument may be omitted.
+    pub optional: bool,
+    /// Whether the argument may be repeated. If several consecutive arguments
+    /// are variadic, they repeat together as a group.
+    pub variadic: bool,
+}
+
+const fn arg(name: &'static str, ty: ArgType) -> ArgSignature {
+    ArgSignature {
+        name,
+        ty,
+        optional: false,
+        variadic: false,
+    }
+}
+const fn optional(name: &'static str, ty: ArgType) -> ArgSignature {
+    ArgSignature {
+        optional: true,
+        ..arg(name, ty)
+    }
+}
+/// Argument that may be repeated, including zero times if `optional` is true.
+const fn variadic(name: &'static str, ty: ArgType, optional: bool) -> ArgSignature {
+    ArgSignature {
+        optional,
+        variadic: true,
+        ..arg(name, ty)
+    }
+}
+
+/// Signature of a formula function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    /// Name of the function, in uppercase.
+    pub name: &'static str,
+    pub args: &'static [ArgSignature],
+    /// Short description of what the function does.
+    pub description: &'static str,
+}
+impl FunctionSignature {
+    /// Returns the minimum number of arguments.
+    pub fn min_args(&self) -> usize {
+        self.args.iter().filter(|arg| !arg.optional).count()
+    }
+    /// Returns the maximum number of arguments, or `None` if there is no
+    /// maximum.
+    pub fn max_args(&self) -> Option<usize> {
+        match self.args.iter().any(|arg| arg.variadic) {
+            true => None,
+            false => Some(self.args.len()),
+        }
+    }
+}
+impl fmt::Display for FunctionSignature {
+    /// Formats the signature as it would be written in a formula, such as
+    /// `WEEKNUM(date, [return_type])`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match (arg.optional, arg.variadic) {
+                (false, false) => write!(f, "{}", arg.name)?,
+                (true, false) => write!(f, "[{}]", arg.name)?,
+                (false, true) => write!(f, "{}...", arg.name)?,
+                (true, true) => write!(f, "[{}...]", arg.name)?,
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+/// Returns the signature of every formula function, grouped the same way as
+/// the code editor completions list.
+pub fn function_signatures() -> &'static [FunctionSignature] {
+    SIGNATURES
+}
+
+/// Returns the name of the function most similar to `name`, among the built-in
+/// functions and `others`, or `None` if none is similar enough to be a likely
+/// typo. Names are compared ignoring case.
+pub fn similar_function_name<'a>(
+    name: &str,
+    others: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let name = name.to_ascii_uppercase();
+    let max_distance = (name.len() / 3).max(1);
+    SIGNATURES
+        .iter()
+        .map(|sig| sig.name)
+        .chain(others)
+        .map(|candidate| {
+            (
+                edit_distance(&name, &candidate.to_ascii_uppercase()),
+                candidate,
+            )
+        })
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Returns the number of characters that must be inserted, deleted, or
+/// replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    // Distances from a prefix of `a` to each prefix of `b`.
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the signature of the function with the given name, ignoring case.
+pub fn function_signature(name: &str) -> Option<&'static FunctionSignature> {
+    SIGNATURES
+        .iter()
+        .find(|sig| sig.name.eq_ignore_ascii_case(name))
+}
+
+use ArgType::*;
+
+const SIGNATURES: &[FunctionSignature] = &[
+    // Mathematical functions
+    FunctionSignature {
+        name: "SUM",
+        args: &[variadic("numbers", Number, true)],
+        description: "Adds all the numbers.",
+    },
+    FunctionSignature {
+        name: "PRODUCT",
+        args: &[variadic("numbers", Number, true)],
+        description: "Multiplies all the numbers.",
+    },
+    FunctionSignature {
+        name: "SUMX2MY2",
+        args: &[arg("array_x", Array), arg("array_y", Array)],
+        description: "Sums the differences of the squares of paired values.",
+    },
+    FunctionSignature {
+        name: "SUMX2PY2",
+        args: &[arg("array_x", Array), arg("array_y", Array)],
+        description: "Sums the sums of the squares of paired values.",
+    },
+    FunctionSignature {
+        name: "SUMXMY2",
+        args: &[arg("array_x", Array), arg("array_y", Array)],
+        description: "Sums the squares of the differences of paired values.",
+    },
+    FunctionSignature {
+        name: "SERIESSUM",
+        args: &[
+            arg("x", Number),
+            arg("n", Number),
+            arg("m", Number),
+            arg("coefficients", Array),
+        ],
+        description: "Sums a power series.",
+    },
+    FunctionSignature {
+        name: "ROUND",
+        args: &[arg("number", Number), arg("digits", Number)],
+        description: "Rounds a number to a number of decimal places.",
+    },
+    FunctionSignature {
+        name: "FLOOR",
+        args: &[arg("number", Number), arg("significance", Number)],
+        description: "Rounds a number down to a multiple of a significance with the same sign.",
+    },
+    FunctionSignature {
+        name: "CEILING",
+        args: &[arg("number", Number), arg("significance", Number)],
+        description: "Rounds a number up to a multiple of a significance with the same sign.",
+    },
+    FunctionSignature {
+        name: "FLOOR.PRECISE",
+        args: &[arg("number", Number), optional("significance", Number)],
+        description: "Rounds a number down to a multiple of a significance, regardless of sign.",
+    },
+    FunctionSignature {
+        name: "CEILING.PRECISE",
+        args: &[arg("number", Number), optional("significance", Number)],
+        description: "Rounds a number up to a multiple of a significance, regardless of sign.",
+    },
+    FunctionSignature {
+        name: "ISO.CEILING",
+        args: &[arg("number", Number), optional("significance", Number)],
+        description: "Same as CEILING.PRECISE.",
+    },
+    FunctionSignature {
+        name: "RANDARRAY",
+        args: &[
+            optional("rows", Number),
+            optional("columns", Number),
+            optional("min", Number),
+            optional("max", Number),
+            optional("integer", Bool),
+        ],
+        description: "Returns an array of random numbers.",
+    },
+    FunctionSignature {
+        name: "SEQUENCE",
+        args: &[
+            arg("rows", Number),
+            optional("columns", Number),
+            optional("start", Number),
+            optional("step", Number),
+        ],
+        description: "Returns an array of evenly spaced numbers.",
+    },
+    // Matrix functions
+    FunctionSignature {
+        name: "MMULT",
+        args: &[arg("array1", Array), arg("array2", Array)],
+        description: "Multiplies two matrices.",
+    },
+    FunctionSignature {
+        name: "MINVERSE",
+        args: &[arg("array", Array)],
+        description: "Returns the inverse of a square matrix.",
+    },
+    FunctionSignature {
+        name: "MDETERM",
+        args: &[arg("array", Array)],
+        description: "Returns the determinant of a square matrix.",
+    },
+    FunctionSignature {
+        name: "MUNIT",
+        args: &[arg("dimension", Number)],
+        description: "Returns an identity matrix.",
+    },
+    // Logic functions
+    FunctionSignature {
+        name: "TRUE",
+        args: &[],
+        description: "Returns TRUE.",
+    },
+    FunctionSignature {
+        name: "FALSE",
+        args: &[],
+        description: "Returns FALSE.",
+    },
+    FunctionSignature {
+        name: "NOT",
+        args: &[arg("boolean", Bool)],
+        description: "Returns TRUE if the argument is FALSE, and vice versa.",
+    },
+    FunctionSignature {
+        name: "AND",
+        args: &[variadic("booleans", Bool, true)],
+        description: "Returns TRUE if all the arguments are TRUE. Arguments after the first FALSE are not evaluated.",
+    },
+    FunctionSignature {
+        name: "OR",
+        args: &[variadic("booleans", Bool, true)],
+        description: "Returns TRUE if any of the arguments are TRUE. Arguments after the first TRUE are not evaluated.",
+    },
+    FunctionSignature {
+        name: "XOR",
+        args: &[variadic("booleans", Bool, true)],
+        description: "Returns TRUE if an odd number of the arguments are TRUE.",
+    },
+    FunctionSignature {
+        name: "IF",
+        args: &[
+            arg("condition", Bool),
+            arg("value_if_true", Any),
+            arg("value_if_false", Any),
+        ],
+        description: "Returns one of two values depending on a condition. Only the value that is returned is evaluated.",
+    },
+    FunctionSignature {
+        name: "LET",
+        args: &[
+            variadic("name", Any, false),
+            variadic("value", Any, false),
+            arg("calculation", Any),
+        ],
+        description: "Binds each name to a value, which later values and the calculation can refer to by name. Each value is evaluated only once.",
+    },
+    FunctionSignature {
+        name: "LAMBDA",
+        args: &[variadic("parameter", Any, true), arg("calculation", Any)],
+        description: "Returns a function with the given parameters that evaluates the calculation when it is called. Bind it to a name with LET to call it.",
+    },
+    FunctionSignature {
+        name: "MAP",
+        args: &[arg("array", Any), arg("lambda", Any)],
+        description: "Calls a LAMBDA with one parameter on each value in an array, and returns an array of the results.",
+    },
+    FunctionSignature {
+        name: "REDUCE",
+        args: &[
+            arg("initial_value", Any),
+            arg("array", Any),
+            arg("lambda", Any),
+        ],
+        description: "Calls a LAMBDA with two parameters on each value in an array, passing the previous result and the value, and returns the final result. The first call is passed the initial value.",
+    },
+    FunctionSignature {
+        name: "SCAN",
+        args: &[
+            arg("initial_value", Any),
+            arg("array", Any),
+            arg("lambda", Any),
+        ],
+        description: "Like REDUCE, but returns an array of the result after each value, such as a running total.",
+    },
+    // Error functions
+    FunctionSignature {
+        name: "NA",
+        args: &[],
+        description: "Returns the #N/A error.",
+    },
+    FunctionSignature {
+        name: "ERROR",
+        args: &[arg("name", Text)],
+        description: "Returns the error with the given code or name, such as \"#DIV/0!\".",
+    },
+    FunctionSignature {
+        name: "ISERROR",
+        args: &[arg("value", Any)],
+        description: "Returns TRUE if the value is an error.",
+    },
+    FunctionSignature {
+        name: "TYPE",
+        args: &[arg("value", Any)],
+        description: "Returns 1 for a number or blank, 2 for text, 4 for a logical value, 16 for an error, 64 for an array, or 128 for a lambda.",
+    },
+    FunctionSignature {
+        name: "ISBLANK",
+        args: &[arg("value", Any)],
+        description: "Returns TRUE if the value is a blank cell. An empty string is not blank.",
+    },
+    FunctionSignature {
+        name: "ISOMITTED",
+        args: &[arg("argument", Any)],
+        description: "Returns TRUE if a parameter of a LAMBDA was omitted in the call.",
+    },
+    // Statistics functions
+    FunctionSignature {
+        name: "AVERAGE",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the arithmetic mean of the numbers.",
+    },
+    FunctionSignature {
+        name: "COUNT",
+        args: &[variadic("values", Any, true)],
+        description: "Counts the values, ignoring blanks.",
+    },
+    FunctionSignature {
+        name: "COUNTUNIQUE",
+        args: &[variadic("values", Any, false)],
+        description: "Counts the distinct values, ignoring blanks and errors.",
+    },
+    FunctionSignature {
+        name: "COUNTBLANK",
+        args: &[arg("range", Array)],
+        description: "Counts the blank cells and empty strings in the range.",
+    },
+    FunctionSignature {
+        name: "MIN",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the smallest number.",
+    },
+    FunctionSignature {
+        name: "MAX",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the largest number.",
+    },
+    FunctionSignature {
+        name: "SUMSQ",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the sum of the squares of the numbers.",
+    },
+    FunctionSignature {
+        name: "DEVSQ",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the sum of the squared deviations of the numbers from their mean.",
+    },
+    FunctionSignature {
+        name: "AVEDEV",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the mean of the absolute deviations of the numbers from their mean.",
+    },
+    FunctionSignature {
+        name: "GEOMEAN",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the geometric mean of positive numbers.",
+    },
+    FunctionSignature {
+        name: "HARMEAN",
+        args: &[variadic("numbers", Number, true)],
+        description: "Returns the harmonic mean of positive numbers.",
+    },
+    FunctionSignature {
+        name: "FREQUENCY",
+        args: &[arg("data", Array), arg("bins", Array)],
+        description: "Counts how many numbers fall into each bin, returning a column with one more count than there are bins for the numbers larger than every bin.",
+    },
+    FunctionSignature {
+        name: "SUMIF",
+        args: &[
+            arg("range", Array),
+            arg("criterion", Any),
+            optional("sum_range", Array),
+        ],
+        description: "Adds the numbers whose cells meet the criterion.",
+    },
+    FunctionSignature {
+        name: "SUMIFS",
+        args: &[
+            arg("sum_range", Array),
+            variadic("criteria_range", Array, false),
+            variadic("criteria", Any, false),
+        ],
+        description: "Adds the numbers whose cells meet all the criteria.",
+    },
+    FunctionSignature {
+        name: "COUNTIF",
+        args: &[arg("range", Array), arg("criterion", Any)],
+        description: "Counts the cells that meet the criterion.",
+    },
+    FunctionSignature {
+        name: "COUNTIFS",
+        args: &[
+            variadic("criteria_range", Array, false),
+            variadic("criteria", Any, false),
+        ],
+        description: "Counts the cells that meet all the criteria.",
+    },
+    FunctionSignature {
+        name: "AVERAGEIF",
+        args: &[
+            arg("range", Array),
+            arg("criterion", Any),
+            optional("average_range", Array),
+        ],
+        description: "Returns the arithmetic mean of the numbers whose cells meet the criterion.",
+    },
+    FunctionSignature {
+        name: "AVERAGEIFS",
+        args: &[
+            arg("average_range", Array),
+            variadic("criteria_range", Array, false),
+            variadic("criteria", Any, false),
+        ],
+        description:
+            "Returns the arithmetic mean of the numbers whose cells meet all the criteria.",
+    },
+    FunctionSignature {
+        name: "MAXIFS",
+        args: &[
+            arg("max_range", Array),
+            variadic("criteria_range", Array, false),
+            variadic("criteria", Any, false),
+        ],
+        description: "Returns the largest number whose cells meet all the criteria.",
+    },
+    FunctionSignature {
+        name: "MINIFS",
+        args: &[
+            arg("min_range", Array),
+            variadic("criteria_range", Array, false),
+            variadic("criteria", Any, false),
+        ],
+        description: "Returns the smallest number whose cells meet all the criteria.",
+    },
+    FunctionSignature {
+        name: "AGGREGATE",
+        args: &[
+            arg("function_num", Number),
+            arg("options", Number),
+            arg("array", Array),
+            variadic("k_or_ref", Any, true),
+        ],
+        description: "Applies a function selected by number, optionally ignoring errors, hidden rows, and nested subtotals.",
+    },
+    FunctionSignature {
+        name: "SUBTOTAL",
+        args: &[arg("function_num", Number), variadic("refs", Array, false)],
+        description: "Applies a function selected by number, ignoring nested subtotals.",
+    },
+    FunctionSignature {
+        name: "SLOPE",
+        args: &[arg("known_y", Array), arg("known_x", Array)],
+        description: "Returns the slope of the least-squares line.",
+    },
+    FunctionSignature {
+        name: "INTERCEPT",
+        args: &[arg("known_y", Array), arg("known_x", Array)],
+        description: "Returns the y-intercept of the least-squares line.",
+    },
+    FunctionSignature {
+        name: "CORREL",
+        args: &[arg("array1", Array), arg("array2", Array)],
+        description: "Returns the Pearson correlation coefficient.",
+    },
+    FunctionSignature {
+        name: "RSQ",
+        args: &[arg("known_y", Array), arg("known_x", Array)],
+        description: "Returns the square of the Pearson correlation coefficient.",
+    },
+    FunctionSignature {
+        name: "LINEST",
+        args: &[
+            arg("known_y", Array),
+            optional("known_x", Array),
+            optional("const", Bool),
+            optional("stats", Bool),
+        ],
+        description: "Returns the coefficients of a least-squares linear fit.",
+    },
+    FunctionSignature {
+        name: "LOGEST",
+        args: &[
+            arg("known_y", Array),
+            optional("known_x", Array),
+            optional("const", Bool),
+            optional("stats", Bool),
+        ],
+        description: "Returns the coefficients of a least-squares exponential fit.",
+    },
+    FunctionSignature {
+        name: "TREND",
+        args: &[
+            arg("known_y", Array),
+            optional("known_x", Array),
+            optional("new_x", Array),
+            optional("const", Bool),
+        ],
+        description: "Returns values along a least-squares linear fit.",
+    },
+    FunctionSignature {
+        name: "GROWTH",
+        args: &[
+            arg("known_y", Array),
+            optional("known_x", Array),
+            optional("new_x", Array),
+            optional("const", Bool),
+        ],
+        description: "Returns values along a least-squares exponential fit.",
+    },
+    // Probability distributions
+    FunctionSignature {
+        name: "NORM.DIST",
+        args: &[
+            arg("x", Number),
+            arg("mean", Number),
+            arg("standard_dev", Number),
+            arg("cumulative", Bool),
+        ],
+        description: "Returns the normal distribution at x, either cumulative or as a probability density.",
+    },
+    FunctionSignature {
+        name: "NORM.INV",
+        args: &[
+            arg("probability", Number),
+            arg("mean", Number),
+            arg("standard_dev", Number),
+        ],
+        description: "Returns the value at which the cumulative normal distribution reaches a probability.",
+    },
+    FunctionSignature {
+        name: "NORM.S.DIST",
+        args: &[arg("z", Number), arg("cumulative", Bool)],
+        description: "Returns the standard normal distribution at z, either cumulative or as a probability density.",
+    },
+    FunctionSignature {
+        name: "NORM.S.INV",
+        args: &[arg("probability", Number)],
+        description: "Returns the value at which the cumulative standard normal distribution reaches a probability.",
+    },
+    FunctionSignature {
+        name: "PHI",
+        args: &[arg("x", Number)],
+        description: "Returns the probability density of the standard normal distribution at x.",
+    },
+    FunctionSignature {
+        name: "GAUSS",
+        args: &[arg("z", Number)],
+        description: "Returns the probability that a standard normal variable is between the mean and z standard deviations from the mean.",
+    },
+    FunctionSignature {
+        name: "NORMDIST",
+        args: &[
+            arg("x", Number),
+            arg("mean", Number),
+            arg("standard_dev", Number),
+            arg("cumulative", Bool),
+        ],
+        description: "Same as NORM.DIST.",
+    },
+    FunctionSignature {
+        name: "NORMINV",
+        args: &[
+            arg("probability", Number),
+            arg("mean", Number),
+            arg("standard_dev", Number),
+        ],
+        description: "Same as NORM.INV.",
+    },
+    FunctionSignature {
+        name: "NORMSDIST",
+        args: &[arg("z", Number)],
+        description: "Returns the cumulative standard normal distribution at z.",
+    },
+    FunctionSignature {
+        name: "NORMSINV",
+        args: &[arg("probability", Number)],
+        description: "Same as NORM.S.INV.",
+    },
+    FunctionSignature {
+        name: "BINOM.DIST",
+        args: &[
+            arg("number_s", Number),
+            arg("trials", Number),
+            arg("probability_s", Number),
+            arg("cumulative", Bool),
+        ],
+        description: "Returns the probability of a number of successes in a number of independent trials, or of at most that many successes if cumulative is true.",
+    },
+    FunctionSignature {
+        name: "POISSON.DIST",
+        args: &[arg("x", Number), arg("mean", Number), arg("cumulative", Bool)],
+        description: "Returns the probability of a number of events in a Poisson distribution, or of at most that many events if cumulative is true.",
+    },
+    FunctionSignature {
+        name: "HYPGEOM.DIST",
+        args: &[
+            arg("sample_s", Number),
+            arg("number_sample", Number),
+            arg("population_s", Number),
+            arg("number_pop", Number),
+            arg("cumulative", Bool),
+        ],
+        description: "Returns the probability of a number of successes in a sample drawn without replacement from a population, or of at most that many successes if cumulative is true.",
+    },
+    FunctionSignature {
+        name: "EXPON.DIST",
+        args: &[arg("x", Number), arg("lambda", Number), arg("cumulative", Bool)],
+        description: "Returns the exponential distribution with rate lambda at x, either cumulative or as a probability density. x must not be negative, and lambda must be positive.",
+    },
+    FunctionSignature {
+        name: "GAMMALN",
+        args: &[arg("x", Number)],
+        description: "Returns the natural logarithm of the gamma function at x, which must be positive.",
+    },
+    FunctionSignature {
+        name: "GAMMALN.PRECISE",
+        args: &[arg("x", Number)],
+        description: "Same as GAMMALN.",
+    },
+    FunctionSignature {
+        name: "BINOMDIST",
+        args: &[
+            arg("number_s", Number),
+            arg("trials", Number),
+            arg("probability_s", Number),
+            arg("cumulative", Bool),
+        ],
+        description: "Same as BINOM.DIST.",
+    },
+    FunctionSignature {
+        name: "POISSON",
+        args: &[arg("x", Number), arg("mean", Number), arg("cumulative", Bool)],
+        description: "Same as POISSON.DIST.",
+    },
+    FunctionSignature {
+        name: "EXPONDIST",
+        args: &[arg("x", Number), arg("lambda", Number), arg("cumulative", Bool)],
+        description: "Same as EXPON.DIST.",
+    },
+    // Database functions
+    FunctionSignature {
+        name: "DSUM",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Adds the numbers in a field of the records that match the criteria.",
+    },
+    FunctionSignature {
+        name: "DAVERAGE",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Returns the average of the numbers in a field of the records that match the criteria.",
+    },
+    FunctionSignature {
+        name: "DCOUNT",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Counts the numbers in a field of the records that match the criteria.",
+    },
+    FunctionSignature {
+        name: "DMAX",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Returns the largest number in a field of the records that match the criteria.",
+    },
+    FunctionSignature {
+        name: "DMIN",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Returns the smallest number in a field of the records that match the criteria.",
+    },
+    FunctionSignature {
+        name: "DGET",
+        args: &[arg("database", Array), arg("field", Any), arg("criteria", Array)],
+        description: "Returns the value of a field in the only record that matches the criteria.",
+    },
+    // Date functions
+    FunctionSignature {
+        name: "DATE",
+        args: &[arg("year", Number), arg("month", Number), arg("day", Number)],
+        description: "Returns the date serial number of a year, month, and day.",
+    },
+    FunctionSignature {
+        name: "YEAR",
+        args: &[arg("date", Date)],
+        description: "Returns the year of a date.",
+    },
+    FunctionSignature {
+        name: "MONTH",
+        args: &[arg("date", Date)],
+        description: "Returns the month of a date, from 1 to 12.",
+    },
+    FunctionSignature {
+        name: "DAY",
+        args: &[arg("date", Date)],
+        description: "Returns the day of the month of a date, from 1 to 31.",
+    },
+    #[cfg(feature = "datetime")]
+    FunctionSignature {
+        name: "DATETIME",
+        args: &[arg("text", Text)],
+        description: "Converts ISO 8601 text with a date, time, and UTC offset, such as 2024-03-05T10:30:00+02:00, to a date-time.",
+    },
+    FunctionSignature {
+        name: "DATEVALUE",
+        args: &[arg("date_text", Text)],
+        description: "Converts text to a date serial number.",
+    },
+    FunctionSignature {
+        name: "TIMEVALUE",
+        args: &[arg("time_text", Text)],
+        description: "Converts text to a fraction of a day.",
+    },
+    FunctionSignature {
+        name: "WEEKNUM",
+        args: &[arg("date", Date), optional("return_type", Number)],
+        description: "Returns the week of the year.",
+    },
+    FunctionSignature {
+        name: "ISOWEEKNUM",
+        args: &[arg("date", Date)],
+        description: "Returns the ISO week of the year.",
+    },
+    FunctionSignature {
+        name: "EDATE",
+        args: &[arg("start_date", Date), arg("months", Number)],
+        description: "Returns the date a number of months away.",
+    },
+    FunctionSignature {
+        name: "EOMONTH",
+        args: &[arg("start_date", Date), arg("months", Number)],
+        description: "Returns the last day of the month a number of months away.",
+    },
+    FunctionSignature {
+        name: "WORKDAY",
+        args: &[
+            arg("start_date", Date),
+            arg("days", Number),
+            optional("holidays", Array),
+        ],
+        description: "Returns the date a number of workdays away.",
+    },
+    FunctionSignature {
+        name: "WORKDAY.INTL",
+        args: &[
+            arg("start_date", Date),
+            arg("days", Number),
+            optional("weekend", Any),
+            optional("holidays", Array),
+        ],
+        description: "Returns the date a number of workdays away, with a custom weekend.",
+    },
+    FunctionSignature {
+        name: "NETWORKDAYS",
+        args: &[
+            arg("start_date", Date),
+            arg("end_date", Date),
+            optional("holidays", Array),
+        ],
+        description: "Counts the workdays between two dates.",
+    },
+    FunctionSignature {
+        name: "NETWORKDAYS.INTL",
+        args: &[
+            arg("start_date", Date),
+            arg("end_date", Date),
+            optional("weekend", Any),
+            optional("holidays", Array),
+        ],
+        description: "Counts the workdays between two dates, with a custom weekend.",
+    },
+    FunctionSignature {
+        name: "DAYS",
+        args: &[arg("end_date", Date), arg("start_date", Date)],
+        description: "Returns the number of days between two dates.",
+    },
+    FunctionSignature {
+        name: "DAYS360",
+        args: &[
+            arg("start_date", Date),
+            arg("end_date", Date),
+            optional("european", Bool),
+        ],
+        description: "Returns the number of days between two dates in a 360-day year, using the US method or, if european is TRUE, the European method.",
+    },
+    FunctionSignature {
+        name: "YEARFRAC",
+        args: &[
+            arg("start_date", Date),
+            arg("end_date", Date),
+            optional("basis", Number),
+        ],
+        description: "Returns the number of years between two dates, using a day-count basis from 0 to 4: US 30/360, actual/actual, actual/360, actual/365, or European 30/360.",
+    },
+    // String functions
+    FunctionSignature {
+        name: "LEN",
+        args: &[arg("text", Text)],
+        description: "Returns the number of characters in a string.",
+    },
+    FunctionSignature {
+        name: "LEFT",
+        args: &[arg("text", Text), optional("n", Number)],
+        description: "Returns the first characters of a string.",
+    },
+    FunctionSignature {
+        name: "RIGHT",
+        args: &[arg("text", Text), optional("n", Number)],
+        description: "Returns the last characters of a string.",
+    },
+    FunctionSignature {
+        name: "MID",
+        args: &[arg("text", Text), arg("start", Number), arg("n", Number)],
+        description: "Returns characters from the middle of a string.",
+    },
+    FunctionSignature {
+        name: "SUBSTITUTE",
+        args: &[
+            arg("text", Text),
+            arg("old_text", Text),
+            arg("new_text", Text),
+            optional("instance_num", Number),
+        ],
+        description: "Replaces every occurrence of some text in a string, or only the given occurrence.",
+    },
+    FunctionSignature {
+        name: "REPLACE",
+        args: &[
+            arg("text", Text),
+            arg("start", Number),
+            arg("n", Number),
+            arg("new_text", Text),
+        ],
+        description: "Replaces characters at a position in a string.",
+    },
+    FunctionSignature {
+        name: "PROPER",
+        args: &[arg("text", Text)],
+        description: "Capitalizes the first letter of each word in a string.",
+    },
+    FunctionSignature {
+        name: "EXACT",
+        args: &[arg("text1", Text), arg("text2", Text)],
+        description: "Returns whether two strings are identical, including case. Unlike the = operator, this is case-sensitive, and numbers are compared by how they are written as text.",
+    },
+    FunctionSignature {
+        name: "CONCAT",
+        args: &[variadic("text", Text, true)],
+        description: "Joins the values into one string.",
+    },
+    FunctionSignature {
+        name: "REPT",
+        args: &[arg("text", Text), arg("number_times", Number)],
+        description: "Repeats text a given number of times.",
+    },
+    FunctionSignature {
+        name: "TEXT",
+        args: &[arg("value", Any), arg("format", Text)],
+        description: "Formats a value as text using a number format.",
+    },
+    FunctionSignature {
+        name: "DOLLAR",
+        args: &[arg("number", Number), optional("decimals", Number)],
+        description: "Formats a number as currency text, rounded to a number of decimal places.",
+    },
+    FunctionSignature {
+        name: "FIXED",
+        args: &[
+            arg("number", Number),
+            optional("decimals", Number),
+            optional("no_commas", Bool),
+        ],
+        description: "Formats a number as text, rounded to a number of decimal places.",
+    },
+    FunctionSignature {
+        name: "NUMBERVALUE",
+        args: &[
+            arg("text", Text),
+            optional("decimal_separator", Text),
+            optional("group_separator", Text),
+        ],
+        description: "Converts text to a number using the given decimal and group separators, which default to \".\" and \",\", regardless of locale. Whitespace is ignored, and each trailing percent sign divides the number by 100.",
+    },
+    FunctionSignature {
+        name: "TEXTSPLIT",
+        args: &[
+            arg("text", Text),
+            arg("col_delimiter", Text),
+            optional("row_delimiter", Text),
+            optional("ignore_empty", Bool),
+        ],
+        description: "Splits text into columns, and optionally rows, at each delimiter.",
+    },
+    FunctionSignature {
+        name: "ARRAYTOTEXT",
+        args: &[arg("array", Array), optional("format", Number)],
+        description: "Returns an array as text, either concise or as an array literal.",
+    },
+    FunctionSignature {
+        name: "VALUETOTEXT",
+        args: &[arg("value", Any), optional("format", Number)],
+        description: "Returns a value as text, either concise or as a literal.",
+    },
+    FunctionSignature {
+        name: "ENCODEURL",
+        args: &[arg("text", Text)],
+        description: "Percent-encodes text for use in a URL.",
+    },
+    FunctionSignature {
+        name: "REGEXMATCH",
+        args: &[arg("text", Text), arg("pattern", Text)],
+        description: "Returns whether the text contains a match of a regular expression.",
+    },
+    FunctionSignature {
+        name: "REGEXEXTRACT",
+        args: &[
+            arg("text", Text),
+            arg("pattern", Text),
+            optional("return_mode", Number),
+        ],
+        description: "Returns the first match of a regular expression in the text, or the text of each of its capture groups. With a return mode of 1, returns a column of every match.",
+    },
+    FunctionSignature {
+        name: "REGEXREPLACE",
+        args: &[
+            arg("text", Text),
+            arg("pattern", Text),
+            arg("replacement", Text),
+        ],
+        description: "Replaces every match of a regular expression in the text. In the replacement, $1 is the text of the first capture group.",
+    },
+    FunctionSignature {
+        name: "CODE",
+        args: &[arg("text", Text)],
+        description: "Returns the ASCII code of the first character of the text.",
+    },
+    FunctionSignature {
+        name: "UNICODE",
+        args: &[arg("text", Text)],
+        description: "Returns the Unicode code point of the first character of the text.",
+    },
+    FunctionSignature {
+        name: "CHAR",
+        args: &[arg("number", Number)],
+        description: "Returns the character with an ASCII code.",
+    },
+    FunctionSignature {
+        name: "UNICHAR",
+        args: &[arg("number", Number)],
+        description: "Returns the character with a Unicode code point.",
+    },
+    // Engineering functions
+    FunctionSignature {
+        name: "BIN2OCT",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a binary number to octal.",
+    },
+    FunctionSignature {
+        name: "BIN2DEC",
+        args: &[arg("number", Text)],
+        description: "Converts a binary number to decimal.",
+    },
+    FunctionSignature {
+        name: "BIN2HEX",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a binary number to hexadecimal.",
+    },
+    FunctionSignature {
+        name: "OCT2BIN",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a octal number to binary.",
+    },
+    FunctionSignature {
+        name: "OCT2DEC",
+        args: &[arg("number", Text)],
+        description: "Converts a octal number to decimal.",
+    },
+    FunctionSignature {
+        name: "OCT2HEX",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a octal number to hexadecimal.",
+    },
+    FunctionSignature {
+        name: "DEC2BIN",
+        args: &[arg("number", Number), optional("places", Number)],
+        description: "Converts a decimal number to binary.",
+    },
+    FunctionSignature {
+        name: "DEC2OCT",
+        args: &[arg("number", Number), optional("places", Number)],
+        description: "Converts a decimal number to octal.",
+    },
+    FunctionSignature {
+        name: "DEC2HEX",
+        args: &[arg("number", Number), optional("places", Number)],
+        description: "Converts a decimal number to hexadecimal.",
+    },
+    FunctionSignature {
+        name: "HEX2BIN",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a hexadecimal number to binary.",
+    },
+    FunctionSignature {
+        name: "HEX2OCT",
+        args: &[arg("number", Text), optional("places", Number)],
+        description: "Converts a hexadecimal number to octal.",
+    },
+    FunctionSignature {
+        name: "HEX2DEC",
+        args: &[arg("number", Text)],
+        description: "Converts a hexadecimal number to decimal.",
+    },
+    FunctionSignature {
+        name: "BITAND",
+        args: &[arg("number1", Number), arg("number2", Number)],
+        description: "Returns the bitwise AND of two numbers.",
+    },
+    FunctionSignature {
+        name: "BITOR",
+        args: &[arg("number1", Number), arg("number2", Number)],
+        description: "Returns the bitwise OR of two numbers.",
+    },
+    FunctionSignature {
+        name: "BITXOR",
+        args: &[arg("number1", Number), arg("number2", Number)],
+        description: "Returns the bitwise XOR of two numbers.",
+    },
+    FunctionSignature {
+        name: "BITLSHIFT",
+        args: &[arg("number", Number), arg("shift_amount", Number)],
+        description: "Shifts the bits of a number to the left.",
+    },
+    FunctionSignature {
+        name: "BITRSHIFT",
+        args: &[arg("number", Number), arg("shift_amount", Number)],
+        description: "Shifts the bits of a number to the right.",
+    },
+    FunctionSignature {
+        name: "CONVERT",
+        args: &[arg("number", Number), arg("from_unit", Text), arg("to_unit", Text)],
+        description: "Converts a number from one unit of measurement to another, such as from \"mi\" to \"km\".",
+    },
+    FunctionSignature {
+        name: "COMPLEX",
+        args: &[
+            arg("real", Number),
+            arg("imaginary", Number),
+            optional("suffix", Text),
+        ],
+        description: "Returns a complex number written as text, such as 3+4i.",
+    },
+    FunctionSignature {
+        name: "IMREAL",
+        args: &[arg("number", Text)],
+        description: "Returns the real part of a complex number.",
+    },
+    FunctionSignature {
+        name: "IMAGINARY",
+        args: &[arg("number", Text)],
+        description: "Returns the imaginary part of a complex number.",
+    },
+    FunctionSignature {
+        name: "IMABS",
+        args: &[arg("number", Text)],
+        description: "Returns the magnitude of a complex number.",
+    },
+    FunctionSignature {
+        name: "IMSUM",
+        args: &[variadic("numbers", Any, false)],
+        description: "Adds complex numbers together.",
+    },
+    FunctionSignature {
+        name: "IMPRODUCT",
+        args: &[variadic("numbers", Any, false)],
+        description: "Multiplies complex numbers together.",
+    },
+    // Cell references
+    FunctionSignature {
+        name: "INDEX",
+        args: &[
+            arg("array", Array),
+            arg("row", Number),
+            optional("column", Number),
+        ],
+        description: "Returns the value or cell at a position in an array or range.",
+    },
+    FunctionSignature {
+        name: "XMATCH",
+        args: &[
+            arg("lookup_value", Any),
+            arg("lookup_array", Array),
+            optional("match_mode", Number),
+            optional("search_mode", Number),
+        ],
+        description: "Returns the position of a value in a row or column. match_mode is 0 for exact, -1 or 1 for exact or next smaller or larger, or 2 for wildcards; search_mode is 1 or -1 to search from the first or last value, or 2 or -2 for a binary search of values sorted ascending or descending.",
+    },
+    FunctionSignature {
+        name: "XLOOKUP",
+        args: &[
+            arg("lookup_value", Any),
+            arg("lookup_array", Array),
+            arg("return_array", Array),
+            optional("if_not_found", Any),
+            optional("match_mode", Number),
+            optional("search_mode", Number),
+        ],
+        description: "Searches a row or column for a value and returns the corresponding row or column of another array. The modes are the same as for XMATCH.",
+    },
+    FunctionSignature {
+        name: "MATCH",
+        args: &[
+            arg("lookup_value", Any),
+            arg("lookup_array", Array),
+            optional("match_type", Number),
+        ],
+        description: "Returns the 1-based position of a value in a row or column. If match_type is 1 (the default), finds the largest value less than or equal to lookup_value in values sorted in ascending order; if 0, finds an exact match, with wildcards; if -1, finds the smallest value greater than or equal to lookup_value in values sorted in descending order. Returns an error if there is no match.",
+    },
+    FunctionSignature {
+        name: "SORT",
+        args: &[
+            arg("array", Array),
+            optional("sort_index", Number),
+            optional("sort_order", Number),
+            optional("by_col", Bool),
+        ],
+        description: "Sorts the rows of an array by the values in column sort_index (1 by default), or its columns by a row if by_col is true. sort_order is 1 for ascending (the default) or -1 for descending. Numbers come before text, which comes before booleans, errors, and blanks, and text is compared ignoring case. Rows with equal values keep their original order, and blanks are always last.",
+    },
+    FunctionSignature {
+        name: "UNIQUE",
+        args: &[
+            arg("array", Array),
+            optional("by_col", Bool),
+            optional("exactly_once", Bool),
+        ],
+        description: "Returns the distinct rows of an array in the order they first appear, or its distinct columns if by_col is true. If exactly_once is true, only returns the rows that appear exactly once. Text is compared ignoring case.",
+    },
+    FunctionSignature {
+        name: "WRAPROWS",
+        args: &[
+            arg("vector", Array),
+            arg("wrap_count", Number),
+            optional("pad_with", Any),
+        ],
+        description: "Splits a row or column into rows of wrap_count values each, filling the last row with pad_with, or #N/A by default.",
+    },
+    FunctionSignature {
+        name: "WRAPCOLS",
+        args: &[
+            arg("vector", Array),
+            arg("wrap_count", Number),
+            optional("pad_with", Any),
+        ],
+        description: "Splits a row or column into columns of wrap_count values each, filling the last column with pad_with, or #N/A by default.",
+    },
+    FunctionSignature {
+        name: "TOCOL",
+        args: &[
+            arg("array", Array),
+            optional("ignore", Number),
+            optional("scan_by_col", Bool),
+        ],
+        description: "Returns the values of an array as a single column, reading it row by row, or column by column if scan_by_col is true. If ignore is 1, leaves out blanks; if 2, leaves out errors; if 3, leaves out both.",
+    },
+    FunctionSignature {
+        name: "TOROW",
+        args: &[
+            arg("array", Array),
+            optional("ignore", Number),
+            optional("scan_by_col", Bool),
+        ],
+        description: "Returns the values of an array as a single row, reading it row by row, or column by column if scan_by_col is true. If ignore is 1, leaves out blanks; if 2, leaves out errors; if 3, leaves out both.",
+    },
+    FunctionSignature {
+        name: "CELL",
+        args: &[arg("x", Number), arg("y", Number)],
+        description: "Returns the value of the cell at the given coordinates.",
+    },
+    FunctionSignature {
+        name: "C",
+        args: &[arg("x", Number), arg("y", Number)],
+        description: "Same as CELL.",
+    },
+    FunctionSignature {
+        name: "ISFORMULA",
+        args: &[arg("reference", Any)],
+        description: "Returns whether a cell contains a formula.",
+    },
+    FunctionSignature {
+        name: "FORMULATEXT",
+        args: &[arg("reference", Any)],
+        description: "Returns the formula in a cell as text.",
+    },
+];
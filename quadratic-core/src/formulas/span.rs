@@ -33,6 +33,14 @@ impl Span {
     pub fn of_str(self, s: &str) -> &str {
         &s[self.start..self.end]
     }
+    /// Returns the 0-indexed line number of the start of the span in a
+    /// string, and the byte index of the start within that line.
+    pub fn line_col(self, s: &str) -> (usize, usize) {
+        let before = &s[..self.start];
+        let line = before.matches('\n').count();
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, self.start - line_start)
+    }
 }
 impl<T> From<Spanned<T>> for Span {
     fn from(spanned: Spanned<T>) -> Self {
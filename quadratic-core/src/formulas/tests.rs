@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use smallvec::smallvec;
+use itertools::Itertools;
 
 use super::*;
 
@@ -35,7 +35,7 @@ fn test_formula_indirect() {
 
     assert_eq!(
         FormulaErrorMsg::CircularReference,
-        form.eval_blocking(&mut GridMock, Pos::new(3, 5))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(3, 5)))
             .unwrap_err()
             .msg,
     );
@@ -46,6 +46,74 @@ fn test_formula_indirect() {
     );
 }
 
+#[test]
+fn test_formula_custom_functions() {
+    let mut custom = CustomFunctions::new();
+    assert!(custom.register("DOUBLE", 1, Some(1), |args| {
+        let x = args[0].to_number()?;
+        if x < 0.0 {
+            return Err(FormulaErrorMsg::InvalidArgument.with_span(&args[0]));
+        }
+        Ok(Value::Number(x * 2.0))
+    }));
+    assert!(custom.register("fail", 0, None, |_| {
+        Err(FormulaErrorMsg::NotAvailable.into())
+    }));
+    // Built-in functions can't be replaced.
+    assert!(!custom.register("sum", 0, None, |_| Ok(Value::Number(0.0))));
+    assert!(!custom.register("Cell", 2, Some(2), |_| Ok(Value::Number(0.0))));
+    assert!(custom.contains("double"));
+    assert!(!custom.contains("sum"));
+
+    let eval_custom = |s: &str| {
+        let mut grid = PanicGridMock;
+        let mut ctx = Ctx::new(&mut grid, Pos::ORIGIN);
+        ctx.custom_functions = Some(&custom);
+        parse_formula(s, Pos::ORIGIN)
+            .unwrap()
+            .eval_blocking(&mut ctx)
+    };
+    assert_eq!("14", eval_custom("double(7)").unwrap().to_string());
+    assert_eq!("15", eval_custom("SUM(DOUBLE(3), 9)").unwrap().to_string());
+    assert_eq!("6", eval_custom("SUM(1, 2, 3)").unwrap().to_string());
+
+    // Errors with a span keep it, and others get the function call's span.
+    let err = eval_custom("1 + DOUBLE(-2)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::InvalidArgument, err.msg);
+    assert_eq!(Some(Span { start: 11, end: 13 }), err.span);
+    let err = eval_custom("1 + FAIL(1, 2)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::NotAvailable, err.msg);
+    assert_eq!(Some(Span { start: 4, end: 14 }), err.span);
+    let err = eval_custom("DOUBLE(1, 2)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadArgumentCount, err.msg);
+    assert_eq!(Some(Span { start: 0, end: 12 }), err.span);
+
+    // Without custom functions, the name is unknown.
+    assert_eq!(
+        FormulaErrorMsg::BadFunctionName,
+        eval(&mut PanicGridMock, "DOUBLE(7)").unwrap_err().msg,
+    );
+
+    // Unknown names suggest a similar built-in or custom function.
+    for (src, suggestion) in [
+        ("DUBLE(7)", Some("DOUBLE")),
+        ("doubel(7)", Some("DOUBLE")),
+        ("SUMM(1, 2)", Some("SUM")),
+        ("averge(1)", Some("AVERAGE")),
+        ("XYZZY(1)", None),
+    ] {
+        let err = eval_custom(src).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadFunctionName, err.msg, "{src}");
+        assert_eq!(suggestion, err.suggestion.as_deref(), "{src}");
+    }
+    assert_eq!(
+        "column 4 to 10: There is no function with this name (did you mean DOUBLE?)",
+        eval_custom("1 + DUBLE(7)").unwrap_err().to_string(),
+    );
+    let err = eval(&mut PanicGridMock, "DUBLE(7)").unwrap_err();
+    assert_eq!(None, err.suggestion);
+}
+
 #[test]
 fn test_formula_cell_ref() {
     let form = parse_formula("SUM($D$4, $B0, E$n6, B0, nB2)", Pos::new(3, 4)).unwrap();
@@ -64,7 +132,7 @@ fn test_formula_cell_ref() {
     // Evaluate at C4, causing a circular reference.
     assert_eq!(
         FormulaErrorMsg::CircularReference,
-        form.eval_blocking(&mut GridMock, Pos::new(3, 4))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(3, 4)))
             .unwrap_err()
             .msg,
     );
@@ -72,7 +140,7 @@ fn test_formula_cell_ref() {
     // Evaluate at A2
     assert_eq!(
         "11111".to_string(),
-        form.eval_blocking(&mut GridMock, Pos::new(1, 2))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(1, 2)))
             .unwrap()
             .to_string(),
     );
@@ -92,18 +160,79 @@ fn test_formula_circular_array_ref() {
 
     assert_eq!(
         FormulaErrorMsg::CircularReference,
-        form.eval_blocking(&mut GridMock, Pos::new(1, 2))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(1, 2)))
             .unwrap_err()
             .msg,
     )
 }
 
 #[test]
+#[allow(clippy::identity_op)]
 fn test_formula_math_operators() {
     assert_eq!(
         (1 * -6 + -2 - 1 * (-3_i32).pow(2_u32.pow(3))).to_string(),
         eval_to_string(&mut PanicGridMock, "1 * -6 + -2 - 1 * -3 ^ 2 ^ 3"),
     );
+    assert_eq!("-4", eval_to_string(&mut PanicGridMock, "1 - 2 - 3"));
+    assert_eq!("2", eval_to_string(&mut PanicGridMock, "12 / 3 / 2"));
+    assert_eq!("TRUE", eval_to_string(&mut PanicGridMock, "1 + 2 * 3 = 7"));
+}
+
+#[test]
+fn test_operator_precedence() {
+    make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
+
+    for (source, expected_ast, expected_value) in [
+        ("1+2&3*4", "&(+(1.0, 2.0), *(3.0, 4.0))", "312"),
+        ("1&2+3", "&(1.0, +(2.0, 3.0))", "15"),
+        ("1+2=3", "=(+(1.0, 2.0), 3.0)", "TRUE"),
+        ("'a'&1='a1'", "=(&(\"a\", 1.0), \"a1\")", "TRUE"),
+        // `^` is right-associative.
+        ("2^3^2", "^(2.0, ^(3.0, 2.0))", "512"),
+        ("2^-1^2", "^(2.0, ^(-(1.0), 2.0))", "2"),
+        // Everything else is left-associative, including comparisons.
+        ("1-2-3", "-(-(1.0, 2.0), 3.0)", "-4"),
+        ("8/4/2", "/(/(8.0, 4.0), 2.0)", "1"),
+        // Booleans are greater than all numbers, so `TRUE` is not less than 3.
+        ("1<2<3", "<(<(1.0, 2.0), 3.0)", "FALSE"),
+        ("3>2>1", ">(>(3.0, 2.0), 1.0)", "TRUE"),
+        ("1=1=TRUE()", "=(=(1.0, 1.0), TRUE())", "TRUE"),
+        // Negation binds tighter than `^`, and `%` is in between.
+        ("-2^2", "^(-(2.0), 2.0)", "4"),
+        ("2^-1", "^(2.0, -(1.0))", "0.5"),
+        ("--2", "-(-(2.0))", "2"),
+        ("-2%", "%(-(2.0))", "-0.02"),
+        ("2*-3%", "*(2.0, %(-(3.0)))", "-0.06"),
+        ("10%^2", "^(%(10.0), 2.0)", "0.010000000000000002"),
+        ("2^300%", "^(2.0, %(300.0))", "8"),
+        // The cell range operator binds tighter than everything but `#`.
+        ("-A1:A2", "-(:(R[1]C[0], R[2]C[0]))", "{-1; -2}"),
+        ("A1:A2*2", "*(:(R[1]C[0], R[2]C[0]), 2.0)", "{2; 4}"),
+        (
+            "A1:A2:B3",
+            ":(:(R[1]C[0], R[2]C[0]), R[3]C[1])",
+            "{1, 11; 2, 12; 3, 13}",
+        ),
+    ] {
+        let formula = parse_formula(source, Pos::ORIGIN).unwrap();
+        assert_eq!(expected_ast, formula.to_string(), "{source:?}");
+        assert_eq!(
+            expected_value,
+            eval_to_string(&mut GridMock, source),
+            "{source:?}"
+        );
+    }
+
+    // A prefix operator can't appear after `:`.
+    assert!(parse_formula("A1:-A2", Pos::ORIGIN).is_err());
+    assert_eq!(
+        ":(R[1]C[0], #(R[2]C[1]))",
+        parse_formula("A1:B2#", Pos::ORIGIN).unwrap().to_string(),
+    );
+    assert_eq!(
+        "+(@(:(R[1]C[0], R[2]C[0])), 1.0)",
+        parse_formula("@A1:A2+1", Pos::ORIGIN).unwrap().to_string(),
+    );
 }
 
 #[test]
@@ -114,6 +243,24 @@ fn test_formula_concat() {
     );
 }
 
+#[test]
+fn test_formula_exact() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("FALSE", eval_to_string(g, "EXACT(\"A\", \"a\")"));
+    assert_eq!("TRUE", eval_to_string(g, "\"A\" = \"a\""));
+    assert_eq!("TRUE", eval_to_string(g, "EXACT('word', 'word')"));
+    assert_eq!("FALSE", eval_to_string(g, "EXACT('word', 'word ')"));
+    // Numbers are compared as text.
+    assert_eq!("TRUE", eval_to_string(g, "EXACT(1.50, '1.5')"));
+    assert_eq!("FALSE", eval_to_string(g, "EXACT(1, '1.0')"));
+    assert_eq!("TRUE", eval_to_string(g, "1 = '1.0'"));
+    assert_eq!(
+        "{TRUE, FALSE}",
+        eval_to_string(g, "EXACT({'a', 'b'}, {'a', 'B'})"),
+    );
+}
+
 #[test]
 fn test_formula_if() {
     let form = parse_formula("IF(A1=2, 'yep', 'nope')", Pos::new(0, 0)).unwrap();
@@ -126,18 +273,79 @@ fn test_formula_if() {
 
     assert_eq!(
         "yep".to_string(),
-        form.eval_blocking(&mut GridMock, Pos::new(0, 0))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(0, 0)))
             .unwrap()
             .to_string(),
     );
     assert_eq!(
         "nope".to_string(),
-        form.eval_blocking(&mut GridMock, Pos::new(1, 0))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(1, 0)))
             .unwrap()
             .to_string(),
     );
 }
 
+#[test]
+fn test_short_circuit() {
+    let g = &mut PanicGridMock;
+
+    // Errors and cell references in arguments that aren't needed are never
+    // evaluated.
+    assert_eq!("TRUE", eval_to_string(g, "OR(TRUE(), 1/0)"));
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, "OR(FALSE(), {FALSE(), TRUE()}, A1, NA())")
+    );
+    assert_eq!("FALSE", eval_to_string(g, "AND(FALSE(), 1/0)"));
+    assert_eq!(
+        "FALSE",
+        eval_to_string(g, "AND(TRUE(), {TRUE(), FALSE()}, A1:B2)")
+    );
+    assert_eq!("yes", eval_to_string(g, "IF(1 < 2, 'yes', 1/0)"));
+    assert_eq!("no", eval_to_string(g, "IF(1 > 2, A1, 'no')"));
+    assert_eq!("1", eval_to_string(g, "IF(TRUE(), 1, IF(A1, 2, 3))"));
+
+    // Arguments before the deciding one are still evaluated.
+    for src in [
+        "OR(#DIV/0!, TRUE())",
+        "AND(NA(), FALSE())",
+        "OR(FALSE(), NA())",
+    ] {
+        assert!(eval(g, src).is_err(), "{src}");
+    }
+    assert_eq!("#DIV/0!", eval_to_string(g, "IF(1 < 2, #DIV/0!, 'no')"));
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, "AND(TRUE(), 'true', {TRUE(), TRUE()})")
+    );
+    assert_eq!("FALSE", eval_to_string(g, "OR(FALSE(), 'false', '')"));
+    assert_eq!(
+        FormulaErrorMsg::TypeMismatch {
+            func: "AND".into(),
+            arg_index: 1,
+            expected: ValueType::Boolean,
+            got: ValueType::Text,
+        },
+        eval(g, "AND(TRUE(), 'x', FALSE())").unwrap_err().msg,
+    );
+
+    // An array condition chooses each value separately, so both values are
+    // evaluated.
+    assert_eq!(
+        "{1, no}",
+        eval_to_string(g, "IF({TRUE(), FALSE()}, 1, 'no')")
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "IF({TRUE(), TRUE()}, 1, NA(1))").unwrap_err().msg,
+    );
+    assert_eq!("1", eval_to_string(g, "IF(TRUE(), 1, NA(1))"));
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "IF(TRUE(), 1)").unwrap_err().msg,
+    );
+}
+
 #[test]
 fn test_formula_average() {
     let form = parse_formula("AVERAGE(3, B1:D3)", Pos::new(-1, -1)).unwrap();
@@ -152,12 +360,116 @@ fn test_formula_average() {
 
     assert_eq!(
         "7.5".to_string(),
-        form.eval_blocking(&mut GridMock, Pos::new(-1, -1))
+        form.eval_blocking(&mut Ctx::new(&mut GridMock, Pos::new(-1, -1)))
             .unwrap()
             .to_string(),
     );
 }
 
+#[test]
+fn test_formula_sumsq_devsq_avedev_means() {
+    let g = &mut PanicGridMock;
+
+    // The mean is 5.
+    let data = "{2, 4, 4; 4, 5, 5}, 7, '9'";
+    assert_eq!("232", eval_to_string(g, &format!("SUMSQ({data})")));
+    assert_eq!("32", eval_to_string(g, &format!("DEVSQ({data})")));
+    assert_eq!("1.5", eval_to_string(g, &format!("AVEDEV({data})")));
+
+    assert_eq!("2", eval_to_string(g, "ROUND(GEOMEAN(1, 2, 4), 10)"));
+    assert_eq!("4", eval_to_string(g, "ROUND(GEOMEAN({2, 8}), 10)"));
+    assert_eq!("3", eval_to_string(g, "ROUND(HARMEAN(2, 6), 10)"));
+    assert_eq!(
+        "1.7142857143",
+        eval_to_string(g, "ROUND(HARMEAN({1, 2, 4}), 10)"),
+    );
+
+    // Non-numeric values in arrays are ignored, but errors are not.
+    let data = "{1, 'x', ''; TRUE(), 3, 'abc'}";
+    assert_eq!("10", eval_to_string(g, &format!("SUMSQ({data})")));
+    assert_eq!("2", eval_to_string(g, &format!("DEVSQ({data})")));
+    assert_eq!("1", eval_to_string(g, &format!("AVEDEV({data})")));
+    assert_eq!("3", eval_to_string(g, "ROUND(GEOMEAN({1, 'x', 9}), 10)"));
+    assert_eq!("1.5", eval_to_string(g, "HARMEAN({1, 'x', 3})"));
+    for f in ["SUMSQ", "DEVSQ", "AVEDEV", "GEOMEAN", "HARMEAN"] {
+        let src = format!("{f}({{1, NA(), 3}})");
+        assert_eq!(
+            FormulaErrorMsg::NotAvailable,
+            eval(g, &src).unwrap_err().msg
+        );
+    }
+
+    // Non-positive values
+    for src in [
+        "GEOMEAN(1, 0)",
+        "GEOMEAN(2, -2)",
+        "HARMEAN(1, -1)",
+        "HARMEAN(-1, 0)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::BadNumber,
+            eval(g, src).unwrap_err().msg,
+            "{src}"
+        );
+    }
+    assert_eq!(
+        FormulaErrorMsg::DivideByZero,
+        eval(g, "HARMEAN(1, 0)").unwrap_err().msg,
+    );
+
+    // No numbers
+    assert_eq!("0", eval_to_string(g, "SUMSQ({'a', ''})"));
+    for f in ["DEVSQ", "AVEDEV", "GEOMEAN", "HARMEAN"] {
+        let src = format!("{f}({{'a', ''}})");
+        assert_eq!(
+            FormulaErrorMsg::DivideByZero,
+            eval(g, &src).unwrap_err().msg
+        );
+    }
+}
+
+#[test]
+fn test_formula_frequency() {
+    make_stateless_grid_mock!(|pos| {
+        const SCORES: [&str; 10] = ["79", "85", "78", "85", "50", "81", "95", "88", "97", "n/a"];
+        (pos.x == 1).then(|| {
+            SCORES
+                .get(usize::try_from(pos.y - 1).ok()?)
+                .map(|s| s.to_string())
+        })?
+    });
+    let g = &mut GridMock;
+
+    // The last count is of the scores above every bin.
+    assert_eq!(
+        "{1; 2; 4; 2}",
+        eval_to_string(g, "FREQUENCY(B1:B10, {70, 79, 89})"),
+    );
+    // Bins don't need to be sorted, and the counts are in the same order.
+    assert_eq!(
+        "{4; 1; 2; 2}",
+        eval_to_string(g, "FREQUENCY(B1:B10, {89; 70; 79})"),
+    );
+    // A repeated bin only counts numbers once.
+    assert_eq!(
+        "{3; 0; 6}",
+        eval_to_string(g, "FREQUENCY(B1:B10, {80, 80})"),
+    );
+    assert_eq!("{0; 9}", eval_to_string(g, "FREQUENCY(B1:B10, 0)"));
+    assert_eq!("{9}", eval_to_string(g, "FREQUENCY(B1:B10, '')"));
+    assert_eq!(
+        "{1; 0; 1}",
+        eval_to_string(g, "FREQUENCY({1, 'x', 3}, {1, 2})")
+    );
+
+    let err = eval(g, "FREQUENCY(B1:B10, {70, 'high'})").unwrap_err();
+    assert_eq!(FormulaErrorMsg::NotANumber("\"high\"".into()), err.msg);
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "FREQUENCY({1, NA()}, 1)").unwrap_err().msg,
+    );
+}
+
 #[test]
 fn test_formula_array_op() {
     make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
@@ -168,30 +480,33 @@ fn test_formula_array_op() {
 
     assert_eq!((11 * 31).to_string(), eval_to_string(&mut g, "B1 * D1"));
     assert_eq!(
-        Value::Array(vec![
-            smallvec![f(11 * 31), f(21 * 31)],
-            smallvec![f(12 * 31), f(22 * 31)],
-            smallvec![f(13 * 31), f(23 * 31)],
-            smallvec![f(14 * 31), f(24 * 31)],
-        ]),
+        Value::array(vec![
+            vec![f(11 * 31), f(21 * 31)],
+            vec![f(12 * 31), f(22 * 31)],
+            vec![f(13 * 31), f(23 * 31)],
+            vec![f(14 * 31), f(24 * 31)],
+        ])
+        .unwrap(),
         eval(&mut g, "B1:C4 * D1").unwrap(),
     );
     assert_eq!(
-        Value::Array(vec![
-            smallvec![f(11 * 31), f(11 * 41)],
-            smallvec![f(11 * 32), f(11 * 42)],
-            smallvec![f(11 * 33), f(11 * 43)],
-            smallvec![f(11 * 34), f(11 * 44)],
-        ]),
+        Value::array(vec![
+            vec![f(11 * 31), f(11 * 41)],
+            vec![f(11 * 32), f(11 * 42)],
+            vec![f(11 * 33), f(11 * 43)],
+            vec![f(11 * 34), f(11 * 44)],
+        ])
+        .unwrap(),
         eval(&mut g, "B1 * D1:E4").unwrap(),
     );
     assert_eq!(
-        Value::Array(vec![
-            smallvec![f(11 * 31), f(21 * 41)],
-            smallvec![f(12 * 32), f(22 * 42)],
-            smallvec![f(13 * 33), f(23 * 43)],
-            smallvec![f(14 * 34), f(24 * 44)],
-        ]),
+        Value::array(vec![
+            vec![f(11 * 31), f(21 * 41)],
+            vec![f(12 * 32), f(22 * 42)],
+            vec![f(13 * 33), f(23 * 43)],
+            vec![f(14 * 34), f(24 * 44)],
+        ])
+        .unwrap(),
         eval(&mut g, "B1:C4 * D1:E4").unwrap(),
     );
     assert_eq!(
@@ -204,11 +519,12 @@ fn test_formula_array_op() {
 fn test_array_parsing() {
     let f = |x| Value::Number(x as f64);
     assert_eq!(
-        Value::Array(vec![
-            smallvec![f(11), f(12)],
-            smallvec![f(21), f(22)],
-            smallvec![f(31), f(32)],
-        ]),
+        Value::array(vec![
+            vec![f(11), f(12)],
+            vec![f(21), f(22)],
+            vec![f(31), f(32)],
+        ])
+        .unwrap(),
         eval(&mut PanicGridMock, "{11, 12; 21, 22; 31, 32}").unwrap(),
     );
 
@@ -258,12 +574,20 @@ fn test_hyphen_after_cell_ref() {
     assert_eq!("25", eval_to_string(&mut GridMock, "Z1-5"));
 }
 
-fn eval_to_string(grid: &mut impl GridProxy, s: &str) -> String {
+fn eval_to_string(grid: &mut dyn GridProxy, s: &str) -> String {
     eval(grid, s).unwrap().to_string()
 }
-fn eval(grid: &mut impl GridProxy, s: &str) -> FormulaResult<Value> {
+fn eval(grid: &mut dyn GridProxy, s: &str) -> FormulaResult<Value> {
+    eval_with_settings(grid, s, EvalSettings::default())
+}
+fn eval_with_settings(
+    grid: &mut dyn GridProxy,
+    s: &str,
+    settings: EvalSettings,
+) -> FormulaResult<Value> {
+    let mut ctx = Ctx::with_settings(grid, Pos::ORIGIN, settings);
     parse_formula(s, Pos::ORIGIN)?
-        .eval_blocking(grid, Pos::ORIGIN)
+        .eval_blocking(&mut ctx)
         .map(|value| value.inner)
 }
 
@@ -272,3 +596,5073 @@ fn eval(grid: &mut impl GridProxy, s: &str) -> FormulaResult<Value> {
 fn test_currency_string() {
     assert_eq!("30", eval_to_string(&mut PanicGridMock, "\"$10\" + 20"));
 }
+
+#[test]
+fn test_eval_step_limit() {
+    make_stateless_grid_mock!(|_| None);
+
+    // A huge range should fail before fetching any cells.
+    assert_eq!(
+        FormulaErrorMsg::EvaluationLimitExceeded {
+            limit: DEFAULT_MAX_EVAL_STEPS,
+        },
+        eval(&mut PanicGridMock, "SUM(A1:ZZZ999999)")
+            .unwrap_err()
+            .msg,
+    );
+
+    // Small formulas are fine with a small limit ...
+    let settings = EvalSettings {
+        max_eval_steps: 8,
+        ..Default::default()
+    };
+    assert_eq!(
+        Value::Number(3.0),
+        eval_with_settings(&mut GridMock, "SUM(A1:B2) + 3", settings).unwrap(),
+    );
+    // ... until they are not.
+    let err = eval_with_settings(&mut GridMock, "SUM(A1:B3) + 3", settings).unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::EvaluationLimitExceeded { limit: 8 },
+        err.msg,
+    );
+    assert_eq!(
+        "Formula is too complex (exceeded limit of 8 steps)",
+        err.msg.to_string(),
+    );
+}
+
+#[test]
+fn test_array_size_limit() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("{1, 2, 3; 4, 5, 6}", eval_to_string(g, "SEQUENCE(2, 3)"));
+    assert_eq!("{10; 8; 6}", eval_to_string(g, "SEQUENCE(3, 1, 10, -2)"));
+
+    // Arrays that are too large fail before they are allocated, even if they
+    // are within the step limit.
+    let err = eval(g, "SUM(SEQUENCE(3000, 2000))").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::ArrayTooLarge {
+            limit: DEFAULT_MAX_ARRAY_SIZE,
+        },
+        err.msg,
+    );
+    assert_eq!(Some(Span { start: 4, end: 24 }), err.span);
+    assert_eq!(
+        "Array is too large (exceeded limit of 5000000 elements)",
+        err.msg.to_string(),
+    );
+
+    // The limit applies to array literals, ranges, and other functions.
+    let settings = EvalSettings {
+        max_array_size: 4,
+        ..Default::default()
+    };
+    make_stateless_grid_mock!(|_| Some("1".to_string()));
+    for s in [
+        "{1, 2; 3, 4; 5, 6}",
+        "{1, 2, 3, 4, 5}",
+        "SUM(A1:B3)",
+        "SEQUENCE(5)",
+        "RANDARRAY(1, 5)",
+        "MUNIT(3)",
+        "MMULT({1; 2; 3}, {1, 2})",
+        "WRAPROWS({1, 2, 3}, 5)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::ArrayTooLarge { limit: 4 },
+            eval_with_settings(&mut GridMock, s, settings)
+                .unwrap_err()
+                .msg,
+            "{s:?}",
+        );
+    }
+    for s in [
+        "{1, 2; 3, 4}",
+        "SUM(A1:B2)",
+        "SEQUENCE(2, 2) * {1, 2; 3, 4}",
+    ] {
+        assert!(
+            eval_with_settings(&mut GridMock, s, settings).is_ok(),
+            "{s:?}"
+        );
+    }
+}
+
+#[test]
+fn test_text_length_limit() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("ababab", eval_to_string(g, "REPT('ab', 3)"));
+    assert_eq!("", eval_to_string(g, "REPT('ab', 0)"));
+    assert_eq!("", eval_to_string(g, "REPT('', 1e300)"));
+    assert_eq!("{x, xx}", eval_to_string(g, "REPT('x', {1, 2.9})"));
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "REPT('x', -1)").unwrap_err().msg,
+    );
+
+    // The length is checked before the text is built.
+    let err = eval(g, "REPT('x', 1e9)").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::TextTooLong {
+            limit: DEFAULT_MAX_TEXT_LENGTH,
+        },
+        err.msg,
+    );
+    assert_eq!(Some(Span { start: 0, end: 14 }), err.span);
+    assert_eq!(
+        "Text is too long (exceeded limit of 32767 bytes)",
+        err.msg.to_string(),
+    );
+    assert_eq!(32767, eval_to_string(g, "REPT('x', 32767)").len());
+
+    let settings = EvalSettings {
+        max_text_length: 10,
+        ..Default::default()
+    };
+    for s in [
+        "REPT('abc', 4)",
+        "'abcdef' & 'ghijk'",
+        "CONCAT('abcdef', {'g', 'hi'; 'jk', 'l'})",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::TextTooLong { limit: 10 },
+            eval_with_settings(g, s, settings).unwrap_err().msg,
+            "{s:?}",
+        );
+    }
+    // Text built up a little at a time stops growing at the limit, and the
+    // error is the result of the lambda.
+    assert_eq!(
+        Value::Error(FormulaErrorMsg::TextTooLong { limit: 10 }),
+        eval_with_settings(
+            g,
+            "REDUCE('', SEQUENCE(100), LAMBDA(acc, n, acc & 'x'))",
+            settings,
+        )
+        .unwrap(),
+    );
+    for s in [
+        "REPT('ab', 5)",
+        "'abcdef' & 'ghij'",
+        "CONCAT('abcdef', {'g', 'hi'; 'j', ''})",
+        "REDUCE('', SEQUENCE(10), LAMBDA(acc, n, acc & 'x'))",
+    ] {
+        assert!(eval_with_settings(g, s, settings).is_ok(), "{s:?}");
+    }
+}
+
+#[test]
+fn test_function_call_trace() {
+    let g = &mut PanicGridMock;
+    let settings = EvalSettings {
+        trace_function_calls: true,
+        ..Default::default()
+    };
+
+    let source = "ROUND(IF(TRUE(), SUM(1, 2 * 'a'), 0), 2)";
+    let err = eval_with_settings(g, source, settings).unwrap_err();
+    assert_eq!(vec!["SUM", "IF", "ROUND"], err.call_trace);
+    assert_eq!(
+        "column 28 to 31: in ROUND → in IF → in SUM → Expected number, got \"a\"",
+        err.to_string(),
+    );
+
+    // Nothing is recorded by default, or for errors that are caught.
+    let err = eval(g, source).unwrap_err();
+    assert!(err.call_trace.is_empty());
+    assert_eq!(
+        "column 28 to 31: Expected number, got \"a\"",
+        err.to_string(),
+    );
+    let err = eval_with_settings(g, "ABS(ISERROR(SQRT('x')), 1)", settings).unwrap_err();
+    assert_eq!(vec!["ABS"], err.call_trace);
+}
+
+#[test]
+fn test_render_error_with_source() {
+    let g = &mut PanicGridMock;
+
+    let source = "SUM(1, 2 * 'a')";
+    let err = eval(g, source).unwrap_err();
+    assert_eq!(
+        "SUM(1, 2 * 'a')\n           ^^^\nExpected number, got \"a\"",
+        render_with_source(&err, source),
+    );
+
+    // The call trace is shown with the message.
+    let settings = EvalSettings {
+        trace_function_calls: true,
+        ..Default::default()
+    };
+    let err = eval_with_settings(g, source, settings).unwrap_err();
+    assert_eq!(
+        "SUM(1, 2 * 'a')\n           ^^^\nin SUM → Expected number, got \"a\"",
+        render_with_source(&err, source),
+    );
+
+    // Only the line containing the error is shown, and the carets line up
+    // with characters rather than bytes.
+    let source = "IF(\n  'é' == 'é',\n  1 +,\n  2\n)";
+    let err = parse_formula(source, Pos::ORIGIN).unwrap_err();
+    assert_eq!(
+        format!("line 3:\n  1 +,\n     ^\n{}", err.msg),
+        render_with_source(&err, source),
+    );
+    let source = "'é' & (2 * 'xy')";
+    let err = eval(g, source).unwrap_err();
+    assert_eq!(
+        "'é' & (2 * 'xy')\n           ^^^^\nExpected number, got \"xy\"",
+        render_with_source(&err, source),
+    );
+
+    // A span over several lines is underlined to the end of the first one.
+    let source = "1 + ROUND(2,\n 3, 4)";
+    let err = eval(g, source).unwrap_err();
+    assert_eq!(Some(Span { start: 4, end: 19 }), err.span, "{err}");
+    assert_eq!(
+        format!("line 1:\n1 + ROUND(2,\n    ^^^^^^^^\n{}", err.msg),
+        render_with_source(&err, source),
+    );
+
+    // Without a span, only the message is shown.
+    let err = FormulaError::from(FormulaErrorMsg::DivideByZero);
+    assert_eq!(err.msg.to_string(), render_with_source(&err, "1 / 0"));
+}
+
+#[test]
+fn test_evaluate_with_trace() {
+    make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
+
+    fn trace_strings(src: &str) -> (FormulaResult<Value>, Vec<(&str, String)>) {
+        let mut grid = GridMock;
+        let mut ctx = Ctx::new(&mut grid, Pos::ORIGIN);
+        let (result, trace) = evaluate_with_trace(src, &mut ctx);
+        let trace = trace
+            .into_iter()
+            .map(|(span, value)| (span.of_str(src), value.to_string()))
+            .collect();
+        (result, trace)
+    }
+
+    let src = "SUM(A1:A3) * (B1 + 0.5)";
+    let (result, trace) = trace_strings(src);
+    assert_eq!(Value::Number(69.0), result.unwrap());
+    assert_eq!(
+        [
+            ("A1:A3", "{1; 2; 3}"),
+            ("SUM(A1:A3)", "6"),
+            ("B1", "11"),
+            ("0.5", "0.5"),
+            ("B1 + 0.5", "11.5"),
+            ("(B1 + 0.5)", "11.5"),
+            (src, "69"),
+        ]
+        .map(|(s, v)| (s, v.to_string()))
+        .to_vec(),
+        trace,
+    );
+
+    // An error is recorded once, where evaluation stopped.
+    let src = "IF(A1 > 0, 'x' * 2, 0)";
+    let (result, trace) = trace_strings(src);
+    let err = result.unwrap_err();
+    assert_eq!(Some("'x'"), err.span.map(|span| span.of_str(src)));
+    assert_eq!(
+        [
+            ("A1", "1"),
+            ("0", "0"),
+            ("A1 > 0", "TRUE"),
+            ("'x'", "x"),
+            ("2", "2"),
+            ("'x'", &Value::Error(err.msg).to_string()),
+        ]
+        .map(|(s, v)| (s, v.to_string()))
+        .to_vec(),
+        trace,
+    );
+
+    // Nothing is traced if the formula doesn't parse.
+    assert_eq!(vec![] as Vec<(&str, String)>, trace_strings("SUM(").1);
+}
+
+#[test]
+fn test_eval_warnings() {
+    make_stateless_grid_mock!(|pos| Some(match (pos.x, pos.y) {
+        (0, 2) => "$5".to_string(),
+        _ => pos.y.to_string(),
+    }));
+
+    fn eval_with_warnings(src: &str, settings: EvalSettings) -> (Value, Vec<(&str, String)>) {
+        let mut grid = GridMock;
+        let mut ctx = Ctx::with_settings(&mut grid, Pos::ORIGIN, settings);
+        let result = parse_formula(src, Pos::ORIGIN)
+            .unwrap()
+            .eval_blocking_with_warnings(&mut ctx)
+            .unwrap();
+        let warnings = result
+            .warnings
+            .into_iter()
+            .map(|w| (w.span.unwrap().of_str(src), w.msg.to_string()))
+            .collect();
+        (result.value, warnings)
+    }
+    // Text is only read as a number in a range if asked for.
+    let default = EvalSettings {
+        coerce_numeric_text: true,
+        ..Default::default()
+    };
+    let strict = EvalSettings {
+        strictness: Strictness::Text,
+        ..default
+    };
+    let (value, warnings) = eval_with_warnings("SUM(A1:A3, B1:B2)", EvalSettings::default());
+    assert_eq!(Value::Number(7.0), value);
+    assert!(warnings.is_empty());
+
+    // Text in a range that is read as a number gives a warning.
+    let (value, warnings) = eval_with_warnings("SUM(A1:A3, B1:B2)", default);
+    assert_eq!(Value::Number(12.0), value);
+    let warning = ("A1:A3", r#"Text "$5" was read as a number"#.to_string());
+    assert_eq!(vec![warning.clone()], warnings);
+    for src in [
+        "AVERAGE(A1:A3)",
+        "MIN(A1:A3)",
+        "MAX(A1:A3)",
+        "PRODUCT(A1:A3)",
+    ] {
+        assert_eq!(vec![warning.clone()], eval_with_warnings(src, default).1);
+    }
+    // Each range gets its own warning.
+    let (_, warnings) = eval_with_warnings("SUM(A1:A3) + SUM(A2)", default);
+    assert_eq!(1, warnings.len());
+    let (_, warnings) = eval_with_warnings("SUM(A1:A3) + SUM(A2:A3)", default);
+    assert_eq!(2, warnings.len());
+
+    // Cells that contain numbers are read as numbers, so text that looks
+    // exactly like a number only gives a warning if it comes from an array
+    // literal in strict mode.
+    let (value, warnings) = eval_with_warnings("SUM(B1:B3, {'7', 1})", default);
+    assert_eq!(Value::Number(14.0), value);
+    assert!(warnings.is_empty());
+    let (_, warnings) = eval_with_warnings("SUM(B1:B3, {'7', 1})", strict);
+    let warning = ("{'7', 1}", r#"Text "7" was read as a number"#.to_string());
+    assert_eq!(vec![warning], warnings);
+
+    // Text outside a range and blank text don't give warnings.
+    assert!(eval_with_warnings("SUM('$5', {'', 1})", default)
+        .1
+        .is_empty());
+    // A range of several cells as an operand would have been intersected by
+    // older spreadsheets.
+    let settings = EvalSettings::default();
+    let warning = |range| {
+        let msg = FormulaWarningMsg::ImplicitIntersection.to_string();
+        (range, msg)
+    };
+    let (value, warnings) = eval_with_warnings("B1:B3 * 2", settings);
+    assert_eq!("{2; 4; 6}", value.to_string());
+    assert_eq!(vec![warning("B1:B3")], warnings);
+    let (_, warnings) = eval_with_warnings("B1:B2 + (B3:B4)", settings);
+    assert_eq!(vec![warning("B1:B2"), warning("(B3:B4)")], warnings);
+    for src in [
+        "@B0:B3 * 2",
+        "B2:B2 * 2",
+        "B2 * 2",
+        "SUM(B1:B3) * 2",
+        "SUM(B1:B3)",
+    ] {
+        assert!(eval_with_warnings(src, settings).1.is_empty(), "{src}");
+    }
+
+    // Warnings are only collected when asked for.
+    let mut grid = GridMock;
+    let mut ctx = Ctx::new(&mut grid, Pos::ORIGIN);
+    parse_formula("SUM(A1:A3)", Pos::ORIGIN)
+        .unwrap()
+        .eval_blocking(&mut ctx)
+        .unwrap();
+    assert!(ctx.take_warnings().is_empty());
+}
+
+#[test]
+fn test_float_tolerance() {
+    let exact = EvalSettings::default();
+    let tolerant = EvalSettings {
+        float_tolerance: Some(FloatTolerance::EXCEL),
+        ..Default::default()
+    };
+    let g = &mut PanicGridMock;
+
+    // Exact comparison by default. (Decimal arithmetic has no roundoff error
+    // here.)
+    #[cfg(not(feature = "decimal"))]
+    {
+        assert_eq!("FALSE", eval_to_string(g, "0.1 + 0.2 = 0.3"));
+        assert_eq!("TRUE", eval_to_string(g, "0.1 + 0.2 != 0.3"));
+        assert_eq!("TRUE", eval_to_string(g, "0.1 + 0.2 > 0.3"));
+        assert_eq!("FALSE", eval_to_string(g, "0.1 + 0.2 <= 0.3"));
+    }
+
+    // Tolerant comparison treats roundoff error as equal.
+    let eval_tolerant =
+        |g: &mut PanicGridMock, s| eval_with_settings(g, s, tolerant).unwrap().to_string();
+    assert_eq!("TRUE", eval_tolerant(g, "0.1 + 0.2 = 0.3"));
+    assert_eq!("FALSE", eval_tolerant(g, "0.1 + 0.2 != 0.3"));
+    assert_eq!("FALSE", eval_tolerant(g, "0.1 + 0.2 > 0.3"));
+    assert_eq!("FALSE", eval_tolerant(g, "0.1 + 0.2 < 0.3"));
+    assert_eq!("TRUE", eval_tolerant(g, "0.1 + 0.2 <= 0.3"));
+    assert_eq!("TRUE", eval_tolerant(g, "0.1 + 0.2 >= 0.3"));
+    assert_eq!("TRUE", eval_tolerant(g, "'0.3' = 0.1 + 0.2"));
+
+    // Genuinely distinct values are still ordered.
+    for settings in [exact, tolerant] {
+        let mut eval_str = |s| eval_with_settings(g, s, settings).unwrap().to_string();
+        assert_eq!("TRUE", eval_str("0.3 < 0.3000000001"));
+        assert_eq!("FALSE", eval_str("0.3 = 0.3000000001"));
+        assert_eq!("TRUE", eval_str("1e-20 > 0"));
+        assert_eq!("FALSE", eval_str("1e-20 = 0"));
+        assert_eq!("TRUE", eval_str("'abc' = 'abc'"));
+        assert_eq!("FALSE", eval_str("'abc' = 'abd'"));
+    }
+}
+
+#[test]
+fn test_text_compare_mode() {
+    let insensitive = EvalSettings::default();
+    let sensitive = EvalSettings {
+        text_compare: TextCompareMode::CaseSensitive,
+        ..Default::default()
+    };
+    let g = &mut PanicGridMock;
+    let mut eval_str = |s, settings| match eval_with_settings(g, s, settings) {
+        Ok(value) => value.to_string(),
+        Err(e) => e.msg.excel_code().unwrap_or_default().to_string(),
+    };
+
+    // Each formula gives the first result ignoring case and the second
+    // comparing case.
+    for (formula, ignoring_case, comparing_case) in [
+        // Comparison operators
+        ("'abc' = 'ABC'", "TRUE", "FALSE"),
+        ("'abc' != 'ABC'", "FALSE", "TRUE"),
+        ("'abc' <= 'ABC'", "TRUE", "FALSE"),
+        ("'abc' < 'abd'", "TRUE", "TRUE"),
+        ("'ABC' < 'abd'", "TRUE", "TRUE"),
+        ("'a' = 'a'", "TRUE", "TRUE"),
+        // Lookups
+        ("MATCH('APPLE', {'pear', 'apple'}, 0)", "2", "#N/A"),
+        ("MATCH('apple', {'pear', 'apple'}, 0)", "2", "2"),
+        ("MATCH('A*', {'pear', 'apple'}, 0)", "2", "#N/A"),
+        ("XMATCH('APPLE', {'pear'; 'apple'})", "2", "#N/A"),
+        ("XMATCH('Apple', {'apple', 'Apple'})", "1", "2"),
+        ("XLOOKUP('PEAR', {'apple', 'pear'}, {1, 2})", "2", "#N/A"),
+        ("XLOOKUP('pear', {'apple', 'pear'}, {1, 2})", "2", "2"),
+        ("XMATCH('P*', {'apple', 'pear'}, 2)", "2", "#N/A"),
+        // Criteria
+        ("COUNTIF({'apple', 'Apple', 'APPLE'}, 'apple')", "3", "1"),
+        ("COUNTIF({'apple', 'Apple', 'APPLE'}, 'A*')", "3", "2"),
+        ("COUNTIF({'apple', 'Apple', 'APPLE'}, '<>apple')", "0", "2"),
+        ("SUMIF({'a', 'A', 'b'}, 'a', {1, 2, 4})", "3", "1"),
+        (
+            "DSUM({'k', 'v'; 'a', 1; 'A', 2}, 'v', {'k'; 'A'})",
+            "3",
+            "2",
+        ),
+        // `EXACT` is always case-sensitive.
+        ("EXACT('abc', 'ABC')", "FALSE", "FALSE"),
+        ("EXACT('abc', 'abc')", "TRUE", "TRUE"),
+    ] {
+        assert_eq!(ignoring_case, eval_str(formula, insensitive), "{formula}");
+        assert_eq!(comparing_case, eval_str(formula, sensitive), "{formula}");
+    }
+}
+
+#[test]
+fn test_coerce_numeric_text() {
+    make_stateless_grid_mock!(|pos| match (pos.x, pos.y) {
+        (0, 1) => Some("1".to_string()),
+        (0, 2) => Some("2".to_string()),
+        (0, 3) => Some("abc".to_string()),
+        _ => None,
+    });
+    let g = &mut GridMock;
+    let coerce = EvalSettings {
+        coerce_numeric_text: true,
+        ..Default::default()
+    };
+    let eval_coerce = |s: &str| eval_with_settings(&mut GridMock, s, coerce);
+
+    // Cells that contain numbers are numbers either way, and text passed
+    // directly is always read as a number.
+    for formula in ["SUM(A1:A2)", "SUM(A1, (A2))", "SUM(A1:A2, '-3')"] {
+        assert_eq!(
+            eval(g, formula).unwrap(),
+            eval_coerce(formula).unwrap(),
+            "{formula}"
+        );
+    }
+    assert_eq!("3", eval_to_string(g, "SUM(A1:A2)"));
+    assert_eq!("6", eval_to_string(g, "SUM('5', 1)"));
+
+    // Text in ranges and arrays that looks like a number is ignored by
+    // default, and read as a number if asked for.
+    for (formula, ignored, coerced) in [
+        ("SUM(A1:A2, {'5', 1})", "4", "9"),
+        ("SUM(TEXTSPLIT('3,4', ','))", "0", "7"),
+        ("PRODUCT({'5', 2})", "2", "10"),
+        ("AVERAGE(A1:A2, {'5', 1})", "1.3333333333333333", "2.25"),
+        ("MIN({'0', 5})", "5", "0"),
+        ("MAX({'9', 5})", "5", "9"),
+    ] {
+        assert_eq!(ignored, eval_to_string(g, formula), "{formula}");
+        assert_eq!(
+            coerced,
+            eval_coerce(formula).unwrap().to_string(),
+            "{formula}"
+        );
+    }
+
+    // Other text in a range is also ignored by default, but is an error if
+    // text is read as numbers.
+    assert_eq!("3", eval_to_string(g, "SUM(A1:A3)"));
+    assert_eq!("1.5", eval_to_string(g, "AVERAGE(A1:A3)"));
+    assert_eq!(
+        "TypeMismatch",
+        eval_coerce("SUM(A1:A3)").unwrap_err().msg.short_name(),
+    );
+}
+
+#[test]
+fn test_missing_close_paren_error() {
+    for source in ["=SUM(1,2", "=SUM(1,2   ", "=SUM(1,2 // comment"] {
+        let err = parse_formula(source, Pos::ORIGIN).unwrap_err();
+        // Empty span right after the `2`.
+        assert_eq!(Some(Span::empty(8)), err.span, "wrong span for {source:?}");
+        let FormulaErrorMsg::Expected { expected, got } = err.msg else {
+            panic!(
+                "expected `Expected` error for {source:?}, got {:?}",
+                err.msg
+            );
+        };
+        assert!(expected.contains("right paren"));
+        assert_eq!(Some("end of formula".into()), got);
+    }
+
+    let err = parse_formula("=(1+2", Pos::ORIGIN).unwrap_err();
+    assert_eq!(Some(Span::empty(5)), err.span);
+    assert_eq!(
+        "Expected right paren, got end of formula",
+        err.msg.to_string(),
+    );
+
+    // Unexpected token
+    let err = parse_formula("=SUM(1,2 3)", Pos::ORIGIN).unwrap_err();
+    assert_eq!(Some(Span { start: 9, end: 10 }), err.span);
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "argument separator (comma) or right paren".into(),
+            got: Some("3".into()),
+        },
+        err.msg,
+    );
+}
+
+#[test]
+fn test_unimplemented_syntax() {
+    for (source, feature, spanned) in [
+        ("Sheet1!A1", "sheet references", "Sheet1!A1"),
+        ("SUM('My Sheet'!A1:A3)", "sheet references", "'My Sheet'!A1"),
+        ("SUM(A1:Sheet2!B1)", "sheet references", "Sheet2!B1"),
+        (
+            "SUM(Sales[Amount]) * 2",
+            "structured references",
+            "Sales[Amount]",
+        ),
+        ("SUM(A1:B2 B1:C2)", "intersection operator", "B2 B1"),
+        ("A:A  B1", "intersection operator", "A:A  B1"),
+    ] {
+        let err = parse_formula(source, Pos::ORIGIN).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::Unimplemented(feature.into()),
+            err.msg,
+            "{source}",
+        );
+        assert_eq!(spanned, err.span.unwrap().of_str(source), "{source}");
+    }
+    assert_eq!(
+        "This feature is unimplemented: sheet references",
+        parse_formula("Sheet1!A1", Pos::ORIGIN)
+            .unwrap_err()
+            .msg
+            .to_string(),
+    );
+
+    // Row ranges parse, but can't be evaluated.
+    let g = &mut PanicGridMock;
+    let err = eval(g, "SUM(1:3)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::Unimplemented("row ranges".into()), err.msg);
+    assert_eq!(Some(Span { start: 4, end: 7 }), err.span);
+
+    // Other syntax errors are unaffected, even if they contain these
+    // characters.
+    for source in [
+        "SUM(A1 2)",
+        "'!' +",
+        "[1]",
+        "SUM(1, 2 3)",
+        "SUM(Sheet1:Sheet3!)",
+    ] {
+        let err = parse_formula(source, Pos::ORIGIN).unwrap_err();
+        assert!(
+            matches!(err.msg, FormulaErrorMsg::Expected { .. }),
+            "{source}: {err}",
+        );
+    }
+    assert_eq!("TRUE", eval_to_string(g, "'a!' != 'b'"));
+}
+
+#[test]
+fn test_formula_3d_references() {
+    /// Cell B2 on each month's sheet is 1, 2, or 3 times a power of ten, and
+    /// B3 is blank on `Feb`.
+    struct SheetsGridMock;
+    #[async_trait(?Send)]
+    impl GridProxy for SheetsGridMock {
+        async fn get(&mut self, _pos: Pos) -> Option<String> {
+            None
+        }
+        async fn sheet_names(&mut self) -> Vec<String> {
+            ["Jan", "Feb", "Mar", "Q1 Total"].map(String::from).to_vec()
+        }
+        async fn get_on_sheet(&mut self, sheet: &str, pos: Pos) -> Option<String> {
+            let scale = match sheet {
+                "Jan" => 1,
+                "Feb" => 10,
+                "Mar" => 100,
+                _ => return Some("999".to_string()),
+            };
+            match (pos.x, pos.y) {
+                (1, 3) if sheet == "Feb" => None,
+                (1, y @ 2..=4) => Some((scale * (y - 1)).to_string()),
+                _ => None,
+            }
+        }
+    }
+    let g = &mut SheetsGridMock;
+
+    assert_eq!("111", eval_to_string(g, "SUM(Jan:Mar!B2)"));
+    assert_eq!("646", eval_to_string(g, "SUM(Jan:Mar!B2:B4)"));
+    assert_eq!("646", eval_to_string(g, "SUM('Jan':'Mar'!B2:B4)"));
+    assert_eq!("11", eval_to_string(g, "SUM(Jan:Feb!B2)"));
+    assert_eq!("3", eval_to_string(g, "SUM(Jan:Jan!B2:B3)"));
+    assert_eq!("3", eval_to_string(g, "SUM(JAN:JAN!B2:B3)"));
+    assert_eq!("111", eval_to_string(g, "SUM(JAN:MAR!B2)"));
+    assert_eq!("1110", eval_to_string(g, "SUM(Jan:'Q1 Total'!B2)"));
+    assert_eq!("112", eval_to_string(g, "SUM(Jan:Mar!B2, 1)"));
+    assert_eq!("37", eval_to_string(g, "AVERAGE(Jan:Mar!B2)"));
+    assert_eq!("8", eval_to_string(g, "COUNT(Jan:Mar!B2:B4)"));
+    assert_eq!("300", eval_to_string(g, "MAX(Jan:Mar!B2:B4)"));
+
+    // Sheets out of order or that don't exist are bad references.
+    for (source, spanned) in [
+        ("SUM(Mar:Jan!B2)", "Mar:Jan"),
+        ("SUM(Jan:Apr!B2)", "Apr"),
+        ("SUM('Dec':Jan!B2)", "'Dec'"),
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadCellReference, err.msg, "{source}");
+        assert_eq!(spanned, err.span.unwrap().of_str(source), "{source}");
+    }
+
+    // Only aggregate functions accept 3-D references.
+    for (source, spanned) in [
+        ("'Jan':'Mar'!B2:B9 + 1", "'Jan':'Mar'!B2:B9"),
+        ("ABS(Jan:Mar!B2)", "Jan:Mar!B2"),
+        ("SUM(-Jan:Mar!B2)", "Jan:Mar!B2"),
+        ("INDEX(Jan:Mar!B2:B4, 1)", "Jan:Mar!B2:B4"),
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::Unimplemented("3-D references outside of aggregate functions".into()),
+            err.msg,
+            "{source}",
+        );
+        assert_eq!(spanned, err.span.unwrap().of_str(source), "{source}");
+    }
+
+    // Without a list of sheets, every sheet is unknown.
+    let err = eval(&mut PanicGridMock, "SUM(Sheet1:Sheet3!A1)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadCellReference, err.msg);
+}
+
+#[test]
+fn test_unexpected_token_error() {
+    let args_end = "argument separator (comma) or right paren";
+    let array_end = "argument separator (comma), array row seperator (semicolon), or right brace";
+    for (source, expected, got, span) in [
+        // Mismatched brackets
+        ("=(1+2]", "right paren", "]", 5..6),
+        ("=SUM(1, 2]", args_end, "]", 9..10),
+        ("={1, 2)", array_end, ")", 6..7),
+        ("={1, 2; 3", array_end, "end of formula", 9..9),
+        ("=SUM(1, 2))", "end of formula", ")", 10..11),
+        ("=)", "expression", ")", 1..2),
+        // Misplaced operators and separators
+        ("=1 + * 2", "expression", "*", 5..6),
+        ("=SUM(1; 2)", args_end, ";", 6..7),
+        ("=, 3", "expression", ",", 1..2),
+        ("=1 2", "end of formula", "2", 3..4),
+        // Multi-character tokens
+        ("=IF(1, 'a' 'b')", args_end, "'b'", 11..14),
+        ("=SUM(1 A1)", args_end, "A1", 7..9),
+    ] {
+        let err = parse_formula(source, Pos::ORIGIN).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::Expected {
+                expected: expected.into(),
+                got: Some(got.into()),
+            },
+            err.msg,
+            "wrong error for {source:?}",
+        );
+        let span = Span {
+            start: span.start,
+            end: span.end,
+        };
+        assert_eq!(Some(span), err.span, "wrong span for {source:?}");
+    }
+
+    assert_eq!(
+        "Expected right paren, got ]",
+        parse_formula("=(1+2]", Pos::ORIGIN)
+            .unwrap_err()
+            .msg
+            .to_string(),
+    );
+}
+
+#[test]
+fn test_deeply_nested_parens() {
+    // This runs on the default test thread stack, so it also checks that
+    // formulas at the limit don't overflow the stack.
+    let depth = DEFAULT_MAX_NESTING_DEPTH;
+
+    // The limit is inclusive.
+    let source = "(".repeat(depth) + "1" + &")".repeat(depth);
+    assert_eq!("1", eval_to_string(&mut PanicGridMock, &source));
+    let source = "SUM(".repeat(depth) + "1" + &")".repeat(depth);
+    assert_eq!("1", eval_to_string(&mut PanicGridMock, &source));
+    let source = "(".repeat(depth + 1) + "1" + &")".repeat(depth + 1);
+    let err = parse_formula(&source, Pos::ORIGIN).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+    assert_eq!(
+        Some(Span {
+            start: depth + 1,
+            end: depth + 2
+        }),
+        err.span
+    );
+
+    // Far past the limit is a clean error, not a stack overflow.
+    let n = 10_000;
+    let source = "(".repeat(n) + "1" + &")".repeat(n);
+    let err = parse_formula(&source, Pos::ORIGIN).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+    let source = "-".repeat(n) + "1";
+    let err = parse_formula(&source, Pos::ORIGIN).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+
+    // A long chain of operators parses without nesting, but evaluating it
+    // recurses, so evaluation has its own limit.
+    let n = DEFAULT_MAX_EVAL_DEPTH;
+    let source = "1".to_string() + &"+1".repeat(n - 1);
+    assert_eq!(n.to_string(), eval_to_string(&mut PanicGridMock, &source));
+    let source = "1".to_string() + &"+1".repeat(n);
+    let err = eval_with_settings(&mut PanicGridMock, &source, EvalSettings::default()).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+
+    // The limits are configurable.
+    let source = "(".repeat(51) + "1" + &")".repeat(51);
+    let err = parse_formula_with_max_depth(&source, Pos::ORIGIN, 50).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+    let source = "SUM(".repeat(50) + &")".repeat(50);
+    assert!(parse_formula_with_max_depth(&source, Pos::ORIGIN, 50).is_ok());
+    let source = "-{".repeat(30) + "1" + &"}".repeat(30);
+    let err = parse_formula_with_max_depth(&source, Pos::ORIGIN, 50).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+    let settings = EvalSettings {
+        max_eval_depth: 10,
+        ..Default::default()
+    };
+    let err =
+        eval_with_settings(&mut PanicGridMock, "1+2+3+4+5+6+7+8+9+10+11", settings).unwrap_err();
+    assert_eq!(FormulaErrorMsg::NestingTooDeep, err.msg);
+}
+
+#[test]
+fn test_asts_equivalent() {
+    let equivalent = |a: &str, b: &str| {
+        let a = parse_formula(a, Pos::ORIGIN).unwrap();
+        let b = parse_formula(b, Pos::ORIGIN).unwrap();
+        asts_equivalent(&a.ast, &b.ast)
+    };
+
+    for (a, b) in [
+        ("SUM(A1, 2)", "  sum( A1,2 )"),
+        ("(A1 + B2) * 3", "(A1+B2)*3"),
+        ("(1 + 2) + 3", "1 + 2 + 3"),
+        ("((A1))", "A1"),
+        ("{1, 2; 3, 4}", "{1,2;3,4}"),
+        ("LET(x, 1, x + 1)", "let(X, 1, x + 1)"),
+        ("IF(A1, , 2)", "if(A1,,2)"),
+        ("1.0", "1"),
+        ("#N/A", "#n/a"),
+    ] {
+        assert!(equivalent(a, b), "{a:?} should be equivalent to {b:?}");
+        assert!(equivalent(b, a), "{b:?} should be equivalent to {a:?}");
+    }
+
+    for (a, b) in [
+        ("(1 + 2) + 3", "1 + (2 + 3)"),
+        ("SUM(A1, B1)", "SUM(B1, A1)"),
+        ("A1", "$A$1"),
+        ("A1", "A$1"),
+        ("A1:B2", "A1:$B2"),
+        ("'abc'", "'ABC'"),
+        ("SUM(A1)", "SUM(A1, 0)"),
+        ("{1, 2; 3, 4}", "{1, 2, 3, 4}"),
+        ("{1, 2}", "{1; 2}"),
+        ("2 * 3", "3 * 2"),
+        ("MAX(1, 2)", "MIN(1, 2)"),
+        ("-1", "1"),
+    ] {
+        assert!(!equivalent(a, b), "{a:?} shouldn't be equivalent to {b:?}");
+        assert!(!equivalent(b, a), "{b:?} shouldn't be equivalent to {a:?}");
+    }
+
+    // Cell references are relative to where the formula is.
+    let a = parse_formula("A1", Pos::ORIGIN).unwrap();
+    let b = parse_formula("A2", Pos::new(0, 1)).unwrap();
+    let c = parse_formula("A1", Pos::new(0, 1)).unwrap();
+    assert!(asts_equivalent(&a.ast, &b.ast));
+    assert!(!asts_equivalent(&a.ast, &c.ast));
+}
+
+#[test]
+fn test_formula_maxifs_minifs() {
+    make_stateless_grid_mock!(|pos| Some(
+        match (pos.x, pos.y) {
+            // Column A: fruit names
+            (0, 1) => "apple",
+            (0, 2) => "banana",
+            (0, 3) => "apple",
+            (0, 4) => "avocado",
+            // Column B: prices
+            (1, 1) => "3",
+            (1, 2) => "1",
+            (1, 3) => "5",
+            (1, 4) => "4",
+            // Column C: quantities
+            (2, 1) => "10",
+            (2, 2) => "20",
+            (2, 3) => "30",
+            (2, 4) => "",
+            _ => "",
+        }
+        .to_string()
+    ));
+    let g = &mut GridMock;
+
+    assert_eq!("5", eval_to_string(g, "MAXIFS(B1:B4, A1:A4, 'apple')"));
+    assert_eq!("3", eval_to_string(g, "MINIFS(B1:B4, A1:A4, 'apple')"));
+    assert_eq!("5", eval_to_string(g, "MAXIFS(B1:B4, A1:A4, 'a*')"));
+    assert_eq!("1", eval_to_string(g, "MINIFS(B1:B4, A1:A4, '<>a*')"));
+
+    // Multiple criteria use AND semantics.
+    assert_eq!(
+        "3",
+        eval_to_string(g, "MAXIFS(B1:B4, A1:A4, 'a*', C1:C4, '<20')"),
+    );
+    assert_eq!(
+        "4",
+        eval_to_string(g, "MAXIFS(B1:B4, A1:A4, 'a*', C1:C4, '')"),
+    );
+    assert_eq!(
+        "1",
+        eval_to_string(g, "MINIFS(B1:B4, B1:B4, '>=1', C1:C4, 20)"),
+    );
+
+    // No matches returns zero.
+    assert_eq!("0", eval_to_string(g, "MAXIFS(B1:B4, A1:A4, 'cherry')"));
+    assert_eq!("0", eval_to_string(g, "MINIFS(B1:B4, A1:A4, 'cherry')"));
+
+    // Mismatched ranges
+    assert_eq!(
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (4, 1),
+            got: (3, 1),
+        },
+        eval(g, "MAXIFS(B1:B4, A1:A3, 'apple')").unwrap_err().msg,
+    );
+
+    // Bad argument counts
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "MAXIFS(B1:B4, A1:A4)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "MINIFS(B1:B4, A1:A4, 'apple', C1:C4)")
+            .unwrap_err()
+            .msg,
+    );
+}
+
+#[test]
+fn test_formula_countunique() {
+    make_stateless_grid_mock!(|pos| match (pos.x, pos.y) {
+        (1, 0) => Some("apple".to_string()),
+        (1, 1) => Some("3".to_string()),
+        (1, 2) => Some("apple".to_string()),
+        _ => None,
+    });
+    let g = &mut GridMock;
+
+    assert_eq!("3", eval_to_string(g, "COUNTUNIQUE(1, 2, 2, 3, 1)"));
+    assert_eq!("3", eval_to_string(g, "COUNTUNIQUE({1, 'x'; 'y', 'x'})"));
+    // Numbers and text that look the same are equal.
+    assert_eq!("1", eval_to_string(g, "COUNTUNIQUE(1, '1', {1.0})"));
+    // Blanks and errors are ignored.
+    assert_eq!("2", eval_to_string(g, "COUNTUNIQUE(B0:B5, 3)"));
+    assert_eq!("1", eval_to_string(g, "COUNTUNIQUE('', 7, NA(), #DIV/0!)"));
+    assert_eq!("0", eval_to_string(g, "COUNTUNIQUE('')"));
+    assert_eq!(
+        "4",
+        eval_to_string(g, "COUNTUNIQUE(B0:B2, 'banana', {3, 4.5; 'apple', 4.5})"),
+    );
+}
+
+#[test]
+fn test_formula_blank() {
+    make_stateless_grid_mock!(|pos| match (pos.x, pos.y) {
+        (0, 2) => Some("x".to_string()),
+        (0, 3) => Some("0".to_string()),
+        _ => None,
+    });
+    let g = &mut GridMock;
+
+    assert_eq!(Value::Blank, eval(g, "A1").unwrap());
+    // Blank is zero in arithmetic and empty in text.
+    assert_eq!("1", eval_to_string(g, "A1 + 1"));
+    assert_eq!("x", eval_to_string(g, "A1 & 'x'"));
+    // Blank equals both zero and the empty string.
+    assert_eq!("TRUE", eval_to_string(g, "A1 = 0"));
+    assert_eq!("TRUE", eval_to_string(g, "A1 = ''"));
+    assert_eq!("TRUE", eval_to_string(g, "A1 = FALSE()"));
+    assert_eq!("FALSE", eval_to_string(g, "A1 = A2"));
+
+    // Only a blank cell is blank, not an empty string or zero.
+    assert_eq!("TRUE", eval_to_string(g, "ISBLANK(A1)"));
+    assert_eq!("FALSE", eval_to_string(g, "ISBLANK('')"));
+    assert_eq!("FALSE", eval_to_string(g, "ISBLANK(A3)"));
+    assert_eq!("{TRUE; FALSE; FALSE}", eval_to_string(g, "ISBLANK(A1:A3)"));
+
+    // `COUNTBLANK` also counts empty strings.
+    assert_eq!("2", eval_to_string(g, "COUNTBLANK(A1:A4)"));
+    assert_eq!("2", eval_to_string(g, "COUNTBLANK({1, ''; '', 0})"));
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "COUNTBLANK(A1, A2)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_sumif_countif_averageif() {
+    make_stateless_grid_mock!(|pos| Some(
+        match (pos.x, pos.y) {
+            // Column A: fruit names
+            (0, 1) => "apple",
+            (0, 2) => "banana",
+            (0, 3) => "apple",
+            (0, 4) => "avocado",
+            // Column B: prices
+            (1, 1) => "3",
+            (1, 2) => "1",
+            (1, 3) => "5",
+            (1, 4) => "4",
+            _ => "",
+        }
+        .to_string()
+    ));
+    let g = &mut GridMock;
+
+    assert_eq!("8", eval_to_string(g, "SUMIF(A1:A4, 'apple', B1:B4)"));
+    assert_eq!("12", eval_to_string(g, "SUMIF(B1:B4, '>2')"));
+    assert_eq!("2", eval_to_string(g, "COUNTIF(A1:A4, 'apple')"));
+    assert_eq!("3", eval_to_string(g, "COUNTIF(A1:A4, 'a*')"));
+    assert_eq!("4", eval_to_string(g, "AVERAGEIF(A1:A4, 'apple', B1:B4)"));
+    assert_eq!("4", eval_to_string(g, "AVERAGEIF(B1:B4, '>=3')"));
+
+    // No matches
+    assert_eq!("0", eval_to_string(g, "SUMIF(A1:A4, 'cherry', B1:B4)"));
+    assert_eq!("0", eval_to_string(g, "COUNTIF(A1:A4, 'cherry')"));
+    assert_eq!(
+        FormulaErrorMsg::DivideByZero,
+        eval(g, "AVERAGEIF(A1:A4, 'cherry', B1:B4)")
+            .unwrap_err()
+            .msg,
+    );
+
+    // Bad argument counts
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "COUNTIF(A1:A4)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "SUMIF(A1:A4, 'apple', B1:B4, B1:B4)")
+            .unwrap_err()
+            .msg,
+    );
+}
+
+#[test]
+fn test_formula_normal_distribution() {
+    let g = &mut PanicGridMock;
+
+    // Values from standard normal tables, to 4 decimal places.
+    for (formula, expected) in [
+        ("NORM.S.DIST(0, TRUE())", "0.5"),
+        ("NORM.S.DIST(1, TRUE())", "0.8413"),
+        ("NORM.S.DIST(1.96, TRUE())", "0.975"),
+        ("NORM.S.DIST(-1.5, TRUE())", "0.0668"),
+        ("NORM.S.DIST(2.33, TRUE())", "0.9901"),
+        ("NORM.S.DIST(-3.09, TRUE())", "0.001"),
+        ("NORM.S.DIST(0, FALSE())", "0.3989"),
+        ("NORM.S.DIST(1, FALSE())", "0.242"),
+        ("NORM.S.INV(0.975)", "1.96"),
+        ("NORM.S.INV(0.95)", "1.6449"),
+        ("NORM.S.INV(0.5)", "0"),
+        ("NORM.S.INV(0.01)", "-2.3263"),
+        ("PHI(0.75)", "0.3011"),
+        ("PHI(-0.75)", "0.3011"),
+        ("GAUSS(2)", "0.4772"),
+        ("GAUSS(-1)", "-0.3413"),
+        // Excel's examples
+        ("NORM.DIST(42, 40, 1.5, TRUE())", "0.9088"),
+        ("NORM.DIST(42, 40, 1.5, FALSE())", "0.1093"),
+        ("NORM.INV(0.908789, 40, 1.5)", "42"),
+        ("NORM.INV(0.025, 100, 15)", "70.6005"),
+        // Older names
+        ("NORMDIST(42, 40, 1.5, TRUE())", "0.9088"),
+        ("NORMDIST(42, 40, 1.5, FALSE())", "0.1093"),
+        ("NORMINV(0.908789, 40, 1.5)", "42"),
+        ("NORMSDIST(1.96)", "0.975"),
+        ("NORMSINV(0.95)", "1.6449"),
+    ] {
+        let rounded = format!("ROUND({formula}, 4)");
+        assert_eq!(expected, eval_to_string(g, &rounded), "{formula}");
+    }
+    // The inverse undoes the cumulative distribution.
+    assert_eq!(
+        "{-2, 0.5, 3}",
+        eval_to_string(
+            g,
+            "ROUND(NORM.S.INV(NORM.S.DIST({-2, 0.5, 3}, TRUE())), 10)"
+        ),
+    );
+    assert_eq!(
+        "{-2, 0.5, 3}",
+        eval_to_string(
+            g,
+            "ROUND(NORM.INV(NORM.DIST({-2, 0.5, 3}, 1, 2, TRUE()), 1, 2), 10)"
+        ),
+    );
+
+    // Tails keep their relative precision.
+    for (formula, expected) in [
+        ("NORMSDIST(-8) * 1e16", "6.2209605743"),
+        ("NORMSINV(1e-10)", "-6.3613409024"),
+        ("NORMSINV(1e-300)", "-37.0470962994"),
+    ] {
+        let rounded = format!("ROUND({formula}, 10)");
+        assert_eq!(expected, eval_to_string(g, &rounded), "{formula}");
+    }
+    // Compare the far tail directly, since scaling it up to round it is
+    // itself subject to roundoff.
+    let Value::Number(tail) = eval(g, "NORM.S.DIST(-30, TRUE())").unwrap() else {
+        panic!("expected a number");
+    };
+    let expected = 4.906713927148187e-198;
+    assert!((tail - expected).abs() < expected * 1e-10, "{tail}");
+    // The standard deviation must be positive, and the probability must be
+    // strictly between 0 and 1.
+    for (formula, bad_arg) in [
+        ("NORM.DIST(1, 0, 0, TRUE())", "0"),
+        ("NORM.DIST(1, 0, -1, FALSE())", "-1"),
+        ("NORM.INV(0.5, 0, 0)", "0"),
+        ("NORM.INV(0, 0, 1)", "0"),
+        ("NORM.INV(1, 0, 1)", "1"),
+        ("NORM.S.INV(0)", "0"),
+        ("NORM.S.INV(1.5)", "1.5"),
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(Some("#NUM!"), err.msg.excel_code(), "{formula}");
+        assert_eq!(bad_arg, err.span.unwrap().of_str(formula), "{formula}");
+    }
+}
+
+#[test]
+fn test_formula_discrete_distributions() {
+    let g = &mut PanicGridMock;
+
+    // Exact values, computed with rational arithmetic.
+    for (formula, expected) in [
+        ("BINOM.DIST(6, 10, 0.5, FALSE())", "0.205078125"),
+        ("BINOM.DIST(6, 10, 0.5, TRUE())", "0.828125"),
+        ("BINOM.DIST(6.9, 20, 0.5, TRUE())", "0.05765914917"),
+        ("BINOM.DIST(0, 10, 0, FALSE())", "1"),
+        ("BINOM.DIST(10, 10, 1, FALSE())", "1"),
+        ("BINOM.DIST(9, 10, 1, TRUE())", "0"),
+        ("POISSON.DIST(0, 0, FALSE())", "1"),
+        ("POISSON.DIST(3, 0, TRUE())", "1"),
+    ] {
+        let rounded = format!("ROUND({formula}, 12)");
+        assert_eq!(expected, eval_to_string(g, &rounded), "{formula}");
+    }
+    for (formula, expected) in [
+        ("POISSON.DIST(2, 5, FALSE())", "0.0842243375"),
+        ("POISSON.DIST(2, 5, TRUE())", "0.1246520195"),
+        ("POISSON.DIST(100, 100, TRUE())", "0.5265621985"),
+        ("HYPGEOM.DIST(1, 4, 8, 20, FALSE())", "0.3632610939"),
+        ("HYPGEOM.DIST(1, 4, 8, 20, TRUE())", "0.4654282766"),
+        // Binomial coefficients this large would overflow.
+        ("BINOM.DIST(500, 1000, 0.5, FALSE())", "0.0252250182"),
+        ("BINOM.DIST(5000, 10000, 0.5, FALSE())", "0.0079786461"),
+    ] {
+        let rounded = format!("ROUND({formula}, 10)");
+        assert_eq!(expected, eval_to_string(g, &rounded), "{formula}");
+    }
+    assert_eq!(
+        "{0.0009765625, 0.0107421875, 0.0546875}",
+        eval_to_string(g, "ROUND(BINOM.DIST({0, 1, 2}, 10, 0.5, TRUE()), 12)"),
+    );
+    // Small binomial probabilities are exact without rounding.
+    for (formula, expected) in [
+        ("BINOM.DIST(1, 2, 0.5, FALSE())", 0.5),
+        ("BINOM.DIST(6, 10, 0.5, FALSE())", 0.205078125),
+        ("BINOM.DIST(2, 3, 0.25, FALSE())", 0.140625),
+        ("BINOM.DIST(2, 10, 0.5, TRUE())", 0.0546875),
+    ] {
+        assert_eq!(
+            Value::Number(expected),
+            eval(g, formula).unwrap(),
+            "{formula}"
+        );
+    }
+
+    // Counts must be non-negative and consistent with each other, and
+    // probabilities must be between 0 and 1.
+    for (formula, bad_arg) in [
+        ("BINOM.DIST(-1, 10, 0.5, TRUE())", "-1"),
+        ("BINOM.DIST(11, 10, 0.5, FALSE())", "11"),
+        ("BINOM.DIST(1, -10, 0.5, FALSE())", "-10"),
+        ("BINOM.DIST(1, 10, 1.5, FALSE())", "1.5"),
+        ("BINOM.DIST(1, 10, -0.5, FALSE())", "-0.5"),
+        ("POISSON.DIST(-1, 5, FALSE())", "-1"),
+        ("POISSON.DIST(1, -5, TRUE())", "-5"),
+        ("HYPGEOM.DIST(5, 4, 8, 20, FALSE())", "5"),
+        ("HYPGEOM.DIST(3, 4, 2, 20, FALSE())", "3"),
+        ("HYPGEOM.DIST(0, 4, 18, 20, FALSE())", "0"),
+        ("HYPGEOM.DIST(1, 21, 8, 20, FALSE())", "21"),
+        ("HYPGEOM.DIST(1, 4, 21, 20, FALSE())", "21"),
+        ("HYPGEOM.DIST(1, 4, 8, -20, FALSE())", "-20"),
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(Some("#NUM!"), err.msg.excel_code(), "{formula}");
+        assert_eq!(bad_arg, err.span.unwrap().of_str(formula), "{formula}");
+    }
+
+    // Each term of a cumulative sum is an evaluation step.
+    let settings = EvalSettings {
+        max_eval_steps: 1000,
+        ..Default::default()
+    };
+    let formula = "POISSON.DIST(1e9, 1e9, TRUE())";
+    assert_eq!(
+        "EvaluationLimitExceeded",
+        eval_with_settings(g, formula, settings)
+            .unwrap_err()
+            .msg
+            .short_name(),
+    );
+}
+
+#[test]
+fn test_formula_expon_dist_gammaln() {
+    let g = &mut PanicGridMock;
+
+    for (formula, expected) in [
+        // Excel's examples
+        ("EXPON.DIST(0.2, 10, TRUE())", "0.8646647168"),
+        ("EXPON.DIST(0.2, 10, FALSE())", "1.3533528324"),
+        ("EXPON.DIST(0, 3, FALSE())", "3"),
+        ("EXPON.DIST(0, 3, TRUE())", "0"),
+        ("GAMMALN(4.5)", "2.4537365708"),
+        ("GAMMALN(0.1)", "2.2527126517"),
+        ("GAMMALN(100)", "359.1342053696"),
+        ("GAMMALN(1)", "0"),
+        ("GAMMALN.PRECISE(4.5)", "2.4537365708"),
+        // Older names
+        ("EXPONDIST(0.2, 10, TRUE())", "0.8646647168"),
+        ("BINOMDIST(6, 10, 0.5, FALSE())", "0.205078125"),
+        ("BINOMDIST(6, 10, 0.5, TRUE())", "0.828125"),
+        ("POISSON(2, 5, FALSE())", "0.0842243375"),
+        ("POISSON(2, 5, TRUE())", "0.1246520195"),
+    ] {
+        let rounded = format!("ROUND({formula}, 10)");
+        assert_eq!(expected, eval_to_string(g, &rounded), "{formula}");
+    }
+    // The cumulative distribution is precise for tiny x.
+    assert_eq!(
+        "1",
+        eval_to_string(g, "ROUND(EXPON.DIST(1e-20, 1, TRUE()) * 1e20, 10)")
+    );
+    // GAMMALN(n + 1) is the logarithm of n!, which is 3628800 for n = 10.
+    assert_eq!("15.1044125731", eval_to_string(g, "ROUND(GAMMALN(11), 10)"));
+
+    for (formula, bad_arg) in [
+        ("EXPON.DIST(-1, 1, TRUE())", "-1"),
+        ("EXPON.DIST(1, 0, TRUE())", "0"),
+        ("EXPONDIST(1, -2, FALSE())", "-2"),
+        ("GAMMALN(0)", "0"),
+        ("GAMMALN(-1.5)", "-1.5"),
+        ("BINOMDIST(11, 10, 0.5, FALSE())", "11"),
+        ("BINOMDIST(-1, 10, 0.5, FALSE())", "-1"),
+        ("POISSON(2, -1, FALSE())", "-1"),
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(Some("#NUM!"), err.msg.excel_code(), "{formula}");
+        assert_eq!(bad_arg, err.span.unwrap().of_str(formula), "{formula}");
+    }
+}
+
+#[test]
+fn test_formula_database_functions() {
+    make_stateless_grid_mock!(|pos| {
+        const CELLS: [[&str; 9]; 6] = [
+            [
+                "Name", "Type", "Height", "Yield", "", "Name", "Height", "Type", "Height",
+            ],
+            [
+                "Apple", "Tree", "18", "14", "", "Apple", ">10", "Tree", ">12",
+            ],
+            ["Pear", "Tree", "12", "10", "", "Pear", "", "", ""],
+            ["Cherry", "Tree", "13", "9", "", "", "", "", ""],
+            ["Apple", "Bush", "14", "10", "", "", "", "", ""],
+            ["Pear", "Bush", "9", "8", "", "", "", "", ""],
+        ];
+        let row = CELLS.get(usize::try_from(pos.y - 1).ok()?)?;
+        let cell = row.get(usize::try_from(pos.x).ok()?)?;
+        (!cell.is_empty()).then(|| cell.to_string())
+    });
+    let g = &mut GridMock;
+
+    // Each row of criteria is an alternative: apples taller than 10, or any
+    // pear.
+    let criteria = "F1:G3";
+    for (expected, func) in [
+        ("42", "DSUM"),
+        ("4", "DCOUNT"),
+        ("10.5", "DAVERAGE"),
+        ("14", "DMAX"),
+        ("8", "DMIN"),
+    ] {
+        let formula = format!("{func}(A1:D6, 'Yield', {criteria})");
+        assert_eq!(expected, eval_to_string(g, &formula), "{formula}");
+    }
+    // Criteria in the same row must all match: trees taller than 12.
+    assert_eq!("23", eval_to_string(g, "DSUM(A1:D6, 'Yield', H1:I2)"));
+    // The field can be a column number, and names ignore case.
+    assert_eq!("23", eval_to_string(g, "DSUM(A1:D6, 4, H1:I2)"));
+    assert_eq!("31", eval_to_string(g, "DSUM(A1:D6, 'height', H1:I2)"));
+    // Criteria can be arrays and use wildcards.
+    assert_eq!(
+        "18",
+        eval_to_string(g, "DSUM(A1:D6, 'Yield', {'Name'; 'P*'})"),
+    );
+    assert_eq!(
+        "5",
+        eval_to_string(g, "DCOUNT(A1:D6, 'Yield', {'Type'; ''})"),
+    );
+    // Text is ignored.
+    assert_eq!("0", eval_to_string(g, "DCOUNT(A1:D6, 'Name', F1:G3)"));
+
+    assert_eq!(
+        "9",
+        eval_to_string(g, "DGET(A1:D6, 'Yield', {'Name'; 'Cherry'})"),
+    );
+    assert_eq!(
+        "Cherry",
+        eval_to_string(g, "DGET(A1:D6, 1, {'Height'; 13})"),
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadNumber,
+        eval(g, "DGET(A1:D6, 'Yield', H1:I2)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "DGET(A1:D6, 'Yield', {'Name'; 'Plum'})")
+            .unwrap_err()
+            .msg,
+    );
+    // Several rows of criteria in an array.
+    assert_eq!(
+        "Cherry",
+        eval_to_string(
+            g,
+            "DGET(A1:D6, 'Name', {'Type', 'Yield'; 'Tree', 9; 'Bush', 11})",
+        ),
+    );
+    assert_eq!(
+        "3",
+        eval_to_string(
+            g,
+            "DCOUNT(A1:D6, 'Height', {'Name', 'Type'; 'Pear', ''; 'Cherry', 'Tree'})",
+        ),
+    );
+
+    for formula in [
+        "DSUM(A1:D6, 'Weight', F1:G3)",
+        "DSUM(A1:D6, 'Yield', {'Weight'; 1})",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::BadCellReference,
+            eval(g, formula).unwrap_err().msg,
+            "{formula}",
+        );
+    }
+    for formula in [
+        "DSUM(A1:D6, 5, F1:G3)",
+        "DSUM(A1:D6, 0, F1:G3)",
+        "DSUM(A1:D1, 'Yield', F1:G3)",
+        "DSUM(A1:D6, 'Yield', F1:G1)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidArgument,
+            eval(g, formula).unwrap_err().msg,
+            "{formula}",
+        );
+    }
+}
+
+#[test]
+fn test_date_criteria() {
+    make_stateless_grid_mock!(|pos| Some(match (pos.x, pos.y) {
+        // Column A: dates as serial numbers, from 2020-12-30 to
+        // 2021-01-03
+        (0, 1..=5) => (44194 + pos.y).to_string(),
+        // Column B: amounts
+        (1, 1..=5) => (pos.y * 10).to_string(),
+        _ => String::new(),
+    }));
+    let g = &mut GridMock;
+
+    // Criteria built from dates compare numerically, and give the same
+    // result as comparing serial numbers.
+    for (criterion, count) in [
+        ("'>=2021-01-01'", "3"),
+        ("'>=' & DATEVALUE('2021-01-01')", "3"),
+        ("'>=44197'", "3"),
+        ("'<1/1/2021'", "2"),
+        ("'2020-12-31'", "1"),
+        ("'<>31-Dec-2020'", "4"),
+        ("'>' & TEXT(44196, 'yyyy-mm-dd')", "3"),
+    ] {
+        let src = format!("COUNTIFS(A1:A5, {criterion})");
+        assert_eq!(count, eval_to_string(g, &src), "{src}");
+    }
+    let src = "COUNTIFS(A1:A5, '>=2020-12-31', A1:A5, '<=2021-01-02')";
+    assert_eq!("3", eval_to_string(g, src));
+    let src = "SUMIFS(B1:B5, A1:A5, '>2020-12-31', A1:A5, '<2021-01-03')";
+    assert_eq!("70", eval_to_string(g, src));
+    assert_eq!(
+        "40",
+        eval_to_string(g, "AVERAGEIF(A1:A5, '>=1/1/2021', B1:B5)")
+    );
+
+    // The criterion is in the same date system as the serial numbers.
+    // 2025-01-03 is 44198 in the 1904 date system.
+    let settings = EvalSettings {
+        date_system: DateSystem::Excel1904,
+        ..Default::default()
+    };
+    for src in [
+        "COUNTIF(A1:A5, '>=2025-01-03')",
+        "COUNTIF(A1:A5, '>=' & DATEVALUE('2025-01-03'))",
+        "COUNTIF(A1:A5, '>=44198')",
+    ] {
+        let result = eval_with_settings(g, src, settings).unwrap();
+        assert_eq!(Value::Number(2.0), result, "{src}");
+    }
+    assert_eq!("0", eval_to_string(g, "COUNTIF(A1:A5, '>=2025-01-03')"));
+}
+
+#[test]
+fn test_conditional_aggregation_inclusion() {
+    make_stateless_grid_mock!(|pos| Some(match (pos.x, pos.y) {
+        // Column A: powers of two, so that each subset has a unique sum
+        (0, 1..=6) => (1 << (pos.y - 1)).to_string(),
+        // Column B: text
+        (1, y) => ["apple", "banana", "", "Apple", "cherry", "avocado"]
+            .get(y as usize - 1)
+            .unwrap_or(&"")
+            .to_string(),
+        // Column C: numbers and text
+        (2, y) => ["5", "10", "x", "15", "", "20"]
+            .get(y as usize - 1)
+            .unwrap_or(&"")
+            .to_string(),
+        _ => String::new(),
+    }));
+    let g = &mut GridMock;
+
+    for criteria in [
+        "B1:B6, 'a*'",
+        "B1:B6, '<>a*'",
+        "B1:B6, ''",
+        "B1:B6, '<>'",
+        "C1:C6, '>=10'",
+        "C1:C6, '<>10'",
+        "C1:C6, 'x'",
+        "B1:B6, 'a*', C1:C6, '<20'",
+        "B1:B6, '?????', C1:C6, '<>'",
+        "B1:B6, 'cherry', C1:C6, 5",
+    ] {
+        let sum = eval(g, &format!("SUMIFS(A1:A6, {criteria})")).unwrap();
+        let mask = sum.as_number().unwrap() as u32;
+        let count = eval(g, &format!("COUNTIFS({criteria})")).unwrap();
+        assert_eq!(
+            Value::Number(mask.count_ones() as f64),
+            count,
+            "for {criteria}",
+        );
+        let average = eval(g, &format!("AVERAGEIFS(A1:A6, {criteria})"));
+        if mask == 0 {
+            assert_eq!(FormulaErrorMsg::DivideByZero, average.unwrap_err().msg);
+        } else {
+            assert_eq!(
+                Value::Number(mask as f64 / mask.count_ones() as f64),
+                average.unwrap(),
+                "for {criteria}",
+            );
+        }
+        let max = eval(g, &format!("MAXIFS(A1:A6, {criteria})")).unwrap();
+        let expected_max = match mask {
+            0 => 0,
+            _ => 1 << (31 - mask.leading_zeros()),
+        };
+        assert_eq!(Value::Number(expected_max as f64), max, "for {criteria}");
+    }
+    assert_eq!("3", eval_to_string(g, "COUNTIFS(B1:B6, 'a*')"));
+    assert_eq!("41", eval_to_string(g, "SUMIFS(A1:A6, B1:B6, 'a*')"));
+}
+
+#[test]
+fn test_implicit_intersection() {
+    make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
+
+    let eval_at = |s: &str, pos: Pos| {
+        parse_formula(s, Pos::ORIGIN)?
+            .eval_blocking(&mut Ctx::new(&mut GridMock, pos))
+            .map(|v| v.inner)
+    };
+
+    // Parsing
+    let form = parse_formula("=@$B$1:$B$5 + 1", Pos::ORIGIN).unwrap();
+    assert_eq!("+(@(:(R1C1, R5C1)), 1.0)", form.to_string());
+
+    // Single column: take the formula's row.
+    assert_eq!(
+        Value::String("13".to_string()),
+        eval_at("@$B$1:$B$5", Pos::new(5, 3)).unwrap(),
+    );
+    // Single row: take the formula's column.
+    assert_eq!(
+        Value::Number(44.0),
+        eval_at("@$A$2:$F$2 * 2", Pos::new(2, 9)).unwrap(),
+    );
+    // Single cell
+    assert_eq!(
+        Value::String("11".to_string()),
+        eval_at("@$B$1", Pos::new(5, 9)).unwrap(),
+    );
+    // Two-dimensional range, which must contain the formula cell itself.
+    assert_eq!(
+        FormulaErrorMsg::CircularReference,
+        eval_at("@($B$1:$D$5)", Pos::new(2, 4)).unwrap_err().msg,
+    );
+    // No intersection, which is `#VALUE!` as in Excel.
+    for (source, pos) in [
+        ("@$B$1:$B$5", Pos::new(5, 6)),
+        ("@$A$2:$F$2", Pos::new(7, 0)),
+        ("@$B$1:$D$5", Pos::new(2, 6)),
+    ] {
+        let err = eval_at(source, pos).unwrap_err();
+        assert_eq!(FormulaErrorMsg::NoImplicitIntersection, err.msg);
+        assert_eq!(Some("#VALUE!"), err.msg.excel_code());
+    }
+
+    // Arrays that aren't ranges use the top-left value.
+    assert_eq!(
+        Value::Number(1.0),
+        eval_at("@{1, 2; 3, 4}", Pos::ORIGIN).unwrap(),
+    );
+    assert_eq!(
+        Value::Number(-13.0),
+        eval_at("-@$B$1:$B$5", Pos::new(5, 3)).unwrap(),
+    );
+    // Non-arrays are unaffected.
+    assert_eq!(
+        Value::Number(-3.0),
+        eval_at("-@(1 + 2)", Pos::ORIGIN).unwrap(),
+    );
+}
+
+#[test]
+fn test_formula_paired_array_sums() {
+    let g = &mut PanicGridMock;
+
+    // x = {1, 2, 3}, y = {4, 5, 6}
+    // x^2 = {1, 4, 9}, y^2 = {16, 25, 36}
+    assert_eq!("-63", eval_to_string(g, "SUMX2MY2({1, 2, 3}, {4, 5, 6})"));
+    assert_eq!("91", eval_to_string(g, "SUMX2PY2({1, 2, 3}, {4, 5, 6})"));
+    assert_eq!("27", eval_to_string(g, "SUMXMY2({1, 2, 3}, {4, 5, 6})"));
+    assert_eq!("27", eval_to_string(g, "SUMXMY2({1; 2; 3}, {4; 5; 6})"));
+
+    // Pairs with non-numeric values are skipped.
+    assert_eq!(
+        "-15",
+        eval_to_string(g, "SUMX2MY2({1, 'a', 3}, {4, 5, ''})"),
+    );
+
+    // Mismatched sizes
+    assert_eq!(
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (1, 3),
+            got: (1, 2),
+        },
+        eval(g, "SUMXMY2({1, 2, 3}, {4, 5})").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (1, 3),
+            got: (3, 1),
+        },
+        eval(g, "SUMXMY2({1, 2, 3}, {4; 5; 6})").unwrap_err().msg,
+    );
+
+    // 2 * 3^1 + 4 * 3^3 + 5 * 3^5 = 6 + 108 + 1215
+    assert_eq!("1329", eval_to_string(g, "SERIESSUM(3, 1, 2, {2, 4, 5})"));
+    assert_eq!("0", eval_to_string(g, "SERIESSUM(3, 1, 2, '')"));
+    assert!(eval(g, "SERIESSUM(3, 1, 2, {2, 'a'})").is_err());
+}
+
+#[test]
+fn test_formula_paired_array_sums_of_cells() {
+    make_stateless_grid_mock!(|pos| match (pos.x, pos.y) {
+        (0, 1) => Some("2".to_string()),
+        (0, 2) => Some("3".to_string()),
+        (0, 3) => Some("n/a".to_string()),
+        (0, 4) => Some("5".to_string()),
+        (1, 1) => Some("1".to_string()),
+        (1, 2) => Some("4".to_string()),
+        (1, 3) => Some("6".to_string()),
+        (1, 5) => Some("7".to_string()),
+        _ => None,
+    });
+    let g = &mut GridMock;
+
+    // Only rows 1 and 2 have two numbers: (2, 1) and (3, 4).
+    assert_eq!("-4", eval_to_string(g, "SUMX2MY2(A1:A5, B1:B5)"));
+    assert_eq!("30", eval_to_string(g, "SUMX2PY2(A1:A5, B1:B5)"));
+    assert_eq!("2", eval_to_string(g, "SUMXMY2(A1:A5, B1:B5)"));
+    // The same pairs are used by `CORREL`.
+    assert_eq!("1", eval_to_string(g, "CORREL(A1:A5, B1:B5)"));
+
+    assert_eq!(
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (5, 1),
+            got: (4, 1),
+        },
+        eval(g, "SUMX2PY2(A1:A5, B1:B4)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_strict_coercion() {
+    make_stateless_grid_mock!(|pos| Some(
+        match (pos.x, pos.y) {
+            (0, 1) => "5",
+            (0, 2) => "five",
+            _ => "",
+        }
+        .to_string()
+    ));
+    let g = &mut GridMock;
+    let strict = EvalSettings {
+        strictness: Strictness::Text,
+        ..Default::default()
+    };
+    let text_error = FormulaErrorMsg::Expected {
+        expected: "a number".into(),
+        got: Some("text".into()),
+    };
+
+    // Text literals are coerced by default, but not in strict mode.
+    for source in ["'5' + 3", "3 * '5'", "-'5'", "'5'%", "'5' < 3"] {
+        assert!(eval(g, source).is_ok(), "{source:?} should evaluate");
+        let err = eval_with_settings(g, source, strict).unwrap_err();
+        assert_eq!(text_error, err.msg, "wrong error for {source:?}");
+    }
+    assert_eq!("8", eval_to_string(g, "'5' + 3"));
+    let err = eval_with_settings(g, "1 + '5'", strict).unwrap_err();
+    assert_eq!(Some(Span { start: 4, end: 7 }), err.span);
+
+    // Cells containing numbers are still numbers.
+    assert_eq!("8", eval_to_string(g, "A1 + 3"));
+    assert_eq!(
+        Value::Number(8.0),
+        eval_with_settings(g, "A1 + 3", strict).unwrap(),
+    );
+    assert_eq!(
+        Value::Bool(true),
+        eval_with_settings(g, "A1 > 3", strict).unwrap(),
+    );
+
+    // Cells containing text are an error either way, but a different one.
+    assert_ne!(text_error, eval(g, "A2 + 3").unwrap_err().msg);
+    assert_eq!(
+        text_error,
+        eval_with_settings(g, "A2 + 3", strict).unwrap_err().msg,
+    );
+
+    // Blank cells and booleans are fine in both modes.
+    assert_eq!(
+        Value::Number(4.0),
+        eval_with_settings(g, "A3 + TRUE() + 3", strict).unwrap(),
+    );
+}
+
+#[test]
+fn test_strict_mode() {
+    make_stateless_grid_mock!(|pos| Some(
+        match (pos.x, pos.y) {
+            (0, 1) => "5",
+            (0, 2) => "five",
+            _ => "",
+        }
+        .to_string()
+    ));
+    let g = &mut GridMock;
+    let strict = EvalSettings {
+        strictness: Strictness::All,
+        ..Default::default()
+    };
+
+    // Each formula evaluates leniently by default, but is an error in strict
+    // mode.
+    for (source, lenient, strict_error) in [
+        ("'5' + 3", "8", FormulaErrorMsg::NotANumber("\"5\"".into())),
+        (
+            "A3 + 3",
+            "3",
+            FormulaErrorMsg::Expected {
+                expected: "a number".into(),
+                got: Some("blank".into()),
+            },
+        ),
+        (
+            "1 = '1'",
+            "TRUE",
+            FormulaErrorMsg::Expected {
+                expected: "number".into(),
+                got: Some("string".into()),
+            },
+        ),
+        (
+            "TRUE() > 0",
+            "TRUE",
+            FormulaErrorMsg::Expected {
+                expected: "boolean".into(),
+                got: Some("number".into()),
+            },
+        ),
+        (
+            "'a' != A1",
+            "TRUE",
+            FormulaErrorMsg::Expected {
+                expected: "string".into(),
+                got: Some("number".into()),
+            },
+        ),
+    ] {
+        assert_eq!(lenient, eval_to_string(g, source), "{source:?}");
+        let err = eval_with_settings(g, source, strict).unwrap_err();
+        assert_eq!(strict_error, err.msg, "{source:?}");
+    }
+    let err = eval_with_settings(g, "1 = '1'", strict).unwrap_err();
+    assert_eq!(Some(Span { start: 4, end: 7 }), err.span);
+
+    // Values of the same type are fine, and cells that look like numbers
+    // are numbers.
+    for (source, expected) in [
+        ("A1 + 3", "8"),
+        ("A1 > 3", "TRUE"),
+        ("A2 = 'five'", "TRUE"),
+        ("A3 = ''", "TRUE"),
+        ("TRUE() + 1", "2"),
+        ("{1, 2} < {2, 2}", "{TRUE, FALSE}"),
+    ] {
+        let value = eval_with_settings(g, source, strict).unwrap();
+        assert_eq!(expected, value.to_string(), "{source:?}");
+    }
+
+    // Functions other than operators still coerce values.
+    assert_eq!(
+        Value::Number(6.0),
+        eval_with_settings(g, "SUM(A1, A3, 1)", strict).unwrap(),
+    );
+}
+
+#[test]
+fn test_formula_text_units() {
+    let g = &mut PanicGridMock;
+    let code_points = EvalSettings {
+        text_unit: TextUnit::CodePoint,
+        ..Default::default()
+    };
+    let eval_code_points =
+        |g: &mut PanicGridMock, s: &str| eval_with_settings(g, s, code_points).unwrap().to_string();
+
+    // The family emoji is five code points: three people joined by two
+    // zero-width joiners. The accent is a combining mark after `e`.
+    let family = "👨\u{200D}👩\u{200D}👧";
+    let accent = "e\u{301}";
+
+    for (source, graphemes, code_points) in [
+        (format!("LEN('{family}')"), "1", "5"),
+        (format!("LEN('a{family}b')"), "3", "7"),
+        (format!("LEN('caf{accent}')"), "4", "5"),
+        (format!("LEFT('{family}!')"), family, "👨"),
+        (
+            format!("RIGHT('caf{accent}', 2)"),
+            &format!("f{accent}"),
+            accent,
+        ),
+        (
+            format!("MID('caf{accent}s', 4, 2)"),
+            &format!("{accent}s"),
+            accent,
+        ),
+        (format!("MID('{family}{family}', 2, 1)"), family, "\u{200D}"),
+    ] {
+        assert_eq!(graphemes, eval_to_string(g, &source), "{source:?}");
+        assert_eq!(code_points, eval_code_points(g, &source), "{source:?}");
+    }
+
+    // Counts past the end of the text are fine.
+    assert_eq!("abc", eval_to_string(g, "LEFT('abc', 10)"));
+    assert_eq!("", eval_to_string(g, "MID('abc', 5, 2)"));
+    assert_eq!("", eval_to_string(g, "LEFT('abc', 0)"));
+    assert_eq!(
+        "{ab, bc}",
+        eval_to_string(g, "{LEFT('abc', 2), RIGHT('abc', 2)}")
+    );
+    for source in [
+        "LEFT('abc', -1)",
+        "RIGHT('abc', -1)",
+        "MID('abc', 0, 1)",
+        "MID('abc', 1, -1)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidArgument,
+            eval(g, source).unwrap_err().msg,
+            "{source:?}",
+        );
+    }
+
+    // `PROPER` uses word segmentation with graphemes. With code points, a
+    // combining mark is not a letter, so it starts a new word.
+    let source = format!("PROPER(\"it's {accent}CLAIR o'neil\")");
+    assert_eq!(
+        format!("It's E\u{301}clair O'neil"),
+        eval_to_string(g, &source),
+    );
+    assert_eq!(
+        format!("It'S E\u{301}Clair O'Neil"),
+        eval_code_points(g, &source),
+    );
+}
+
+#[test]
+fn test_formula_substitute_replace() {
+    let g = &mut PanicGridMock;
+
+    // `SUBSTITUTE` finds text, and `REPLACE` replaces by position.
+    assert_eq!("a-b-c", eval_to_string(g, "SUBSTITUTE('a b c', ' ', '-')"),);
+    assert_eq!(
+        "a b-c",
+        eval_to_string(g, "SUBSTITUTE('a b c', ' ', '-', 2)"),
+    );
+    assert_eq!(
+        "a b c",
+        eval_to_string(g, "SUBSTITUTE('a b c', ' ', '-', 3)"),
+    );
+    assert_eq!("a-b c", eval_to_string(g, "REPLACE('a b c', 2, 1, '-')"));
+    assert_eq!("abc", eval_to_string(g, "REPLACE('a b c', 2, 3, 'b')"));
+    assert_eq!("a b c!", eval_to_string(g, "REPLACE('a b c', 10, 1, '!')"));
+    assert_eq!("xa b c", eval_to_string(g, "REPLACE('a b c', 1, 0, 'x')"));
+    assert_eq!(
+        "{1-2 3, 1 2-3}",
+        eval_to_string(g, "SUBSTITUTE('1 2 3', ' ', '-', {1, 2})"),
+    );
+
+    // Occurrences don't overlap, and are counted from the left.
+    assert_eq!("xxa", eval_to_string(g, "SUBSTITUTE('aaaaa', 'aa', 'x')"));
+    assert_eq!(
+        "aaxa",
+        eval_to_string(g, "SUBSTITUTE('aaaaa', 'aa', 'x', 2)")
+    );
+    assert_eq!(
+        "aaaaa",
+        eval_to_string(g, "SUBSTITUTE('aaaaa', 'aa', 'x', 3)")
+    );
+    assert_eq!(
+        "xaba",
+        eval_to_string(g, "SUBSTITUTE('ababa', 'ab', 'x', 1)")
+    );
+    // Case matters.
+    assert_eq!("Ab-", eval_to_string(g, "SUBSTITUTE('Abb', 'b', '-', 2)"));
+    assert_eq!("Abb", eval_to_string(g, "SUBSTITUTE('Abb', 'a', '-')"));
+
+    // Empty text to find leaves the text unchanged.
+    assert_eq!("abc", eval_to_string(g, "SUBSTITUTE('abc', '', '-')"));
+    assert_eq!("abc", eval_to_string(g, "SUBSTITUTE('abc', '', '-', 1)"));
+    assert_eq!("", eval_to_string(g, "SUBSTITUTE('', 'a', '-')"));
+
+    // Occurrences are made of whole characters.
+    let accent = "e\u{301}";
+    let source = format!("SUBSTITUTE('{accent}e', 'e', 'x')");
+    assert_eq!(format!("{accent}x"), eval_to_string(g, &source));
+
+    for (source, bad_arg) in [
+        ("SUBSTITUTE('abc', 'b', 'x', 0)", "0"),
+        ("SUBSTITUTE('abc', 'b', 'x', -1)", "-1"),
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg, "{source:?}");
+        assert_eq!(bad_arg, err.span.unwrap().of_str(source), "{source:?}");
+    }
+    for source in ["REPLACE('abc', 0, 1, 'x')", "REPLACE('abc', 1, -1, 'x')"] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidArgument,
+            eval(g, source).unwrap_err().msg,
+            "{source:?}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_weeknum() {
+    let g = &mut PanicGridMock;
+
+    // 2021-01-01 is a Friday.
+    assert_eq!("1", eval_to_string(g, "WEEKNUM(44197)"));
+    assert_eq!("53", eval_to_string(g, "ISOWEEKNUM(44197)"));
+    assert_eq!("53", eval_to_string(g, "WEEKNUM(44197, 21)"));
+    // 2021-01-02 is a Saturday.
+    assert_eq!("1", eval_to_string(g, "WEEKNUM(44198, 1)"));
+    assert_eq!("2", eval_to_string(g, "WEEKNUM(44198, 16)"));
+    assert_eq!("53", eval_to_string(g, "ISOWEEKNUM(44198)"));
+    // 2021-01-03 is a Sunday.
+    assert_eq!("2", eval_to_string(g, "WEEKNUM(44199)"));
+    assert_eq!("1", eval_to_string(g, "WEEKNUM(44199, 2)"));
+    assert_eq!("2", eval_to_string(g, "WEEKNUM(44199, 17)"));
+    // 2021-01-04 is a Monday.
+    assert_eq!("2", eval_to_string(g, "WEEKNUM(44200, 2)"));
+    assert_eq!("2", eval_to_string(g, "WEEKNUM(44200, 11)"));
+    assert_eq!("1", eval_to_string(g, "WEEKNUM(44200, 12)"));
+    assert_eq!("1", eval_to_string(g, "ISOWEEKNUM(44200)"));
+
+    // 2020-12-31 is a Thursday.
+    assert_eq!("53", eval_to_string(g, "WEEKNUM(44196)"));
+    assert_eq!("53", eval_to_string(g, "ISOWEEKNUM(44196)"));
+    // 2019-12-30 and 2024-12-30 are Mondays in the next year's first ISO week.
+    assert_eq!("53", eval_to_string(g, "WEEKNUM(43829)"));
+    assert_eq!("1", eval_to_string(g, "ISOWEEKNUM(43829)"));
+    assert_eq!("1", eval_to_string(g, "ISOWEEKNUM(45656)"));
+    // 2010-01-03 is a Sunday in the last ISO week of 2009.
+    assert_eq!("53", eval_to_string(g, "ISOWEEKNUM(40181)"));
+
+    // Times of day are ignored.
+    assert_eq!("1", eval_to_string(g, "ISOWEEKNUM(44200.75)"));
+    assert_eq!("{53, 1}", eval_to_string(g, "ISOWEEKNUM({44197, 44200})"));
+
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "WEEKNUM(44197, 3)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "ISOWEEKNUM(-1)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "WEEKNUM(44197, 1, 1)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_month_arithmetic() {
+    let g = &mut PanicGridMock;
+
+    // 2021-01-31 + 1 month = 2021-02-28
+    assert_eq!("44255", eval_to_string(g, "EDATE(44227, 1)"));
+    // 2021-01-31 - 11 months = 2020-02-29
+    assert_eq!("43890", eval_to_string(g, "EDATE(44227, -11)"));
+    // 2021-01-01 + 12 months = 2022-01-01
+    assert_eq!("44562", eval_to_string(g, "EDATE(44197, 12.9)"));
+    // End of the month two months after 2021-01-01 = 2021-03-31
+    assert_eq!("44286", eval_to_string(g, "EOMONTH(44197, 2)"));
+    // End of the month before 2021-01-01 = 2020-12-31
+    assert_eq!("44196", eval_to_string(g, "EOMONTH(44197, -1)"));
+    assert_eq!(
+        "{44227, 44255}",
+        eval_to_string(g, "EOMONTH(44197, {0, 1})"),
+    );
+
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "EDATE(1, -1)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "EOMONTH(1, 1e300)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_workdays() {
+    let g = &mut PanicGridMock;
+
+    // From Friday 2021-12-24 to Friday 2022-01-07, there are two weekends.
+    assert_eq!("11", eval_to_string(g, "NETWORKDAYS(44554, 44568)"));
+    assert_eq!("-11", eval_to_string(g, "NETWORKDAYS(44568, 44554)"));
+    assert_eq!("1", eval_to_string(g, "NETWORKDAYS(44554, 44554)"));
+    assert_eq!("0", eval_to_string(g, "NETWORKDAYS(44555, 44556)"));
+    // Holidays on weekends or outside the range don't count twice.
+    assert_eq!(
+        "9",
+        eval_to_string(g, "NETWORKDAYS(44554, 44568, {44554, 44561, 44563, 44600})"),
+    );
+
+    // From Friday 2021-12-24, the next business day is Monday 2021-12-27.
+    assert_eq!("44557", eval_to_string(g, "WORKDAY(44554, 1)"));
+    assert_eq!("44554", eval_to_string(g, "WORKDAY(44557, -1)"));
+    assert_eq!("44555", eval_to_string(g, "WORKDAY(44555, 0)"));
+    // Crossing into 2022 with Monday 2022-01-03 as a holiday.
+    assert_eq!("44565", eval_to_string(g, "WORKDAY(44561, 1, 44564)"));
+    assert_eq!(
+        "44566",
+        eval_to_string(g, "WORKDAY(44557, 5, {44558, 44559})"),
+    );
+
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "WORKDAY(44557, -1e9)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "WORKDAY(44557)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_day_counts() {
+    let g = &mut PanicGridMock;
+    let days = |f: &str, start: &str, end: &str, rest: &str| {
+        format!("{f}(DATEVALUE('{start}'), DATEVALUE('{end}'){rest})")
+    };
+
+    assert_eq!(
+        "30",
+        eval_to_string(g, &days("DAYS", "2021-03-31", "2021-03-01", ""))
+    );
+    assert_eq!(
+        "-30",
+        eval_to_string(g, &days("DAYS", "2021-03-01", "2021-03-31", ""))
+    );
+    assert_eq!(
+        "1",
+        eval_to_string(g, "DAYS(DATEVALUE('2021-01-01'), 44196.9)")
+    );
+
+    // End-of-month rules for the US and European methods.
+    for (start, end, us, european) in [
+        ("2011-01-30", "2011-02-28", "28", "28"),
+        ("2011-02-28", "2011-03-31", "30", "32"),
+        ("2011-01-15", "2011-01-31", "16", "15"),
+        ("2011-01-30", "2011-12-31", "330", "330"),
+    ] {
+        assert_eq!(us, eval_to_string(g, &days("DAYS360", start, end, "")));
+        assert_eq!(
+            us,
+            eval_to_string(g, &days("DAYS360", start, end, ", FALSE()"))
+        );
+        assert_eq!(
+            european,
+            eval_to_string(g, &days("DAYS360", start, end, ", TRUE()"))
+        );
+    }
+    assert_eq!(
+        "-30",
+        eval_to_string(g, &days("DAYS360", "2011-02-28", "2011-01-30", ""))
+    );
+
+    // Each basis, from 2012-01-01 to 2012-07-30.
+    let mut yearfrac = |basis: &str| {
+        let src = days("YEARFRAC", "2012-01-01", "2012-07-30", basis);
+        eval_to_string(g, &format!("ROUND({src}, 8)"))
+    };
+    assert_eq!("0.58055556", yearfrac(""));
+    assert_eq!("0.58055556", yearfrac(", 0"));
+    assert_eq!("0.57650273", yearfrac(", 1"));
+    assert_eq!("0.58611111", yearfrac(", 2"));
+    assert_eq!("0.57808219", yearfrac(", 3"));
+    assert_eq!("0.58055556", yearfrac(", 4"));
+
+    // End of February with the 30/360 bases, in days.
+    for (start, end, basis, expected) in [
+        ("2011-02-28", "2012-02-29", 0, "360"),
+        ("2011-02-28", "2012-02-29", 4, "361"),
+        ("2011-02-28", "2011-03-31", 0, "31"),
+        ("2011-02-28", "2011-03-31", 4, "32"),
+        ("2011-01-31", "2011-03-31", 0, "60"),
+        ("2011-01-15", "2011-01-31", 0, "16"),
+    ] {
+        let src = days("YEARFRAC", start, end, &format!(", {basis}"));
+        let days_360 = eval_to_string(g, &format!("ROUND({src} * 360, 8)"));
+        assert_eq!(expected, days_360, "{src}");
+    }
+
+    // Actual/actual uses 366 days if there is a leap day between the dates,
+    // and the average year length if they are more than a year apart.
+    for (start, end, expected) in [
+        ("2011-06-01", "2012-03-01", 274.0 / 366.0),
+        ("2011-06-01", "2012-02-28", 272.0 / 365.0),
+        ("2000-01-01", "2003-01-01", 1096.0 / 365.25),
+    ] {
+        let src = days("YEARFRAC", start, end, ", 1");
+        assert_eq!(Value::Number(expected), eval(g, &src).unwrap(), "{src}");
+    }
+    // The order of the dates doesn't matter.
+    assert_eq!(
+        eval_to_string(g, &days("YEARFRAC", "2011-06-01", "2012-03-01", ", 1")),
+        eval_to_string(g, &days("YEARFRAC", "2012-03-01", "2011-06-01", ", 1")),
+    );
+
+    // Unknown basis
+    for basis in ["-1", "5"] {
+        let src = days(
+            "YEARFRAC",
+            "2012-01-01",
+            "2012-07-30",
+            &format!(", {basis}"),
+        );
+        assert_eq!(FormulaErrorMsg::BadNumber, eval(g, &src).unwrap_err().msg);
+    }
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "DAYS360(-1, 10)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_workdays_intl() {
+    let g = &mut PanicGridMock;
+
+    // From Friday 2021-12-24 to Friday 2022-01-07, with various weekends.
+    assert_eq!("11", eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568)"));
+    assert_eq!("11", eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, 1)"));
+    assert_eq!(
+        "11",
+        eval_to_string(g, "networkdays.intl(44554, 44568, '0000011')"),
+    );
+    assert_eq!(
+        "13",
+        eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, 11)")
+    );
+    assert_eq!(
+        "13",
+        eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, '1000000')"),
+    );
+    assert_eq!("10", eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, 7)"));
+    // Friday 2021-12-31 is already on the weekend; Monday 2022-01-03 is not.
+    assert_eq!(
+        "10",
+        eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, 7, {44561, 44600})"),
+    );
+    assert_eq!(
+        "9",
+        eval_to_string(g, "NETWORKDAYS.INTL(44554, 44568, 7, {44561, 44564})"),
+    );
+
+    // With a Friday-Saturday weekend, the next business day is Sunday.
+    assert_eq!("44556", eval_to_string(g, "WORKDAY.INTL(44554, 1, 7)"));
+    assert_eq!(
+        "44555",
+        eval_to_string(g, "WORKDAY.INTL(44554, 1, '0000000')")
+    );
+    assert_eq!(
+        "44558",
+        eval_to_string(g, "WORKDAY.INTL(44554, 1, 1, 44557)")
+    );
+    assert_eq!("44554", eval_to_string(g, "WORKDAY.INTL(44556, -1, 17)"));
+
+    for bad_weekend in ["'1111111'", "8", "0"] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidArgument,
+            eval(g, &format!("WORKDAY.INTL(44554, 1, {bad_weekend})"))
+                .unwrap_err()
+                .msg,
+        );
+    }
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "WORKDAY(44554, 1, 1, 44557)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "NETWORKDAYS.INTL(44554, 44568, 1, 44557, 0)")
+            .unwrap_err()
+            .msg,
+    );
+}
+
+#[test]
+fn test_formula_datevalue_timevalue() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("44269", eval_to_string(g, "DATEVALUE('2021-03-14')"));
+    assert_eq!("44269", eval_to_string(g, "DATEVALUE('3/14/2021')"));
+    assert_eq!("44269", eval_to_string(g, "DATEVALUE('14-Mar-2021')"));
+    assert_eq!(
+        "{44269, 44270}",
+        eval_to_string(g, "DATEVALUE({'2021-03-14', '2021-03-15'})"),
+    );
+    assert_eq!(
+        "12",
+        eval_to_string(g, "ISOWEEKNUM(DATEVALUE('2021-03-25'))")
+    );
+    assert_eq!("0.5", eval_to_string(g, "TIMEVALUE('12:00')"));
+    assert_eq!("0.75", eval_to_string(g, "TIMEVALUE('6:00 PM')"));
+
+    // Ambiguous dates are rejected rather than guessed.
+    let err = eval(g, "DATEVALUE('03-04-2021')").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "date".into(),
+            got: Some("\"03-04-2021\"".into()),
+        },
+        err.msg,
+    );
+    assert_eq!(Some(Span { start: 10, end: 22 }), err.span);
+    assert_eq!(
+        "Expected",
+        eval(g, "TIMEVALUE('25:00')").unwrap_err().msg.short_name(),
+    );
+}
+
+#[test]
+fn test_formula_date_system() {
+    let g = &mut PanicGridMock;
+    let excel_1904 = EvalSettings {
+        date_system: DateSystem::Excel1904,
+        ..Default::default()
+    };
+    let eval_1904 = |s: &str| eval_with_settings(&mut PanicGridMock, s, excel_1904);
+    let eval_1904_to_string = |s: &str| eval_1904(s).unwrap().to_string();
+
+    // The same calendar date is 1462 days (four years and a day) earlier in
+    // the 1904 system.
+    assert_eq!("44269", eval_to_string(g, "DATEVALUE('2021-03-14')"));
+    assert_eq!("42807", eval_1904_to_string("DATEVALUE('2021-03-14')"));
+    for s in ["2021-03-14", "1904-01-01", "1900-03-01", "12/31/9999"] {
+        let formula = format!("DATEVALUE('{s}')");
+        let serial_1900: f64 = eval(g, &formula).unwrap().try_into().unwrap();
+        match eval_1904(&formula) {
+            Ok(serial_1904) => assert_eq!(Value::Number(serial_1900 - 1462.0), serial_1904),
+            Err(e) => assert!(serial_1900 < 1462.0, "{s:?}: {e}"),
+        }
+    }
+    let text = "TEXT(42807, 'yyyy-mm-dd dddd')";
+    assert_eq!("2021-03-14 Sunday", eval_1904_to_string(text));
+    assert_eq!("2017-03-13 Monday", eval_to_string(g, text));
+    assert_eq!(
+        "1904-01-01 Friday",
+        eval_1904_to_string("TEXT(0, 'yyyy-mm-dd dddd')")
+    );
+
+    // 1904 really is a leap year, and the 1900 leap year bug doesn't apply.
+    assert_eq!("31", eval_1904_to_string("EDATE(0, 1)"));
+    assert_eq!("59", eval_1904_to_string("EOMONTH(0, 1)"));
+    assert_eq!("60", eval_to_string(g, "DATEVALUE('1900-02-29')"));
+
+    // Weekdays shift with the epoch.
+    assert_eq!("1", eval_1904_to_string("WEEKNUM(1)"));
+    assert_eq!("2", eval_1904_to_string("WEEKNUM(2)"));
+    assert_eq!("3", eval_1904_to_string("WORKDAY(0, 1)"));
+    assert_eq!("0", eval_1904_to_string("WORKDAY(1, -1)"));
+    assert_eq!("5", eval_1904_to_string("NETWORKDAYS(0, 6)"));
+    assert_eq!("4", eval_1904_to_string("NETWORKDAYS(0, 6, 3)"));
+
+    // Dates before 1904 don't exist.
+    for s in [
+        "DATEVALUE('1900-02-29')",
+        "DATEVALUE('1903-12-31')",
+        "EDATE(0, -1)",
+        "WORKDAY(0, -1)",
+        "WEEKNUM(-1)",
+        "EDATE(2957003, 1)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidDate,
+            eval_1904(s).unwrap_err().msg,
+            "{s:?}",
+        );
+    }
+    assert_eq!("2957003", eval_1904_to_string("DATEVALUE('12/31/9999')"));
+
+    // DATE counts from the epoch of the date system.
+    assert_eq!("43831", eval_to_string(g, "DATE(2020, 1, 1)"));
+    assert_eq!("42369", eval_1904_to_string("DATE(2020, 1, 1)"));
+    assert_eq!("0", eval_1904_to_string("DATE(1904, 1, 1)"));
+    assert_eq!("2957003", eval_1904_to_string("DATE(9999, 12, 31)"));
+    assert_eq!(
+        "1904-01-01",
+        eval_1904_to_string("TEXT(DATE(1904, 1, 1), 'yyyy-mm-dd')"),
+    );
+    for s in ["DATE(1900, 2, 29)", "DATE(1903, 12, 31)"] {
+        assert_eq!(
+            "InvalidDate",
+            eval_1904(s).unwrap_err().msg.short_name(),
+            "{s}"
+        );
+    }
+
+    // The nonexistent 1900-02-29 is serial number 60 in the 1900 system, and
+    // days roll over around it like in Excel.
+    assert_eq!("59", eval_to_string(g, "DATE(1900, 2, 28)"));
+    assert_eq!("60", eval_to_string(g, "DATE(1900, 2, 29)"));
+    assert_eq!("60", eval_to_string(g, "DATE(1900, 3, 0)"));
+    assert_eq!("61", eval_to_string(g, "DATE(1900, 2, 30)"));
+    assert_eq!("61", eval_to_string(g, "DATE(1900, 3, 1)"));
+    assert_eq!("0", eval_to_string(g, "DATE(1900, 1, 0)"));
+    assert_eq!(
+        "{1900, 2, 29}",
+        eval_to_string(g, "{YEAR(60), MONTH(60), DAY(60)}"),
+    );
+    assert_eq!(
+        "{1900, 3, 1}",
+        eval_to_string(g, "{YEAR(61), MONTH(61), DAY(61)}"),
+    );
+}
+
+#[test]
+fn test_formula_date() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("44269", eval_to_string(g, "DATE(2021, 3, 14)"));
+    assert_eq!("44269", eval_to_string(g, "DATE(2021.9, 3.9, 14.9)"));
+    // Months and days roll over.
+    assert_eq!("44256", eval_to_string(g, "DATE(2021, 3, 1)"));
+    assert_eq!("44255", eval_to_string(g, "DATE(2021, 3, 0)"));
+    assert_eq!("44621", eval_to_string(g, "DATE(2021, 15, 1)"));
+    assert_eq!("43831", eval_to_string(g, "DATE(2021, -11, 1)"));
+    assert_eq!("44286", eval_to_string(g, "DATE(2021, 3, 31)"));
+    assert_eq!("44287", eval_to_string(g, "DATE(2021, 3, 32)"));
+    // Years before 1900 are relative to 1900.
+    assert_eq!(
+        eval_to_string(g, "DATE(2020, 1, 1)"),
+        eval_to_string(g, "DATE(120, 1, 1)")
+    );
+    assert_eq!("1", eval_to_string(g, "DATE(0, 1, 1)"));
+
+    for s in [
+        "DATE(-1, 1, 1)",
+        "DATE(10000, 1, 1)",
+        "DATE(9999, 13, 1)",
+        "DATE(1900, 1, -1)",
+        "DATE(2020, 1, 1e100)",
+    ] {
+        assert_eq!(
+            "InvalidDate",
+            eval(g, s).unwrap_err().msg.short_name(),
+            "{s}"
+        );
+    }
+    assert_eq!("{44197, 44228}", eval_to_string(g, "DATE(2021, {1, 2}, 1)"),);
+}
+
+#[test]
+fn test_formula_regression() {
+    let g = &mut PanicGridMock;
+
+    /// Asserts that a formula evaluates to numbers approximately equal to
+    /// `expected`, in row-major order.
+    fn assert_approx(g: &mut dyn GridProxy, expected: &[f64], s: &str) {
+        let value = eval(g, s).expect(s);
+        let got = Spanned {
+            span: Span::empty(0),
+            inner: value,
+        }
+        .to_numbers()
+        .unwrap();
+        assert_eq!(expected.len(), got.len(), "wrong length for {s:?}");
+        for (e, g) in expected.iter().zip(&got) {
+            assert!((e - g).abs() < 1e-9, "expected {e}, got {g} for {s:?}");
+        }
+    }
+
+    // y = 2.1x - 1.3, with residuals
+    let (y, x) = ("{1, 3, 4, 8, 9}", "{1, 2, 3, 4, 5}");
+    assert_approx(g, &[2.1], &format!("SLOPE({y}, {x})"));
+    assert_approx(g, &[-1.3], &format!("INTERCEPT({y}, {x})"));
+    assert_approx(g, &[21.0 / 460_f64.sqrt()], &format!("CORREL({y}, {x})"));
+    assert_approx(g, &[21.0 / 460_f64.sqrt()], &format!("CORREL({x}, {y})"));
+    assert_approx(g, &[441.0 / 460.0], &format!("RSQ({y}, {x})"));
+    assert_approx(g, &[2.1, -1.3], &format!("LINEST({y}, {x})"));
+    assert_approx(g, &[2.1, -1.3], &format!("LINEST({y})"));
+    assert_approx(g, &[0.8, 2.9, 5.0, 7.1, 9.2], &format!("TREND({y})"));
+    assert_approx(g, &[11.3, 13.4], &format!("TREND({y}, {x}, {{6, 7}})"));
+
+    // Regression statistics
+    let se_y = (1.9_f64 / 3.0).sqrt();
+    #[rustfmt::skip]
+    assert_approx(g, &[
+        2.1, -1.3,
+        se_y / 10_f64.sqrt(), se_y * 1.1_f64.sqrt(),
+        441.0 / 460.0, se_y,
+        44.1 / (1.9 / 3.0), 3.0,
+        44.1, 1.9,
+    ], &format!("LINEST({y}, {x}, TRUE(), TRUE())"));
+    // Without an intercept, there is no standard error for it.
+    assert_approx(
+        g,
+        &[30.0 / 14.0, 0.0],
+        "LINEST({2, 5, 6}, {1, 2, 3}, FALSE())",
+    );
+    let stats = eval(g, "LINEST({2, 5, 6}, {1, 2, 3}, FALSE(), TRUE())").unwrap();
+    assert_eq!(Some((5, 2)), stats.array_size());
+    let Value::Array(stats) = stats else { panic!() };
+    assert_eq!(Some(&Value::Blank), stats.get(1, 1));
+    assert_eq!(Some(&Value::Number(2.0)), stats.get(3, 1));
+
+    // Non-numeric pairs are skipped.
+    assert_approx(g, &[2.0], "SLOPE({2, 'a', 6, 8}, {1, 2, 3, 4})");
+
+    // Multiple regression: y = 2*x1 + 3*x2 + 1
+    let (y, x) = ("{9; 8; 19; 18; 32}", "{1, 2; 2, 1; 3, 4; 4, 3; 5, 7}");
+    assert_approx(g, &[3.0, 2.0, 1.0], &format!("LINEST({y}, {x})"));
+    assert_approx(g, &[16.0, 1.0], &format!("TREND({y}, {x}, {{6, 1; 0, 0}})"));
+    // Same thing, but sideways.
+    let (y, x) = ("{9, 8, 19, 18, 32}", "{1, 2, 3, 4, 5; 2, 1, 4, 3, 7}");
+    assert_approx(g, &[3.0, 2.0, 1.0], &format!("LINEST({y}, {x})"));
+    assert_approx(g, &[16.0, 1.0], &format!("TREND({y}, {x}, {{6, 0; 1, 0}})"));
+
+    // y = 2^x
+    assert_approx(
+        g,
+        &[32.0, 64.0],
+        "GROWTH({2, 4, 8, 16}, {1, 2, 3, 4}, {5, 6})",
+    );
+    assert_approx(g, &[2.0, 4.0, 8.0], "GROWTH({2, 4, 8})");
+
+    // y = 3 * 2^x
+    let (y, x) = ("{6, 12, 24, 48}", "{1, 2, 3, 4}");
+    assert_approx(g, &[2.0, 3.0], &format!("LOGEST({y}, {x})"));
+    assert_approx(g, &[2.0, 3.0], &format!("LOGEST({y})"));
+    assert_approx(g, &[96.0, 1.5], &format!("GROWTH({y}, {x}, {{5, -1}})"));
+    assert_eq!(
+        Some((1, 2)),
+        eval(g, &format!("GROWTH({y}, {x}, {{5, 6}})"))
+            .unwrap()
+            .array_size(),
+    );
+    // Statistics are for the fit to ln(y), which is exact here.
+    let stats = eval(g, &format!("LOGEST({y}, {x}, TRUE(), TRUE())")).unwrap();
+    assert_eq!(Some((5, 2)), stats.array_size());
+    let Value::Array(stats) = stats else { panic!() };
+    let Some(&Value::Number(r_squared)) = stats.get(2, 0) else {
+        panic!("{:?}", stats.get(2, 0));
+    };
+    assert!((r_squared - 1.0).abs() < 1e-9, "{r_squared}");
+    // Without an intercept, b is 1.
+    assert_approx(g, &[4.0, 1.0], "LOGEST({4, 16, 64}, {1, 2, 3}, FALSE())");
+    // Multiple regression: y = 2 * 3^x1 * 0.5^x2
+    let (y, x) = ("{1; 3; 6; 18}", "{0, 1; 1, 1; 1, 0; 2, 0}");
+    assert_approx(g, &[0.5, 3.0, 2.0], &format!("LOGEST({y}, {x})"));
+
+    // Errors
+    for (expected, s) in [
+        ("ArraySizeMismatch", "SLOPE({1, 2, 3}, {1, 2})"),
+        ("ArraySizeMismatch", "LINEST({1; 2; 3}, {1; 2})"),
+        (
+            "ArraySizeMismatch",
+            "TREND({1; 2; 3}, {1, 2; 2, 1; 3, 5}, {1, 2, 3})",
+        ),
+        ("DivideByZero", "SLOPE({1, 2, 3}, {5, 5, 5})"),
+        ("DivideByZero", "CORREL({1, 1, 1}, {1, 2, 3})"),
+        ("NotAvailable", "LINEST({1, 2, 3}, {5, 5, 5})"),
+        ("NotAvailable", "TREND({1; 2; 3}, {1, 2; 2, 4; 3, 6})"),
+        ("BadNumber", "GROWTH({1, 0, 3})"),
+        ("BadNumber", "LOGEST({1, -2, 3})"),
+        ("BadArgumentCount", "LINEST()"),
+        ("BadArgumentCount", "TREND({1}, {1}, {1}, TRUE(), 0)"),
+    ] {
+        assert_eq!(expected, eval(g, s).unwrap_err().msg.short_name(), "{s:?}");
+    }
+}
+
+#[test]
+fn test_formula_matrix_functions() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("{17; 39}", eval_to_string(g, "MMULT({1, 2; 3, 4}, {5; 6})"));
+    assert_eq!("{32}", eval_to_string(g, "MMULT({1, 2, 3}, {4; 5; 6})"));
+    assert_eq!(
+        "{4, 5, 6; 8, 10, 12}",
+        eval_to_string(g, "MMULT({1; 2}, {4, 5, 6})"),
+    );
+    assert_eq!("{6}", eval_to_string(g, "MMULT(2, 3)"));
+    let err = eval(g, "MMULT({1, 2}, {1, 2})").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (2, 2),
+            got: (1, 2),
+        },
+        err.msg,
+    );
+    assert_eq!(Some(Span { start: 14, end: 20 }), err.span);
+
+    assert_eq!(
+        "{0.5, 0; 0, 0.25}",
+        eval_to_string(g, "MINVERSE({2, 0; 0, 4})"),
+    );
+    assert_eq!(
+        "{1, -1; -1, 2}",
+        eval_to_string(g, "MINVERSE({2, 1; 1, 1})"),
+    );
+    assert_eq!(
+        "{TRUE, TRUE; TRUE, TRUE}",
+        eval_to_string(g, "MMULT({2, 1; 1, 1}, MINVERSE({2, 1; 1, 1})) = MUNIT(2)"),
+    );
+    assert_eq!("-2", eval_to_string(g, "MDETERM({1, 2; 3, 4})"));
+    assert_eq!("5", eval_to_string(g, "MDETERM(5)"));
+    assert_eq!("{1, 0, 0; 0, 1, 0; 0, 0, 1}", eval_to_string(g, "MUNIT(3)"),);
+
+    assert_eq!(
+        "{-24, 18, 5; 20, -15, -4; -5, 4, 1}",
+        eval_to_string(g, "ROUND(MINVERSE({1, 2, 3; 0, 1, 4; 5, 6, 0}), 9)"),
+    );
+    assert_eq!(
+        "1",
+        eval_to_string(g, "ROUND(MDETERM({1, 2, 3; 0, 1, 4; 5, 6, 0}), 9)")
+    );
+    assert_eq!(
+        "-5",
+        eval_to_string(g, "ROUND(MDETERM({0, 2, 1; 1, 1, 0; 3, 0, 1}), 9)"),
+    );
+
+    // Singular matrices have no inverse, and their determinant is zero.
+    let err = eval(g, "MINVERSE({1, 2; 2, 4})").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadNumber, err.msg);
+    assert_eq!(Some("#NUM!"), err.msg.excel_code());
+    assert_eq!("0", eval_to_string(g, "MDETERM({1, 2; 2, 4})"));
+
+    for (expected, s) in [
+        ("ArraySizeMismatch", "MINVERSE({1, 2, 3})"),
+        ("ArraySizeMismatch", "MDETERM({1; 2})"),
+        ("TypeMismatch", "MDETERM({1, 'a'; 3, 4})"),
+        ("InvalidArgument", "MUNIT(0)"),
+        ("EvaluationLimitExceeded", "MUNIT(1e9)"),
+    ] {
+        assert_eq!(expected, eval(g, s).unwrap_err().msg.short_name(), "{s:?}");
+    }
+
+    // Multiplying and decomposing take steps for each scalar operation.
+    let settings = EvalSettings {
+        max_eval_steps: 1_000,
+        ..Default::default()
+    };
+    for s in [
+        "MMULT(MUNIT(8), MUNIT(8))",
+        "MINVERSE(MUNIT(8))",
+        "MDETERM(MUNIT(8))",
+    ] {
+        assert!(eval_with_settings(g, s, settings).is_ok(), "{s:?}");
+    }
+    for s in [
+        "MMULT(MUNIT(10), MUNIT(10))",
+        "MINVERSE(MUNIT(10))",
+        "MDETERM(MUNIT(10))",
+    ] {
+        let err = eval_with_settings(g, s, settings).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::EvaluationLimitExceeded { limit: 1_000 },
+            err.msg,
+            "{s:?}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_round() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("3.14", eval_to_string(g, "ROUND(3.14159, 2)"));
+    assert_eq!("3", eval_to_string(g, "ROUND(3.14159, 0)"));
+    assert_eq!("3", eval_to_string(g, "ROUND(2.5, 0)"));
+    assert_eq!("-3", eval_to_string(g, "ROUND(-2.5, 0)"));
+    assert_eq!("1200", eval_to_string(g, "ROUND(1234.5, -2)"));
+    assert_eq!("0", eval_to_string(g, "ROUND(1234.5, -4)"));
+    assert_eq!("{1.2, 3.5}", eval_to_string(g, "ROUND({1.23, 3.45}, 1)"));
+    // Digits are truncated.
+    assert_eq!("3.1", eval_to_string(g, "ROUND(3.14159, 1.9)"));
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "ROUND(3.14159)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_floor_ceiling() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("4", eval_to_string(g, "FLOOR(5, 2)"));
+    assert_eq!("6", eval_to_string(g, "CEILING(5, 2)"));
+    assert_eq!("1.5", eval_to_string(g, "FLOOR(1.7, 0.25)"));
+    // `0.3 / 0.1` is slightly less than 3, but shouldn't round down to 2.
+    assert_eq!("0.3", eval_to_string(g, "ROUND(FLOOR(0.3, 0.1), 10)"));
+    // Negative numbers need a negative significance, and round toward zero
+    // for `FLOOR` and away from zero for `CEILING`.
+    assert_eq!("-4", eval_to_string(g, "FLOOR(-5, -2)"));
+    assert_eq!("-6", eval_to_string(g, "CEILING(-5, -2)"));
+    assert_eq!("{2, 4, 6}", eval_to_string(g, "CEILING({1, 4, 5}, 2)"));
+    assert_eq!("0", eval_to_string(g, "FLOOR(0, -2)"));
+    assert_eq!("0", eval_to_string(g, "CEILING(5, 0)"));
+
+    // The `.PRECISE` variants ignore the sign of the significance.
+    assert_eq!("-6", eval_to_string(g, "FLOOR.PRECISE(-5, 2)"));
+    assert_eq!("-6", eval_to_string(g, "FLOOR.PRECISE(-5, -2)"));
+    assert_eq!("4", eval_to_string(g, "FLOOR.PRECISE(5, -2)"));
+    assert_eq!("-4", eval_to_string(g, "CEILING.PRECISE(-5, 2)"));
+    assert_eq!("6", eval_to_string(g, "CEILING.PRECISE(5, -2)"));
+    assert_eq!("-4", eval_to_string(g, "ISO.CEILING(-5, 2)"));
+    assert_eq!("-3", eval_to_string(g, "FLOOR.PRECISE(-2.5)"));
+    assert_eq!("3", eval_to_string(g, "ISO.CEILING(2.5)"));
+    assert_eq!("0", eval_to_string(g, "FLOOR.PRECISE(5, 0)"));
+    assert_eq!("0", eval_to_string(g, "CEILING.PRECISE(-5, 0)"));
+
+    for (expected, s) in [
+        (FormulaErrorMsg::BadNumber, "FLOOR(-5, 2)"),
+        (FormulaErrorMsg::BadNumber, "FLOOR(5, -2)"),
+        (FormulaErrorMsg::BadNumber, "CEILING(-5, 2)"),
+        (FormulaErrorMsg::DivideByZero, "FLOOR(5, 0)"),
+        (FormulaErrorMsg::BadArgumentCount, "FLOOR(5)"),
+        (FormulaErrorMsg::BadArgumentCount, "FLOOR.PRECISE(5, 1, 1)"),
+        (FormulaErrorMsg::Overflow, "ISO.CEILING(1e308, 1e-308)"),
+        (FormulaErrorMsg::Overflow, "FLOOR(-1e308, -1e-308)"),
+    ] {
+        assert_eq!(expected, eval(g, s).unwrap_err().msg, "{s:?}");
+    }
+}
+
+#[test]
+fn test_formula_randarray() {
+    let g = &mut PanicGridMock;
+    let seeded = EvalSettings {
+        random_seed: Some(42),
+        ..Default::default()
+    };
+    let randarray = |g: &mut PanicGridMock, s: &str| -> Vec<Vec<f64>> {
+        match eval_with_settings(g, s, seeded).unwrap() {
+            Value::Array(a) => a
+                .rows()
+                .map(|row| row.iter().map(|v| v.clone().try_into().unwrap()).collect())
+                .collect(),
+            other => panic!("expected array from {s:?}, got {other:?}"),
+        }
+    };
+
+    // Shape
+    let a = randarray(g, "RANDARRAY(3, 4)");
+    assert_eq!(3, a.len());
+    assert!(a.iter().all(|row| row.len() == 4));
+    assert!(a.iter().flatten().all(|x| (0.0..1.0).contains(x)));
+    assert_eq!(
+        vec![1],
+        randarray(g, "RANDARRAY()")
+            .iter()
+            .map(Vec::len)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(5, randarray(g, "RANDARRAY(5)").len());
+    assert_eq!(
+        vec![2],
+        randarray(g, "RANDARRAY(1, 2.9)")
+            .iter()
+            .map(Vec::len)
+            .collect::<Vec<_>>()
+    );
+
+    // The same seed gives the same numbers.
+    assert_eq!(a, randarray(g, "RANDARRAY(3, 4)"));
+    let other_seed = EvalSettings {
+        random_seed: Some(43),
+        ..seeded
+    };
+    assert_ne!(
+        eval_with_settings(g, "RANDARRAY(3, 4)", seeded).unwrap(),
+        eval_with_settings(g, "RANDARRAY(3, 4)", other_seed).unwrap(),
+    );
+
+    // Bounds
+    let a = randarray(g, "RANDARRAY(20, 20, -5, 5)");
+    assert!(a.iter().flatten().all(|x| (-5.0..5.0).contains(x)));
+    assert!(a.iter().flatten().any(|x| x.fract() != 0.0));
+    let a = randarray(g, "RANDARRAY(1, 1, 3, 3)");
+    assert_eq!(vec![vec![3.0]], a);
+
+    // Integer mode includes both bounds.
+    let a = randarray(g, "RANDARRAY(20, 20, 1, 6, TRUE())");
+    assert!(a.iter().flatten().all(|x| x.fract() == 0.0));
+    for i in 1..=6 {
+        assert!(a.iter().flatten().any(|&x| x == i as f64), "missing {i}");
+    }
+    assert!(a.iter().flatten().all(|x| (1.0..=6.0).contains(x)));
+    let a = randarray(g, "RANDARRAY(10, 10, 0.5, 2.5, TRUE())");
+    assert!(a.iter().flatten().all(|&x| x == 1.0 || x == 2.0));
+
+    for (expected, s) in [
+        (FormulaErrorMsg::BadNumber, "RANDARRAY(0)"),
+        (FormulaErrorMsg::BadNumber, "RANDARRAY(2, -1)"),
+        (FormulaErrorMsg::BadNumber, "RANDARRAY(2, 2, 5, 1)"),
+        (FormulaErrorMsg::BadNumber, "RANDARRAY(2, 2, 2)"),
+        (
+            FormulaErrorMsg::BadNumber,
+            "RANDARRAY(2, 2, 1.2, 1.8, TRUE())",
+        ),
+        (
+            FormulaErrorMsg::EvaluationLimitExceeded {
+                limit: DEFAULT_MAX_EVAL_STEPS,
+            },
+            "RANDARRAY(100000, 100000)",
+        ),
+        (
+            FormulaErrorMsg::BadArgumentCount,
+            "RANDARRAY(1, 1, 0, 1, TRUE(), 1)",
+        ),
+    ] {
+        assert_eq!(expected, eval(g, s).unwrap_err().msg, "{s:?}");
+    }
+
+    assert!(parse_formula("1 + SUM(RANDARRAY(2))", Pos::ORIGIN)
+        .unwrap()
+        .is_volatile());
+    assert!(!parse_formula("1 + SUM({1, 2})", Pos::ORIGIN)
+        .unwrap()
+        .is_volatile());
+}
+
+#[test]
+fn test_volatile_and_dynamic_references() {
+    for (s, volatile, dynamic) in [
+        ("SUM(A1:B3)", false, false),
+        ("IF(A1, {1, 2}, 'x')", false, false),
+        ("RANDARRAY(2)", true, false),
+        ("(1 + randarray(2))", true, false),
+        ("CELL(1, A1)", true, true),
+        ("SUM({1, c(0, 2)})", true, true),
+        ("LET(x, 3, CELL(x, 0))", true, true),
+        ("SUM(1, IF(A1, -ABS(RANDARRAY(1)), 2))", true, false),
+        ("{1, 2; 3, (4 * c(0, 1))}", true, true),
+        // The body of a `LAMBDA` counts even though it isn't called yet.
+        ("LAMBDA(x, x + RANDARRAY(1))", true, false),
+        ("LET(f, LAMBDA(x, CELL(x, 0)), MAP({1, 2}, f))", true, true),
+        ("LET(f, LAMBDA(x, x * 2), f(RANDARRAY(1)))", true, false),
+        ("LET(f, LAMBDA(x, x * 2), f(3))", false, false),
+    ] {
+        let formula = parse_formula(s, Pos::ORIGIN).unwrap();
+        assert_eq!(volatile, formula.ast.has_volatile(), "{s:?}");
+        assert_eq!(volatile, formula.is_volatile(), "{s:?}");
+        assert_eq!(dynamic, formula.has_dynamic_references(), "{s:?}");
+    }
+}
+
+#[test]
+fn test_formula_year_month_day() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("2024", eval_to_string(g, "YEAR(DATEVALUE('2024-03-05'))"));
+    assert_eq!("3", eval_to_string(g, "MONTH(DATEVALUE('2024-03-05'))"));
+    assert_eq!(
+        "5",
+        eval_to_string(g, "DAY(DATEVALUE('2024-03-05') + 0.75)")
+    );
+    assert_eq!(
+        "{1900, 2, 29}",
+        eval_to_string(g, "{YEAR(60), MONTH(60), DAY(60)}")
+    );
+
+    let settings = EvalSettings {
+        date_system: DateSystem::Excel1904,
+        ..Default::default()
+    };
+    let result = eval_with_settings(g, "YEAR(0)", settings).unwrap();
+    assert_eq!(Value::Number(1904.0), result);
+
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "YEAR(-1)").unwrap_err().msg,
+    );
+}
+
+#[cfg(feature = "datetime")]
+#[test]
+fn test_formula_datetime() {
+    let g = &mut PanicGridMock;
+
+    let dt = "DATETIME('2024-03-05T22:30:00-05:00')";
+    assert_eq!("2024-03-05T22:30:00-05:00", eval_to_string(g, dt));
+    assert_eq!("datetime", eval(g, dt).unwrap().type_name());
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, "DATETIME('2024-03-05 22:30')").unwrap_err().msg,
+    );
+
+    // Date functions use the local date and time.
+    assert_eq!(
+        "{2024, 3, 5}",
+        eval_to_string(g, &format!("{{YEAR({dt}), MONTH({dt}), DAY({dt})}}"))
+    );
+    assert_eq!(
+        eval_to_string(g, "DATEVALUE('2024-03-05') + 0.9375"),
+        eval_to_string(g, &format!("{dt} * 1")),
+    );
+    // Even in the 1904 date system.
+    let settings = EvalSettings {
+        date_system: DateSystem::Excel1904,
+        ..Default::default()
+    };
+    let result = eval_with_settings(g, &format!("YEAR({dt})"), settings).unwrap();
+    assert_eq!(Value::Number(2024.0), result);
+
+    // Adding and subtracting numbers moves by days.
+    assert_eq!(
+        "2024-03-07T10:30:00-05:00",
+        eval_to_string(g, &format!("{dt} + 1.5"))
+    );
+    assert_eq!(
+        "2024-03-07T10:30:00-05:00",
+        eval_to_string(g, &format!("1.5 + {dt}"))
+    );
+    assert_eq!(
+        "2024-03-05T21:30:00-05:00",
+        eval_to_string(g, &format!("{dt} - 1/24"))
+    );
+    assert_eq!(
+        "0.5",
+        eval_to_string(g, &format!("{dt} - DATETIME('2024-03-05T10:30:00-05:00')")),
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidDate,
+        eval(g, &format!("{dt} + 1e7")).unwrap_err().msg,
+    );
+
+    // Date-times compare by instant, regardless of offset.
+    let same_instant = "DATETIME('2024-03-06T03:30:00Z')";
+    let later = "DATETIME('2024-03-06T04:30:00+02:00')";
+    assert_eq!("TRUE", eval_to_string(g, &format!("{dt} = {same_instant}")));
+    assert_eq!(
+        "FALSE",
+        eval_to_string(g, &format!("{dt} != {same_instant}"))
+    );
+    assert_eq!("TRUE", eval_to_string(g, &format!("{later} < {dt}")));
+    // A date-time compares with a number by its serial number.
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, &format!("{dt} > DATEVALUE('2024-03-05')")),
+    );
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, &format!("{dt} = DATEVALUE('2024-03-05') + 0.9375")),
+    );
+
+    assert_eq!(
+        "DATETIME(\"2024-03-05T22:30:00-05:00\")",
+        eval(g, dt).unwrap().to_formula_literal(),
+    );
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn test_decimal_arithmetic() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("0.3", eval_to_string(g, "0.1 + 0.2"));
+    assert_eq!(Value::Bool(true), eval(g, "0.1 + 0.2 == 0.3").unwrap());
+    assert_eq!("0.1", eval_to_string(g, "0.3 - 0.2"));
+    assert_eq!("0.6", eval_to_string(g, "SUM(0.1, 0.2, 0.3)"));
+    assert_eq!("0.06", eval_to_string(g, "PRODUCT(0.1, 0.2, 3)"));
+    assert_eq!("0.035", eval_to_string(g, "3.5%"));
+    // The `f64` closest to 2.675 is slightly less than it.
+    assert_eq!("2.68", eval_to_string(g, "ROUND(2.675, 2)"));
+
+    assert_eq!("0.0000000000001", eval_to_string(g, "0.0000001 * 0.000001"));
+}
+
+/// Tests values outside the range of decimals, which give the same results
+/// with and without the `decimal` feature.
+#[test]
+fn test_arithmetic_range() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!(Value::from(1e300), eval(g, "1 + 1e300").unwrap());
+    assert_eq!(Value::from(1e301), eval(g, "1e300 * 10").unwrap());
+    assert_eq!(
+        Value::from(1e28 - 1.1e29),
+        eval(g, "1e28 - 1.1e29").unwrap()
+    );
+    assert_eq!(Value::from(5e28 * 0.8), eval(g, "5e28 * 0.8").unwrap());
+    assert_eq!(Value::from(1e-30), eval(g, "1e-30 + 0").unwrap());
+    assert_eq!(
+        Value::from(1e-15 * 2e-15),
+        eval(g, "1e-15 * 2e-15").unwrap()
+    );
+    assert_eq!(Value::from(5e-31), eval(g, "1e-30 / 2").unwrap());
+    assert_eq!(Value::from(0.0), eval(g, "ROUND(1e-30, 2)").unwrap());
+    assert_eq!(Value::from(1e30), eval(g, "ROUND(1e30, 0)").unwrap());
+
+    assert_eq!(Value::from(f64::INFINITY), eval(g, "1 / 0").unwrap());
+    assert_eq!(Value::from(f64::NEG_INFINITY), eval(g, "-1 / 0").unwrap());
+    assert_eq!(
+        Value::from(f64::INFINITY),
+        eval(g, "1e300 * 1e300").unwrap()
+    );
+    assert_eq!(Value::from(f64::INFINITY), eval(g, "(1 / 0) + 1").unwrap());
+}
+
+#[test]
+fn test_function_signatures() {
+    // Every function in the registry has a signature.
+    let registry = include_str!("functions.rs")
+        .split("pub fn pure_function_from_name")
+        .nth(1)
+        .unwrap();
+    let name_regex = regex::Regex::new(r#""([a-z][a-z\d.]*)""#).unwrap();
+    let mut function_names = registry
+        .lines()
+        .take_while(|line| !line.starts_with('}'))
+        .filter_map(|line| line.trim_start().split_once(" => ").map(|(names, _)| names))
+        .flat_map(|names| name_regex.captures_iter(names))
+        .map(|captures| captures[1].to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    function_names.extend(
+        [
+            "CELL",
+            "C",
+            "SUBTOTAL",
+            "AGGREGATE",
+            "AND",
+            "OR",
+            "ISERROR",
+            "TYPE",
+            "ISOMITTED",
+            "LET",
+            "LAMBDA",
+            "MAP",
+            "REDUCE",
+            "SCAN",
+            "ISFORMULA",
+            "FORMULATEXT",
+            "INDEX",
+        ]
+        .map(String::from),
+    );
+    #[cfg(not(feature = "datetime"))]
+    function_names.retain(|name| name != "DATETIME");
+    assert!(function_names.len() > 40);
+    for name in &function_names {
+        assert!(
+            function_signature(name).is_some(),
+            "missing signature for {name}",
+        );
+    }
+    // And every signature is for a real function.
+    for sig in function_signatures() {
+        assert!(
+            function_names.iter().any(|name| name == sig.name),
+            "signature for unknown function {}",
+            sig.name,
+        );
+    }
+
+    // Argument counts outside the bounds in the signature are rejected, and
+    // those inside are not.
+    make_stateless_grid_mock!(|_| None);
+    let g = &mut GridMock;
+    let call = |name: &str, n: usize| format!("{name}({})", vec!["1"; n].join(", "));
+    for sig in function_signatures() {
+        let min = sig.min_args();
+        let group_size = sig.args.iter().filter(|arg| arg.variadic).count();
+        let mut valid = vec![min, min + group_size];
+        let mut invalid = vec![];
+        if let Some(max) = sig.max_args() {
+            valid = (min..=max).collect();
+            invalid.push(max + 1);
+        } else if group_size > 1 {
+            invalid.push(min + 1);
+        }
+        if min > 0 {
+            invalid.push(min - 1);
+        }
+
+        for n in valid {
+            let source = call(sig.name, n);
+            if let Err(e) = eval(g, &source) {
+                assert_ne!(
+                    FormulaErrorMsg::BadArgumentCount,
+                    e.msg,
+                    "{source} should accept {n} arguments",
+                );
+            }
+        }
+        for n in invalid {
+            let source = call(sig.name, n);
+            assert_eq!(
+                FormulaErrorMsg::BadArgumentCount,
+                eval(g, &source).unwrap_err().msg,
+                "{source} should reject {n} arguments",
+            );
+        }
+    }
+
+    assert_eq!(
+        "WEEKNUM(date, [return_type])",
+        function_signature("weeknum").unwrap().to_string(),
+    );
+    assert_eq!(
+        "MAXIFS(max_range, criteria_range..., criteria...)",
+        function_signature("MAXIFS").unwrap().to_string(),
+    );
+    assert_eq!(
+        "SUM([numbers...])",
+        function_signature("Sum").unwrap().to_string()
+    );
+}
+
+#[test]
+fn test_formula_aggregate() {
+    make_stateless_grid_mock!(|pos| Some(
+        match (pos.x, pos.y) {
+            (0, 1) => "10",
+            (0, 2) => "#DIV/0!",
+            (0, 3) => "30",
+            (0, 4) => "text",
+            (0, 5) => "20",
+            _ => "",
+        }
+        .to_string()
+    ));
+    let g = &mut GridMock;
+
+    // Error cells evaluate to errors.
+    assert_eq!("#DIV/0!", eval_to_string(g, "A2"));
+    let err = eval(g, "SUM(A1:A5)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::DivideByZero, err.msg);
+
+    // Without ignoring errors
+    for options in [0, 1, 4, 5] {
+        let err = eval(g, &format!("AGGREGATE(9, {options}, A1:A5)")).unwrap_err();
+        assert_eq!(FormulaErrorMsg::DivideByZero, err.msg);
+        assert_eq!(Some(Span { start: 16, end: 21 }), err.span);
+    }
+
+    // Ignoring errors
+    for options in [2, 3, 6, 7] {
+        let formula = format!("AGGREGATE(9, {options}, A1:A5)");
+        assert_eq!("60", eval_to_string(g, &formula));
+    }
+    assert_eq!("20", eval_to_string(g, "AGGREGATE(1, 6, A1:A5)"));
+    assert_eq!("3", eval_to_string(g, "AGGREGATE(2, 6, A1:A5)"));
+    assert_eq!("4", eval_to_string(g, "AGGREGATE(3, 6, A1:A5)"));
+    assert_eq!("30", eval_to_string(g, "AGGREGATE(4, 6, A1:A5)"));
+    assert_eq!("10", eval_to_string(g, "AGGREGATE(5, 6, A1:A5)"));
+    assert_eq!("6000", eval_to_string(g, "AGGREGATE(6, 6, A1:A5)"));
+    assert_eq!("10", eval_to_string(g, "AGGREGATE(7, 6, A1:A5)"));
+    assert_eq!("100", eval_to_string(g, "AGGREGATE(10, 6, A1:A5)"));
+    assert_eq!("20", eval_to_string(g, "AGGREGATE(12, 6, A1:A5)"));
+    assert_eq!("100", eval_to_string(g, "AGGREGATE(9, 6, A1:A5, 40)"));
+
+    // Functions that take `k`
+    assert_eq!("30", eval_to_string(g, "AGGREGATE(14, 6, A1:A5, 1)"));
+    assert_eq!("20", eval_to_string(g, "AGGREGATE(15, 6, A1:A5, 2)"));
+    assert_eq!("15", eval_to_string(g, "AGGREGATE(16, 6, A1:A5, 0.25)"));
+    assert_eq!("30", eval_to_string(g, "AGGREGATE(17, 6, A1:A5, 4)"));
+    assert_eq!("10", eval_to_string(g, "AGGREGATE(18, 6, A1:A5, 0.25)"));
+    assert_eq!("20", eval_to_string(g, "AGGREGATE(19, 6, A1:A5, 2)"));
+    assert_eq!("2", eval_to_string(g, "AGGREGATE(13, 0, {1, 2, 3, 2})"));
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "AGGREGATE(13, 0, {1, 2, 3})").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadNumber,
+        eval(g, "AGGREGATE(14, 6, A1:A5, 4)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "AGGREGATE(14, 6, A1:A5)").unwrap_err().msg,
+    );
+
+    // Unknown function numbers and options
+    for (formula, span) in [
+        ("AGGREGATE(0, 6, A1:A5)", Span { start: 10, end: 11 }),
+        ("AGGREGATE(20, 6, A1:A5)", Span { start: 10, end: 12 }),
+        ("AGGREGATE(9, 8, A1:A5)", Span { start: 13, end: 14 }),
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg);
+        assert_eq!(Some(span), err.span);
+    }
+}
+
+#[test]
+fn test_formula_spill_range() {
+    /// `SEQUENCE(4, 2)` in B1 has spilled into B1:C4.
+    struct SpillGridMock;
+    #[async_trait(?Send)]
+    impl GridProxy for SpillGridMock {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            match (pos.x, pos.y) {
+                (1..=2, 1..=4) => Some(((pos.y - 1) * 2 + pos.x).to_string()),
+                _ => None,
+            }
+        }
+        async fn get_spill_extent(&mut self, pos: Pos) -> Option<Pos> {
+            (pos == Pos::new(1, 1)).then_some(Pos::new(2, 4))
+        }
+    }
+    let g = &mut SpillGridMock;
+
+    assert_eq!("36", eval_to_string(g, "SUM(B1#)"));
+    assert_eq!("{1, 2; 3, 4; 5, 6; 7, 8}", eval_to_string(g, "B1#"));
+    assert_eq!("36", eval_to_string(g, "SUM($B$1#)"));
+    // Spill ranges can be used anywhere a reference can.
+    assert_eq!("36", eval_to_string(g, "SUM(D2:B1#)"));
+    assert_eq!("5", eval_to_string(g, "INDEX(B1#, 3, 1)"));
+
+    // Cells that didn't spill
+    let err = eval(g, "SUM(B2#)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadCellReference, err.msg);
+    assert_eq!(Some(Span { start: 4, end: 7 }), err.span);
+    let err = eval(g, "SUM((B1:C1)#)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadCellReference, err.msg);
+    assert_eq!(Some(Span { start: 4, end: 11 }), err.span);
+    let err = eval(g, "SUM(5#)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadCellReference, err.msg);
+    assert_eq!(Some(Span { start: 4, end: 5 }), err.span);
+}
+
+#[test]
+fn test_formula_column_range() {
+    /// Grid containing only the given cells, which counts how many cells are
+    /// fetched one at a time.
+    struct SparseGridMock {
+        cells: Vec<((i64, i64), &'static str)>,
+        gets: usize,
+    }
+    #[async_trait(?Send)]
+    impl GridProxy for SparseGridMock {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            self.gets += 1;
+            let (_, contents) = self.cells.iter().find(|&&(xy, _)| xy == (pos.x, pos.y))?;
+            Some(contents.to_string())
+        }
+        async fn used_region(&mut self) -> Option<(Pos, Pos)> {
+            let (min_x, max_x) = self
+                .cells
+                .iter()
+                .map(|((x, _), _)| *x)
+                .minmax()
+                .into_option()?;
+            let (min_y, max_y) = self
+                .cells
+                .iter()
+                .map(|((_, y), _)| *y)
+                .minmax()
+                .into_option()?;
+            Some((Pos::new(min_x, min_y), Pos::new(max_x, max_y)))
+        }
+        async fn iter_range(
+            &mut self,
+            min: Pos,
+            max: Pos,
+        ) -> Box<dyn Iterator<Item = (Pos, String)>> {
+            let cells = self
+                .cells
+                .iter()
+                .map(|&((x, y), contents)| (Pos::new(x, y), contents.to_string()))
+                .filter(|(pos, _)| (min.x..=max.x).contains(&pos.x))
+                .filter(|(pos, _)| (min.y..=max.y).contains(&pos.y))
+                .collect_vec();
+            Box::new(cells.into_iter())
+        }
+    }
+
+    // Numbers scattered over a million rows of column B, and text in C2.
+    let g = &mut SparseGridMock {
+        cells: vec![
+            ((1, -3), "5"),
+            ((1, 1), "1"),
+            ((1, 500_000), "10"),
+            ((1, 1_000_000), "100"),
+            ((2, 2), "apple"),
+        ],
+        gets: 0,
+    };
+    assert_eq!("116", eval_to_string(g, "SUM(B:B)"));
+    assert_eq!("116", eval_to_string(g, "SUM($B:B, D:E)"));
+    assert_eq!("5", eval_to_string(g, "COUNT(C:B)"));
+    assert_eq!("29", eval_to_string(g, "AVERAGE(B:B)"));
+    assert_eq!("1", eval_to_string(g, "MIN(B:B)"));
+    assert_eq!("100", eval_to_string(g, "MAX(B:B, 7)"));
+    assert_eq!("0", eval_to_string(g, "SUM(D:F)"));
+    // Blank cells were never fetched.
+    assert_eq!(0, g.gets);
+
+    // The formula is in A0, so any range containing column A contains it.
+    for src in ["SUM(A:A)", "SUM(nA:B)", "A:A"] {
+        assert_eq!(
+            FormulaErrorMsg::CircularReference,
+            eval(g, src).unwrap_err().msg,
+            "{src}",
+        );
+    }
+
+    // Other uses of a column range fetch every cell in the rows of the used
+    // region.
+    let g = &mut SparseGridMock {
+        cells: vec![((1, -1), "1"), ((1, 2), "2"), ((2, 1), "x")],
+        gets: 0,
+    };
+    assert_eq!("{2; 0; 0; 4}", eval_to_string(g, "B:B * 2"));
+    assert_eq!("3", eval_to_string(g, "SUM(B:B * 1)"));
+    assert_eq!("2", eval_to_string(g, "INDEX(B:B, 4)"));
+    assert_eq!("0", eval_to_string(g, "@B:B + 0"));
+    assert_eq!(10, g.gets);
+
+    assert_eq!(
+        "SUM(C1:C[2])",
+        parse_formula("SUM($B:C)", Pos::ORIGIN).unwrap().to_string(),
+    );
+}
+
+#[test]
+fn test_formula_subtotal() {
+    /// Column A has two groups of numbers, each followed by its subtotal, then
+    /// an error and an `AGGREGATE` of the cells above. Row 2 is hidden.
+    struct SubtotalGridMock;
+    #[async_trait(?Send)]
+    impl GridProxy for SubtotalGridMock {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            let value = match (pos.x, pos.y) {
+                (0, 1) => "10",
+                (0, 2) => "20",
+                (0, 3) => "30",
+                (0, 4) => "1",
+                (0, 5) => "2",
+                (0, 6) => "3",
+                (0, 7) => "#DIV/0!",
+                (0, 8) => "100",
+                _ => "",
+            };
+            Some(value.to_string())
+        }
+        async fn get_formula(&mut self, pos: Pos) -> Option<String> {
+            match (pos.x, pos.y) {
+                (0, 3) => Some("=SUBTOTAL(9, A1:A2)".to_string()),
+                (0, 6) => Some("=(subtotal(109, A4:A5))".to_string()),
+                (0, 8) => Some("=AGGREGATE(9, 6, A1:A7) - 66".to_string()),
+                _ => None,
+            }
+        }
+        async fn is_row_hidden(&mut self, y: i64) -> bool {
+            y == 2
+        }
+    }
+    let g = &mut SubtotalGridMock;
+
+    // Nested subtotals are ignored.
+    assert_eq!("66", eval_to_string(g, "SUM(A1:A6)"));
+    assert_eq!("33", eval_to_string(g, "SUBTOTAL(9, A1:A6)"));
+    assert_eq!("33", eval_to_string(g, "SUBTOTAL(9, A1:A3, A4:A6)"));
+    assert_eq!("4", eval_to_string(g, "SUBTOTAL(2, A1:A6)"));
+    assert_eq!("20", eval_to_string(g, "SUBTOTAL(4, A1:A6)"));
+    assert_eq!("400", eval_to_string(g, "SUBTOTAL(6, A1:A6)"));
+    assert_eq!("0", eval_to_string(g, "SUBTOTAL(9, A3)"));
+
+    // Hidden rows are ignored by 101-111.
+    assert_eq!("13", eval_to_string(g, "SUBTOTAL(109, A1:A6)"));
+    assert_eq!("3", eval_to_string(g, "SUBTOTAL(102, A1:A6)"));
+
+    // Non-reference arguments are aggregated as-is.
+    assert_eq!("6", eval_to_string(g, "SUBTOTAL(9, {1, 2, 3})"));
+
+    for formula in [
+        "SUBTOTAL(0, A1:A6)",
+        "SUBTOTAL(12, A1:A6)",
+        "SUBTOTAL(100, A1)",
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg);
+    }
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "SUBTOTAL(9)").unwrap_err().msg,
+    );
+
+    // `AGGREGATE` options 0-3 ignore nested subtotals and aggregates, odd
+    // options ignore hidden rows, and options 2, 3, 6, and 7 ignore errors.
+    for (options, expected) in [(0, "33"), (1, "13"), (4, "166"), (5, "146")] {
+        let formula = format!("AGGREGATE(9, {options}, A1:A6, A8)");
+        assert_eq!(expected, eval_to_string(g, &formula), "{formula}");
+    }
+    for (options, expected) in [(2, "33"), (3, "13"), (6, "166"), (7, "146")] {
+        let formula = format!("AGGREGATE(9, {options}, A1:A8)");
+        assert_eq!(expected, eval_to_string(g, &formula), "{formula}");
+    }
+    for options in [0, 1, 4, 5] {
+        let formula = format!("AGGREGATE(9, {options}, A1:A8)");
+        let err = eval(g, &formula).unwrap_err();
+        assert_eq!(FormulaErrorMsg::DivideByZero, err.msg, "{formula}");
+        assert_eq!(Some(Span { start: 16, end: 21 }), err.span, "{formula}");
+    }
+    // Functions that take `k` skip the same cells.
+    assert_eq!("20", eval_to_string(g, "AGGREGATE(14, 2, A1:A8, 1)"));
+    assert_eq!("10", eval_to_string(g, "AGGREGATE(14, 3, A1:A8, 1)"));
+    assert_eq!("100", eval_to_string(g, "AGGREGATE(14, 6, A1:A8, 1)"));
+    // Non-reference arguments are aggregated as-is.
+    assert_eq!("6", eval_to_string(g, "AGGREGATE(9, 3, {1, 2, 3})"));
+}
+
+#[test]
+fn test_formula_isformula_formulatext() {
+    /// A1 contains a formula that refers to B1, which contains a constant.
+    struct FormulaGridMock;
+    #[async_trait(?Send)]
+    impl GridProxy for FormulaGridMock {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            match (pos.x, pos.y) {
+                (0, 1) => Some("10".to_string()),
+                (1, 1) => Some("5".to_string()),
+                _ => None,
+            }
+        }
+        async fn get_formula(&mut self, pos: Pos) -> Option<String> {
+            match (pos.x, pos.y) {
+                (0, 1) => Some("=B1 * 2".to_string()),
+                _ => None,
+            }
+        }
+    }
+    let g = &mut FormulaGridMock;
+
+    assert_eq!("TRUE", eval_to_string(g, "ISFORMULA(A1)"));
+    assert_eq!("FALSE", eval_to_string(g, "ISFORMULA(B1)"));
+    assert_eq!("FALSE", eval_to_string(g, "ISFORMULA(C1)"));
+    assert_eq!("=B1 * 2", eval_to_string(g, "FORMULATEXT(A1)"));
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "FORMULATEXT(B1)").unwrap_err().msg,
+    );
+
+    // A multi-cell reference uses the top-left cell.
+    assert_eq!("TRUE", eval_to_string(g, "ISFORMULA(A1:B2)"));
+    assert_eq!("FALSE", eval_to_string(g, "ISFORMULA(B1:C2)"));
+    assert_eq!("=B1 * 2", eval_to_string(g, "FORMULATEXT(B2:A1)"));
+
+    // The argument must be a reference.
+    let err = eval(g, "ISFORMULA(5)").unwrap_err();
+    assert_eq!(
+        "Expected cell reference, got numeric literal",
+        err.msg.to_string()
+    );
+    assert_eq!(Some(Span { start: 10, end: 11 }), err.span);
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "FORMULATEXT(A1, B1)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_xmatch() {
+    let g = &mut PanicGridMock;
+
+    // Exact match, which ignores case and matches text that looks like a
+    // number.
+    let list = "{10, 20, 'Pear', 20, 'apple', TRUE()}";
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH(20, {list})")));
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH('20', {list})")));
+    assert_eq!("3", eval_to_string(g, &format!("XMATCH('pear', {list})")));
+    assert_eq!("6", eval_to_string(g, &format!("XMATCH(TRUE(), {list})")));
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH(20, {list}, 0, 1)")));
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, &format!("XMATCH(15, {list})")).unwrap_err().msg,
+    );
+
+    // Next smaller or larger only considers values of the same type.
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH(25, {list}, -1)")));
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH(15, {list}, 1)")));
+    assert_eq!("1", eval_to_string(g, &format!("XMATCH(15, {list}, -1)")));
+    assert_eq!("5", eval_to_string(g, &format!("XMATCH('b', {list}, -1)")));
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, &format!("XMATCH(25, {list}, 1)")).unwrap_err().msg,
+    );
+
+    // Wildcards.
+    assert_eq!("3", eval_to_string(g, &format!("XMATCH('p*', {list}, 2)")));
+    assert_eq!(
+        "5",
+        eval_to_string(g, &format!("XMATCH('?pple', {list}, 2)"))
+    );
+    assert_eq!("1", eval_to_string(g, &format!("XMATCH(10, {list}, 2)")));
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, &format!("XMATCH('p?', {list}, 2)"))
+            .unwrap_err()
+            .msg,
+    );
+
+    // Search from the last value.
+    assert_eq!(
+        "4",
+        eval_to_string(g, &format!("XMATCH(20, {list}, 0, -1)"))
+    );
+    assert_eq!(
+        "5",
+        eval_to_string(g, &format!("XMATCH('*', {list}, 2, -1)"))
+    );
+
+    // Binary search of sorted values, in a column.
+    let ascending = "{1; 3; 3; 5; 8; 'apple'; 'pear'}";
+    let descending = "{'pear'; 'apple'; 8; 5; 3; 3; 1}";
+    for (needle, match_mode, expected_asc, expected_desc) in [
+        ("5", 0, "4", "4"),
+        ("4", -1, "3", "5"),
+        ("4", 1, "4", "4"),
+        ("0", 1, "1", "7"),
+        ("9", -1, "5", "3"),
+        ("'b'", -1, "6", "2"),
+        ("'APPLE'", 0, "6", "2"),
+    ] {
+        let formula = format!("XMATCH({needle}, {ascending}, {match_mode}, 2)");
+        assert_eq!(expected_asc, eval_to_string(g, &formula), "{formula}");
+        let formula = format!("XMATCH({needle}, {descending}, {match_mode}, -2)");
+        assert_eq!(expected_desc, eval_to_string(g, &formula), "{formula}");
+    }
+    for formula in [
+        format!("XMATCH(4, {ascending}, 0, 2)"),
+        format!("XMATCH(9, {ascending}, 1, 2)"),
+        format!("XMATCH(0, {descending}, -1, -2)"),
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::NotAvailable,
+            eval(g, &formula).unwrap_err().msg,
+            "{formula}",
+        );
+    }
+
+    // Omitted modes use the defaults.
+    assert_eq!("2", eval_to_string(g, &format!("XMATCH(20, {list}, , )")));
+
+    for formula in [
+        "XMATCH(1, {1, 2; 3, 4})",
+        "XMATCH(1, {1, 2}, 3)",
+        "XMATCH(1, {1, 2}, 0, 0)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::InvalidArgument,
+            eval(g, formula).unwrap_err().msg,
+            "{formula}",
+        );
+    }
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "XMATCH(1)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_xlookup() {
+    let g = &mut PanicGridMock;
+
+    let table = "{'a', 1, 10; 'b', 2, 20; 'c', 3, 30}";
+    assert_eq!(
+        "20",
+        eval_to_string(g, "XLOOKUP('b', {'a'; 'b'; 'c'}, {10; 20; 30})"),
+    );
+    // A column of keys returns a whole row.
+    assert_eq!(
+        "{b, 2, 20}",
+        eval_to_string(g, &format!("XLOOKUP(2, {{1; 2; 3}}, {table})")),
+    );
+    // A row of keys returns a whole column.
+    assert_eq!(
+        "{1; 2; 3}",
+        eval_to_string(g, &format!("XLOOKUP('y', {{'x', 'y', 'z'}}, {table})")),
+    );
+    // The modes are the same as `XMATCH`.
+    assert_eq!(
+        "30",
+        eval_to_string(g, "XLOOKUP(2.5, {1, 2, 3}, {10, 20, 30}, , 1)"),
+    );
+    assert_eq!(
+        "20",
+        eval_to_string(g, "XLOOKUP(2.5, {1, 2, 3}, {10, 20, 30}, , -1, 2)"),
+    );
+    assert_eq!(
+        "x",
+        eval_to_string(
+            g,
+            "XLOOKUP('b*', {'abc', 'bcd', 'bde'}, {'z', 'x', 'y'}, , 2)"
+        ),
+    );
+    assert_eq!(
+        "y",
+        eval_to_string(
+            g,
+            "XLOOKUP('b*', {'abc', 'bcd', 'bde'}, {'z', 'x', 'y'}, , 2, -1)"
+        ),
+    );
+
+    // Not found.
+    assert_eq!(
+        "none",
+        eval_to_string(g, "XLOOKUP(4, {1, 2, 3}, {10, 20, 30}, 'none')"),
+    );
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "XLOOKUP(4, {1, 2, 3}, {10, 20, 30})")
+            .unwrap_err()
+            .msg,
+    );
+
+    // The return array must have the same length as the lookup array.
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "XLOOKUP(1, {1, 2, 3}, {10, 20})").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_match() {
+    let g = &mut PanicGridMock;
+
+    // By default, finds the largest value that is not greater, in values
+    // sorted in ascending order.
+    assert_eq!("2", eval_to_string(g, "MATCH(25, {10, 20, 30})"));
+    assert_eq!("3", eval_to_string(g, "MATCH(30, {10; 20; 30}, 1)"));
+    assert_eq!("2", eval_to_string(g, "MATCH('b', {'a', 'B', 'c'})"));
+    // Exact match, with wildcards.
+    assert_eq!("3", eval_to_string(g, "MATCH('C', {'a', 'b', 'c'}, 0)"));
+    assert_eq!(
+        "2",
+        eval_to_string(g, "MATCH('b*', {'abc', 'bcd', 'bde'}, 0)")
+    );
+    assert_eq!("1", eval_to_string(g, "MATCH('1', {1, 2}, 0)"));
+    // Smallest value that is not smaller, in descending order.
+    assert_eq!("2", eval_to_string(g, "MATCH(25, {40, 30, 20}, -1)"));
+
+    // There is no match, and values of other types never match.
+    for source in [
+        "MATCH(5, {10, 20})",
+        "MATCH(45, {40, 30, 20}, -1)",
+        "MATCH(TRUE(), {1, 2}, 0)",
+        "MATCH('z', {1, 2})",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::NotAvailable,
+            eval(g, source).unwrap_err().msg,
+            "{source:?}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_sort_unique() {
+    let g = &mut PanicGridMock;
+
+    // Numbers, then text ignoring case, then booleans, then errors, then
+    // blanks, which stay last when sorting in descending order.
+    let mixed = "{'b'; TRUE(); 10; ''; NA(); 'A'; FALSE(); '9'; 'a'}";
+    assert_eq!(
+        "{9; 10; A; a; b; FALSE; TRUE; #N/A; }",
+        eval_to_string(g, &format!("SORT({mixed})")),
+    );
+    assert_eq!(
+        "{#N/A; TRUE; FALSE; b; A; a; 10; 9; }",
+        eval_to_string(g, &format!("SORT({mixed}, 1, -1)")),
+    );
+
+    // Rows with equal keys keep their original order, in either direction.
+    let table = "{'x', 2; 'Y', 1; 'y', 3; 'X', 1}";
+    assert_eq!(
+        "{x, 2; X, 1; Y, 1; y, 3}",
+        eval_to_string(g, &format!("SORT({table})")),
+    );
+    assert_eq!(
+        "{Y, 1; X, 1; x, 2; y, 3}",
+        eval_to_string(g, &format!("SORT({table}, 2)")),
+    );
+    assert_eq!(
+        "{Y, 1; y, 3; x, 2; X, 1}",
+        eval_to_string(g, &format!("SORT({table}, 1, -1)")),
+    );
+    assert_eq!(
+        "{2, 3, 1; a, b, c}",
+        eval_to_string(g, "SORT({3, 1, 2; 'b', 'c', 'a'}, 2, 1, TRUE())"),
+    );
+    assert_eq!(
+        FormulaErrorMsg::IndexOutOfBounds,
+        eval(g, &format!("SORT({table}, 3)")).unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, &format!("SORT({table}, 1, 0)")).unwrap_err().msg,
+    );
+
+    // `UNIQUE` compares values the same way, keeping the first occurrence.
+    assert_eq!(
+        "{b; 1; a}",
+        eval_to_string(g, "UNIQUE({'b'; 1; 'B'; 'a'; '1'; 'b'})"),
+    );
+    assert_eq!(
+        "{Y, 1}",
+        eval_to_string(g, "UNIQUE({'x', 1; 'Y', 1; 'X', 1}, FALSE(), TRUE())"),
+    );
+    assert_eq!(
+        "{x, Y, X; 1, 1, 2}",
+        eval_to_string(g, "UNIQUE({'x', 'Y', 'y', 'X'; 1, 1, 1, 2}, TRUE())"),
+    );
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "UNIQUE({1; 1}, FALSE(), TRUE())").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_wraprows_wrapcols() {
+    make_stateless_grid_mock!(
+        |pos| (pos.x == 1 && (1..=6).contains(&pos.y)).then(|| pos.y.to_string())
+    );
+    let g = &mut GridMock;
+
+    // Exact fit
+    assert_eq!(
+        "{1, 2, 3; 4, 5, 6}",
+        eval_to_string(g, "WRAPROWS(B1:B6, 3)"),
+    );
+    assert_eq!(
+        "{1, 3, 5; 2, 4, 6}",
+        eval_to_string(g, "WRAPCOLS(B1:B6, 2)"),
+    );
+    assert_eq!(
+        "{1, 2, 3, 4, 5, 6}",
+        eval_to_string(g, "WRAPROWS(B1:B6, 6)")
+    );
+
+    // Padded remainder
+    assert_eq!(
+        "{1, 2, 3, 4; 5, 6, #N/A, #N/A}",
+        eval_to_string(g, "WRAPROWS(B1:B6, 4)"),
+    );
+    assert_eq!(
+        "{a, d; b, x; c, x}",
+        eval_to_string(g, "WRAPCOLS({'a', 'b', 'c', 'd'}, 3, 'x')"),
+    );
+    assert_eq!("{7, 0, 0}", eval_to_string(g, "WRAPROWS(7, 3, 0)"));
+
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "WRAPROWS({1, 2; 3, 4}, 2)").unwrap_err().msg,
+    );
+    for src in ["WRAPROWS(B1:B6, 0)", "WRAPCOLS(B1:B6, -1)"] {
+        let err = eval(g, src).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg, "{src}");
+    }
+}
+
+#[test]
+fn test_formula_tocol_torow() {
+    let g = &mut PanicGridMock;
+
+    let array = "{1, 2, 3; 4, 5, 6}";
+    assert_eq!(
+        "{1; 2; 3; 4; 5; 6}",
+        eval_to_string(g, &format!("TOCOL({array})")),
+    );
+    assert_eq!(
+        "{1; 4; 2; 5; 3; 6}",
+        eval_to_string(g, &format!("TOCOL({array}, 0, TRUE())")),
+    );
+    assert_eq!(
+        "{1, 2, 3, 4, 5, 6}",
+        eval_to_string(g, &format!("TOROW({array})")),
+    );
+    assert_eq!(
+        "{1, 4, 2, 5, 3, 6}",
+        eval_to_string(g, &format!("TOROW({array}, , TRUE())")),
+    );
+    assert_eq!("{7}", eval_to_string(g, "TOROW(7)"));
+    // Flattening and wrapping are inverses.
+    assert_eq!(
+        array,
+        eval_to_string(g, &format!("WRAPROWS(TOROW({array}), 3)")),
+    );
+
+    let array = "{1, ''; NA(), 2; '', #DIV/0!}";
+    assert_eq!(
+        "{1, , #N/A, 2, , #DIV/0!}",
+        eval_to_string(g, &format!("TOROW({array})")),
+    );
+    assert_eq!(
+        "{1, #N/A, 2, #DIV/0!}",
+        eval_to_string(g, &format!("TOROW({array}, 1)")),
+    );
+    assert_eq!(
+        "{1, , 2, }",
+        eval_to_string(g, &format!("TOROW({array}, 2)")),
+    );
+    assert_eq!("{1, 2}", eval_to_string(g, &format!("TOROW({array}, 3)")));
+    assert_eq!(
+        "{1; #N/A; 2; #DIV/0!}",
+        eval_to_string(g, &format!("TOCOL({array}, 1, TRUE())")),
+    );
+    assert_eq!(
+        "{1; 2}",
+        eval_to_string(g, &format!("TOCOL({array}, 3, TRUE())")),
+    );
+
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "TOCOL({''; NA()}, 3)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "TOCOL({1, 2}, 4)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_comparison_order() {
+    let g = &mut PanicGridMock;
+
+    // Comparison operators use the same order as `SORT`.
+    for (source, expected) in [
+        ("'apple' < 'Banana'", "TRUE"),
+        ("'abc' = 'ABC'", "TRUE"),
+        ("'10' > 9", "TRUE"),
+        ("1000 < 'a'", "TRUE"),
+        ("'z' < FALSE()", "TRUE"),
+        ("FALSE() < TRUE()", "TRUE"),
+        ("TRUE() = 1", "FALSE"),
+        ("{1, 'a', TRUE()} > 'B'", "{FALSE, FALSE, TRUE}"),
+        // Blank is coerced to the type of the other operand.
+        ("'' = 0", "TRUE"),
+        ("'' < 'a'", "TRUE"),
+        ("'' = FALSE()", "TRUE"),
+        ("'' < -1", "FALSE"),
+    ] {
+        assert_eq!(expected, eval_to_string(g, source), "{source:?}");
+    }
+}
+
+#[test]
+fn test_formula_error_literals() {
+    let g = &mut PanicGridMock;
+
+    for (code, msg) in [
+        ("#DIV/0!", FormulaErrorMsg::DivideByZero),
+        ("#N/A", FormulaErrorMsg::NotAvailable),
+        ("#NAME?", FormulaErrorMsg::BadFunctionName),
+        ("#NULL!", FormulaErrorMsg::NoIntersection),
+        ("#NUM!", FormulaErrorMsg::BadNumber),
+        ("#REF!", FormulaErrorMsg::BadCellReference),
+        ("#VALUE!", FormulaErrorMsg::InvalidArgument),
+    ] {
+        assert_eq!(Value::Error(msg.clone()), eval(g, code).unwrap());
+        assert_eq!(code, eval_to_string(g, &code.to_ascii_lowercase()));
+        assert_eq!(code, eval_to_string(g, &format!("ERROR('{code}')")));
+        let short_name = msg.short_name();
+        assert_eq!(code, eval_to_string(g, &format!("ERROR('{short_name}')")));
+
+        // Error literals propagate like runtime errors.
+        let err = eval(g, &format!("1 + {code}")).unwrap_err();
+        assert_eq!(msg, err.msg);
+        assert_eq!(
+            Some(Span {
+                start: 4,
+                end: 4 + code.len()
+            }),
+            err.span
+        );
+        assert_eq!(msg, eval(g, &format!("SUM(1, {code})")).unwrap_err().msg);
+
+        assert_eq!("TRUE", eval_to_string(g, &format!("ISERROR({code})")));
+        assert_eq!("TRUE", eval_to_string(g, &format!("ISERROR(1 + {code})")));
+        assert_eq!(
+            "{FALSE, TRUE}",
+            eval_to_string(g, &format!("ISERROR({{1, {code}}})")),
+        );
+    }
+
+    assert_eq!("#N/A", eval_to_string(g, "NA()"));
+    assert_eq!("TRUE", eval_to_string(g, "ISERROR(NA())"));
+    assert_eq!("TRUE", eval_to_string(g, "ISERROR(AGGREGATE(1, 0, {'a'}))"));
+    assert_eq!("FALSE", eval_to_string(g, "ISERROR(1 / 2)"));
+    assert_eq!("FALSE", eval_to_string(g, "ISERROR('#N/A')"));
+
+    // Errors that a cell can't display are not caught.
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "ISERROR(NA(1))").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "ISERROR(1, 2)").unwrap_err().msg,
+    );
+
+    // Unknown error codes
+    let err = parse_formula("#FOO!", Pos::ORIGIN).unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadErrorCode, err.msg);
+    assert_eq!(Some(Span { start: 0, end: 5 }), err.span);
+    let err = eval(g, "ERROR('oops')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadErrorCode, err.msg);
+    assert_eq!(Some(Span { start: 6, end: 12 }), err.span);
+}
+
+#[test]
+fn test_parse_error_literal() {
+    let errors_with_codes = [
+        FormulaErrorMsg::DivideByZero,
+        FormulaErrorMsg::NotAvailable,
+        FormulaErrorMsg::BadFunctionName,
+        FormulaErrorMsg::BadErrorCode,
+        FormulaErrorMsg::NoIntersection,
+        FormulaErrorMsg::BadNumber,
+        FormulaErrorMsg::Overflow,
+        FormulaErrorMsg::NegativeExponent,
+        FormulaErrorMsg::InvalidDate,
+        FormulaErrorMsg::NotANumber("x".into()),
+        FormulaErrorMsg::BadCellReference,
+        FormulaErrorMsg::IndexOutOfBounds,
+        FormulaErrorMsg::Expected {
+            expected: "x".into(),
+            got: None,
+        },
+        FormulaErrorMsg::ArraySizeMismatch {
+            expected: (1, 2),
+            got: (3, 4),
+        },
+        FormulaErrorMsg::ExpectedScalar { got_size: (2, 2) },
+        FormulaErrorMsg::TypeMismatch {
+            func: "SUM".into(),
+            arg_index: 0,
+            expected: ValueType::Number,
+            got: ValueType::Text,
+        },
+        FormulaErrorMsg::InvalidArgument,
+    ];
+    for msg in errors_with_codes {
+        let code = msg.excel_code().unwrap();
+        let parsed = parse_error_literal(code).unwrap();
+        assert_eq!(Some(code), parsed.excel_code(), "{msg:?}");
+        // Parsing the code again gives the same error.
+        assert_eq!(Some(parsed.clone()), parse_error_literal(code));
+        assert_eq!(
+            Some(parsed),
+            parse_error_literal(&code.to_ascii_lowercase())
+        );
+    }
+
+    assert_eq!(
+        Some(FormulaErrorMsg::BadCellReference),
+        parse_error_literal("  #REF!\n"),
+    );
+    for s in [
+        "", "REF!", "#REF", "#REF!!", "#ERROR!", "#FOO!", "# N/A", "'#N/A",
+    ] {
+        assert_eq!(None, parse_error_literal(s), "{s:?}");
+    }
+}
+
+#[test]
+fn test_upstream_error() {
+    /// Grid where A1 contains Python code that raised an exception.
+    struct GridWithPythonError;
+    #[async_trait(?Send)]
+    impl GridProxy for GridWithPythonError {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            Some(pos.y.to_string())
+        }
+        async fn get_error(&mut self, pos: Pos) -> Option<String> {
+            (pos == Pos::new(0, 1)).then(|| "ZeroDivisionError: division by zero".to_string())
+        }
+    }
+    let g = &mut GridWithPythonError;
+
+    let expected = FormulaErrorMsg::UpstreamError {
+        source: "ZeroDivisionError: division by zero".into(),
+    };
+    for src in ["A1 + 1", "SUM(A1:A3)", "CONCAT(B1, A1)", "ROUND(A1, 2)"] {
+        assert_eq!(expected, eval(g, src).unwrap_err().msg, "{src}");
+    }
+    assert_eq!(
+        "Error in referenced cell: ZeroDivisionError: division by zero",
+        expected.to_string(),
+    );
+    let err = eval(g, "2 * A1").unwrap_err();
+    assert_eq!(Some(Span { start: 4, end: 6 }), err.span);
+
+    // The error is only in the cell that failed.
+    assert_eq!("5", eval_to_string(g, "SUM(A2:A3)"));
+    assert_eq!("TRUE", eval_to_string(g, "ISERROR(A1)"));
+    assert_eq!("16", eval_to_string(g, "TYPE(A1)"));
+    assert_eq!("1", eval_to_string(g, "IF(TRUE(), 1, A1)"));
+}
+
+#[test]
+fn test_pending_cells() {
+    /// Grid where B1 and B2 are still being computed by Python code.
+    struct GridWithPendingCells;
+    #[async_trait(?Send)]
+    impl GridProxy for GridWithPendingCells {
+        async fn get(&mut self, pos: Pos) -> Option<String> {
+            (pos.x == 0).then(|| pos.y.to_string())
+        }
+        async fn is_pending(&mut self, pos: Pos) -> bool {
+            pos.x == 1 && (1..=2).contains(&pos.y)
+        }
+        async fn used_region(&mut self) -> Option<(Pos, Pos)> {
+            Some((Pos::new(0, 1), Pos::new(1, 3)))
+        }
+    }
+    let outcome = |src: &str| {
+        parse_formula(src, Pos::ORIGIN)
+            .unwrap()
+            .eval_blocking_outcome(&mut Ctx::new(&mut GridWithPendingCells, Pos::ORIGIN))
+    };
+
+    // Any pending input makes the whole result pending, even if it would
+    // otherwise be an error or not depend on the cell's value.
+    for src in [
+        "B1",
+        "B1 + A1",
+        "SUM(A1:B3)",
+        "SUM(B:B)",
+        "ISBLANK(B2)",
+        "IFERROR(B1, 0)",
+        "NA() + B1",
+        "LET(x, B2, 1)",
+        "MAP(A1:A2, LAMBDA(y, y + B1))",
+    ] {
+        assert!(
+            matches!(outcome(src), EvalOutcome::Pending(_)),
+            "{src:?} should be pending",
+        );
+    }
+    let EvalOutcome::Pending(cells) = outcome("B2 + B1 + B2 * A3") else {
+        panic!("expected a pending outcome");
+    };
+    assert_eq!(vec![Pos::new(1, 2), Pos::new(1, 1)], cells);
+
+    // Cells that are not read don't matter.
+    for (src, expected) in [
+        ("A1 + A2", "3"),
+        ("IF(TRUE(), 1, B1)", "1"),
+        ("SUM(A1:A3)", "6"),
+        ("B3", ""),
+    ] {
+        match outcome(src) {
+            EvalOutcome::Value(value) => assert_eq!(expected, value.to_string(), "{src:?}"),
+            other => panic!("expected a value for {src:?}, got {other:?}"),
+        }
+    }
+    assert!(matches!(
+        outcome("NA() + B3"),
+        EvalOutcome::Error(FormulaError {
+            msg: FormulaErrorMsg::NotAvailable,
+            ..
+        }),
+    ));
+
+    // Plain evaluation reads pending cells as blank.
+    assert_eq!("1", eval_to_string(&mut GridWithPendingCells, "B1 + A1"));
+}
+
+#[test]
+fn test_array_result() {
+    let pos = Pos::new(1, 2);
+    let outcome = |src: &str| {
+        parse_formula(src, pos)
+            .unwrap()
+            .eval_blocking_outcome(&mut Ctx::new(&mut PanicGridMock, pos))
+    };
+
+    let EvalOutcome::Array(array) = outcome("SEQUENCE(3, 2)") else {
+        panic!("expected an array");
+    };
+    assert_eq!(CellRef::absolute(pos), array.anchor);
+    assert_eq!((3, 2), (array.rows, array.cols));
+    assert_eq!(Pos::new(2, 4), array.extent());
+    assert_eq!(
+        "{1, 2; 3, 4; 5, 6}",
+        Value::Array(array.values.clone()).to_string(),
+    );
+    assert!(array.contains(pos));
+    assert!(array.contains(Pos::new(2, 4)));
+    assert!(!array.contains(Pos::new(3, 4)));
+    assert!(!array.contains(Pos::new(1, 5)));
+
+    // A 1x1 array still spills, into just its anchor.
+    let EvalOutcome::Array(array) = outcome("{5}") else {
+        panic!("expected an array");
+    };
+    assert_eq!((1, 1, pos), (array.rows, array.cols, array.extent()));
+
+    // Other values don't.
+    assert!(matches!(
+        outcome("SUM(SEQUENCE(3, 2))"),
+        EvalOutcome::Value(_)
+    ));
+}
+
+#[test]
+fn test_formula_type() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("1", eval_to_string(g, "TYPE(3.5)"));
+    assert_eq!("1", eval_to_string(g, "TYPE(1 + 2)"));
+    assert_eq!("2", eval_to_string(g, "TYPE('abc')"));
+    assert_eq!("2", eval_to_string(g, "TYPE('')"));
+    assert_eq!("4", eval_to_string(g, "TYPE(FALSE())"));
+    assert_eq!("4", eval_to_string(g, "TYPE(1 < 2)"));
+    assert_eq!("16", eval_to_string(g, "TYPE(NA())"));
+    assert_eq!("16", eval_to_string(g, "TYPE(#DIV/0!)"));
+    assert_eq!("16", eval_to_string(g, "TYPE(1 + #REF!)"));
+    assert_eq!("64", eval_to_string(g, "TYPE({1, 2; 3, 4})"));
+    assert_eq!("64", eval_to_string(g, "TYPE({NA()})"));
+    assert_eq!("64", eval_to_string(g, "TYPE({1, 2} + {3, 4})"));
+
+    // Errors that a cell can't display are not caught.
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "TYPE(NA(1))").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "TYPE()").unwrap_err().msg,
+    );
+
+    // A blank cell is a number, but an empty string is text.
+    make_stateless_grid_mock!(|_| None);
+    let g = &mut GridMock;
+    assert_eq!("1", eval_to_string(g, "TYPE(A1)"));
+    assert_eq!("2", eval_to_string(g, "TYPE('')"));
+}
+
+#[test]
+fn test_omitted_arguments() {
+    make_stateless_grid_mock!(|_| None);
+    let g = &mut GridMock;
+
+    // An omitted argument is the same as a blank cell.
+    assert_eq!("", eval_to_string(g, "IF(TRUE(),,1)"));
+    assert_eq!(eval_to_string(g, "TYPE(A1)"), eval_to_string(g, "TYPE(,)"));
+    assert_eq!("3", eval_to_string(g, "SUM(1,,2)"));
+    assert_eq!("3", eval_to_string(g, "SUM(1, A1, 2)"));
+    assert_eq!("12", eval_to_string(g, "CONCAT(1,,2,)"));
+
+    // A trailing comma does not add an omitted argument, but a comma before an
+    // argument does.
+    let formula = parse_formula("SUM(,1,,2,)", Pos::ORIGIN).unwrap();
+    assert_eq!("SUM(, 1.0, , 2.0)", formula.ast.to_string());
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "NA(,)").unwrap_err().msg,
+    );
+
+    // There are no `LAMBDA` parameters, so nothing can be omitted.
+    for src in ["ISOMITTED(A1)", "ISOMITTED(,)", "ISERROR(ISOMITTED(1))"] {
+        let err = eval(g, src).unwrap_err();
+        assert_eq!(FormulaErrorMsg::NotInLambda, err.msg, "{src}");
+    }
+    assert_eq!(
+        Some(Span { start: 0, end: 13 }),
+        eval(g, "ISOMITTED(A1)").unwrap_err().span,
+    );
+}
+
+#[test]
+fn test_formula_let() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("8", eval_to_string(g, "LET(x, 2, y, x * 3, x + y)"));
+    assert_eq!("{2, 4}", eval_to_string(g, "LET(a, {1, 2}, a * 2)"));
+    // Names ignore case, and inner names shadow outer ones.
+    assert_eq!("5", eval_to_string(g, "LET(Total, 5, total)"));
+    assert_eq!(
+        "12",
+        eval_to_string(g, "LET(x, 1, y, x + 2, LET(x, y * 4, x))")
+    );
+    assert_eq!("3", eval_to_string(g, "LET(x, 1, LET(x, 2, x) + x)"));
+    // A name can be the same as a function.
+    assert_eq!("7", eval_to_string(g, "LET(sum, 4, SUM(sum, 3))"));
+
+    // Names are only bound inside the `LET`.
+    let err = eval(g, "LET(x, 1, x) + x").unwrap_err();
+    assert_eq!(FormulaErrorMsg::BadName, err.msg);
+    assert_eq!(Some(Span { start: 15, end: 16 }), err.span);
+    assert_eq!(
+        FormulaErrorMsg::BadName,
+        eval(g, "LET(x, y, y, 1, x)").unwrap_err().msg,
+    );
+
+    // Cell references can't be names.
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "name".into(),
+            got: Some("cell reference".into()),
+        },
+        eval(g, "LET(A1, 1, A1)").unwrap_err().msg,
+    );
+    // A name can only be bound once in each `LET`.
+    let err = eval(g, "LET(x, 1, X, 2, x)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::InvalidArgument, err.msg);
+    assert_eq!(Some(Span { start: 10, end: 11 }), err.span);
+
+    for src in ["LET(x)", "LET(x, 1)", "LET(x, 1, y, 2)"] {
+        assert_eq!(
+            FormulaErrorMsg::BadArgumentCount,
+            eval(g, src).unwrap_err().msg,
+            "{src}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_lambda() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!(
+        "12",
+        eval_to_string(g, "LET(f, LAMBDA(x, y, x * y), f(3, 4))")
+    );
+    assert_eq!(
+        "LAMBDA(x, +(x, 1.0))",
+        eval_to_string(g, "LAMBDA(x, x + 1)"),
+    );
+    assert_eq!("128", eval_to_string(g, "TYPE(LAMBDA(1))"));
+    // A lambda keeps the names bound where it was created, and doesn't see
+    // names bound where it is called.
+    assert_eq!(
+        "15",
+        eval_to_string(g, "LET(n, 10, add, LAMBDA(x, x + n), LET(n, 100, add(5)))"),
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadName,
+        eval(g, "LET(f, LAMBDA(x + n), LET(n, 1, f()))")
+            .unwrap_err()
+            .msg,
+    );
+    // Lambdas can be passed to other lambdas.
+    assert_eq!(
+        "9",
+        eval_to_string(
+            g,
+            "LET(twice, LAMBDA(f, x, f(f(x))), inc, LAMBDA(x, x + 1), twice(inc, 7))",
+        ),
+    );
+
+    // Omitted arguments are blank.
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, "LET(f, LAMBDA(a, b, ISOMITTED(b)), f(1))"),
+    );
+    assert_eq!(
+        "{TRUE, FALSE}",
+        eval_to_string(
+            g,
+            "LET(f, LAMBDA(a, b, {ISOMITTED(a), ISOMITTED(b)}), f(, 2))"
+        ),
+    );
+    assert_eq!(
+        "2",
+        eval_to_string(
+            g,
+            "LET(f, LAMBDA(a, b, IF(ISOMITTED(b), a * 2, a * b)), f(1))"
+        ),
+    );
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "LAMBDA parameter".into(),
+            got: Some("numeric literal".into()),
+        },
+        eval(g, "LET(f, LAMBDA(a, ISOMITTED(1)), f(1))")
+            .unwrap_err()
+            .msg,
+    );
+
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "LET(f, LAMBDA(x, x), f(1, 2))").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "LAMBDA(x, X, x)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadFunctionName,
+        eval(g, "LET(f, 1, f(2))").unwrap_err().msg,
+    );
+    assert_eq!("6", eval_to_string(g, "LET(sum, LAMBDA(x, x * 2), sum(3))"));
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "number".into(),
+            got: Some("lambda".into()),
+        },
+        eval(g, "LAMBDA(1) + 1").unwrap_err().msg,
+    );
+
+    // Recursion works, but is limited so that it can't overflow the stack.
+    assert_eq!(
+        "10",
+        eval_to_string(
+            g,
+            "LET(f, LAMBDA(self, n, IF(n = 0, 0, 1 + self(self, n - 1))), f(f, 10))",
+        ),
+    );
+    assert_eq!(
+        FormulaErrorMsg::NestingTooDeep,
+        eval(g, "LET(f, LAMBDA(g, g(g)), f(f))").unwrap_err().msg,
+    );
+    assert_eq!(
+        "{#ERROR!, #ERROR!}",
+        eval_to_string(g, "MAP({1, 2}, LAMBDA(x, LET(f, LAMBDA(g, g(g)), f(f))))"),
+    );
+}
+
+#[test]
+fn test_formula_map_reduce_scan() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!(
+        "{1, 4; 9, 16}",
+        eval_to_string(g, "MAP({1, 2; 3, 4}, LAMBDA(x, x * x))"),
+    );
+    assert_eq!("25", eval_to_string(g, "MAP(5, LAMBDA(x, x * x))"));
+    assert_eq!(
+        "{1, 3, 6, 10}",
+        eval_to_string(g, "SCAN(0, {1, 2, 3, 4}, LAMBDA(total, x, total + x))"),
+    );
+    assert_eq!(
+        "{11; 13}",
+        eval_to_string(g, "SCAN(10, {1; 2}, LAMBDA(total, x, total + x))"),
+    );
+    assert_eq!(
+        "24",
+        eval_to_string(g, "REDUCE(1, {1, 2, 3, 4}, LAMBDA(acc, x, acc * x))"),
+    );
+    assert_eq!(
+        "{1, 2}",
+        eval_to_string(g, "REDUCE({0, 0}, {1, 2}, LAMBDA(acc, x, {1, 2}))"),
+    );
+    // The lambda can be bound to a name, and can use names bound outside.
+    assert_eq!(
+        "{3, 6}",
+        eval_to_string(g, "LET(n, 3, f, LAMBDA(x, x * n), MAP({1, 2}, f))"),
+    );
+
+    // Errors stay with the value that caused them.
+    assert_eq!(
+        "{1, #N/A, 2}",
+        eval_to_string(g, "MAP({1, 0, 2}, LAMBDA(x, IF(x = 0, NA(), x)))"),
+    );
+    assert_eq!(
+        "{1, #N/A, #N/A}",
+        eval_to_string(
+            g,
+            "SCAN(0, {1, 0, 2}, LAMBDA(acc, x, IF(x = 0, NA(), acc + x)))"
+        ),
+    );
+    assert_eq!("{1, 2}", eval_to_string(g, "MAP({1, 2}, LAMBDA(x, {x}))"),);
+    assert_eq!(
+        "{#VALUE!, #VALUE!}",
+        eval_to_string(g, "MAP({1, 2}, LAMBDA(x, {x, x}))"),
+    );
+
+    // The lambda must take the right number of parameters.
+    for src in [
+        "MAP({1, 2}, LAMBDA(a, b, a))",
+        "REDUCE(0, {1, 2}, LAMBDA(a, a))",
+        "SCAN(0, {1, 2}, LAMBDA(a, b, c, a))",
+        "MAP({1, 2})",
+        "SCAN({1, 2}, LAMBDA(a, b, a))",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::BadArgumentCount,
+            eval(g, src).unwrap_err().msg,
+            "{src}",
+        );
+    }
+    assert_eq!(
+        FormulaErrorMsg::Expected {
+            expected: "lambda".into(),
+            got: Some("number".into()),
+        },
+        eval(g, "MAP({1, 2}, 3)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_base_conversion() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("1010", eval_to_string(g, "DEC2BIN(10)"));
+    assert_eq!("00001010", eval_to_string(g, "DEC2BIN(10, 8)"));
+    assert_eq!("12", eval_to_string(g, "DEC2OCT(10)"));
+    assert_eq!("FF", eval_to_string(g, "DEC2HEX(255)"));
+    assert_eq!("00FF", eval_to_string(g, "DEC2HEX(255, 4.9)"));
+    assert_eq!("FF", eval_to_string(g, "DEC2HEX(255.9)"));
+    assert_eq!("10", eval_to_string(g, "BIN2DEC(1010)"));
+    assert_eq!("12", eval_to_string(g, "BIN2OCT('1010')"));
+    assert_eq!("00A", eval_to_string(g, "BIN2HEX('1010', 3)"));
+    assert_eq!("8", eval_to_string(g, "OCT2DEC(10)"));
+    assert_eq!("1000", eval_to_string(g, "OCT2BIN(10)"));
+    assert_eq!("8", eval_to_string(g, "OCT2HEX(10)"));
+    assert_eq!("255", eval_to_string(g, "HEX2DEC('ff')"));
+    assert_eq!("11111111", eval_to_string(g, "HEX2BIN('FF')"));
+    assert_eq!("377", eval_to_string(g, "HEX2OCT('FF')"));
+    assert_eq!("{1, 10, 11}", eval_to_string(g, "DEC2BIN({1, 2, 3})"));
+
+    // Negative numbers use 10-digit two's complement, ignoring `places`.
+    assert_eq!("1111111111", eval_to_string(g, "DEC2BIN(-1)"));
+    assert_eq!("1000000000", eval_to_string(g, "DEC2BIN(-512, 3)"));
+    assert_eq!("7777777634", eval_to_string(g, "DEC2OCT(-100)"));
+    assert_eq!("FFFFFFFF9C", eval_to_string(g, "DEC2HEX(-100)"));
+    assert_eq!("-512", eval_to_string(g, "BIN2DEC('1000000000')"));
+    assert_eq!("-100", eval_to_string(g, "OCT2DEC('7777777634')"));
+    assert_eq!("-100", eval_to_string(g, "HEX2DEC('FFFFFFFF9C')"));
+    assert_eq!("1110011100", eval_to_string(g, "HEX2BIN('FFFFFFFF9C')"));
+    assert_eq!("FFFFFFFF9C", eval_to_string(g, "OCT2HEX('7777777634')"));
+
+    // Out of range
+    for formula in [
+        "DEC2BIN(512)",
+        "DEC2BIN(-513)",
+        "DEC2OCT(536870912)",
+        "DEC2HEX(549755813888)",
+        "DEC2HEX(-549755813889)",
+        "HEX2BIN('200')",
+        "HEX2BIN('FFFFFFFDFF')",
+        "DEC2BIN(10, 3)",
+        "DEC2BIN(10, 0)",
+        "DEC2BIN(10, 11)",
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert!(
+            matches!(err.msg, FormulaErrorMsg::NotANumber(_)),
+            "{formula}: {err}",
+        );
+        assert_eq!(Some("#NUM!"), err.msg.excel_code());
+    }
+
+    // Invalid digits
+    let err = eval(g, "BIN2DEC('102')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::NotANumber("\"102\"".into()), err.msg);
+    assert_eq!(Some(Span { start: 8, end: 13 }), err.span);
+    let err = eval(g, "HEX2DEC('12345678901')").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::NotANumber("\"12345678901\"".into()),
+        err.msg,
+    );
+    let err = eval(g, "DEC2BIN(10, 3)").unwrap_err();
+    assert_eq!(Some(Span { start: 12, end: 13 }), err.span);
+
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "HEX2DEC('FF', 4)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_bitwise() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("8", eval_to_string(g, "BITAND(12, 10)"));
+    assert_eq!("14", eval_to_string(g, "BITOR(12, 10)"));
+    assert_eq!("6", eval_to_string(g, "BITXOR(12, 10)"));
+    assert_eq!("{4, 2}", eval_to_string(g, "BITAND({12, 10}, 6)"));
+    assert_eq!("20", eval_to_string(g, "BITLSHIFT(5, 2)"));
+    assert_eq!("1", eval_to_string(g, "BITRSHIFT(5, 2)"));
+    // Negative shifts go the other way.
+    assert_eq!("1", eval_to_string(g, "BITLSHIFT(5, -2)"));
+    assert_eq!("20", eval_to_string(g, "BITRSHIFT(5, -2)"));
+    assert_eq!("{2, 4, 8}", eval_to_string(g, "BITLSHIFT(1, {1, 2, 3})"));
+
+    // Boundary values
+    let max = "281474976710655"; // 2^48-1
+    assert_eq!(max, eval_to_string(g, &format!("BITOR({max}, 0)")));
+    assert_eq!(max, eval_to_string(g, &format!("BITXOR({max}, 0)")));
+    assert_eq!("0", eval_to_string(g, &format!("BITAND({max}, 0)")));
+    assert_eq!("140737488355328", eval_to_string(g, "BITLSHIFT(1, 47)"));
+    assert_eq!("0", eval_to_string(g, &format!("BITRSHIFT({max}, 53)")));
+    assert_eq!("1", eval_to_string(g, &format!("BITRSHIFT({max}, 47)")));
+    assert_eq!("0", eval_to_string(g, "BITLSHIFT(0, 53)"));
+
+    for s in [
+        "BITAND(-1, 1)",
+        "BITOR(1.5, 1)",
+        "BITXOR(1, 281474976710656)",
+        // Shift amounts over 53
+        "BITLSHIFT(0, 54)",
+        "BITRSHIFT(1, -54)",
+        "BITRSHIFT(1, 54)",
+        "BITLSHIFT(1, 2.5)",
+        // Results over 2^48-1
+        "BITLSHIFT(1, 48)",
+        "BITRSHIFT(3, -47)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::BadNumber,
+            eval(g, s).unwrap_err().msg,
+            "{s:?}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_convert() {
+    let g = &mut PanicGridMock;
+    let mut assert_converts = |expected: f64, s: &str| {
+        let got = eval(g, s).expect(s).to_string().parse::<f64>().unwrap();
+        assert!(
+            (expected - got).abs() < 1e-9,
+            "expected {expected}, got {got} for {s:?}"
+        );
+    };
+
+    assert_converts(1.609344, "CONVERT(1, 'mi', 'km')");
+    assert_converts(1.0, "CONVERT(12, 'in', 'ft')");
+    assert_converts(0.45359237, "CONVERT(1, 'lbm', 'kg')");
+    assert_converts(24.0, "CONVERT(1, 'day', 'hr')");
+    assert_converts(4.0, "CONVERT(1, 'gal', 'qt')");
+    assert_converts(101.325, "CONVERT(1, 'atm', 'kPa')");
+
+    // Prefixes, which are squared or cubed for area and volume.
+    assert_converts(1.5, "CONVERT(1500, 'mg', 'g')");
+    assert_converts(10.0, "CONVERT(1, 'dam', 'm')");
+    assert_converts(1000.0, "CONVERT(1, 'L', 'ml')");
+    assert_converts(1e6, "CONVERT(1, 'km2', 'm2')");
+    assert_converts(1e-6, "CONVERT(1, 'cm3', 'm3')");
+    assert_converts(8192.0, "CONVERT(1, 'kibyte', 'bit')");
+    assert_converts(1000.0, "CONVERT(1, 'kbyte', 'byte')");
+
+    // Temperatures have different zero points.
+    assert_converts(212.0, "CONVERT(100, 'C', 'F')");
+    assert_converts(0.0, "CONVERT(32, 'F', 'C')");
+    assert_converts(-40.0, "CONVERT(-40, 'fah', 'cel')");
+    assert_converts(273.15, "CONVERT(0, 'C', 'K')");
+    assert_converts(491.67, "CONVERT(0, 'C', 'Rank')");
+    assert_converts(100.0, "CONVERT(80, 'Reau', 'C')");
+    assert_converts(0.5, "CONVERT(500, 'mK', 'K')");
+
+    assert_eq!(
+        "{100, 250}",
+        eval_to_string(g, "CONVERT({1, 2.5}, 'm', 'cm')")
+    );
+
+    // Units of different dimensions can't be converted.
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "CONVERT(1, 'm', 'sec')").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "CONVERT(1, 'C', 'J')").unwrap_err().msg,
+    );
+    // Unknown units, including prefixes on units that don't take them.
+    for (formula, unit) in [
+        ("CONVERT(1, 'furlong', 'm')", "furlong"),
+        ("CONVERT(1, 'm', 'M')", "M"),
+        ("CONVERT(1, 'kin', 'm')", "kin"),
+        ("CONVERT(1, 'kim', 'm')", "kim"),
+        ("CONVERT(1, 'KM', 'm')", "KM"),
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::UnknownUnit(format!("{unit:?}").into()),
+            eval(g, formula).unwrap_err().msg,
+        );
+    }
+}
+
+#[test]
+fn test_formula_text() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("$1,234.50", eval_to_string(g, "TEXT(1234.5, '$#,##0.00')"));
+    assert_eq!("(5.00)", eval_to_string(g, "TEXT(-5, '0.00;(0.00)')"));
+    assert_eq!("12.5%", eval_to_string(g, "TEXT(0.125, '0.0%')"));
+    assert_eq!("2021-03-14", eval_to_string(g, "TEXT(44269, 'yyyy-mm-dd')"));
+    assert_eq!("0042", eval_to_string(g, "TEXT('42', '0000')"));
+    assert_eq!("TRUE", eval_to_string(g, "TEXT(TRUE(), '0.00')"));
+    assert_eq!("<abc>", eval_to_string(g, "TEXT('abc', '0;0;0;<@>')"));
+    assert_eq!("{1.0, 2.0}", eval_to_string(g, "TEXT({1, 2}, '0.0')"));
+
+    let err = eval(g, "TEXT(-1, 'yyyy')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::InvalidDate, err.msg);
+    assert_eq!(Some(Span { start: 5, end: 7 }), err.span);
+    let err = eval(g, "TEXT(1, '0;0;0;0;0')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::InvalidArgument, err.msg);
+    assert_eq!(Some(Span { start: 8, end: 19 }), err.span);
+}
+
+#[test]
+fn test_formula_dollar_fixed() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("$1,234.57", eval_to_string(g, "DOLLAR(1234.567)"));
+    assert_eq!("$1,234.6", eval_to_string(g, "DOLLAR(1234.567, 1)"));
+    assert_eq!("$1,235", eval_to_string(g, "DOLLAR(1234.567, 0)"));
+    assert_eq!("$0.12", eval_to_string(g, "DOLLAR(0.123)"));
+    // Negative amounts are in parentheses.
+    assert_eq!("($1,234.57)", eval_to_string(g, "DOLLAR(-1234.567)"));
+    assert_eq!("($1,200)", eval_to_string(g, "DOLLAR(-1234.567, -2)"));
+
+    assert_eq!("1,234.57", eval_to_string(g, "FIXED(1234.567)"));
+    assert_eq!("1,234.6", eval_to_string(g, "FIXED(1234.567, 1)"));
+    assert_eq!("1,234,567.000", eval_to_string(g, "FIXED(1234567, 3)"));
+    // Negative numbers have a minus sign.
+    assert_eq!("-1,234.6", eval_to_string(g, "FIXED(-1234.567, 1)"));
+    assert_eq!("-0.50", eval_to_string(g, "FIXED(-0.5)"));
+    assert_eq!("0.00", eval_to_string(g, "FIXED(-0.001)"));
+    // Negative decimals round to the left of the decimal point.
+    assert_eq!("1,230", eval_to_string(g, "FIXED(1234.567, -1)"));
+    assert_eq!("-1,000", eval_to_string(g, "FIXED(-1234.567, -3)"));
+    assert_eq!("0", eval_to_string(g, "FIXED(1234.567, -4)"));
+
+    // `no_commas` leaves out the thousands separators.
+    assert_eq!("1234567.00", eval_to_string(g, "FIXED(1234567, 2, TRUE())"));
+    assert_eq!(
+        "1,234,567.00",
+        eval_to_string(g, "FIXED(1234567, 2, FALSE())")
+    );
+    assert_eq!(
+        "-1234567",
+        eval_to_string(g, "FIXED(-1234567.4, 0, TRUE())")
+    );
+    assert_eq!("{1.0, 2.5}", eval_to_string(g, "FIXED({1, 2.54}, 1)"));
+
+    assert_eq!(
+        FormulaErrorMsg::BadNumber,
+        eval(g, "FIXED(1, 128)").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "DOLLAR(1, 2, TRUE())").unwrap_err().msg,
+    );
+
+    // The currency symbol and separators depend on the locale.
+    let settings = EvalSettings {
+        number_locale: NumberLocale {
+            currency_symbol: "€",
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+        ..Default::default()
+    };
+    for (expected, src) in [
+        ("€1.234,57", "DOLLAR(1234.567)"),
+        ("(€1.200)", "DOLLAR(-1234.567, -2)"),
+        ("-1.234.567,0", "FIXED(-1234567, 1)"),
+        ("1234567,00", "FIXED(1234567, 2, TRUE())"),
+    ] {
+        assert_eq!(
+            expected,
+            eval_with_settings(g, src, settings).unwrap().to_string(),
+            "{src}",
+        );
+    }
+    // `TEXT` is not affected.
+    assert_eq!(
+        "$1,234.57",
+        eval_with_settings(g, "TEXT(1234.567, '$#,##0.00')", settings)
+            .unwrap()
+            .to_string(),
+    );
+}
+
+#[test]
+fn test_formula_numbervalue() {
+    let g = &mut PanicGridMock;
+
+    for (source, expected) in [
+        ("NUMBERVALUE('1,234.56')", "1234.56"),
+        ("NUMBERVALUE('1.234,56', ',', '.')", "1234.56"),
+        ("NUMBERVALUE('1 234,5', ',', ' ')", "1234.5"),
+        ("NUMBERVALUE(\"1'234'567\", '.', \"'\")", "1234567"),
+        ("NUMBERVALUE(' -3.5e2 ')", "-350"),
+        ("NUMBERVALUE('.5')", "0.5"),
+        ("NUMBERVALUE('12,')", "12"),
+        ("NUMBERVALUE('50%')", "0.5"),
+        ("NUMBERVALUE('5 % %')", "0.0005"),
+        ("NUMBERVALUE('')", "0"),
+        ("NUMBERVALUE(7)", "7"),
+        // Only the first character of each separator is used.
+        ("NUMBERVALUE('1_5', '_,', ';.')", "1.5"),
+        // A blank separator is the default.
+        ("NUMBERVALUE('1;000.5', , ';')", "1000.5"),
+        ("NUMBERVALUE({'1,5'; '2,25'}, ',', '.')", "{1.5; 2.25}"),
+    ] {
+        assert_eq!(expected, eval_to_string(g, source), "{source:?}");
+    }
+
+    for (source, text) in [
+        ("NUMBERVALUE('1.2.3')", "1.2.3"),
+        ("NUMBERVALUE('1.234,5')", "1.234,5"),
+        ("NUMBERVALUE('1,234.5', ',', '.')", "1,234.5"),
+        ("NUMBERVALUE('%5')", "%5"),
+        ("NUMBERVALUE('%')", "%"),
+        ("NUMBERVALUE('$5')", "$5"),
+        ("NUMBERVALUE('inf')", "inf"),
+        ("NUMBERVALUE('1-2')", "1-2"),
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::NotANumber(format!("{text:?}").into()),
+            err.msg,
+            "{source:?}",
+        );
+    }
+    let err = eval(g, "NUMBERVALUE('1.5', '.', '.')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::InvalidArgument, err.msg);
+    assert_eq!(Some(Span { start: 24, end: 27 }), err.span);
+}
+
+#[test]
+fn test_formula_textsplit() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("{a, b, c}", eval_to_string(g, "TEXTSPLIT('a,b,c', ',')"));
+    assert_eq!(
+        "{a, b; c, #N/A}",
+        eval_to_string(g, "TEXTSPLIT('a b;c', ' ', ';')"),
+    );
+    assert_eq!("{a; b}", eval_to_string(g, "TEXTSPLIT('a;b', '', ';')"));
+    assert_eq!("{a, , b}", eval_to_string(g, "TEXTSPLIT('a--b', '-')"));
+    assert_eq!(
+        "{a, b}",
+        eval_to_string(g, "TEXTSPLIT('a--b', '-', '', TRUE())"),
+    );
+    assert!(matches!(
+        eval(g, "TEXTSPLIT('a', '')").unwrap_err().msg,
+        FormulaErrorMsg::Expected { .. },
+    ));
+}
+
+#[test]
+fn test_formula_char_codes() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("65", eval_to_string(g, "CODE('ABC')"));
+    assert_eq!("{97, 98}", eval_to_string(g, "CODE({'a', 'b'})"));
+    assert_eq!("A", eval_to_string(g, "CHAR(65.9)"));
+    assert_eq!("A", eval_to_string(g, "CHAR(CODE('A'))"));
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "CODE('é')").unwrap_err().msg,
+    );
+    for source in ["CHAR(0)", "CHAR(128)", "CHAR(-1)"] {
+        assert_eq!(
+            FormulaErrorMsg::BadNumber,
+            eval(g, source).unwrap_err().msg,
+            "{source:?}",
+        );
+    }
+
+    // Multi-byte characters are one code point, not several bytes.
+    assert_eq!("233", eval_to_string(g, "UNICODE('é')"));
+    assert_eq!("8364", eval_to_string(g, "UNICODE('€uro')"));
+    assert_eq!("128512", eval_to_string(g, "UNICODE('😀')"));
+    assert_eq!("€", eval_to_string(g, "UNICHAR(8364)"));
+    assert_eq!("😀", eval_to_string(g, "UNICHAR(128512)"));
+    assert_eq!("😀", eval_to_string(g, "UNICHAR(UNICODE('😀'))"));
+
+    // Surrogates and values past the end of Unicode are not characters.
+    for source in [
+        "UNICHAR(0)",
+        "UNICHAR(55296)",
+        "UNICHAR(57343)",
+        "UNICHAR(1114112)",
+        "UNICHAR(1e300)",
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg, "{source:?}");
+        assert_eq!(
+            Some(Span {
+                start: 8,
+                end: source.len() - 1
+            }),
+            err.span
+        );
+    }
+
+    // Empty text has no first character.
+    for source in ["CODE('')", "UNICODE('')"] {
+        assert_eq!(
+            FormulaErrorMsg::NotAvailable,
+            eval(g, source).unwrap_err().msg,
+            "{source:?}",
+        );
+    }
+}
+
+#[test]
+fn test_formula_arraytotext() {
+    let g = &mut PanicGridMock;
+
+    let array = r#"{1, 'a"b\\c', TRUE(); -2.5, NA(), ''}"#;
+    assert_eq!(
+        r#"1, a"b\c, TRUE, -2.5, #N/A, "#,
+        eval_to_string(g, &format!("ARRAYTOTEXT({array})")),
+    );
+    assert_eq!(
+        r#"{1, "a\"b\\c", TRUE(); -2.5, #N/A, ""}"#,
+        eval_to_string(g, &format!("ARRAYTOTEXT({array}, 1)")),
+    );
+    assert_eq!("{7}", eval_to_string(g, "ARRAYTOTEXT(7, 1)"));
+    assert_eq!("x", eval_to_string(g, "VALUETOTEXT('x')"));
+    assert_eq!(r#""x""#, eval_to_string(g, "VALUETOTEXT('x', 1)"));
+    assert_eq!("#N/A", eval_to_string(g, "VALUETOTEXT(NA(), 1)"));
+    assert_eq!(r#"{"a", 1}"#, eval_to_string(g, "VALUETOTEXT({'a', 1}, 1)"),);
+
+    // The strict format parses back to the same values.
+    for array in [
+        array,
+        "{0.125, -3; 1e21, 'x'}",
+        r#"{FALSE(), #DIV/0!, #REF!, '\''}"#,
+    ] {
+        let value = eval(g, array).unwrap();
+        let text = eval_to_string(g, &format!("ARRAYTOTEXT({array}, 1)"));
+        assert_eq!(value, eval(g, &text).unwrap(), "{text}");
+    }
+
+    for formula in ["ARRAYTOTEXT({1}, 2)", "VALUETOTEXT(1, -1)"] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadNumber, err.msg, "{formula}");
+    }
+}
+
+#[test]
+fn test_formula_encodeurl() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!(
+        "a%20b%26c%3Dd%2Fe-f_g.h~i",
+        eval_to_string(g, "ENCODEURL('a b&c=d/e-f_g.h~i')"),
+    );
+    assert_eq!("caf%C3%A9", eval_to_string(g, "ENCODEURL('café')"));
+    assert_eq!("", eval_to_string(g, "ENCODEURL('')"));
+}
+
+#[test]
+fn test_formula_regex() {
+    let g = &mut PanicGridMock;
+
+    // Matching
+    assert_eq!(
+        "TRUE",
+        eval_to_string(g, r"REGEXMATCH('order 1234', '\\d{4}')")
+    );
+    assert_eq!(
+        "FALSE",
+        eval_to_string(g, r"REGEXMATCH('order 123', '\\d{4}')")
+    );
+    assert_eq!("FALSE", eval_to_string(g, "REGEXMATCH('ABC', 'abc')"));
+    assert_eq!("TRUE", eval_to_string(g, "REGEXMATCH('ABC', '(?i)abc')"));
+    assert_eq!("TRUE", eval_to_string(g, r"REGEXMATCH(1.5, '^1\\.5$')"));
+    assert_eq!(
+        "{TRUE, FALSE, TRUE}",
+        eval_to_string(g, "REGEXMATCH({'cat', 'dog', 'cart'}, '^ca')"),
+    );
+
+    // Extraction
+    assert_eq!(
+        "1234",
+        eval_to_string(g, r"REGEXEXTRACT('a 1234 b 56', '\\d+')")
+    );
+    assert_eq!(
+        "{1234; 56}",
+        eval_to_string(g, r"REGEXEXTRACT('a 1234 b 56', '\\d+', 1)"),
+    );
+    assert_eq!(
+        "example.com",
+        eval_to_string(g, "REGEXEXTRACT('bob@example.com', '@(.+)$')"),
+    );
+    assert_eq!(
+        "{bob, example.com}",
+        eval_to_string(g, r"REGEXEXTRACT('bob@example.com', '(\\w+)@(.+)')"),
+    );
+    assert_eq!(
+        "{a, 1; b, ; c, 3}",
+        eval_to_string(g, r"REGEXEXTRACT('a=1, b=, c=3', '(\\w)=(\\d)?', 1)"),
+    );
+    for src in [
+        r"REGEXEXTRACT('abc', '\\d')",
+        r"REGEXEXTRACT('abc', '\\d', 1)",
+    ] {
+        assert_eq!(
+            FormulaErrorMsg::NotAvailable,
+            eval(g, src).unwrap_err().msg,
+            "{src}",
+        );
+    }
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "REGEXEXTRACT('abc', 'b', 2)").unwrap_err().msg,
+    );
+
+    // Replacement
+    assert_eq!(
+        "2024-01-31",
+        eval_to_string(
+            g,
+            r"REGEXREPLACE('01/31/2024', '(\\d+)/(\\d+)/(\\d+)', '$3-$1-$2')",
+        ),
+    );
+    assert_eq!(
+        "a-b-c",
+        eval_to_string(g, r"REGEXREPLACE('a  b   c', '\\s+', '-')"),
+    );
+    assert_eq!("x1y", eval_to_string(g, "REGEXREPLACE('x1y', 'z', '$$')"),);
+    assert_eq!(
+        "{a$, ab}",
+        eval_to_string(g, r"REGEXREPLACE({'a1', 'ab'}, '\\d', '$$')"),
+    );
+
+    // Invalid patterns are reported at the pattern.
+    for src in [
+        "REGEXMATCH('abc', 'a(b')",
+        "REGEXEXTRACT('abc', 'a(b')",
+        "REGEXREPLACE('abc', 'a(b', 'x')",
+    ] {
+        let err = eval(g, src).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::BadRegex {
+                msg: "unclosed group".into(),
+            },
+            err.msg,
+            "{src}",
+        );
+        assert_eq!("'a(b'", err.span.unwrap().of_str(src), "{src}");
+        assert_eq!(Some("#VALUE!"), err.msg.excel_code());
+    }
+    // Backreferences are not supported.
+    assert!(matches!(
+        eval(g, r"REGEXMATCH('aa', '(a)\\1')").unwrap_err().msg,
+        FormulaErrorMsg::BadRegex { .. },
+    ));
+    // Patterns that are too large to compile are invalid.
+    assert!(matches!(
+        eval(g, r"REGEXMATCH('a', '\\w{1000}{1000}')")
+            .unwrap_err()
+            .msg,
+        FormulaErrorMsg::BadRegex { .. },
+    ));
+
+    // Replacements and matches count against the limits.
+    let settings = EvalSettings {
+        max_text_length: 10,
+        ..Default::default()
+    };
+    assert_eq!(
+        FormulaErrorMsg::TextTooLong { limit: 10 },
+        eval_with_settings(g, "REGEXREPLACE('abcdef', '', '-')", settings)
+            .unwrap_err()
+            .msg,
+    );
+    let settings = EvalSettings {
+        max_eval_steps: 20,
+        ..Default::default()
+    };
+    assert!(matches!(
+        eval_with_settings(g, "REGEXEXTRACT(REPT('a', 100), 'a', 1)", settings)
+            .unwrap_err()
+            .msg,
+        FormulaErrorMsg::EvaluationLimitExceeded { .. },
+    ));
+}
+
+#[test]
+fn test_formula_complex() {
+    let g = &mut PanicGridMock;
+
+    assert_eq!("3+4i", eval_to_string(g, "COMPLEX(3, 4)"));
+    assert_eq!("3-4j", eval_to_string(g, "COMPLEX(3, -4, 'j')"));
+    assert_eq!("i", eval_to_string(g, "COMPLEX(0, 1)"));
+    assert_eq!("1.5", eval_to_string(g, "COMPLEX(1.5, 0, '')"));
+    assert_eq!("3", eval_to_string(g, "IMREAL('3-4i')"));
+    assert_eq!("-4", eval_to_string(g, "IMAGINARY('3-4i')"));
+    assert_eq!("-1", eval_to_string(g, "IMAGINARY('-j')"));
+    assert_eq!("0", eval_to_string(g, "IMAGINARY(7)"));
+
+    // Magnitude
+    assert_eq!("5", eval_to_string(g, "IMABS('3+4i')"));
+    assert_eq!("13", eval_to_string(g, "IMABS('-5-12i')"));
+    assert_eq!("2", eval_to_string(g, "IMABS(-2)"));
+
+    // Addition
+    assert_eq!("4+6i", eval_to_string(g, "IMSUM('1+2i', '3+4i')"));
+    assert_eq!("4", eval_to_string(g, "IMSUM('1+i', '3-i')"));
+    assert_eq!("5+j", eval_to_string(g, "IMSUM({'1+j', 4, ''})"));
+    assert_eq!("0", eval_to_string(g, "IMSUM('')"));
+
+    // Multiplication
+    assert_eq!("-5+10i", eval_to_string(g, "IMPRODUCT('1+2i', '3+4i')"));
+    assert_eq!("-1", eval_to_string(g, "IMPRODUCT('i', 'i')"));
+    assert_eq!("6-3j", eval_to_string(g, "IMPRODUCT(3, '2-j')"));
+    assert_eq!("2", eval_to_string(g, "IMPRODUCT({'1+i'; '1-i'})"));
+
+    // Results are written canonically, whatever form the inputs were in.
+    assert_eq!("1+2.5i", eval_to_string(g, "IMSUM('1.0+2.50i')"));
+    assert_eq!("-i", eval_to_string(g, "IMSUM('1-0.5i', '-1-0.5i')"));
+    assert_eq!("2000j", eval_to_string(g, "IMPRODUCT('2E+3j')"));
+    assert_eq!("0", eval_to_string(g, "IMPRODUCT('3+4i', 0)"));
+    assert_eq!("i", eval_to_string(g, "COMPLEX(0, 1.0)"));
+
+    // Invalid complex numbers
+    let err = eval(g, "IMREAL('3+4k')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::NotANumber("\"3+4k\"".into()), err.msg);
+    assert_eq!(Some(Span { start: 7, end: 13 }), err.span);
+    let err = eval(g, "IMSUM('1+i', 'x')").unwrap_err();
+    assert_eq!(FormulaErrorMsg::NotANumber("\"x\"".into()), err.msg);
+    assert_eq!(Some(Span { start: 13, end: 16 }), err.span);
+
+    // Invalid suffix
+    let err = eval(g, "COMPLEX(1, 2, 'k')").unwrap_err();
+    assert!(matches!(err.msg, FormulaErrorMsg::Expected { .. }), "{err}");
+    assert_eq!(Some(Span { start: 14, end: 17 }), err.span);
+
+    // Mixed suffixes
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "IMSUM('i', 'j')").unwrap_err().msg,
+    );
+    assert_eq!(
+        FormulaErrorMsg::InvalidArgument,
+        eval(g, "IMPRODUCT('1+2j', 3, '4-i')").unwrap_err().msg,
+    );
+
+    // These are the same errors as in Excel.
+    for (expected, formula) in [
+        ("#NUM!", "IMREAL('3+4k')"),
+        ("#NUM!", "IMABS('1+2ij')"),
+        ("#NUM!", "IMPRODUCT('1+', 2)"),
+        ("#VALUE!", "IMSUM('i', 'j')"),
+        ("#VALUE!", "COMPLEX(1, 2, 'I')"),
+    ] {
+        let err = eval(g, formula).unwrap_err();
+        assert_eq!(Some(expected), err.msg.excel_code(), "{formula}");
+    }
+}
+
+#[test]
+fn test_formula_index() {
+    make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
+    let g = &mut GridMock;
+
+    assert_eq!("13", eval_to_string(g, "INDEX(B1:B5, 3)"));
+    assert_eq!("23", eval_to_string(g, "INDEX(A1:C5, 3, 3)"));
+    // A single row is indexed by column.
+    assert_eq!("21", eval_to_string(g, "INDEX(A1:E1, 3)"));
+    assert_eq!("6", eval_to_string(g, "INDEX({4, 5; 6, 7}, 2)"));
+    assert_eq!("7", eval_to_string(g, "INDEX({4, 5; 6, 7}, 2, 2)"));
+    assert_eq!("5", eval_to_string(g, "INDEX({4, 5}, 2)"));
+
+    let err = eval(g, "INDEX(A1:A5, 6)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::IndexOutOfBounds, err.msg);
+    assert_eq!(Some(Span { start: 13, end: 14 }), err.span);
+    let err = eval(g, "INDEX({1, 2; 3, 4}, 1, 0)").unwrap_err();
+    assert_eq!(FormulaErrorMsg::IndexOutOfBounds, err.msg);
+    assert_eq!(
+        FormulaErrorMsg::BadArgumentCount,
+        eval(g, "INDEX(A1:A5, 1, 1, 1)").unwrap_err().msg,
+    );
+}
+
+#[test]
+fn test_formula_index_range_endpoints() {
+    make_stateless_grid_mock!(|pos| Some((pos.x * 10 + pos.y).to_string()));
+    let g = &mut GridMock;
+
+    // Function calls parse as operands of the range operator.
+    let form = parse_formula("INDEX(A1:A5, 2):INDEX(B1:B5, 4)", Pos::ORIGIN).unwrap();
+    let ast::AstNodeContents::FunctionCall { func, args } = &form.ast.inner else {
+        panic!("expected range operator, got {form}");
+    };
+    assert_eq!(":", func.inner);
+    for (arg, column) in args.iter().zip(["A1:A5", "B1:B5"]) {
+        let ast::AstNodeContents::FunctionCall { func, args } = &arg.inner else {
+            panic!("expected INDEX, got {arg}");
+        };
+        assert_eq!("INDEX", func.inner);
+        assert_eq!(
+            column,
+            args[0].span.of_str("INDEX(A1:A5, 2):INDEX(B1:B5, 4)")
+        );
+    }
+
+    assert_eq!(
+        "{2, 12; 3, 13; 4, 14}",
+        eval_to_string(g, "INDEX(A1:A5, 2):INDEX(B1:B5, 4)"),
+    );
+    assert_eq!("{1, 11; 2, 12}", eval_to_string(g, "A1:INDEX(B1:B5, 2)"));
+    assert_eq!("{3; 4; 5}", eval_to_string(g, "INDEX(A1:A5, 3):A5"));
+    assert_eq!("36", eval_to_string(g, "SUM(A1:INDEX(A1:A10, 8))"));
+    // Reversed corners and ranges on either side
+    assert_eq!("{2, 12; 3, 13}", eval_to_string(g, "INDEX(B1:B5, 3):A2"));
+    assert_eq!(
+        "{1, 11; 2, 12}",
+        eval_to_string(g, "(A1:A2):INDEX(B1:B5, 1)")
+    );
+    // An INDEX result can itself be indexed.
+    assert_eq!("13", eval_to_string(g, "INDEX(A1:INDEX(B1:B5, 4), 3, 2)"));
+
+    for (source, span) in [
+        ("A1:5", Span { start: 3, end: 4 }),
+        ("INDEX({1, 2}, 1):B2", Span { start: 0, end: 16 }),
+        ("A1:'B2'", Span { start: 3, end: 7 }),
+    ] {
+        let err = eval(g, source).unwrap_err();
+        assert_eq!(FormulaErrorMsg::BadCellReference, err.msg, "{source}");
+        assert_eq!(Some(span), err.span, "{source}");
+    }
+}
+
+#[test]
+fn test_formula_type_mismatch() {
+    let g = &mut PanicGridMock;
+
+    let err = eval(g, "ROUND(1.5, 'two')").unwrap_err();
+    assert_eq!(
+        FormulaErrorMsg::TypeMismatch {
+            func: "ROUND".into(),
+            arg_index: 1,
+            expected: ValueType::Number,
+            got: ValueType::Text,
+        },
+        err.msg,
+    );
+    assert_eq!(Some(Span { start: 11, end: 16 }), err.span);
+    assert_eq!(
+        "Argument 2 of ROUND expected a number but got text",
+        err.msg.to_string(),
+    );
+    assert_eq!(Some("#VALUE!"), err.msg.excel_code());
+
+    let err = eval(g, "if('maybe', 1, 2)").unwrap_err();
+    assert_eq!(
+        "Argument 1 of IF expected a boolean but got text",
+        err.msg.to_string(),
+    );
+
+    // Errors are propagated rather than reported as the wrong type.
+    assert_eq!(
+        FormulaErrorMsg::NotAvailable,
+        eval(g, "ROUND(1, NA())").unwrap_err().msg,
+    );
+    // Operators don't have numbered arguments.
+    assert!(matches!(
+        eval(g, "1 + 'two'").unwrap_err().msg,
+        FormulaErrorMsg::Expected { .. },
+    ));
+}
+
+#[test]
+fn test_formula_expected_scalar() {
+    let g = &mut PanicGridMock;
+    let m = "{1, 2; 3, 4}";
+
+    for source in [
+        format!("AGGREGATE({m}, 0, 1)"),
+        format!("AGGREGATE(1, {m}, 1)"),
+        format!("AGGREGATE(14, 0, {{1, 2}}, {m})"),
+        format!("SUBTOTAL({m}, {{1, 2}})"),
+        format!("INDEX({{1, 2; 3, 4}}, {m}, 1)"),
+        format!("INDEX({{1, 2; 3, 4}}, 1, {m})"),
+        format!("SUMIF({{1, 2}}, {m})"),
+        format!("COUNTIF({{1, 2}}, {m})"),
+        format!("SERIESSUM({m}, 1, 1, {{1}})"),
+        format!("SERIESSUM(1, {m}, 1, {{1}})"),
+        format!("SERIESSUM(1, 1, {m}, {{1}})"),
+        format!("MUNIT({m})"),
+        format!("LINEST({{1, 2, 3}}, {{1, 2, 3}}, {m})"),
+        format!("LINEST({{1, 2, 3}}, {{1, 2, 3}}, TRUE(), {m})"),
+        format!("TREND({{1, 2, 3}}, {{1, 2, 3}}, {{4}}, {m})"),
+        format!("GROWTH({{1, 2, 3}}, {{1, 2, 3}}, {{4}}, {m})"),
+        format!("WORKDAY({m}, 1)"),
+        format!("WORKDAY(1, {m})"),
+        format!("WORKDAY.INTL(1, 1, {m})"),
+        format!("NETWORKDAYS({m}, 10)"),
+        format!("NETWORKDAYS(1, {m})"),
+        format!("NETWORKDAYS.INTL(1, 10, {m})"),
+        format!("TEXTSPLIT('a b', ' ', '', {m})"),
+        format!("ARRAYTOTEXT({{1}}, {m})"),
+        format!("VALUETOTEXT(1, {m})"),
+    ] {
+        let err = eval(g, &source).unwrap_err();
+        assert_eq!(
+            FormulaErrorMsg::ExpectedScalar { got_size: (2, 2) },
+            err.msg,
+            "wrong error for {source:?}",
+        );
+        let span = err.span.expect("error should have a span");
+        assert_eq!(m, &source[span.start..span.end]);
+    }
+
+    assert_eq!(
+        "Expected a single value, got a 2x2 array",
+        eval(g, "MUNIT({1, 2; 3, 4})").unwrap_err().msg.to_string(),
+    );
+    // Arrays with one value are fine.
+    assert_eq!("{1, 0; 0, 1}", eval_to_string(g, "MUNIT({2})"));
+    assert_eq!("2", eval_to_string(g, "SUMIF({1, 2}, {2})"));
+}
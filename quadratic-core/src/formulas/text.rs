@@ -0,0 +1,140 @@
+//! Splitting text into characters and words for string functions.
+//!
+//! What counts as one character depends on [`TextUnit`]. For example, the
+//! family emoji 👨‍👩‍👧 is five code points joined into one grapheme cluster,
+//! and `e` followed by a combining acute accent is two code points that
+//! display as the single character `é`.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Unit that string functions such as `LEN` and `MID` count characters in.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TextUnit {
+    /// Each Unicode code point is a character.
+    CodePoint,
+    /// Each extended grapheme cluster is a character, which matches what the
+    /// user sees as one character.
+    #[default]
+    Grapheme,
+}
+impl TextUnit {
+    /// Splits text into characters.
+    pub fn split(self, s: &str) -> Vec<&str> {
+        match self {
+            TextUnit::CodePoint => s
+                .char_indices()
+                .map(|(i, c)| &s[i..i + c.len_utf8()])
+                .collect(),
+            TextUnit::Grapheme => s.graphemes(true).collect(),
+        }
+    }
+
+    /// Capitalizes the first letter of each word and makes all other letters
+    /// lowercase, for `PROPER`.
+    ///
+    /// With code points, a word starts at any letter that does not follow
+    /// another letter, like in Excel, so `it's` becomes `It'S`. With
+    /// graphemes, words are found using Unicode word segmentation, so `it's`
+    /// becomes `It's`.
+    pub fn proper(self, s: &str) -> String {
+        match self {
+            TextUnit::CodePoint => {
+                let mut ret = String::with_capacity(s.len());
+                let mut prev_is_letter = false;
+                for c in s.chars() {
+                    if prev_is_letter {
+                        ret.extend(c.to_lowercase());
+                    } else {
+                        ret.extend(c.to_uppercase());
+                    }
+                    prev_is_letter = c.is_alphabetic();
+                }
+                ret
+            }
+            TextUnit::Grapheme => s
+                .split_word_bounds()
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) if first.is_alphabetic() => first
+                            .to_uppercase()
+                            .chain(chars.flat_map(char::to_lowercase))
+                            .collect(),
+                        _ => word.to_lowercase(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces occurrences of `old` in `s` with `new`, for `SUBSTITUTE`. If
+    /// `instance` is given, only that occurrence is replaced, counting from 1.
+    ///
+    /// Occurrences are found from left to right and don't overlap, so `aa`
+    /// occurs twice in `aaaaa`, at the first and third characters. They are
+    /// made of whole characters, so with graphemes, `e` doesn't occur in `é`
+    /// even if it is written with a combining accent. If `old` is empty, `s`
+    /// is returned unchanged.
+    pub fn substitute(self, s: &str, old: &str, new: &str, instance: Option<usize>) -> String {
+        let chars = self.split(s);
+        let old = self.split(old);
+        if old.is_empty() {
+            return s.to_string();
+        }
+
+        let mut ret = String::with_capacity(s.len());
+        let mut count = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i..].starts_with(&old) {
+                count += 1;
+                if instance.is_none_or(|n| n == count) {
+                    ret.push_str(new);
+                } else {
+                    ret.extend(chars[i..i + old.len()].iter().copied());
+                }
+                i += old.len();
+            } else {
+                ret.push_str(chars[i]);
+                i += 1;
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_units() {
+        let family = "👨\u{200D}👩\u{200D}👧";
+        let accent = "e\u{301}";
+        assert_eq!(5, TextUnit::CodePoint.split(family).len());
+        assert_eq!(vec![family], TextUnit::Grapheme.split(family));
+        assert_eq!(vec!["e", "\u{301}"], TextUnit::CodePoint.split(accent));
+        assert_eq!(vec![accent], TextUnit::Grapheme.split(accent));
+
+        assert_eq!("It'S O'Neil", TextUnit::CodePoint.proper("it's o'NEIL"));
+        assert_eq!("It's O'neil", TextUnit::Grapheme.proper("it's o'NEIL"));
+        assert_eq!("2nd Ave.", TextUnit::Grapheme.proper("2ND ave."));
+    }
+
+    #[test]
+    fn test_substitute_whole_characters() {
+        let accented = "cafe\u{301} cafe";
+        assert_eq!(
+            "cafe\u{301} cafx",
+            TextUnit::Grapheme.substitute(accented, "e", "x", None),
+        );
+        assert_eq!(
+            "cafx\u{301} cafx",
+            TextUnit::CodePoint.substitute(accented, "e", "x", None),
+        );
+        assert_eq!(
+            "cafe\u{301} cafx",
+            TextUnit::CodePoint.substitute(accented, "e", "x", Some(2)),
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Units of measurement for `CONVERT`.
+//!
+//! Each unit is converted to and from the base unit of its dimension, such as
+//! meters for length, by scaling and then adding an offset. Only temperatures
+//! have an offset, since their zero points differ.
+//!
+//! Units are written like in Excel, and are case-sensitive. Units marked as
+//! prefixable also accept a metric prefix, such as `km` or `mg`, and units of
+//! information also accept a binary prefix, such as `kibyte`. A prefix on a
+//! unit of area or volume is squared or cubed, so `km2` is a square
+//! kilometer.
+
+/// What a unit measures. Only units of the same dimension can be converted
+/// to each other.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dimension {
+    Mass,
+    Length,
+    Time,
+    Pressure,
+    Force,
+    Energy,
+    Power,
+    Magnetism,
+    Temperature,
+    Volume,
+    Area,
+    Information,
+    Speed,
+}
+
+/// Unit of measurement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Unit {
+    /// Symbols that the unit can be written as.
+    pub symbols: &'static [&'static str],
+    pub dimension: Dimension,
+    /// Number of base units in one of this unit.
+    pub factor: f64,
+    /// Number of base units added after scaling. This is zero except for
+    /// temperatures.
+    pub offset: f64,
+    /// Power that a prefix on this unit is raised to, or zero if the unit
+    /// doesn't accept a prefix.
+    pub prefix_power: i32,
+}
+impl Unit {
+    /// Parses a unit, which may have a prefix. Returns `None` if the unit is
+    /// unknown.
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(unit) = UNITS.iter().find(|unit| unit.symbols.contains(&s)) {
+            return Some(*unit);
+        }
+        let mut prefixes = METRIC_PREFIXES.iter().chain(BINARY_PREFIXES);
+        prefixes.find_map(|&(prefix, scale)| {
+            let rest = s.strip_prefix(prefix)?;
+            let unit = UNITS.iter().find(|unit| unit.symbols.contains(&rest))?;
+            let is_binary = BINARY_PREFIXES.iter().any(|&(p, _)| p == prefix);
+            if unit.prefix_power == 0 || is_binary && unit.dimension != Dimension::Information {
+                return None;
+            }
+            Some(Unit {
+                factor: unit.factor * scale.powi(unit.prefix_power),
+                ..*unit
+            })
+        })
+    }
+
+    /// Converts `n` of this unit to `to`. Returns `None` if the units have
+    /// different dimensions.
+    pub fn convert(self, n: f64, to: Unit) -> Option<f64> {
+        if self.dimension != to.dimension {
+            return None;
+        }
+        let base = n * self.factor + self.offset;
+        Some((base - to.offset) / to.factor)
+    }
+}
+
+/// Returns a unit without an offset.
+const fn unit(
+    symbols: &'static [&'static str],
+    dimension: Dimension,
+    factor: f64,
+    prefix_power: i32,
+) -> Unit {
+    Unit {
+        symbols,
+        dimension,
+        factor,
+        offset: 0.0,
+        prefix_power,
+    }
+}
+
+/// Metric prefixes and their scales. Longer prefixes come first, so that
+/// `da` is found before `d`.
+const METRIC_PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1),
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+/// Binary prefixes, which are only used with units of information.
+const BINARY_PREFIXES: &[(&str, f64)] = &[
+    ("Yi", 1208925819614629174706176.0),
+    ("Zi", 1180591620717411303424.0),
+    ("Ei", 1152921504606846976.0),
+    ("Pi", 1125899906842624.0),
+    ("Ti", 1099511627776.0),
+    ("Gi", 1073741824.0),
+    ("Mi", 1048576.0),
+    ("ki", 1024.0),
+];
+
+/// Inches in meters, which many other units are defined from.
+const INCH: f64 = 0.0254;
+/// US gallons in cubic meters.
+const GALLON: f64 = 231.0 * INCH * INCH * INCH;
+/// UK gallons in cubic meters.
+const UK_GALLON: f64 = 4.54609e-3;
+
+/// Known units. The base units are grams, meters, seconds, pascals, newtons,
+/// joules, watts, teslas, kelvins, cubic meters, square meters, bits, and
+/// meters per second.
+const UNITS: &[Unit] = {
+    use Dimension::*;
+    &[
+        // Mass
+        unit(&["g"], Mass, 1.0, 1),
+        unit(&["sg"], Mass, 14593.90293720636, 0),
+        unit(&["lbm"], Mass, 453.59237, 0),
+        unit(&["u"], Mass, 1.66053906660e-24, 1),
+        unit(&["ozm"], Mass, 453.59237 / 16.0, 0),
+        unit(&["grain"], Mass, 453.59237 / 7000.0, 0),
+        unit(&["cwt", "shweight"], Mass, 45359.237, 0),
+        unit(&["uk_cwt", "lcwt", "hweight"], Mass, 50802.34544, 0),
+        unit(&["stone"], Mass, 6350.29318, 0),
+        unit(&["ton"], Mass, 907184.74, 0),
+        unit(&["uk_ton", "LTON", "brton"], Mass, 1016046.9088, 0),
+        // Length
+        unit(&["m"], Length, 1.0, 1),
+        unit(&["mi"], Length, 1609.344, 0),
+        unit(&["Nmi"], Length, 1852.0, 0),
+        unit(&["in"], Length, INCH, 0),
+        unit(&["ft"], Length, 12.0 * INCH, 0),
+        unit(&["yd"], Length, 36.0 * INCH, 0),
+        unit(&["ang"], Length, 1e-10, 1),
+        unit(&["ell"], Length, 45.0 * INCH, 0),
+        unit(&["ly"], Length, 9460730472580800.0, 1),
+        unit(&["parsec", "pc"], Length, 3.085677581491367e16, 0),
+        unit(&["Picapt", "Pica"], Length, INCH / 72.0, 0),
+        unit(&["pica"], Length, INCH / 6.0, 0),
+        unit(&["survey_mi"], Length, 1609.3472186944373, 0),
+        // Time
+        unit(&["yr"], Time, 365.25 * 86400.0, 0),
+        unit(&["day", "d"], Time, 86400.0, 0),
+        unit(&["hr"], Time, 3600.0, 0),
+        unit(&["mn", "min"], Time, 60.0, 0),
+        unit(&["sec", "s"], Time, 1.0, 1),
+        // Pressure
+        unit(&["Pa", "p"], Pressure, 1.0, 1),
+        unit(&["atm", "at"], Pressure, 101325.0, 1),
+        unit(&["mmHg"], Pressure, 133.322, 1),
+        unit(&["psi"], Pressure, 6894.757293168361, 0),
+        unit(&["Torr"], Pressure, 101325.0 / 760.0, 0),
+        // Force
+        unit(&["N"], Force, 1.0, 1),
+        unit(&["dyn", "dy"], Force, 1e-5, 1),
+        unit(&["lbf"], Force, 4.4482216152605, 0),
+        unit(&["pond"], Force, 9.80665e-3, 1),
+        // Energy
+        unit(&["J"], Energy, 1.0, 1),
+        unit(&["e"], Energy, 1e-7, 1),
+        unit(&["c"], Energy, 4.184, 1),
+        unit(&["cal"], Energy, 4.1868, 1),
+        unit(&["eV", "ev"], Energy, 1.602176634e-19, 1),
+        unit(&["HPh", "hh"], Energy, 2684519.537696173, 0),
+        unit(&["Wh", "wh"], Energy, 3600.0, 1),
+        unit(&["flb"], Energy, 1.3558179483314003, 0),
+        unit(&["BTU", "btu"], Energy, 1055.05585262, 0),
+        // Power
+        unit(&["W", "w"], Power, 1.0, 1),
+        unit(&["HP", "h"], Power, 745.6998715822702, 0),
+        unit(&["PS"], Power, 735.49875, 0),
+        // Magnetism
+        unit(&["T"], Magnetism, 1.0, 1),
+        unit(&["ga"], Magnetism, 1e-4, 1),
+        // Temperature
+        Unit {
+            offset: 273.15,
+            ..unit(&["C", "cel"], Temperature, 1.0, 0)
+        },
+        Unit {
+            offset: 459.67 * 5.0 / 9.0,
+            ..unit(&["F", "fah"], Temperature, 5.0 / 9.0, 0)
+        },
+        unit(&["K", "kel"], Temperature, 1.0, 1),
+        unit(&["Rank"], Temperature, 5.0 / 9.0, 0),
+        Unit {
+            offset: 273.15,
+            ..unit(&["Reau"], Temperature, 1.25, 0)
+        },
+        // Volume
+        unit(&["m3", "m^3"], Volume, 1.0, 3),
+        unit(&["l", "L", "lt"], Volume, 1e-3, 1),
+        unit(&["tsp"], Volume, GALLON / 768.0, 0),
+        unit(&["tspm"], Volume, 5e-6, 0),
+        unit(&["tbs"], Volume, GALLON / 256.0, 0),
+        unit(&["oz"], Volume, GALLON / 128.0, 0),
+        unit(&["cup"], Volume, GALLON / 16.0, 0),
+        unit(&["pt", "us_pt"], Volume, GALLON / 8.0, 0),
+        unit(&["uk_pt"], Volume, UK_GALLON / 8.0, 0),
+        unit(&["qt"], Volume, GALLON / 4.0, 0),
+        unit(&["uk_qt"], Volume, UK_GALLON / 4.0, 0),
+        unit(&["gal"], Volume, GALLON, 0),
+        unit(&["uk_gal"], Volume, UK_GALLON, 0),
+        unit(&["barrel"], Volume, 42.0 * GALLON, 0),
+        unit(&["bushel"], Volume, 0.03523907016688, 0),
+        unit(&["ft3", "ft^3"], Volume, 1728.0 * INCH * INCH * INCH, 0),
+        unit(&["in3", "in^3"], Volume, INCH * INCH * INCH, 0),
+        unit(&["yd3", "yd^3"], Volume, 46656.0 * INCH * INCH * INCH, 0),
+        unit(&["ang3", "ang^3"], Volume, 1e-30, 3),
+        // Area
+        unit(&["m2", "m^2"], Area, 1.0, 2),
+        unit(&["ha"], Area, 1e4, 0),
+        unit(&["ar"], Area, 100.0, 1),
+        unit(&["in2", "in^2"], Area, INCH * INCH, 0),
+        unit(&["ft2", "ft^2"], Area, 144.0 * INCH * INCH, 0),
+        unit(&["yd2", "yd^2"], Area, 1296.0 * INCH * INCH, 0),
+        unit(&["mi2", "mi^2"], Area, 1609.344 * 1609.344, 0),
+        unit(&["uk_acre"], Area, 4046.8564224, 0),
+        unit(&["us_acre"], Area, 4046.872609874252, 0),
+        unit(&["Nmi2", "Nmi^2"], Area, 1852.0 * 1852.0, 0),
+        unit(&["ang2", "ang^2"], Area, 1e-20, 2),
+        // Information
+        unit(&["bit"], Information, 1.0, 1),
+        unit(&["byte"], Information, 8.0, 1),
+        // Speed
+        unit(&["m/s", "m/sec"], Speed, 1.0, 1),
+        unit(&["m/h", "m/hr"], Speed, 1.0 / 3600.0, 1),
+        unit(&["mph"], Speed, 1609.344 / 3600.0, 0),
+        unit(&["kn"], Speed, 1852.0 / 3600.0, 0),
+        unit(&["admkn"], Speed, 6080.0 * 12.0 * INCH / 3600.0, 0),
+    ]
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_symbols_are_unambiguous() {
+        // Every symbol is parsed as its own unit, rather than as a prefix on
+        // another one.
+        for unit in UNITS {
+            for &symbol in unit.symbols {
+                assert_eq!(Some(*unit), Unit::parse(symbol), "{symbol}");
+            }
+        }
+    }
+}
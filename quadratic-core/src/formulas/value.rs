@@ -1,24 +1,211 @@
 use itertools::Itertools;
 use smallvec::{smallvec, SmallVec};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::fmt;
+use std::sync::Arc;
 
-use super::{FormulaErrorMsg, FormulaResult, Spanned};
+use super::array::Array;
+use super::complex::Complex;
+use super::lambda::Lambda;
+use super::order::SortKey;
+use super::{FormulaError, FormulaErrorMsg, FormulaResult, NumberFormat, Spanned};
 
 const CURRENCY_PREFIX: &[char] = &['$', '¥', '£', '€'];
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub enum Value {
     String(String),
+    /// Empty cell or omitted argument. This is different from the empty
+    /// string, but most functions treat it the same way: it is `0` in
+    /// arithmetic and `""` in text.
+    #[default]
+    Blank,
     Number(f64),
     Bool(bool),
-    Array(Vec<SmallVec<[Value; 1]>>),
-    // TODO: remove this or replace it with a more generic error type
-    MissingErr,
+    Array(Array),
+    /// Error, such as from a cell containing `#DIV/0!`. Most functions return
+    /// this error if given it as an argument.
+    Error(FormulaErrorMsg),
+    /// Function created by `LAMBDA`.
+    Lambda(Arc<Lambda>),
+    /// Date and time with a UTC offset. Where a number is expected, this is
+    /// the date serial number of its local date and time.
+    #[cfg(feature = "datetime")]
+    DateTime(chrono::DateTime<chrono::FixedOffset>),
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::String(String::new())
+impl From<f64> for Value {
+    /// ```
+    /// # use quadratic_core::formulas::Value;
+    /// assert_eq!(Value::Number(2.5), Value::from(2.5));
+    /// ```
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+impl From<i64> for Value {
+    /// ```
+    /// # use quadratic_core::formulas::Value;
+    /// assert_eq!(Value::Number(-3.0), Value::from(-3_i64));
+    /// ```
+    fn from(n: i64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+impl From<&str> for Value {
+    /// ```
+    /// # use quadratic_core::formulas::Value;
+    /// assert_eq!(Value::String("abc".to_string()), Value::from("abc"));
+    /// ```
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+impl From<String> for Value {
+    /// ```
+    /// # use quadratic_core::formulas::Value;
+    /// assert_eq!(Value::String("abc".to_string()), Value::from("abc".to_string()));
+    /// ```
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+impl From<bool> for Value {
+    /// ```
+    /// # use quadratic_core::formulas::Value;
+    /// assert_eq!(Value::Bool(true), Value::from(true));
+    /// ```
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = FormulaError;
+
+    /// Returns the number contained in a numeric value. Other values,
+    /// including strings that look like numbers, are an error.
+    ///
+    /// ```
+    /// # use quadratic_core::formulas::{FormulaErrorMsg, Value};
+    /// assert_eq!(2.5, f64::try_from(Value::from(2.5)).unwrap());
+    ///
+    /// let err = f64::try_from(Value::from("2.5")).unwrap_err();
+    /// assert_eq!(
+    ///     FormulaErrorMsg::Expected {
+    ///         expected: "number".into(),
+    ///         got: Some("string".into()),
+    ///     },
+    ///     err.msg,
+    /// );
+    /// assert!(f64::try_from(Value::from(true)).is_err());
+    /// ```
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(n),
+            other => Err(other.type_error("number")),
+        }
+    }
+}
+impl TryFrom<Value> for String {
+    type Error = FormulaError;
+
+    /// Returns the text contained in a string value. Other values are an
+    /// error; use `to_string()` to format any value as text.
+    ///
+    /// ```
+    /// # use quadratic_core::formulas::{FormulaErrorMsg, Value};
+    /// assert_eq!("abc", String::try_from(Value::from("abc")).unwrap());
+    ///
+    /// let err = String::try_from(Value::from(1.0)).unwrap_err();
+    /// assert_eq!(
+    ///     FormulaErrorMsg::Expected {
+    ///         expected: "string".into(),
+    ///         got: Some("number".into()),
+    ///     },
+    ///     err.msg,
+    /// );
+    /// ```
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(other.type_error("string")),
+        }
+    }
+}
+impl TryFrom<Value> for bool {
+    type Error = FormulaError;
+
+    /// Returns the boolean contained in a boolean value. Other values,
+    /// including the strings `"TRUE"` and `"FALSE"`, are an error.
+    ///
+    /// ```
+    /// # use quadratic_core::formulas::{FormulaErrorMsg, Value};
+    /// assert!(!bool::try_from(Value::from(false)).unwrap());
+    ///
+    /// let err = bool::try_from(Value::from("TRUE")).unwrap_err();
+    /// assert_eq!(
+    ///     FormulaErrorMsg::Expected {
+    ///         expected: "boolean".into(),
+    ///         got: Some("string".into()),
+    ///     },
+    ///     err.msg,
+    /// );
+    /// assert!(bool::try_from(Value::from(1_i64)).is_err());
+    /// ```
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(other.type_error("boolean")),
+        }
+    }
+}
+
+/// Type of a value, as reported in type mismatch errors.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    #[default]
+    Number,
+    Text,
+    Boolean,
+    Array,
+    /// Cell reference or range. Only arguments that are evaluated specially,
+    /// such as those of `CELL`, can require a reference.
+    Reference,
+    Error,
+    Lambda,
+    Blank,
+}
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Number => write!(f, "a number"),
+            ValueType::Text => write!(f, "text"),
+            ValueType::Boolean => write!(f, "a boolean"),
+            ValueType::Array => write!(f, "an array"),
+            ValueType::Reference => write!(f, "a reference"),
+            ValueType::Error => write!(f, "an error"),
+            ValueType::Lambda => write!(f, "a lambda"),
+            ValueType::Blank => write!(f, "blank"),
+        }
+    }
+}
+impl ValueType {
+    /// Returns the type named in an [`FormulaErrorMsg::Expected`] error, such
+    /// as `"number"`, or `None` if the description is not a value type.
+    pub(crate) fn from_description(s: &str) -> Option<Self> {
+        match s.strip_prefix("a ").unwrap_or(s) {
+            "number" => Some(ValueType::Number),
+            "string" | "text" => Some(ValueType::Text),
+            "boolean" => Some(ValueType::Boolean),
+            "array" => Some(ValueType::Array),
+            "cell reference" => Some(ValueType::Reference),
+            "error" => Some(ValueType::Error),
+            "lambda" => Some(ValueType::Lambda),
+            "blank" => Some(ValueType::Blank),
+            _ => None,
+        }
     }
 }
 
@@ -26,59 +213,210 @@ impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::String(s) => write!(f, "{s}"),
+            Value::Blank => Ok(()),
             Value::Number(n) => write!(f, "{n}"),
             Value::Bool(true) => write!(f, "TRUE"),
             Value::Bool(false) => write!(f, "FALSE"),
-            Value::Array(rows) => {
+            Value::Array(a) => {
                 write!(
                     f,
                     "{{{}}}",
-                    rows.iter().map(|row| row.iter().join(", ")).join("; "),
+                    a.rows().map(|row| row.iter().join(", ")).join("; "),
+                )
+            }
+            Value::Error(e) => write!(f, "{}", e.excel_code().unwrap_or("#ERROR!")),
+            Value::Lambda(lambda) => write!(f, "{lambda}"),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(dt) => {
+                write!(
+                    f,
+                    "{}",
+                    dt.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
                 )
             }
-            Value::MissingErr => write!(f, "[missing]"),
         }
     }
 }
 
 impl Value {
+    /// Constructs an array value from a list of rows, which must all have the
+    /// same length.
+    ///
+    /// ```
+    /// # use quadratic_core::formulas::{FormulaErrorMsg, Value};
+    /// let array = Value::array(vec![
+    ///     vec![1.0.into(), "a".into()],
+    ///     vec![true.into(), 2_i64.into()],
+    /// ])
+    /// .unwrap();
+    /// assert_eq!(Some((2, 2)), array.array_size());
+    /// assert_eq!("{1, a; TRUE, 2}", array.to_string());
+    ///
+    /// let err = Value::array(vec![vec![1.0.into(), 2.0.into()], vec![3.0.into()]]);
+    /// assert_eq!(FormulaErrorMsg::NonRectangularArray, err.unwrap_err().msg);
+    /// ```
+    pub fn array(rows: Vec<Vec<Value>>) -> Result<Value, FormulaError> {
+        Array::from_rows(rows).map(Value::Array)
+    }
+
+    /// Returns the type of the value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::String(_) => ValueType::Text,
+            Value::Blank => ValueType::Blank,
+            Value::Number(_) => ValueType::Number,
+            Value::Bool(_) => ValueType::Boolean,
+            Value::Array(_) => ValueType::Array,
+            Value::Error(_) => ValueType::Error,
+            Value::Lambda(_) => ValueType::Lambda,
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => ValueType::Number,
+        }
+    }
+
     pub fn type_name(&self) -> &'static str {
         match self {
             Value::String(_) => "string",
+            Value::Blank => "blank",
             Value::Number(_) => "number",
             Value::Bool(_) => "boolean",
             Value::Array(_) => "array",
-            Value::MissingErr => "missing value",
+            Value::Error(_) => "error",
+            Value::Lambda(_) => "lambda",
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => "datetime",
+        }
+    }
+
+    /// Formats the value for display using a number format. Booleans and
+    /// errors are always shown the same way, such as `TRUE` or `#DIV/0!`, and
+    /// arrays are formatted elementwise.
+    pub fn format(&self, fmt: &NumberFormat) -> String {
+        match self {
+            Value::String(s) => fmt.format_text(s),
+            Value::Blank => String::new(),
+            // Like Excel, show a number that can't be formatted as a date as
+            // a row of `#`.
+            Value::Number(n) => fmt.format_number(*n).unwrap_or_else(|| "#".repeat(8)),
+            Value::Bool(_) | Value::Error(_) | Value::Lambda(_) => self.to_string(),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(dt) => fmt
+                .format_number(super::datetime::to_serial(dt))
+                .unwrap_or_else(|| self.to_string()),
+            Value::Array(a) => format!(
+                "{{{}}}",
+                a.rows()
+                    .map(|row| row.iter().map(|v| v.format(fmt)).join(", "))
+                    .join("; "),
+            ),
+        }
+    }
+
+    /// Returns the value as it would be written in a formula, so that parsing
+    /// the result gives back the same value. Strings are quoted, booleans are
+    /// written as calls to `TRUE()` or `FALSE()`, errors as error literals
+    /// such as `#N/A`, and arrays as array literals.
+    pub fn to_formula_literal(&self) -> String {
+        match self {
+            Value::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Value::Blank => "\"\"".to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(_) => format!("{self}()"),
+            Value::Array(a) => format!(
+                "{{{}}}",
+                a.rows()
+                    .map(|row| row.iter().map(|v| v.to_formula_literal()).join(", "))
+                    .join("; "),
+            ),
+            Value::Error(_) | Value::Lambda(_) => self.to_string(),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => format!("DATETIME(\"{self}\")"),
         }
     }
 
     /// Returns the number of values.
     ///
-    /// Empty strings count as zero. Each value in an array counts separately.
-    /// Other values count as 1.
+    /// Blanks and empty strings count as zero. Each value in an array counts
+    /// separately. Other values count as 1.
     pub fn count(&self) -> usize {
         match self {
-            Value::Array(a) => a.iter().flat_map(|row| row.iter().map(|v| v.count())).sum(),
+            Value::Array(a) => a.iter().map(|v| v.count()).sum(),
             Value::String(n) if n.is_empty() => 0,
-            Value::MissingErr => 0,
+            Value::Blank => 0,
+
+            Value::String(_)
+            | Value::Number(_)
+            | Value::Bool(_)
+            | Value::Error(_)
+            | Value::Lambda(_) => 1,
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => 1,
+        }
+    }
+
+    /// Returns whether this is blank or an empty string, which most functions
+    /// treat the same way. `ISBLANK` is one that tells them apart.
+    pub fn is_blank_or_empty(&self) -> bool {
+        matches!(self, Value::Blank) || matches!(self, Value::String(s) if s.is_empty())
+    }
+
+    /// Returns whether this is an error value.
+    pub fn is_error(&self) -> bool {
+        matches!(self, Value::Error(_))
+    }
+
+    /// Compares two values in the total order described in
+    /// [`super::order`], which sorting and lookup functions use. Unlike the
+    /// comparison operators, blank is never coerced and is sorted last.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        SortKey::new(self).cmp(&SortKey::new(other))
+    }
+
+    /// Returns the number represented by this value, if it is numeric.
+    ///
+    /// Unlike `to_number()`, blank strings and booleans are not considered
+    /// numeric. This is for functions that ignore non-numeric values.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(dt) => Some(super::datetime::to_serial(dt)),
+            Value::String(s) => {
+                let s = s.trim();
+                if s.is_empty() {
+                    return None;
+                }
+                s.parse().ok()
+            }
+            _ => None,
+        }
+    }
 
-            Value::String(_) | Value::Number(_) | Value::Bool(_) => 1,
+    /// Returns an error saying that a value of type `expected` was expected
+    /// instead of this one.
+    fn type_error(&self, expected: &'static str) -> FormulaError {
+        FormulaErrorMsg::Expected {
+            expected: expected.into(),
+            got: Some(self.type_name().into()),
         }
+        .into()
     }
 
     /// Returns the size `(rows, columns)` of the array if this is an array
     /// value, or `None` otherwsie.
     pub fn array_size(&self) -> Option<(usize, usize)> {
         match self {
-            Value::Array(a) => Some((a.len(), a.get(0).unwrap_or(&smallvec![]).len())),
+            Value::Array(a) => Some(a.size()),
             _ => None,
         }
     }
 }
 
-impl Spanned<Value> {
+/// Conversions of a single value. These work on borrowed values too, so that
+/// elements of an array can be converted without cloning them.
+impl<V: Borrow<Value>> Spanned<V> {
     pub fn to_number(&self) -> FormulaResult<f64> {
-        match &self.inner {
+        match self.inner.borrow() {
             Value::String(s) => {
                 let mut s = s.trim();
                 if s.is_empty() {
@@ -95,64 +433,118 @@ impl Spanned<Value> {
                     .with_span(self)
                 })
             }
+            Value::Blank => Ok(0.0),
             Value::Number(n) => Ok(*n),
+            #[cfg(feature = "datetime")]
+            Value::DateTime(dt) => Ok(super::datetime::to_serial(dt)),
             Value::Bool(true) => Ok(1.0),
             Value::Bool(false) => Ok(0.0),
-            _ => Err(FormulaErrorMsg::Expected {
-                expected: "number".into(),
-                got: Some(self.inner.type_name().into()),
-            }
-            .with_span(self.span)),
+            Value::Error(e) => Err(e.clone().with_span(self.span)),
+            Value::Array(_) => self.to_scalar()?.to_number(),
+            other @ Value::Lambda(_) => Err(other.type_error("number").with_span(self.span)),
         }
     }
     pub fn to_integer(&self) -> FormulaResult<i64> {
         Ok(self.to_number()?.round() as i64)
     }
     pub fn to_bool(&self) -> FormulaResult<bool> {
-        match &self.inner {
+        match self.inner.borrow() {
             Value::Bool(b) => Ok(*b),
+            Value::Blank => Ok(false),
             Value::String(s) if s.eq_ignore_ascii_case("TRUE") => Ok(true),
             Value::String(s) if s.eq_ignore_ascii_case("FALSE") => Ok(false),
+            Value::Error(e) => Err(e.clone().with_span(self.span)),
+            Value::Array(_) => self.to_scalar()?.to_bool(),
             _ => Err(FormulaErrorMsg::Expected {
                 expected: "boolean".into(),
-                got: Some(self.inner.type_name().into()),
+                got: Some(self.inner.borrow().type_name().into()),
             }
             .with_span(self.span)),
         }
     }
 
+    /// Returns the value if it is not an array, or the only value in an array
+    /// with one value. Larger arrays are an
+    /// [`FormulaErrorMsg::ExpectedScalar`] error.
+    pub fn to_scalar(&self) -> FormulaResult<Spanned<Value>> {
+        match self.inner.borrow() {
+            Value::Array(a) => match (a.size(), a.first()) {
+                ((1, 1), Some(v)) => Ok(Spanned {
+                    span: self.span,
+                    inner: v.clone(),
+                }),
+                ((rows, cols), _) => Err(FormulaErrorMsg::ExpectedScalar {
+                    got_size: (rows as u32, cols as u32),
+                }
+                .with_span(self.span)),
+            },
+            other => Ok(Spanned {
+                span: self.span,
+                inner: other.clone(),
+            }),
+        }
+    }
+
+    /// Returns an error if this is an error value.
+    pub fn check_error(&self) -> FormulaResult<()> {
+        match self.inner.borrow() {
+            Value::Error(e) => Err(e.clone().with_span(self.span)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Spanned<Value> {
     pub fn to_numbers(&self) -> FormulaResult<SmallVec<[f64; 1]>> {
-        self.to_flat_array_of(Self::to_number)
+        self.to_flat_array_of(|x| x.to_number())
     }
     pub fn to_bools(&self) -> FormulaResult<SmallVec<[bool; 1]>> {
-        self.to_flat_array_of(Self::to_bool)
+        self.to_flat_array_of(|x| x.to_bool())
     }
     pub fn to_strings(&self) -> FormulaResult<SmallVec<[String; 1]>> {
-        self.to_flat_array_of(|x| Ok(x.to_string()))
+        self.to_flat_array_of(|x| Ok(x.inner.to_string()))
+    }
+    pub fn to_complexes(&self) -> FormulaResult<SmallVec<[Complex; 1]>> {
+        // The closure is needed so that the lifetime of the borrowed value
+        // can be different for each element.
+        #[allow(clippy::redundant_closure)]
+        self.to_flat_array_of(|x| Complex::from_value(x))
     }
     fn to_flat_array_of<T>(
         &self,
-        conv: fn(&Self) -> FormulaResult<T>,
+        conv: fn(&Spanned<&Value>) -> FormulaResult<T>,
     ) -> FormulaResult<SmallVec<[T; 1]>> {
         match &self.inner {
             Value::String(s) if s.is_empty() => Ok(smallvec![]),
+            Value::Blank => Ok(smallvec![]),
 
             Value::Array(a) => a
                 .iter()
-                .flatten()
                 .map(|v| {
-                    conv(&Spanned {
-                        inner: v.clone(),
+                    let v = Spanned {
+                        inner: v,
                         span: self.span,
-                    })
+                    };
+                    v.check_error()?;
+                    conv(&v)
                 })
                 .collect(),
 
-            Value::String(_) | Value::Number(_) | Value::Bool(_) => {
-                conv(self).map(|x| smallvec![x])
+            #[cfg(feature = "datetime")]
+            Value::DateTime(_) => conv(&Spanned {
+                inner: &self.inner,
+                span: self.span,
+            })
+            .map(|x| smallvec![x]),
+            Value::String(_) | Value::Number(_) | Value::Bool(_) | Value::Lambda(_) => {
+                conv(&Spanned {
+                    inner: &self.inner,
+                    span: self.span,
+                })
+                .map(|x| smallvec![x])
             }
 
-            Value::MissingErr => Ok(smallvec![]),
+            Value::Error(e) => Err(e.clone().with_span(self.span)),
         }
     }
 
@@ -164,8 +556,7 @@ impl Spanned<Value> {
             Value::Array(a) => Ok(Spanned {
                 span: self.span,
                 inner: a
-                    .get(row)
-                    .and_then(|row| row.get(col))
+                    .get(row, col)
                     .ok_or_else(|| internal_error_value!("array value index out of bounds"))?
                     .clone(),
             }),
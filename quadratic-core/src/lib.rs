@@ -13,7 +13,7 @@ pub mod formulas;
 mod position;
 
 pub use cell::{Cell, CellTypes, JsCell};
-use formulas::{GridProxy, Value};
+use formulas::{Ctx, GridProxy, Value};
 pub use position::Pos;
 
 pub const QUADRANT_SIZE: u64 = 16;
@@ -40,6 +40,13 @@ struct JsFormulaResult {
     pub error_msg: Option<String>,
     pub output_value: Option<String>,
     pub array_output: Option<Vec<Vec<String>>>,
+    pub warnings: Vec<JsFormulaWarning>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct JsFormulaWarning {
+    pub span: Option<[usize; 2]>,
+    pub msg: String,
 }
 
 #[wasm_bindgen]
@@ -55,7 +62,11 @@ pub async fn eval_formula(
     let pos = Pos { x, y };
 
     let formula_result = match formulas::parse_formula(formula_string, pos) {
-        Ok(formula) => formula.eval(&mut grid_proxy, pos).await,
+        Ok(formula) => {
+            formula
+                .eval_with_warnings(&mut Ctx::new(&mut grid_proxy, pos))
+                .await
+        }
         Err(e) => Err(e),
     };
     let cells_accessed = grid_proxy
@@ -68,10 +79,10 @@ pub async fn eval_formula(
         Ok(formula_output) => {
             let mut output_value = None;
             let mut array_output = None;
-            match formula_output.inner {
+            match formula_output.value {
                 Value::Array(a) => {
                     array_output = Some(
-                        a.iter()
+                        a.rows()
                             .map(|row| row.iter().map(|cell| cell.to_string()).collect())
                             .collect(),
                     )
@@ -85,6 +96,14 @@ pub async fn eval_formula(
                 error_msg: None,
                 output_value,
                 array_output,
+                warnings: formula_output
+                    .warnings
+                    .into_iter()
+                    .map(|warning| JsFormulaWarning {
+                        span: warning.span.map(|span| [span.start, span.end]),
+                        msg: warning.msg.to_string(),
+                    })
+                    .collect(),
             }
         }
         Err(error) => JsFormulaResult {
@@ -94,6 +113,7 @@ pub async fn eval_formula(
             error_msg: Some(error.msg.to_string()),
             output_value: None,
             array_output: None,
+            warnings: vec![],
         },
     };
 
@@ -104,18 +124,38 @@ pub async fn eval_formula(
 struct JsGridProxy {
     grid_accessor_fn: js_sys::Function,
     cells_accessed: HashSet<Pos>,
+    /// Most recently fetched cell object, so that reading several properties
+    /// of the same cell only fetches it once.
+    last_cell: Option<(Pos, JsValue)>,
 }
 impl JsGridProxy {
     fn new(grid_accessor_fn: js_sys::Function) -> Self {
         Self {
             grid_accessor_fn,
             cells_accessed: HashSet::new(),
+            last_cell: None,
         }
     }
-}
-#[async_trait(?Send)]
-impl GridProxy for JsGridProxy {
-    async fn get(&mut self, pos: Pos) -> Option<String> {
+
+    /// Fetches a property of the cell object at `pos` as a string.
+    async fn get_cell_property(&mut self, pos: Pos, property: &str) -> Option<String> {
+        self.get_cell_property_value(pos, property)
+            .await?
+            .as_string()
+    }
+    /// Fetches a property of the cell object at `pos`.
+    async fn get_cell_property_value(&mut self, pos: Pos, property: &str) -> Option<JsValue> {
+        let cell_value = self.get_cell_object(pos).await?;
+        js_sys::Reflect::get(&cell_value, &property.into()).ok()
+    }
+    /// Fetches the cell object at `pos`.
+    async fn get_cell_object(&mut self, pos: Pos) -> Option<JsValue> {
+        if let Some((last_pos, cell_value)) = &self.last_cell {
+            if *last_pos == pos {
+                return Some(cell_value.clone());
+            }
+        }
+
         let js_this = JsValue::UNDEFINED;
 
         self.cells_accessed.insert(pos);
@@ -129,7 +169,101 @@ impl GridProxy for JsGridProxy {
             .await
             .ok()?;
         let cell_value = js_sys::Reflect::get(&cell_value_array, &0.into()).ok()?;
-        let cell_string = js_sys::Reflect::get(&cell_value, &"value".into()).ok()?;
-        cell_string.as_string()
+        self.last_cell = Some((pos, cell_value.clone()));
+        Some(cell_value)
+    }
+    /// Fetches the cell objects in the rectangle from `min` to `max`, or in
+    /// the whole sheet if there is no rectangle, along with their positions.
+    async fn get_cell_objects(&mut self, rect: Option<(Pos, Pos)>) -> Vec<(Pos, JsValue)> {
+        let js_this = JsValue::UNDEFINED;
+        let promise = match rect {
+            Some((min, max)) => self
+                .grid_accessor_fn
+                .bind2(&js_this, &min.x.into(), &min.y.into()) // Upper-left corner
+                .call2(&js_this, &max.x.into(), &max.y.into()), // Lower-right corner
+            // The corners default to the whole sheet.
+            None => self.grid_accessor_fn.call0(&js_this),
+        };
+        let Ok(promise) = promise.map(js_sys::Promise::from) else {
+            return vec![];
+        };
+        let Ok(cells) = wasm_bindgen_futures::JsFuture::from(promise).await else {
+            return vec![];
+        };
+        js_sys::Array::from(&cells)
+            .iter()
+            .filter_map(|cell| {
+                let coord = |name: &str| js_sys::Reflect::get(&cell, &name.into()).ok()?.as_f64();
+                let pos = Pos {
+                    x: coord("x")? as i64,
+                    y: coord("y")? as i64,
+                };
+                Some((pos, cell))
+            })
+            .collect()
+    }
+}
+#[async_trait(?Send)]
+impl GridProxy for JsGridProxy {
+    async fn get(&mut self, pos: Pos) -> Option<String> {
+        self.get_cell_property(pos, "value").await
+    }
+
+    async fn get_error(&mut self, pos: Pos) -> Option<String> {
+        // Errors in formulas are reported where they happen, not in the cells
+        // that refer to them.
+        if self.get_cell_property(pos, "type").await.as_deref() == Some("FORMULA") {
+            return None;
+        }
+        let result = self
+            .get_cell_property_value(pos, "evaluation_result")
+            .await?;
+        let success = js_sys::Reflect::get(&result, &"success".into()).ok()?;
+        if success.as_bool() != Some(false) {
+            return None;
+        }
+        let std_err = js_sys::Reflect::get(&result, &"std_err".into()).ok()?;
+        Some(
+            std_err
+                .as_string()
+                .unwrap_or_else(|| "unknown error".to_string()),
+        )
+    }
+
+    async fn get_formula(&mut self, pos: Pos) -> Option<String> {
+        self.get_cell_property(pos, "formula_code").await
+    }
+
+    async fn get_spill_extent(&mut self, pos: Pos) -> Option<Pos> {
+        let array_cells = self.get_cell_property_value(pos, "array_cells").await?;
+        let array_cells: Vec<[i64; 2]> = serde_wasm_bindgen::from_value(array_cells).ok()?;
+        let x = array_cells.iter().map(|[x, _]| *x).max()?;
+        let y = array_cells.iter().map(|[_, y]| *y).max()?;
+        Some(Pos { x, y })
+    }
+
+    async fn used_region(&mut self) -> Option<(Pos, Pos)> {
+        let cells = self.get_cell_objects(None).await;
+        let (min_x, max_x) = cells.iter().map(|(pos, _)| pos.x).minmax().into_option()?;
+        let (min_y, max_y) = cells.iter().map(|(pos, _)| pos.y).minmax().into_option()?;
+        Some((Pos { x: min_x, y: min_y }, Pos { x: max_x, y: max_y }))
+    }
+
+    async fn iter_range(&mut self, min: Pos, max: Pos) -> Box<dyn Iterator<Item = (Pos, String)>> {
+        let mut cells = vec![];
+        for (pos, cell) in self.get_cell_objects(Some((min, max))).await {
+            self.cells_accessed.insert(pos);
+            let value = js_sys::Reflect::get(&cell, &"value".into())
+                .ok()
+                .and_then(|value| value.as_string())
+                .unwrap_or_default();
+            // Let `get_error()` reuse the cell object.
+            self.last_cell = Some((pos, cell));
+            if !value.is_empty() || self.get_error(pos).await.is_some() {
+                cells.push((pos, value));
+            }
+        }
+        cells.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        Box::new(cells.into_iter())
     }
 }
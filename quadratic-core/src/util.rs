@@ -33,7 +33,7 @@ pub fn column_from_name(mut s: &str) -> Option<i64> {
     }
 
     fn digit(c: char) -> Option<i64> {
-        ('A'..='Z').contains(&c).then(|| c as i64 - 'A' as i64)
+        c.is_ascii_uppercase().then(|| c as i64 - 'A' as i64)
     }
 
     let mut chars = s.chars();
@@ -56,7 +56,7 @@ pub fn column_from_name(mut s: &str) -> Option<i64> {
 /// conjuction.
 pub fn join_with_conjunction(conjunction: &str, items: &[impl fmt::Display]) -> String {
     match items {
-        [] => format!("(none)"),
+        [] => "(none)".to_string(),
         [a] => format!("{}", a),
         [a, b] => format!("{} {} {}", a, conjunction, b),
         [all_but_last @ .., z] => {
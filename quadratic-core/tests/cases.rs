@@ -0,0 +1,171 @@
+//! Data-driven formula tests.
+//!
+//! Each `.formula` file in `tests/cases` describes a formula, the contents of
+//! any cells it reads, and the expected result or error. See
+//! `tests/cases/README.md` for the file format.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use async_trait::async_trait;
+use quadratic_core::formulas::{parse_formula, CellRef, Ctx, GridProxy, Span};
+use quadratic_core::Pos;
+
+const CASES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cases");
+
+/// Grid containing the cells listed in a test case.
+#[derive(Debug, Default)]
+struct FixtureGrid {
+    cells: HashMap<Pos, String>,
+}
+#[async_trait(?Send)]
+impl GridProxy for FixtureGrid {
+    async fn get(&mut self, pos: Pos) -> Option<String> {
+        self.cells.get(&pos).cloned()
+    }
+}
+
+/// Expected outcome of evaluating a formula.
+#[derive(Debug)]
+enum Expected {
+    /// Value, formatted as a string.
+    Result(String),
+    /// Error with the given `short_name()` and optional span.
+    Error {
+        short_name: String,
+        span: Option<Span>,
+    },
+}
+
+#[derive(Debug)]
+struct TestCase {
+    formula: String,
+    grid: FixtureGrid,
+    expected: Expected,
+}
+impl TestCase {
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut formula = None;
+        let mut grid = FixtureGrid::default();
+        let mut result = None;
+        let mut error = None;
+        let mut span = None;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(format!("line {}: expected `key: value`", i + 1));
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "formula" => formula = Some(value),
+                "result" => result = Some(value),
+                "error" => error = Some(value),
+                "span" => {
+                    span = Some(parse_span(&value).ok_or(format!("line {}: bad span", i + 1))?)
+                }
+                key => match CellRef::parse_a1(key, Pos::ORIGIN) {
+                    Some(cell_ref) => {
+                        grid.cells.insert(cell_ref.resolve_from(Pos::ORIGIN), value);
+                    }
+                    None => return Err(format!("line {}: unknown key {key:?}", i + 1)),
+                },
+            }
+        }
+
+        let expected = match (result, error) {
+            (Some(result), None) if span.is_none() => Expected::Result(result),
+            (None, Some(short_name)) => Expected::Error { short_name, span },
+            _ => return Err("expected either `result` or `error` (with optional `span`)".into()),
+        };
+        Ok(Self {
+            formula: formula.ok_or("missing `formula`")?,
+            grid,
+            expected,
+        })
+    }
+
+    fn run(mut self) -> Result<(), String> {
+        let result = parse_formula(&self.formula, Pos::ORIGIN)
+            .and_then(|formula| formula.eval_blocking(&mut Ctx::new(&mut self.grid, Pos::ORIGIN)));
+        match (&self.expected, result) {
+            (Expected::Result(expected), Ok(value)) => {
+                let got = value.inner.to_string();
+                check("result", expected, &got)
+            }
+            (Expected::Error { short_name, span }, Err(e)) => {
+                check("error", short_name, e.msg.short_name())?;
+                match span {
+                    Some(span) => check(
+                        "span",
+                        &fmt_span(*span),
+                        &e.span.map_or("none".into(), fmt_span),
+                    ),
+                    None => Ok(()),
+                }
+            }
+            (Expected::Result(expected), Err(e)) => {
+                Err(format!("expected result {expected:?}, got error: {e}"))
+            }
+            (Expected::Error { short_name, .. }, Ok(value)) => Err(format!(
+                "expected error {short_name:?}, got result {:?}",
+                value.inner.to_string(),
+            )),
+        }
+    }
+}
+
+/// Parses a span written as `start..end`.
+fn parse_span(s: &str) -> Option<Span> {
+    let (start, end) = s.split_once("..")?;
+    Some(Span {
+        start: start.trim().parse().ok()?,
+        end: end.trim().parse().ok()?,
+    })
+}
+fn fmt_span(span: Span) -> String {
+    format!("{}..{}", span.start, span.end)
+}
+
+fn check(what: &str, expected: &str, got: &str) -> Result<(), String> {
+    if expected == got {
+        Ok(())
+    } else {
+        Err(format!("expected {what} {expected:?}, got {got:?}"))
+    }
+}
+
+#[test]
+fn test_formula_cases() {
+    let mut paths = fs::read_dir(CASES_DIR)
+        .expect("error reading test cases directory")
+        .map(|entry| entry.expect("error reading test case").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "formula"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "no test cases found in {CASES_DIR}");
+
+    let failures = paths
+        .iter()
+        .filter_map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            run_file(path).err().map(|e| format!("{name}: {e}"))
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        failures.is_empty(),
+        "{} of {} formula test cases failed:\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n"),
+    );
+}
+
+fn run_file(path: &Path) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    TestCase::parse(&contents)?.run()
+}
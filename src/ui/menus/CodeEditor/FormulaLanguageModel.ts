@@ -4,6 +4,23 @@ const keywords = [
   // MATHEMATICAL OPERATORS
   'SUM',
   'PRODUCT',
+  'SUMX2MY2',
+  'SUMX2PY2',
+  'SUMXMY2',
+  'SERIESSUM',
+  'ROUND',
+  'FLOOR',
+  'CEILING',
+  'FLOOR.PRECISE',
+  'CEILING.PRECISE',
+  'ISO.CEILING',
+  'RANDARRAY',
+  'SEQUENCE',
+  // MATRIX FUNCTIONS
+  'MMULT',
+  'MINVERSE',
+  'MDETERM',
+  'MUNIT',
   // LOGIC FUNCTIONS
   'TRUE',
   'FALSE',
@@ -12,13 +29,159 @@ const keywords = [
   'OR',
   'XOR',
   'IF',
+  'LET',
+  'LAMBDA',
+  'MAP',
+  'REDUCE',
+  'SCAN',
+  // ERROR FUNCTIONS
+  'NA',
+  'ERROR',
+  'ISERROR',
+  'TYPE',
+  'ISBLANK',
+  'ISOMITTED',
   // STATISTICS FUNCTIONS
   'AVERAGE',
   'COUNT',
+  'COUNTUNIQUE',
+  'COUNTBLANK',
   'MIN',
   'MAX',
+  'SUMSQ',
+  'DEVSQ',
+  'AVEDEV',
+  'GEOMEAN',
+  'HARMEAN',
+  'FREQUENCY',
+  'SUMIF',
+  'SUMIFS',
+  'COUNTIF',
+  'COUNTIFS',
+  'AVERAGEIF',
+  'AVERAGEIFS',
+  'MAXIFS',
+  'MINIFS',
+  'AGGREGATE',
+  'SUBTOTAL',
+  'SLOPE',
+  'INTERCEPT',
+  'CORREL',
+  'RSQ',
+  'LINEST',
+  'LOGEST',
+  'TREND',
+  'GROWTH',
+  // PROBABILITY DISTRIBUTIONS
+  'NORM.DIST',
+  'NORM.INV',
+  'NORM.S.DIST',
+  'NORM.S.INV',
+  'PHI',
+  'GAUSS',
+  'NORMDIST',
+  'NORMINV',
+  'NORMSDIST',
+  'NORMSINV',
+  'BINOM.DIST',
+  'POISSON.DIST',
+  'HYPGEOM.DIST',
+  'EXPON.DIST',
+  'GAMMALN',
+  'GAMMALN.PRECISE',
+  'BINOMDIST',
+  'POISSON',
+  'EXPONDIST',
+  // DATABASE FUNCTIONS
+  'DSUM',
+  'DAVERAGE',
+  'DCOUNT',
+  'DMAX',
+  'DMIN',
+  'DGET',
+  // DATE FUNCTIONS
+  'DATE',
+  'YEAR',
+  'MONTH',
+  'DAY',
+  'DATETIME',
+  'DATEVALUE',
+  'TIMEVALUE',
+  'WEEKNUM',
+  'ISOWEEKNUM',
+  'EDATE',
+  'EOMONTH',
+  'WORKDAY',
+  'WORKDAY.INTL',
+  'NETWORKDAYS',
+  'NETWORKDAYS.INTL',
+  'DAYS',
+  'DAYS360',
+  'YEARFRAC',
   // STRING FUNCTIONS
+  'LEN',
+  'LEFT',
+  'RIGHT',
+  'MID',
+  'SUBSTITUTE',
+  'REPLACE',
+  'PROPER',
+  'EXACT',
   'CONCAT',
+  'REPT',
+  'TEXT',
+  'DOLLAR',
+  'FIXED',
+  'NUMBERVALUE',
+  'TEXTSPLIT',
+  'ARRAYTOTEXT',
+  'VALUETOTEXT',
+  'ENCODEURL',
+  'REGEXMATCH',
+  'REGEXEXTRACT',
+  'REGEXREPLACE',
+  'CODE',
+  'UNICODE',
+  'CHAR',
+  'UNICHAR',
+  // ENGINEERING FUNCTIONS
+  'BIN2OCT',
+  'BIN2DEC',
+  'BIN2HEX',
+  'OCT2BIN',
+  'OCT2DEC',
+  'OCT2HEX',
+  'DEC2BIN',
+  'DEC2OCT',
+  'DEC2HEX',
+  'HEX2BIN',
+  'HEX2OCT',
+  'HEX2DEC',
+  'BITAND',
+  'BITOR',
+  'BITXOR',
+  'BITLSHIFT',
+  'BITRSHIFT',
+  'CONVERT',
+  'COMPLEX',
+  'IMREAL',
+  'IMAGINARY',
+  'IMABS',
+  'IMSUM',
+  'IMPRODUCT',
+  // LOOKUP FUNCTIONS
+  'INDEX',
+  'XMATCH',
+  'XLOOKUP',
+  'MATCH',
+  'SORT',
+  'UNIQUE',
+  'WRAPROWS',
+  'WRAPCOLS',
+  'TOCOL',
+  'TOROW',
+  'ISFORMULA',
+  'FORMULATEXT',
 ];
 export const FormulaLanguageConfig = {
   ignore_case: true,
@@ -52,7 +215,7 @@ export const FormulaLanguageConfig = {
   ],
   tokenizer: {
     root: [
-      [/[a-zA-Z_$][\w$]*/, { cases: { '@keywords': 'keyword', '@default': 'variable' } }],
+      [/[a-zA-Z_$][\w$]*(\.[\w$]+)*/, { cases: { '@keywords': 'keyword', '@default': 'variable' } }],
 
       // cell references
       [/\$?[A-Z]+\$?n?\d+/, ''],